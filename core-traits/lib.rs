@@ -126,6 +126,60 @@ pub mod attribute {
             }
         }
     }
+
+    /// How the values of a fulltext attribute are split into searchable terms, i.e., its
+    /// `:db/fulltextTokenizer`.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialOrd, PartialEq)]
+    pub enum FulltextTokenizer {
+        /// Unicode-aware words, folding case but preserving diacritics.  This is the default.
+        #[default]
+        Unicode61,
+
+        /// Unicode-aware words, folding case and removing diacritics, so that "café" matches
+        /// "cafe".
+        Unaccented,
+
+        /// Unaccented words, reduced to their English stems, so that "running" matches "runs".
+        Porter,
+
+        /// Every sequence of three characters, so that any substring of at least three characters
+        /// matches.
+        Trigram,
+    }
+
+    impl FulltextTokenizer {
+        pub fn all() -> [FulltextTokenizer; 4] {
+            [
+                FulltextTokenizer::Unicode61,
+                FulltextTokenizer::Unaccented,
+                FulltextTokenizer::Porter,
+                FulltextTokenizer::Trigram,
+            ]
+        }
+
+        pub fn name(self) -> &'static str {
+            match self {
+                FulltextTokenizer::Unicode61 => "unicode61",
+                FulltextTokenizer::Unaccented => "unaccented",
+                FulltextTokenizer::Porter => "porter",
+                FulltextTokenizer::Trigram => "trigram",
+            }
+        }
+
+        /// The SQL table that indexes fulltext values with this tokenizer.
+        pub fn index_table(self) -> &'static str {
+            match self {
+                FulltextTokenizer::Unicode61 => "fulltext_index_unicode61",
+                FulltextTokenizer::Unaccented => "fulltext_index_unaccented",
+                FulltextTokenizer::Porter => "fulltext_index_porter",
+                FulltextTokenizer::Trigram => "fulltext_index_trigram",
+            }
+        }
+
+        pub fn into_typed_value(self) -> TypedValue {
+            TypedValue::typed_ns_keyword("db.fulltextTokenizer", self.name())
+        }
+    }
 }
 
 /// A Mentat schema attribute has a value type and several other flags determining how assertions
@@ -167,6 +221,10 @@ pub struct Attribute {
     /// Fulltext attributes always have string values.
     pub fulltext: bool,
 
+    /// How the values of this attribute are tokenized for fulltext search, i.e., its
+    /// `:db/fulltextTokenizer`.  Only meaningful for fulltext attributes.
+    pub fulltext_tokenizer: attribute::FulltextTokenizer,
+
     /// `true` if this attribute is a component, i.e., it is `:db/isComponent true`.
     ///
     /// Component attributes always have value type `Ref`.
@@ -238,6 +296,15 @@ impl Attribute {
 
         if self.fulltext {
             attribute_map.insert(values::DB_FULLTEXT.clone(), edn::Value::Boolean(true));
+            if self.fulltext_tokenizer != attribute::FulltextTokenizer::default() {
+                attribute_map.insert(
+                    values::DB_FULLTEXT_TOKENIZER.clone(),
+                    edn::Value::Keyword(Keyword::namespaced(
+                        "db.fulltextTokenizer",
+                        self.fulltext_tokenizer.name(),
+                    )),
+                );
+            }
         }
 
         if self.component {
@@ -258,6 +325,7 @@ impl Default for Attribute {
             // There's no particular reason to favour one value type, so Ref it is.
             value_type: ValueType::Ref,
             fulltext: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::default(),
             index: false,
             multival: false,
             unique: None,
@@ -1041,6 +1109,7 @@ mod tests {
             index: true,
             value_type: ValueType::Ref,
             fulltext: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::default(),
             unique: None,
             multival: false,
             component: false,
@@ -1056,6 +1125,7 @@ mod tests {
             index: false,
             value_type: ValueType::Boolean,
             fulltext: true,
+            fulltext_tokenizer: attribute::FulltextTokenizer::default(),
             unique: Some(attribute::Unique::Value),
             multival: false,
             component: false,
@@ -1071,6 +1141,7 @@ mod tests {
             index: false,
             value_type: ValueType::Boolean,
             fulltext: true,
+            fulltext_tokenizer: attribute::FulltextTokenizer::default(),
             unique: Some(attribute::Unique::Identity),
             multival: false,
             component: false,
@@ -42,6 +42,7 @@ lazy_static_namespaced_keyword_value!(DB_CARDINALITY, "db", "cardinality");
 lazy_static_namespaced_keyword_value!(DB_CARDINALITY_MANY, "db.cardinality", "many");
 lazy_static_namespaced_keyword_value!(DB_CARDINALITY_ONE, "db.cardinality", "one");
 lazy_static_namespaced_keyword_value!(DB_FULLTEXT, "db", "fulltext");
+lazy_static_namespaced_keyword_value!(DB_FULLTEXT_TOKENIZER, "db", "fulltextTokenizer");
 lazy_static_namespaced_keyword_value!(DB_IDENT, "db", "ident");
 lazy_static_namespaced_keyword_value!(DB_INDEX, "db", "index");
 lazy_static_namespaced_keyword_value!(DB_INSTALL_ATTRIBUTE, "db.install", "attribute");
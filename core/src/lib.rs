@@ -32,7 +32,7 @@ pub use chrono::{
     Timelike, // For truncation.
 };
 
-pub use edn::parse::parse_query;
+pub use edn::parse::{parse_query, parse_rules};
 pub use edn::{Cloned, FromMicros, FromRc, Keyword, ToMicros, Utc, ValueRc};
 
 pub use crate::cache::{CachedAttributes, UpdateableCache};
@@ -42,7 +42,7 @@ mod tx_report;
 /// Core types defining a Mentat knowledge base.
 mod types;
 
-pub use crate::tx_report::TxReport;
+pub use crate::tx_report::{TxDatom, TxReport};
 
 pub use crate::types::ValueTypeTag;
 
@@ -283,6 +283,7 @@ mod test {
             index: true,
             value_type: ValueType::Ref,
             fulltext: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::default(),
             unique: None,
             multival: false,
             component: false,
@@ -295,6 +296,7 @@ mod test {
             index: false,
             value_type: ValueType::String,
             fulltext: true,
+            fulltext_tokenizer: attribute::FulltextTokenizer::default(),
             unique: Some(attribute::Unique::Value),
             multival: true,
             component: false,
@@ -307,6 +309,7 @@ mod test {
             index: false,
             value_type: ValueType::Boolean,
             fulltext: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::default(),
             unique: Some(attribute::Unique::Identity),
             multival: false,
             component: true,
@@ -11,10 +11,20 @@
 #![allow(dead_code)]
 
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
-use core_traits::Entid;
+use core_traits::{Entid, TypedValue};
 
-use crate::{DateTime, Utc};
+use crate::{DateTime, Schema, Utc};
+
+/// A datom asserted or retracted by a transaction.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct TxDatom {
+    pub e: Entid,
+    pub a: Entid,
+    pub v: TypedValue,
+    pub added: bool,
+}
 
 /// A transaction report summarizes an applied transaction.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
@@ -31,4 +41,25 @@ pub struct TxReport {
     /// existing entid, or is allocated a new entid.  (It is possible for multiple distinct string
     /// literal tempids to all unify to a single freshly allocated entid.)
     pub tempids: BTreeMap<String, Entid>,
+
+    /// Whether the transaction changed the schema.
+    pub schema_changed: bool,
+
+    /// The datoms asserted and retracted by the transaction, including its `:db/txInstant` and any
+    /// datoms it excised, as the transactor applied them.
+    ///
+    /// These are the changes the transaction made, after resolving tempids, lookup refs and
+    /// transaction functions: replacing a cardinality-one value lists both the new value and the
+    /// retraction of the old one, and asserting a datom that's already present, or retracting one
+    /// that isn't, lists nothing.
+    ///
+    /// Collecting these has a cost, so they're only present when they were asked for.
+    pub tx_data: Option<Vec<TxDatom>>,
+
+    /// The schema the transaction was applied against.  Present alongside `tx_data`.
+    pub schema_before: Option<Arc<Schema>>,
+
+    /// The schema after the transaction, which is `schema_before` unless `schema_changed`.  Present
+    /// alongside `tx_data`.
+    pub schema_after: Option<Arc<Schema>>,
 }
@@ -195,8 +195,17 @@ pub enum DbErrorKind {
 
     /// The SQLite store user_version isn't recognized.  This could be an old version of Mentat
     /// trying to open a newer version SQLite store; or it could be a corrupt file; or ...
-    /// #[fail(display = "bad SQL store user_version: {}", _0)]
-    /// BadSQLiteStoreVersion(i32),
+    #[fail(display = "bad SQL store user_version: {}", _0)]
+    BadSQLiteStoreVersion(i32),
+
+    /// Upgrading the SQLite store from one user_version to another failed, and the store was left
+    /// at the first.
+    #[fail(
+        display = "could not upgrade SQL store from user_version {} to {}: {}",
+        _0, _1, _2
+    )]
+    MigrationFailed(i32, i32, String),
+
     /// A bootstrap definition couldn't be parsed or installed.  This is a programmer error, not
     /// a runtime error.
     #[fail(display = "bad bootstrap definition: {}", _0)]
@@ -227,6 +236,10 @@ pub enum DbErrorKind {
     #[fail(display = "schema alteration failed: {}", _0)]
     SchemaAlterationFailed(String),
 
+    /// An observer's datom pattern couldn't be parsed or resolved against the schema.
+    #[fail(display = "bad observer pattern: {}", _0)]
+    BadObserverPattern(String),
+
     /// A transaction tried to violate a constraint of the schema of the Mentat store.
     #[fail(display = "schema constraint violation: {}", _0)]
     SchemaConstraintViolation(SchemaConstraintViolation),
@@ -241,6 +254,33 @@ pub enum DbErrorKind {
     )]
     WrongTypeValueForFtsAssertion,
 
+    /// A `[:db/cas e a old new]` found that `e` doesn't currently have the value `old` for `a`.
+    #[fail(
+        display = "compare-and-swap failed for [{} {}]: expected {:?} but found {:?}",
+        e, a, expected, found
+    )]
+    CasFailed {
+        e: Entid,
+        a: Entid,
+        expected: Option<TypedValue>,
+        found: Option<TypedValue>,
+    },
+
+    /// A tempid named a partition, like `(tempid :myapp.part/logs)`, that isn't installed.
+    #[fail(display = "unknown partition: {}", _0)]
+    UnknownPartition(String),
+
+    /// A partition has no more entids to allocate.
+    #[fail(display = "partition exhausted: {}", _0)]
+    PartitionExhausted(String),
+
+    #[fail(display = "partition installation failed: {}", _0)]
+    PartitionInstallationFailed(String),
+
+    /// A `[e :db/excise target]` named an entity in a partition that doesn't allow excision.
+    #[fail(display = "excision not allowed for entity {}", _0)]
+    ExcisionNotAllowed(Entid),
+
     // SQL errors.
     #[fail(display = "could not update a cache")]
     CacheUpdateFailed,
@@ -36,7 +36,7 @@ pub const USER0: i64 = 0x10000;
 pub const CORE_SCHEMA_VERSION: u32 = 1;
 
 lazy_static! {
-    static ref V1_IDENTS: [(symbols::Keyword, i64); 40] = {
+    static ref V1_IDENTS: [(symbols::Keyword, i64); 50] = {
         [
             (ns_keyword!("db", "ident"), entids::DB_IDENT),
             (ns_keyword!("db.part", "db"), entids::DB_PART_DB),
@@ -108,6 +108,43 @@ lazy_static! {
                 entids::DB_SCHEMA_ATTRIBUTE,
             ),
             (ns_keyword!("db.schema", "core"), entids::DB_SCHEMA_CORE),
+            (
+                ns_keyword!("db.partition", "start"),
+                entids::DB_PARTITION_START,
+            ),
+            (ns_keyword!("db.partition", "end"), entids::DB_PARTITION_END),
+            (
+                ns_keyword!("db.partition", "allowExcision"),
+                entids::DB_PARTITION_ALLOW_EXCISION,
+            ),
+            (
+                ns_keyword!("db", "fulltextTokenizer"),
+                entids::DB_FULLTEXT_TOKENIZER,
+            ),
+            (
+                ns_keyword!("db.fulltextTokenizer", "unicode61"),
+                entids::DB_FULLTEXT_TOKENIZER_UNICODE61,
+            ),
+            (
+                ns_keyword!("db.fulltextTokenizer", "unaccented"),
+                entids::DB_FULLTEXT_TOKENIZER_UNACCENTED,
+            ),
+            (
+                ns_keyword!("db.fulltextTokenizer", "porter"),
+                entids::DB_FULLTEXT_TOKENIZER_PORTER,
+            ),
+            (
+                ns_keyword!("db.fulltextTokenizer", "trigram"),
+                entids::DB_FULLTEXT_TOKENIZER_TRIGRAM,
+            ),
+            (
+                ns_keyword!("db.excise", "excisedAttrs"),
+                entids::DB_EXCISE_EXCISED_ATTRS,
+            ),
+            (
+                ns_keyword!("db.excise", "excisedCount"),
+                entids::DB_EXCISE_EXCISED_COUNT,
+            ),
         ]
     };
     pub static ref V1_PARTS: [(symbols::Keyword, i64, i64, i64, bool); 3] = {
@@ -183,11 +220,37 @@ lazy_static! {
                         :db/cardinality :db.cardinality/one}
  :db/noHistory         {:db/valueType   :db.type/boolean
                         :db/cardinality :db.cardinality/one}
+ ;; One of the :db.fulltextTokenizer/* idents.  Defaults to :db.fulltextTokenizer/unicode61.
+ :db/fulltextTokenizer {:db/valueType   :db.type/ref
+                        :db/cardinality :db.cardinality/one}
  :db.alter/attribute   {:db/valueType   :db.type/ref
                         :db/cardinality :db.cardinality/many}
  :db.schema/version    {:db/valueType   :db.type/long
                         :db/cardinality :db.cardinality/one}
 
+ ;; Asserted on an entity to permanently remove datoms about the referenced entity.
+ :db/excise            {:db/valueType   :db.type/ref
+                        :db/cardinality :db.cardinality/one}
+ :db.excise/attrs      {:db/valueType   :db.type/ref
+                        :db/cardinality :db.cardinality/many}
+ :db.excise/beforeT    {:db/valueType   :db.type/ref
+                        :db/cardinality :db.cardinality/one}
+ :db.excise/before     {:db/valueType   :db.type/instant
+                        :db/cardinality :db.cardinality/one}
+ ;; Recorded on the excising entity: the attributes of the excised datoms, and how many there were.
+ :db.excise/excisedAttrs {:db/valueType   :db.type/ref
+                          :db/cardinality :db.cardinality/many}
+ :db.excise/excisedCount {:db/valueType   :db.type/long
+                          :db/cardinality :db.cardinality/one}
+
+ ;; Asserted on a partition installed with :db.install/partition.
+ :db.partition/start   {:db/valueType   :db.type/long
+                        :db/cardinality :db.cardinality/one}
+ :db.partition/end     {:db/valueType   :db.type/long
+                        :db/cardinality :db.cardinality/one}
+ :db.partition/allowExcision {:db/valueType   :db.type/boolean
+                              :db/cardinality :db.cardinality/one}
+
  ;; unique-value because an attribute can only belong to a single
  ;; schema fragment.
  :db.schema/attribute  {:db/valueType   :db.type/ref
@@ -13,14 +13,18 @@
 use failure::ResultExt;
 
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
 use std::iter::{once, repeat};
 use std::ops::Range;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use itertools;
 use itertools::Itertools;
+use regex::Regex;
 use rusqlite;
+use rusqlite::functions::FunctionFlags;
 use rusqlite::limits::Limit;
 use rusqlite::types::{ToSql, ToSqlOutput};
 use rusqlite::TransactionBehavior;
@@ -31,10 +35,11 @@ use crate::{repeat_values, to_namespaced_keyword};
 use edn::{DateTime, Utc, Uuid, Value};
 
 use crate::entids;
+use crate::fulltext;
 
 use core_traits::{attribute, Attribute, AttributeBitFlags, Entid, TypedValue, ValueType};
 
-use mentat_core::{AttributeMap, FromMicros, IdentMap, Schema, ToMicros, ValueRc};
+use mentat_core::{AttributeMap, FromMicros, IdentMap, SQLValueType, Schema, ToMicros, ValueRc};
 
 use db_traits::errors::{DbErrorKind, Result};
 
@@ -103,9 +108,34 @@ fn make_connection(
         initial_pragmas
     ))?;
 
+    register_functions(&conn)?;
+
     Ok(conn)
 }
 
+/// Register the SQL functions that queries use but SQLite doesn't provide.
+fn register_functions(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    // SQLite parses `x REGEXP y` as a call to `regexp(y, x)`, but leaves it to us to provide the
+    // function. Queries use it for `re-find`.
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            // SQLite holds on to the compiled pattern for as long as the argument is unchanged.
+            let pattern: Arc<Regex> = ctx.get_or_create_aux(0, |pattern| {
+                Regex::new(pattern.as_str()?)
+                    .map_err(|e| rusqlite::Error::UserFunctionError(Box::new(e)))
+            })?;
+            let text = ctx
+                .get_raw(1)
+                .as_str()
+                .map_err(|e| rusqlite::Error::UserFunctionError(Box::new(e)))?;
+            Ok(pattern.is_match(text))
+        },
+    )
+}
+
 pub fn new_connection<T>(uri: T) -> rusqlite::Result<rusqlite::Connection>
 where
     T: AsRef<Path>,
@@ -142,11 +172,16 @@ where
 /// Version history:
 ///
 /// 1: initial Rust Mentat schema.
-pub const CURRENT_VERSION: i32 = 1;
+/// 2: fulltext values are indexed by FTS5, with a tokenizer per attribute.
+pub const CURRENT_VERSION: i32 = 2;
 
 /// MIN_SQLITE_VERSION should be changed when there's a new minimum version of sqlite required
-/// for the project to work.
-const MIN_SQLITE_VERSION: i32 = 3_008_000;
+/// for the project to work.  FTS5 arrived in 3.9.0.  Features that need a newer sqlite, like
+/// upgrading a file-backed store or the trigram tokenizer, check for it when they're used.
+const MIN_SQLITE_VERSION: i32 = 3_009_000;
+
+/// `VACUUM INTO`, which copies a store before upgrading it, arrived in SQLite 3.27.0.
+const MIN_SQLITE_VERSION_FOR_BACKUP: i32 = 3_027_000;
 
 const TRUE: &bool = &true;
 const FALSE: &bool = &false;
@@ -249,6 +284,56 @@ lazy_static! {
         r#"CREATE TABLE known_parts (part TEXT NOT NULL PRIMARY KEY, start INTEGER NOT NULL, end INTEGER NOT NULL, allow_excision SMALLINT NOT NULL)"#,
         ]
     };
+
+    /// SQL statements to be executed, in order, to upgrade the Mentat SQL schema from version 1
+    /// to version 2.
+    ///
+    /// Fulltext values move out of the FTS4 virtual table into a plain table, keeping their rowids,
+    /// since datoms refer to them.  The FTS5 indexes are created by `fulltext::ensure_fulltext_index`.
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    static ref V2_STATEMENTS: Vec<&'static str> = { vec![
+        r#"DROP VIEW all_datoms"#,
+        r#"DROP VIEW fulltext_datoms"#,
+        r#"DROP TRIGGER replace_fulltext_searchid"#,
+        r#"DROP TRIGGER insert_fulltext_searchid"#,
+        r#"DROP VIEW fulltext_values_view"#,
+
+        r#"CREATE TABLE fulltext_values_v2 (id INTEGER PRIMARY KEY, text TEXT NOT NULL, searchid INT)"#,
+        r#"INSERT INTO fulltext_values_v2 (id, text, searchid) SELECT rowid, text, searchid FROM fulltext_values"#,
+        r#"DROP TABLE fulltext_values"#,
+        r#"ALTER TABLE fulltext_values_v2 RENAME TO fulltext_values"#,
+        r#"CREATE UNIQUE INDEX idx_fulltext_values_text ON fulltext_values (text)"#,
+        r#"CREATE INDEX idx_fulltext_values_searchid ON fulltext_values (searchid) WHERE searchid IS NOT NULL"#,
+
+        // As in version 1, this combination of view and triggers allows you to transparently
+        // update-or-insert. Just INSERT INTO fulltext_values_view (text, searchid).
+        r#"CREATE VIEW fulltext_values_view AS SELECT text, searchid FROM fulltext_values"#,
+        r#"CREATE TRIGGER replace_fulltext_searchid
+             INSTEAD OF INSERT ON fulltext_values_view
+             WHEN EXISTS (SELECT 1 FROM fulltext_values WHERE text = new.text)
+             BEGIN
+               UPDATE fulltext_values SET searchid = new.searchid WHERE text = new.text;
+             END"#,
+        r#"CREATE TRIGGER insert_fulltext_searchid
+             INSTEAD OF INSERT ON fulltext_values_view
+             WHEN NOT EXISTS (SELECT 1 FROM fulltext_values WHERE text = new.text)
+             BEGIN
+               INSERT INTO fulltext_values (text, searchid) VALUES (new.text, new.searchid);
+             END"#,
+
+        r#"CREATE VIEW fulltext_datoms AS
+             SELECT e, a, fulltext_values.text AS v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value
+               FROM datoms, fulltext_values
+               WHERE datoms.index_fulltext IS NOT 0 AND datoms.v = fulltext_values.id"#,
+        r#"CREATE VIEW all_datoms AS
+             SELECT e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value
+               FROM datoms
+               WHERE index_fulltext IS 0
+             UNION ALL
+             SELECT e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value
+               FROM fulltext_datoms"#,
+        ]
+    };
 }
 
 /// Set the SQLite user version.
@@ -284,6 +369,9 @@ pub fn create_empty_current_version(
     for statement in (&V1_STATEMENTS).iter() {
         tx.execute(statement, rusqlite::params![])?;
     }
+    for migration in MIGRATIONS.iter() {
+        (migration.upgrade)(&tx)?;
+    }
 
     set_user_version(&tx, CURRENT_VERSION)?;
 
@@ -293,6 +381,172 @@ pub fn create_empty_current_version(
     Ok((tx, DB::new(bootstrap_partition_map, bootstrap_schema)))
 }
 
+/// A step upgrading the Mentat SQL schema from the version before `version` to `version`.
+struct Migration {
+    version: i32,
+    upgrade: fn(&rusqlite::Connection) -> Result<()>,
+}
+
+/// The steps upgrading a version 1 store to `CURRENT_VERSION`, in order.  A new version appends a
+/// step here; fresh stores are created by running every step over the version 1 SQL schema.
+static MIGRATIONS: &[Migration] = &[Migration {
+    version: 2,
+    upgrade: upgrade_to_v2,
+}];
+
+/// Upgrade a version 1 store to version 2.
+fn upgrade_to_v2(conn: &rusqlite::Connection) -> Result<()> {
+    upgrade_v1_layout(conn)?;
+    for statement in V2_STATEMENTS.iter() {
+        conn.execute(statement, rusqlite::params![])?;
+    }
+    // The default index always exists: queries fall back to it.
+    fulltext::ensure_fulltext_index(conn, attribute::FulltextTokenizer::default())
+}
+
+/// Bring a version 1 store written by an early release up to the final version 1 layout.
+///
+/// Early releases kept the transaction log in a `transactions` table, before there were timelines,
+/// and partition indices in a `parts` table, before `known_parts` recorded where partitions end.
+/// Those stores only ever had the bootstrap partitions.  Early releases also implied `:db/index
+/// true` for unique and fulltext attributes, which is now required to be explicit.
+fn upgrade_v1_layout(conn: &rusqlite::Connection) -> Result<()> {
+    let legacy: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'transactions')",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    if !legacy {
+        return Ok(());
+    }
+
+    let mut stmt = conn.prepare("SELECT part FROM parts")?;
+    let parts: Result<Vec<String>> = stmt
+        .query_and_then(rusqlite::params![], |row| Ok(row.get(0)?))?
+        .collect();
+    let parts = parts?;
+    drop(stmt);
+
+    // Ends and excision were fixed by the bootstrap partitions; the indices are recomputed from the
+    // transaction log by the `parts` view.
+    let bootstrap_partition_map = bootstrap::bootstrap_partition_map();
+    let mut partitions = Vec::with_capacity(parts.len());
+    for part in parts {
+        match bootstrap_partition_map.get(&part) {
+            Some(partition) => partitions.push((part, partition.clone())),
+            None => bail!(DbErrorKind::UnknownPartition(part)),
+        }
+    }
+
+    #[rustfmt::skip]
+    let statements = [
+        r#"CREATE TABLE timelined_transactions (e INTEGER NOT NULL, a SMALLINT NOT NULL, v BLOB NOT NULL, tx INTEGER NOT NULL, added TINYINT NOT NULL DEFAULT 1, value_type_tag SMALLINT NOT NULL, timeline TINYINT NOT NULL DEFAULT 0)"#,
+        r#"INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag, timeline)
+             SELECT e, a, v, tx, added, value_type_tag, 0 FROM transactions"#,
+        r#"DROP TABLE transactions"#,
+        r#"CREATE INDEX idx_timelined_transactions_timeline ON timelined_transactions (timeline)"#,
+        r#"CREATE VIEW transactions AS SELECT e, a, v, value_type_tag, tx, added FROM timelined_transactions WHERE timeline IS 0"#,
+        r#"DROP TABLE parts"#,
+        r#"CREATE TABLE known_parts (part TEXT NOT NULL PRIMARY KEY, start INTEGER NOT NULL, end INTEGER NOT NULL, allow_excision SMALLINT NOT NULL)"#,
+    ];
+    for statement in statements.iter() {
+        conn.execute(statement, rusqlite::params![])?;
+    }
+
+    // Each implied `:db/index true` is asserted as part of the transaction that installed its
+    // attribute, and the attribute's existing datoms join the AVET index.
+    conn.execute_batch(&format!(
+        r#"CREATE TEMP TABLE implied_index AS
+             SELECT e, tx FROM datoms
+               WHERE a = {value_type}
+                 AND e IN (SELECT e FROM schema WHERE a = {unique} OR (a = {fulltext} AND v IS NOT 0))
+                 AND e NOT IN (SELECT e FROM schema WHERE a = {index});
+           INSERT INTO datoms (e, a, v, tx, value_type_tag)
+             SELECT e, {index}, 1, tx, {boolean} FROM temp.implied_index;
+           INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag, timeline)
+             SELECT e, {index}, 1, tx, 1, {boolean}, 0 FROM temp.implied_index;
+           INSERT INTO schema (e, a, v, value_type_tag)
+             SELECT e, {index}, 1, {boolean} FROM temp.implied_index;
+           UPDATE datoms SET index_avet = 1 WHERE a IN (SELECT e FROM temp.implied_index);
+           DROP TABLE temp.implied_index;"#,
+        value_type = entids::DB_VALUE_TYPE,
+        unique = entids::DB_UNIQUE,
+        fulltext = entids::DB_FULLTEXT,
+        index = entids::DB_INDEX,
+        boolean = ValueType::Boolean.value_type_tag(),
+    ))?;
+
+    update_known_parts(
+        conn,
+        partitions.iter().map(|(part, partition)| (part, partition)),
+    )
+}
+
+/// Install the bootstrap idents and attributes that the store predates, like
+/// `:db/fulltextTokenizer`.  Each is asserted with the entid it has in a fresh store, so that
+/// entid must not already be in use.
+fn upgrade_bootstrap(conn: &rusqlite::Connection) -> Result<()> {
+    let db = read_db(conn)?;
+    let bootstrap_schema = bootstrap::bootstrap_schema();
+
+    let mut ident_map = db.schema.ident_map.clone();
+    let mut attribute_map = db.schema.attribute_map.clone();
+    let mut missing = false;
+    for (ident, &entid) in bootstrap_schema.ident_map.iter() {
+        match db.schema.ident_map.get(ident) {
+            Some(&existing) if existing == entid => {}
+            Some(&existing) => bail!(DbErrorKind::BadBootstrapDefinition(format!(
+                "{} has entid {} in the store, but {} in the bootstrap schema",
+                ident, existing, entid
+            ))),
+            None => {
+                let in_use: bool = conn.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM datoms WHERE e = ?)",
+                    &[&entid],
+                    |row| row.get(0),
+                )?;
+                if in_use {
+                    bail!(DbErrorKind::BadBootstrapDefinition(format!(
+                        "cannot install {} with entid {}, which is already in use",
+                        ident, entid
+                    )));
+                }
+                ident_map.insert(ident.clone(), entid);
+                missing = true;
+            }
+        }
+        if let Some(attribute) = bootstrap_schema.attribute_map.get(&entid) {
+            if db.schema.attribute_map.get(&entid) != Some(attribute) {
+                attribute_map.insert(entid, attribute.clone());
+                missing = true;
+            }
+        }
+    }
+    if !missing {
+        return Ok(());
+    }
+
+    // The bootstrap transaction asserts everything again, which is a no-op for what the store
+    // already has.  Its entities are resolved against the combined schema, so that they can refer to
+    // the idents being installed.
+    let schema = Schema::from_ident_map_and_attribute_map(ident_map, attribute_map)?;
+    let (_report, _next_partition_map, next_schema, _watcher) = transact(
+        conn,
+        db.partition_map,
+        &db.schema,
+        &schema,
+        NullWatcher(),
+        bootstrap::bootstrap_entities(),
+    )?;
+
+    if next_schema.as_ref() != Some(&schema) {
+        bail!(DbErrorKind::BadBootstrapDefinition(
+            "Upgrade bootstrap transaction did not produce expected schema".to_string()
+        ));
+    }
+    Ok(())
+}
+
 /// Creates a partition map view for the main timeline based on partitions
 /// defined in 'known_parts'.
 fn create_current_partition_view(conn: &rusqlite::Connection) -> Result<()> {
@@ -321,6 +575,32 @@ fn create_current_partition_view(conn: &rusqlite::Connection) -> Result<()> {
     Ok(())
 }
 
+/// Read the names of the partitions defined in 'known_parts'.
+pub(crate) fn read_known_parts(conn: &rusqlite::Connection) -> Result<BTreeSet<String>> {
+    let mut stmt = conn.prepare("SELECT part FROM known_parts")?;
+    let known_parts: Result<BTreeSet<String>> = stmt
+        .query_and_then(rusqlite::params![], |row| Ok(row.get(0)?))?
+        .collect();
+    known_parts
+}
+
+/// Record the ranges of the given partitions in 'known_parts', and recreate the partition map view
+/// to match.  Partitions that aren't given are left alone.
+pub(crate) fn update_known_parts<'p, I>(conn: &rusqlite::Connection, partitions: I) -> Result<()>
+where
+    I: IntoIterator<Item = (&'p String, &'p Partition)>,
+{
+    for (part, partition) in partitions {
+        conn.execute(
+            "INSERT OR REPLACE INTO known_parts (part, start, end, allow_excision) VALUES (?, ?, ?, ?)",
+            rusqlite::params![part, partition.start, partition.end, partition.allow_excision],
+        )?;
+    }
+
+    conn.execute("DROP VIEW IF EXISTS parts", rusqlite::params![])?;
+    create_current_partition_view(conn)
+}
+
 // TODO: rename "SQL" functions to align with "datoms" functions.
 pub fn create_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     let (tx, mut db) = create_empty_current_version(conn)?;
@@ -382,13 +662,66 @@ pub fn ensure_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     match user_version {
         0 => create_current_version(conn),
         CURRENT_VERSION => read_db(conn),
+        v if v > 0 && v < CURRENT_VERSION => upgrade_from_version(conn, v),
+        v => bail!(DbErrorKind::BadSQLiteStoreVersion(v)),
+    }
+}
+
+/// Upgrade a store from the given version to `CURRENT_VERSION`.
+///
+/// A file-backed store is first copied next to itself, as `<path>.v<version>.backup`, so that an
+/// older Mentat can still be pointed at the copy.  The steps then run in one exclusive transaction:
+/// if any of them fails, the store is left exactly as it was.
+fn upgrade_from_version(conn: &mut rusqlite::Connection, version: i32) -> Result<DB> {
+    backup_store(conn, version)?;
+
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+    apply_migrations(&tx, version)
+        .map_err(|e| DbErrorKind::MigrationFailed(version, CURRENT_VERSION, e.to_string()))?;
+    tx.commit()?;
+
+    read_db(conn)
+}
+
+fn apply_migrations(conn: &rusqlite::Connection, version: i32) -> Result<()> {
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        (migration.upgrade)(conn)?;
+        set_user_version(conn, migration.version)?;
+    }
+    upgrade_bootstrap(conn)
+}
+
+/// Copy the store to `<path>.v<version>.backup`, replacing any earlier copy.  In-memory stores have
+/// nothing to copy.
+fn backup_store(conn: &rusqlite::Connection, version: i32) -> Result<()> {
+    let file: String = conn.query_row(
+        "SELECT file FROM pragma_database_list WHERE name = 'main'",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    if file.is_empty() {
+        return Ok(());
+    }
+
+    if rusqlite::version_number() < MIN_SQLITE_VERSION_FOR_BACKUP {
+        bail!(DbErrorKind::MigrationFailed(
+            version,
+            CURRENT_VERSION,
+            format!(
+                "backing up the store requires SQLite {}, but this is SQLite {}",
+                MIN_SQLITE_VERSION_FOR_BACKUP,
+                rusqlite::version_number()
+            )
+        ));
+    }
 
-        // TODO: support updating an existing store.
-        v => bail!(DbErrorKind::NotYetImplemented(format!(
-            "Opening databases with Mentat version: {}",
-            v
-        ))),
+    let backup = PathBuf::from(format!("{}.v{}.backup", file, version));
+    if backup.exists() {
+        fs::remove_file(&backup)
+            .map_err(|e| DbErrorKind::MigrationFailed(version, CURRENT_VERSION, e.to_string()))?;
     }
+    conn.execute("VACUUM INTO ?", &[&backup.to_string_lossy().into_owned()])?;
+    Ok(())
 }
 
 pub trait TypedSQLValue {
@@ -637,6 +970,18 @@ pub trait MentatStoring {
     /// Extract metadata-related [e a typed_value added] datoms resolved in the last
     /// materialized transaction.
     fn resolved_metadata_assertions(&self) -> Result<Vec<(Entid, Entid, TypedValue, bool)>>;
+
+    /// Extract all of the [e a typed_value added] datoms resolved in the last materialized
+    /// transaction: assertions of datoms that weren't already present, and retractions of datoms
+    /// that were, including the cardinality-one values that were replaced.
+    fn resolved_datoms(&self) -> Result<Vec<(Entid, Entid, TypedValue, bool)>>;
+
+    /// Extract the [e a v] datoms currently asserted about the given entity: those with the entity
+    /// as `e`, and those that refer to it as their `:db.type/ref` value `v`.
+    fn datoms_about_entity(&self, e: Entid) -> Result<Vec<(Entid, Entid, TypedValue)>>;
+
+    /// Extract the values currently asserted for the given [e a] pair.
+    fn values_for_ea(&self, e: Entid, a: Entid) -> Result<Vec<TypedValue>>;
 }
 
 /// Take search rows and complete `temp.search_results`.
@@ -1124,6 +1469,68 @@ impl MentatStoring for rusqlite::Connection {
             .collect();
         m
     }
+
+    fn resolved_datoms(&self) -> Result<Vec<(Entid, Entid, TypedValue, bool)>> {
+        // Fulltext values are searched for, and found, by their `fulltext_values` rowid.
+        let sql_stmt = format!(
+            r#"
+            SELECT e, a, CASE WHEN flags & {} IS NOT 0
+                              THEN (SELECT text FROM fulltext_values WHERE rowid = v)
+                              ELSE v END,
+                   value_type_tag, added FROM
+            (
+                SELECT e0 as e, a0 as a, v0 as v, value_type_tag0 as value_type_tag, flags0 as flags,
+                       1 as added
+                FROM temp.search_results
+                WHERE added0 IS 1 AND ((rid IS NULL) OR
+                    ((rid IS NOT NULL) AND (v0 IS NOT v)))
+
+                UNION
+
+                SELECT e0 as e, a0 as a, v, value_type_tag0 as value_type_tag, flags0 as flags,
+                       0 as added
+                FROM temp.search_results
+                WHERE rid IS NOT NULL AND
+                ((added0 IS 0) OR
+                    (added0 IS 1 AND search_type IS ':db.cardinality/one' AND v0 IS NOT v))
+
+            ) ORDER BY a, e, added DESC, v, value_type_tag"#,
+            AttributeBitFlags::IndexFulltext as u8
+        );
+
+        let mut stmt = self.prepare_cached(&sql_stmt)?;
+        let m: Result<Vec<_>> = stmt
+            .query_and_then(rusqlite::params![], row_to_transaction_assertion)?
+            .collect();
+        m
+    }
+
+    fn datoms_about_entity(&self, e: Entid) -> Result<Vec<(Entid, Entid, TypedValue)>> {
+        // Fulltext values are never refs, so we only need `all_datoms` for the first half.
+        let s = r#"
+          SELECT e, a, v, value_type_tag FROM all_datoms WHERE e = ?
+          UNION
+          SELECT e, a, v, value_type_tag FROM datoms WHERE v = ? AND value_type_tag = 0
+          ORDER BY e, a, v, value_type_tag"#;
+
+        let mut stmt = self.prepare_cached(s)?;
+        let m: Result<Vec<_>> = stmt
+            .query_and_then(&[&e, &e], row_to_datom_assertion)?
+            .collect();
+        m
+    }
+
+    fn values_for_ea(&self, e: Entid, a: Entid) -> Result<Vec<TypedValue>> {
+        let s = r#"SELECT v, value_type_tag FROM all_datoms WHERE e = ? AND a = ?"#;
+
+        let mut stmt = self.prepare_cached(s)?;
+        let m: Result<Vec<_>> = stmt
+            .query_and_then(&[&e, &a], |row| -> Result<TypedValue> {
+                TypedValue::from_sql_value_pair(row.get(0)?, row.get(1)?)
+            })?
+            .collect();
+        m
+    }
 }
 
 /// Extract metadata-related [e a typed_value added] datoms committed in the given transaction.
@@ -1284,8 +1691,9 @@ SELECT EXISTS
                     // TODO: improve the failure message.  Perhaps try to mimic what Datomic says in
                     // this case?
                     if !attribute.multival {
-                        let mut rows = cardinality_stmt.query(&[&entid as &dyn ToSql])?;
-                        if rows.next()?.is_some() {
+                        let has_many: bool =
+                            cardinality_stmt.query_row(&[&entid as &dyn ToSql], |row| row.get(0))?;
+                        if has_many {
                             bail!(DbErrorKind::SchemaAlterationFailed(format!(
                                 "Cannot alter schema attribute {} to be :db.cardinality/one",
                                 entid
@@ -1296,10 +1704,21 @@ SELECT EXISTS
                 &NoHistory | &IsComponent => {
                     // There's no on disk change required for either of these.
                 }
+                &FulltextTokenizer => {
+                    // Every index already holds the attribute's values; we just need the index.
+                    fulltext::ensure_fulltext_index(conn, attribute.fulltext_tokenizer)?;
+                }
             }
         }
     }
 
+    for &entid in &metadata_report.attributes_installed {
+        let attribute = new_schema.require_attribute_for_entid(entid)?;
+        if attribute.fulltext {
+            fulltext::ensure_fulltext_index(conn, attribute.fulltext_tokenizer)?;
+        }
+    }
+
     Ok(())
 }
 
@@ -1333,7 +1752,7 @@ mod tests {
     use std::borrow::Borrow;
 
     use super::*;
-    use crate::debug::{tempids, TestConn};
+    use crate::debug::{tempids, transactions_after, TestConn};
     use crate::internal_types::Term;
     use core_traits::{attribute, KnownEntid};
     use db_traits::errors;
@@ -1614,6 +2033,393 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_retract_entity() {
+        let mut conn = TestConn::default();
+
+        // Start by installing a few attributes.
+        assert_transact!(
+            conn,
+            "[[:db/add 111 :db/ident :test/name]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 111 :db/unique :db.unique/identity]
+                                 [:db/add 111 :db/index true]
+                                 [:db/add 222 :db/ident :test/component]
+                                 [:db/add 222 :db/isComponent true]
+                                 [:db/add 222 :db/valueType :db.type/ref]
+                                 [:db/add 222 :db/cardinality :db.cardinality/many]
+                                 [:db/add 333 :db/ident :test/ref]
+                                 [:db/add 333 :db/valueType :db.type/ref]]"
+        );
+
+        assert_transact!(
+            conn,
+            "[[:db/add 500 :test/name \"parent\"]
+                                 [:db/add 500 :test/component 501]
+                                 [:db/add 500 :test/ref 503]
+                                 [:db/add 501 :test/name \"child\"]
+                                 [:db/add 501 :test/component 502]
+                                 [:db/add 502 :test/name \"grandchild\"]
+                                 [:db/add 503 :test/name \"other\"]
+                                 [:db/add 503 :test/ref 500]]"
+        );
+
+        // Retracting an entity retracts its components, recursively, and references to it, but
+        // not the entities it refers to.
+        assert_transact!(conn, "[[:db/retractEntity 500]]");
+        assert_matches!(
+            conn.last_transaction(),
+            "[[500 :test/name \"parent\" ?tx false]
+                          [500 :test/component 501 ?tx false]
+                          [500 :test/ref 503 ?tx false]
+                          [501 :test/name \"child\" ?tx false]
+                          [501 :test/component 502 ?tx false]
+                          [502 :test/name \"grandchild\" ?tx false]
+                          [503 :test/ref 500 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]"
+        );
+
+        // We can name the entity with a lookup-ref.
+        assert_transact!(
+            conn,
+            "[[:db/retractEntity (lookup-ref :test/name \"other\")]]"
+        );
+        assert_matches!(
+            conn.last_transaction(),
+            "[[503 :test/name \"other\" ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]"
+        );
+
+        // Retracting an entity with no datoms does nothing.
+        assert_transact!(conn, "[[:db/retractEntity 500]]");
+        assert_matches!(
+            conn.last_transaction(),
+            "[[?tx :db/txInstant ?ms ?tx true]]"
+        );
+
+        // Tempids can't be retracted.
+        assert_transact!(
+            conn,
+            "[[:db/retractEntity \"t\"]]",
+            Err("not yet implemented: [:db/retractEntity ...] cannot retract tempid t")
+        );
+    }
+
+    #[test]
+    fn test_cas() {
+        let mut conn = TestConn::default();
+
+        // Start by installing a few attributes.
+        assert_transact!(
+            conn,
+            "[[:db/add 111 :db/ident :test/one]
+                                 [:db/add 111 :db/valueType :db.type/long]
+                                 [:db/add 111 :db/cardinality :db.cardinality/one]
+                                 [:db/add 222 :db/ident :test/many]
+                                 [:db/add 222 :db/valueType :db.type/long]
+                                 [:db/add 222 :db/cardinality :db.cardinality/many]]"
+        );
+
+        // `nil` expects no value.
+        assert_transact!(conn, "[[:db/cas 500 :test/one nil 1]]");
+        assert_matches!(
+            conn.last_transaction(),
+            "[[500 :test/one 1 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]"
+        );
+
+        // A matching value is replaced.
+        assert_transact!(conn, "[[:db/cas 500 :test/one 1 2]]");
+        assert_matches!(
+            conn.last_transaction(),
+            "[[500 :test/one 1 ?tx false]
+                          [500 :test/one 2 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]"
+        );
+
+        // A stale value fails the whole transaction.
+        assert_transact!(
+            conn,
+            "[[:db/add 501 :test/one 5]
+                                 [:db/cas 500 :test/one 1 3]]",
+            Err("compare-and-swap failed for [500 111]: expected Some(Long(1)) but found Some(Long(2))")
+        );
+        assert_transact!(
+            conn,
+            "[[:db/cas 500 :test/one nil 3]]",
+            Err("compare-and-swap failed for [500 111]: expected None but found Some(Long(2))")
+        );
+        assert_matches!(
+            conn.datoms(),
+            "[[111 :db/ident :test/one]
+                          [111 :db/valueType :db.type/long]
+                          [111 :db/cardinality :db.cardinality/one]
+                          [222 :db/ident :test/many]
+                          [222 :db/valueType :db.type/long]
+                          [222 :db/cardinality :db.cardinality/many]
+                          [500 :test/one 2]]"
+        );
+
+        // Only :db.cardinality/one attributes can be compared.
+        assert_transact!(conn, "[[:db/cas 500 :test/many nil 1]]",
+                         Err("not yet implemented: Cannot use :db/cas for attribute 222 that is not :db.cardinality :db.cardinality/one"));
+    }
+
+    #[test]
+    fn test_excise() {
+        let mut conn = TestConn::default();
+
+        // Start by installing a few attributes.
+        assert_transact!(
+            conn,
+            "[[:db/add 111 :db/ident :test/name]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 222 :db/ident :test/email]
+                                 [:db/add 222 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/ident :test/bio]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/index true]
+                                 [:db/add 333 :db/fulltext true]]"
+        );
+
+        assert_transact!(
+            conn,
+            "[[:db/add 500 :test/name \"Alice\"]
+                                 [:db/add 500 :test/email \"alice@example.com\"]
+                                 [:db/add 500 :test/bio \"likes cats\"]
+                                 [:db/add 501 :test/name \"Bob\"]
+                                 [:db/add 501 :test/bio \"likes dogs\"]]"
+        );
+        assert_transact!(conn, "[[:db/add 500 :test/name \"Alicia\"]]");
+        assert_matches!(
+            conn.fulltext_values(),
+            "[[1 \"likes cats\"]
+                          [2 \"likes dogs\"]]"
+        );
+
+        // Excision can be limited to particular attributes.  The excision request is recorded,
+        // along with what was excised from the log, but not the excised values.
+        assert_transact!(
+            conn,
+            "[{:db/id \"e\" :db/excise 500 :db.excise/attrs [:test/name]}]"
+        );
+        assert_matches!(
+            conn.last_transaction(),
+            "[[?e :db/excise 500 ?tx true]
+                          [?e :db.excise/attrs :test/name ?tx true]
+                          [?e :db.excise/excisedAttrs :test/name ?tx true]
+                          [?e :db.excise/excisedCount 3 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]"
+        );
+        assert_matches!(
+            conn.datoms(),
+            "[[111 :db/ident :test/name]
+                          [111 :db/valueType :db.type/string]
+                          [222 :db/ident :test/email]
+                          [222 :db/valueType :db.type/string]
+                          [333 :db/ident :test/bio]
+                          [333 :db/valueType :db.type/string]
+                          [333 :db/index true]
+                          [333 :db/fulltext true]
+                          [500 :test/email \"alice@example.com\"]
+                          [500 :test/bio 1]
+                          [501 :test/name \"Bob\"]
+                          [501 :test/bio 2]
+                          [?e :db/excise 500]
+                          [?e :db.excise/attrs :test/name]
+                          [?e :db.excise/excisedAttrs :test/name]
+                          [?e :db.excise/excisedCount 3]]"
+        );
+
+        // Excision removes history, too.
+        assert_matches!(
+            conn.transactions(),
+            "[[[111 :db/ident :test/name ?tx0 true]
+                           [111 :db/valueType :db.type/string ?tx0 true]
+                           [222 :db/ident :test/email ?tx0 true]
+                           [222 :db/valueType :db.type/string ?tx0 true]
+                           [333 :db/ident :test/bio ?tx0 true]
+                           [333 :db/valueType :db.type/string ?tx0 true]
+                           [333 :db/index true ?tx0 true]
+                           [333 :db/fulltext true ?tx0 true]
+                           [?tx0 :db/txInstant ?ms0 ?tx0 true]]
+                          [[500 :test/email \"alice@example.com\" ?tx1 true]
+                           [500 :test/bio 1 ?tx1 true]
+                           [501 :test/name \"Bob\" ?tx1 true]
+                           [501 :test/bio 2 ?tx1 true]
+                           [?tx1 :db/txInstant ?ms1 ?tx1 true]]
+                          [[?tx2 :db/txInstant ?ms2 ?tx2 true]]
+                          [[?e :db/excise 500 ?tx3 true]
+                           [?e :db.excise/attrs :test/name ?tx3 true]
+                           [?e :db.excise/excisedAttrs :test/name ?tx3 true]
+                           [?e :db.excise/excisedCount 3 ?tx3 true]
+                           [?tx3 :db/txInstant ?ms3 ?tx3 true]]]"
+        );
+
+        // Excising everything about an entity removes its fulltext values, but not those shared
+        // with other entities.
+        assert_transact!(conn, "[[:db/add 501 :test/bio \"likes cats\"]]");
+        assert_transact!(conn, "[[:db/add \"e\" :db/excise 500]]");
+        assert_matches!(
+            conn.fulltext_values(),
+            "[[1 \"likes cats\"]
+                          [2 \"likes dogs\"]]"
+        );
+        assert_transact!(conn, "[[:db/add \"e\" :db/excise 501]]");
+        assert_matches!(conn.fulltext_values(), "[]");
+        assert_matches!(
+            conn.datoms(),
+            "[[111 :db/ident :test/name]
+                          [111 :db/valueType :db.type/string]
+                          [222 :db/ident :test/email]
+                          [222 :db/valueType :db.type/string]
+                          [333 :db/ident :test/bio]
+                          [333 :db/valueType :db.type/string]
+                          [333 :db/index true]
+                          [333 :db/fulltext true]
+                          [?e1 :db/excise 500]
+                          [?e1 :db.excise/attrs :test/name]
+                          [?e1 :db.excise/excisedAttrs :test/name]
+                          [?e1 :db.excise/excisedCount 3]
+                          [?e2 :db/excise 500]
+                          [?e2 :db.excise/excisedAttrs :test/email]
+                          [?e2 :db.excise/excisedAttrs :test/bio]
+                          [?e2 :db.excise/excisedCount 2]
+                          [?e3 :db/excise 501]
+                          [?e3 :db.excise/excisedAttrs :test/name]
+                          [?e3 :db.excise/excisedAttrs :test/bio]
+                          [?e3 :db.excise/excisedCount 4]]"
+        );
+
+        // Excision can be limited to datoms transacted before a given transaction.
+        assert_transact!(conn, "[[:db/add 502 :test/name \"first\"]]");
+        assert_transact!(conn, "[[:db/add 502 :test/name \"second\"]]");
+        let before_t = conn.last_tx_id();
+        assert_transact!(
+            conn,
+            format!(
+                "[{{:db/id \"e\" :db/excise 502 :db.excise/beforeT {}}}]",
+                before_t
+            )
+            .as_str()
+        );
+        assert_matches!(
+            conn.last_transaction(),
+            "[[?e :db/excise 502 ?tx true]
+                          [?e :db.excise/beforeT ?before ?tx true]
+                          [?e :db.excise/excisedAttrs :test/name ?tx true]
+                          [?e :db.excise/excisedCount 1 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]"
+        );
+        assert_matches!(
+            conn.transactions().0[8],
+            "[[502 :test/name \"first\" ?tx false]
+                          [502 :test/name \"second\" ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]"
+        );
+        assert_eq!(conn.transactions().0[7].0.len(), 1);
+
+        // Entities in partitions that don't allow excision can't be excised.
+        assert_transact!(
+            conn,
+            "[[:db/add \"e\" :db/excise :db/txInstant]]",
+            Err("excision not allowed for entity 3")
+        );
+        assert_transact!(
+            conn,
+            "[[:db/add \"e\" :db/excise 268435456]]",
+            Err("excision not allowed for entity 268435456")
+        );
+    }
+
+    #[test]
+    fn test_custom_partitions() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(
+            conn,
+            "[[:db/add 111 :db/ident :test/name]
+                                 [:db/add 111 :db/valueType :db.type/string]]"
+        );
+
+        // Install a partition at the end of :db.part/user.
+        assert_transact!(
+            conn,
+            "[{:db/id \"p\"
+                                   :db/ident :test.part/logs
+                                   :db.partition/start 16777216
+                                   :db.partition/end 16842751}
+                                  [:db/add :db.part/db :db.install/partition \"p\"]]"
+        );
+        assert_eq!(
+            conn.partition_map.get(":test.part/logs"),
+            Some(&Partition::new(16777216, 16842751, 16777216, false))
+        );
+        assert_eq!(conn.partition_map[":db.part/user"].end, 16777215);
+
+        // Tempids can name the partition they're allocated in.
+        let report = assert_transact!(
+            conn,
+            "[[:db/add (tempid :test.part/logs \"a\") :test/name \"a\"]
+                                  [:db/add (tempid :test.part/logs) :test/name \"b\"]
+                                  [:db/add \"c\" :test/name \"c\"]]"
+        );
+        assert_eq!(report.tempids.get("a"), Some(&16777216));
+        assert_eq!(report.tempids.get("c"), Some(&65537));
+        assert_matches!(
+            conn.last_transaction(),
+            "[[65537 :test/name \"c\" ?tx true]
+                          [16777216 :test/name \"a\" ?tx true]
+                          [16777217 :test/name \"b\" ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]"
+        );
+
+        // The partition map round-trips through the store.
+        let partition_map = read_partition_map(&conn.sqlite).unwrap();
+        assert_eq!(
+            partition_map.get(":test.part/logs"),
+            conn.partition_map.get(":test.part/logs")
+        );
+        assert_eq!(
+            partition_map.get(":db.part/user"),
+            conn.partition_map.get(":db.part/user")
+        );
+
+        assert_transact!(
+            conn,
+            "[[:db/add (tempid :test.part/unknown) :test/name \"d\"]]",
+            Err("unknown partition: :test.part/unknown")
+        );
+
+        // Partitions can't overlap.
+        assert_transact!(
+            conn,
+            "[{:db/id \"p\"
+                                   :db/ident :test.part/other
+                                   :db.partition/start 16777216
+                                   :db.partition/end 16777300}
+                                  [:db/add :db.part/db :db.install/partition \"p\"]]",
+            Err("partition installation failed: :test.part/other must lie within the unallocated entids [16777219, 16842751] of :test.part/logs")
+        );
+        assert_transact!(
+            conn,
+            "[{:db/id \"p\"
+                                   :db/ident :test.part/other
+                                   :db.partition/start 16777000
+                                   :db.partition/end 16777300}
+                                  [:db/add :db.part/db :db.install/partition \"p\"]]",
+            Err("partition installation failed: :test.part/other must lie within exactly one existing partition, but overlaps [\":db.part/user\", \":test.part/logs\"]")
+        );
+        assert_transact!(
+            conn,
+            "[{:db/id \"p\"
+                                   :db/ident :test.part/other
+                                   :db.partition/start 16777000}
+                                  [:db/add :db.part/db :db.install/partition \"p\"]]",
+            Err("partition installation failed: :test.part/other has no :db.partition/end")
+        );
+    }
+
     #[test]
     fn test_db_doc_is_not_schema() {
         let mut conn = TestConn::default();
@@ -2031,6 +2837,12 @@ mod tests {
                           [101 :test/ident :test/value2 ?tx true]
                           [?tx :db/txInstant ?ms ?tx true]]"
         );
+
+        // :db/index can be retracted.
+        assert_transact!(conn, "[[:db/add 100 :db/index true]]");
+        assert!(conn.schema.attribute_for_entid(100).unwrap().index);
+        assert_transact!(conn, "[[:db/retract 100 :db/index true]]");
+        assert!(!conn.schema.attribute_for_entid(100).unwrap().index);
     }
 
     #[test]
@@ -2191,6 +3003,10 @@ mod tests {
         assert_transact!(conn, "[[:db/add 100 :db/cardinality :db.cardinality/one]]",
                          // TODO: give more helpful error details.
                          Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.cardinality/one"));
+
+        // But we can once there's at most one value for each entity.
+        assert_transact!(conn, "[[:db/retract 200 :test/ident 1]]");
+        assert_transact!(conn, "[[:db/add 100 :db/cardinality :db.cardinality/one]]");
     }
 
     #[test]
@@ -2515,6 +3331,351 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_db_fulltext_tokenizer() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(
+            conn,
+            "[[:db/add 111 :db/ident :test/porter]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 111 :db/index true]
+                                 [:db/add 111 :db/fulltext true]
+                                 [:db/add 111 :db/fulltextTokenizer :db.fulltextTokenizer/porter]
+                                 [:db/add 222 :db/ident :test/plain]
+                                 [:db/add 222 :db/valueType :db.type/string]
+                                 [:db/add 222 :db/index true]
+                                 [:db/add 222 :db/fulltext true]
+                                 [:db/add 333 :db/ident :test/string]
+                                 [:db/add 333 :db/valueType :db.type/string]]"
+        );
+
+        let tokenizer = |conn: &TestConn, entid| {
+            conn.schema
+                .attribute_for_entid(entid)
+                .map(|attribute| attribute.fulltext_tokenizer)
+                .expect("attribute")
+        };
+        assert_eq!(tokenizer(&conn, 111), attribute::FulltextTokenizer::Porter);
+        assert_eq!(
+            tokenizer(&conn, 222),
+            attribute::FulltextTokenizer::Unicode61
+        );
+
+        let index_exists = |conn: &TestConn, tokenizer: attribute::FulltextTokenizer| -> bool {
+            conn.sqlite
+                .query_row(
+                    "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?)",
+                    &[tokenizer.index_table()],
+                    |row| row.get(0),
+                )
+                .expect("sqlite_master")
+        };
+        assert!(index_exists(&conn, attribute::FulltextTokenizer::Unicode61));
+        assert!(index_exists(&conn, attribute::FulltextTokenizer::Porter));
+        assert!(!index_exists(
+            &conn,
+            attribute::FulltextTokenizer::Unaccented
+        ));
+
+        assert_transact!(
+            conn,
+            "[[:db/add 301 :test/porter \"running late\"]
+                                 [:db/add 302 :test/plain \"café au lait\"]]"
+        );
+
+        let matches = |conn: &TestConn, tokenizer: attribute::FulltextTokenizer, search: &str| {
+            let table = tokenizer.index_table();
+            let s = format!(
+                "SELECT rowid FROM {} WHERE {} MATCH ? ORDER BY rowid",
+                table, table
+            );
+            let mut stmt = conn.sqlite.prepare(&s).expect("prepared");
+            let rowids: Vec<i64> = stmt
+                .query_map(&[search], |row| row.get(0))
+                .expect("rows")
+                .map(|rowid| rowid.expect("rowid"))
+                .collect();
+            rowids
+        };
+        // Every index holds every value, whichever attribute it belongs to.
+        assert_eq!(
+            matches(&conn, attribute::FulltextTokenizer::Porter, "runs"),
+            vec![1]
+        );
+        assert_eq!(
+            matches(&conn, attribute::FulltextTokenizer::Porter, "cafe"),
+            vec![2]
+        );
+        assert_eq!(
+            matches(&conn, attribute::FulltextTokenizer::Unicode61, "runs"),
+            Vec::<i64>::new()
+        );
+        assert_eq!(
+            matches(&conn, attribute::FulltextTokenizer::Unicode61, "cafe"),
+            Vec::<i64>::new()
+        );
+
+        // Changing the tokenizer creates and fills the new index.
+        assert_transact!(
+            conn,
+            "[[:db/add 222 :db/fulltextTokenizer :db.fulltextTokenizer/unaccented]]"
+        );
+        assert_eq!(
+            tokenizer(&conn, 222),
+            attribute::FulltextTokenizer::Unaccented
+        );
+        assert!(index_exists(
+            &conn,
+            attribute::FulltextTokenizer::Unaccented
+        ));
+        assert_eq!(
+            matches(&conn, attribute::FulltextTokenizer::Unaccented, "cafe"),
+            vec![2]
+        );
+
+        // Retracting the tokenizer restores the default.
+        assert_transact!(
+            conn,
+            "[[:db/retract 222 :db/fulltextTokenizer :db.fulltextTokenizer/unaccented]]"
+        );
+        assert_eq!(
+            tokenizer(&conn, 222),
+            attribute::FulltextTokenizer::Unicode61
+        );
+
+        // Only fulltext attributes have a tokenizer.
+        assert_transact!(
+            conn,
+            "[[:db/add 333 :db/fulltextTokenizer :db.fulltextTokenizer/porter]]",
+            Err("bad schema assertion: :db/fulltextTokenizer without :db/fulltext true for entid: 333")
+        );
+        assert_transact!(
+            conn,
+            "[[:db/add 111 :db/fulltextTokenizer :db.part/db]]",
+            Err("bad schema assertion: Expected [... :db/fulltextTokenizer :db.fulltextTokenizer/*] but got [... :db/fulltextTokenizer Ref(2)]")
+        );
+    }
+
+    #[test]
+    fn test_upgrade_fulltext_to_v2() {
+        let mut sqlite = new_connection("").expect("connection");
+        let tx = sqlite.transaction().expect("transaction");
+        for statement in V1_STATEMENTS.iter() {
+            tx.execute(statement, rusqlite::params![])
+                .expect("v1 statement");
+        }
+        tx.execute_batch(
+            r#"INSERT INTO fulltext_values_view (text, searchid) VALUES ('hello world', NULL);
+               INSERT INTO fulltext_values_view (text, searchid) VALUES ('café au lait', NULL);
+               INSERT INTO datoms (e, a, v, tx, value_type_tag, index_fulltext) VALUES (65536, 100, 2, 268435457, 10, 1);"#,
+        )
+        .expect("v1 data");
+
+        upgrade_to_v2(&tx).expect("upgraded");
+
+        // Fulltext values keep their rowids, so datoms still refer to them.
+        let v: String = tx
+            .query_row(
+                "SELECT v FROM all_datoms WHERE e = 65536",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .expect("datom");
+        assert_eq!(v, "café au lait");
+
+        // Existing values are indexed, and new values are indexed as they're added.
+        tx.execute(
+            "INSERT INTO fulltext_values_view (text, searchid) VALUES ('au revoir', NULL)",
+            rusqlite::params![],
+        )
+        .expect("inserted");
+        let mut stmt = tx
+            .prepare("SELECT rowid FROM fulltext_index_unicode61 WHERE fulltext_index_unicode61 MATCH 'au' ORDER BY rowid")
+            .expect("prepared");
+        let rowids: Vec<i64> = stmt
+            .query_map(rusqlite::params![], |row| row.get(0))
+            .expect("rows")
+            .map(|rowid| rowid.expect("rowid"))
+            .collect();
+        assert_eq!(rowids, vec![2, 3]);
+    }
+
+    /// Copy a fixture into the given directory, so that upgrading the copy leaves the fixture alone.
+    fn copy_fixture(dir: &tempfile::TempDir, name: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::copy(Path::new("../fixtures").join(name), &path).expect("copied fixture");
+        path
+    }
+
+    /// Upgrade the store, and check that it now has everything a fresh store has.
+    fn assert_upgraded(mut sqlite: rusqlite::Connection) -> TestConn {
+        let db = ensure_current_version(&mut sqlite).expect("upgraded");
+        assert_eq!(get_user_version(&sqlite).unwrap(), CURRENT_VERSION);
+
+        let bootstrap_schema = bootstrap::bootstrap_schema();
+        for (ident, entid) in bootstrap_schema.ident_map.iter() {
+            assert_eq!(db.schema.ident_map.get(ident), Some(entid));
+        }
+        for (entid, attribute) in bootstrap_schema.attribute_map.iter() {
+            assert_eq!(db.schema.attribute_map.get(entid), Some(attribute));
+        }
+        assert_eq!(
+            db.partition_map[":db.part/db"],
+            bootstrap::bootstrap_partition_map()[":db.part/db"]
+        );
+
+        // Opening the upgraded store again doesn't change anything.
+        assert_eq!(ensure_current_version(&mut sqlite).expect("reopened"), db);
+
+        TestConn {
+            sqlite,
+            partition_map: db.partition_map,
+            schema: db.schema,
+        }
+    }
+
+    #[test]
+    fn test_upgrade_v1empty_fixture() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = copy_fixture(&dir, "v1empty.db");
+
+        let mut conn = assert_upgraded(new_connection(&path).expect("connection"));
+
+        // The old store is preserved next to the upgraded one.
+        let backup = new_connection(dir.path().join("v1empty.db.v1.backup")).expect("backup");
+        assert_eq!(get_user_version(&backup).unwrap(), 1);
+        let parts: i64 = backup
+            .query_row("SELECT COUNT(*) FROM parts", rusqlite::params![], |row| {
+                row.get(0)
+            })
+            .expect("parts");
+        assert_eq!(parts, 3);
+
+        // The original transaction survives the move to the timelined log, followed by the
+        // transaction installing the newer bootstrap idents.
+        let transactions = transactions_after(&conn.sqlite, &conn.schema, 0).expect("transactions");
+        assert_eq!(transactions.0.len(), 2);
+        assert_eq!(transactions.0[0].0.len(), 77);
+        assert_eq!(
+            conn.partition_map[":db.part/tx"].next_entid(),
+            bootstrap::TX0 + 2
+        );
+
+        // The upgraded store transacts like a fresh one, including with newer bootstrap attributes.
+        assert_transact!(
+            conn,
+            r#"[{:db/ident :test/text
+                 :db/valueType :db.type/string
+                 :db/cardinality :db.cardinality/one
+                 :db/index true
+                 :db/fulltext true
+                 :db/fulltextTokenizer :db.fulltextTokenizer/porter}
+                [:db/add :db.part/user :db.partition/allowExcision true]]"#
+        );
+        assert_transact!(conn, r#"[[:db/add "t" :test/text "running dogs"]]"#);
+        let matched: i64 = conn
+            .sqlite
+            .query_row(
+                "SELECT COUNT(*) FROM fulltext_index_porter WHERE fulltext_index_porter MATCH 'run'",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .expect("matched");
+        assert_eq!(matched, 1);
+    }
+
+    #[test]
+    fn test_upgrade_v1toodle_empty_fixture() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = copy_fixture(&dir, "v1toodle_empty.db");
+
+        let mut conn = assert_upgraded(new_connection(&path).expect("connection"));
+
+        // User attributes and partitions are untouched.
+        let item_name = conn
+            .schema
+            .attribute_for_ident(&Keyword::namespaced("item", "name"))
+            .expect(":item/name")
+            .0
+            .clone();
+        assert!(item_name.fulltext);
+        // Early releases implied :db/index true for fulltext attributes.
+        assert!(item_name.index);
+        assert_eq!(
+            item_name.fulltext_tokenizer,
+            attribute::FulltextTokenizer::default()
+        );
+        assert_eq!(conn.partition_map[":db.part/user"].next_entid(), 65543);
+
+        assert_transact!(
+            conn,
+            r#"[{:db/id "item" :item/name "Buy milk" :item/label "label"}
+                {:db/id "label" :label/name "errands"}]"#
+        );
+        assert_matches!(
+            conn.last_transaction(),
+            "[[65543 :item/name 2 ?tx true]
+              [65543 :item/label 65544 ?tx true]
+              [65544 :label/name 1 ?tx true]
+              [?tx :db/txInstant ?ms ?tx true]]"
+        );
+        assert_matches!(
+            conn.fulltext_values(),
+            "[[1 \"errands\"]
+              [2 \"Buy milk\"]]"
+        );
+    }
+
+    #[test]
+    fn test_upgrade_failure_rolls_back() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = copy_fixture(&dir, "v1empty.db");
+
+        // An entity using the entid of :db.schema/core, which the store predates, prevents
+        // installing it.
+        let mut sqlite = new_connection(&path).expect("connection");
+        sqlite
+            .execute_batch(
+                r#"INSERT INTO datoms (e, a, v, tx, value_type_tag) VALUES (40, 1, ':test/squatter', 268435456, 13);
+                   INSERT INTO transactions (e, a, v, tx, value_type_tag) VALUES (40, 1, ':test/squatter', 268435456, 13);
+                   INSERT INTO idents (e, a, v, value_type_tag) VALUES (40, 1, ':test/squatter', 13);"#,
+            )
+            .expect("squatted");
+
+        let err = ensure_current_version(&mut sqlite).expect_err("upgrade should fail");
+        assert_eq!(
+            err.kind(),
+            DbErrorKind::MigrationFailed(
+                1,
+                CURRENT_VERSION,
+                "bad bootstrap definition: cannot install :db.schema/core with entid 40, which is already in use".to_string()
+            )
+        );
+
+        // Nothing changed: the store still has the early version 1 layout.
+        assert_eq!(get_user_version(&sqlite).unwrap(), 1);
+        let tables: i64 = sqlite
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name IN ('transactions', 'parts')",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .expect("tables");
+        assert_eq!(tables, 2);
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let mut sqlite = new_connection("").expect("connection");
+        set_user_version(&sqlite, CURRENT_VERSION + 1).expect("set version");
+        let err = ensure_current_version(&mut sqlite).expect_err("newer version");
+        assert_eq!(
+            err.kind(),
+            DbErrorKind::BadSQLiteStoreVersion(CURRENT_VERSION + 1)
+        );
+    }
+
     #[test]
     fn test_lookup_refs_entity_column() {
         let mut conn = TestConn::default();
@@ -3382,6 +4543,21 @@ mod tests {
         test_open_fail(|| new_connection_with_key("../fixtures/v1encrypted.db", "wrong key"));
     }
 
+    #[test]
+    #[cfg(feature = "sqlcipher")]
+    fn test_sqlcipher_upgrade() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = copy_fixture(&dir, "v1encrypted.db");
+
+        let mut conn = assert_upgraded(new_connection_with_key(&path, "key").expect("connection"));
+        assert_transact!(conn, r#"[[:db/add "e" :db/doc "encrypted"]]"#);
+
+        // The backup is encrypted with the same key.
+        let backup = new_connection_with_key(dir.path().join("v1encrypted.db.v1.backup"), "key")
+            .expect("backup");
+        assert_eq!(get_user_version(&backup).unwrap(), 1);
+    }
+
     #[test]
     #[cfg(feature = "sqlcipher")]
     fn test_sqlcipher_some_transactions() {
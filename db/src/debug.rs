@@ -489,12 +489,12 @@ impl TestConn {
 
         // Does not include :db/txInstant.
         let datoms = datoms_after(&conn, &db.schema, 0).unwrap();
-        assert_eq!(datoms.0.len(), 94);
+        assert_eq!(datoms.0.len(), 124);
 
         // Includes :db/txInstant.
         let transactions = transactions_after(&conn, &db.schema, 0).unwrap();
         assert_eq!(transactions.0.len(), 1);
-        assert_eq!(transactions.0[0].0.len(), 95);
+        assert_eq!(transactions.0[0].0.len(), 125);
 
         let mut parts = db.partition_map;
 
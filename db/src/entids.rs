@@ -56,10 +56,23 @@ pub const DB_DOC: Entid = 37;
 pub const DB_SCHEMA_VERSION: Entid = 38;
 pub const DB_SCHEMA_ATTRIBUTE: Entid = 39;
 pub const DB_SCHEMA_CORE: Entid = 40;
+pub const DB_PARTITION_START: Entid = 41;
+pub const DB_PARTITION_END: Entid = 42;
+pub const DB_PARTITION_ALLOW_EXCISION: Entid = 43;
+pub const DB_FULLTEXT_TOKENIZER: Entid = 44;
+pub const DB_FULLTEXT_TOKENIZER_UNICODE61: Entid = 45;
+pub const DB_FULLTEXT_TOKENIZER_UNACCENTED: Entid = 46;
+pub const DB_FULLTEXT_TOKENIZER_PORTER: Entid = 47;
+pub const DB_FULLTEXT_TOKENIZER_TRIGRAM: Entid = 48;
+pub const DB_EXCISE_EXCISED_ATTRS: Entid = 49;
+pub const DB_EXCISE_EXCISED_COUNT: Entid = 50;
 
 /// Return `false` if the given attribute will not change the metadata: recognized idents, schema,
 /// partitions in the partition map.
 pub fn might_update_metadata(attribute: Entid) -> bool {
+    if attribute == DB_FULLTEXT_TOKENIZER {
+        return true;
+    }
     if attribute >= DB_DOC {
         return false;
     }
@@ -84,6 +97,7 @@ pub fn is_a_schema_attribute(attribute: Entid) -> bool {
         DB_IDENT
             | DB_CARDINALITY
             | DB_FULLTEXT
+            | DB_FULLTEXT_TOKENIZER
             | DB_INDEX
             | DB_IS_COMPONENT
             | DB_UNIQUE
@@ -100,9 +114,10 @@ lazy_static! {
 
     /// Attributes that are "schema related".  These might change the "schema" materialized view.
     pub static ref SCHEMA_SQL_LIST: String = {
-        format!("({}, {}, {}, {}, {}, {})",
+        format!("({}, {}, {}, {}, {}, {}, {})",
                 DB_CARDINALITY,
                 DB_FULLTEXT,
+                DB_FULLTEXT_TOKENIZER,
                 DB_INDEX,
                 DB_IS_COMPONENT,
                 DB_UNIQUE,
@@ -111,9 +126,10 @@ lazy_static! {
 
     /// Attributes that are "metadata" related.  These might change one of the materialized views.
     pub static ref METADATA_SQL_LIST: String = {
-        format!("({}, {}, {}, {}, {}, {}, {})",
+        format!("({}, {}, {}, {}, {}, {}, {}, {})",
                 DB_CARDINALITY,
                 DB_FULLTEXT,
+                DB_FULLTEXT_TOKENIZER,
                 DB_IDENT,
                 DB_INDEX,
                 DB_IS_COMPONENT,
@@ -0,0 +1,295 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Excision permanently removes datoms about an entity from the store: from the `datoms`
+//! materialized view, from the transaction log (in every timeline), and from `fulltext_values`.
+//!
+//! An excision is requested by transacting an entity like
+//!
+//! ```edn
+//! {:db/excise         :some/entity
+//!  :db.excise/attrs   [:person/name :person/email] ; Optional: only these attributes.
+//!  :db.excise/beforeT 268435460                     ; Optional: only datoms transacted before this tx.
+//!  :db.excise/before  #inst "2018-01-01T00:00:00Z"} ; Optional: only datoms transacted before this instant.
+//! ```
+//!
+//! The requesting entity is transacted like any other, so the transaction log records what was
+//! excised, and when, even though the excised datoms themselves are gone. The transactor adds a
+//! summary that doesn't reveal any excised values: `:db.excise/excisedAttrs`, the attributes of
+//! the excised datoms, and `:db.excise/excisedCount`, how many assertions and retractions were
+//! removed from the transaction log.
+
+use std::collections::BTreeSet;
+use std::iter::once;
+
+use rusqlite;
+use rusqlite::types::ToSql;
+
+use core_traits::{Entid, TypedValue};
+
+use mentat_core::{DateTime, HasSchema, Schema, ToMicros, Utc};
+
+use db_traits::errors::{DbErrorKind, Result};
+
+use crate::db::TypedSQLValue;
+use crate::entids;
+use crate::internal_types::{added_values, AEVTrie};
+use crate::schema::SchemaBuilding;
+use crate::types::PartitionMap;
+
+/// A request to excise datoms about `target`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Excision {
+    /// The entity that requested the excision.
+    pub(crate) entity: Entid,
+
+    pub(crate) target: Entid,
+
+    /// If present, only datoms with these attributes are excised.
+    pub(crate) attrs: Option<BTreeSet<Entid>>,
+
+    /// If present, only datoms transacted before this transaction are excised.
+    pub(crate) before_tx: Option<Entid>,
+
+    /// If present, only datoms transacted before this instant are excised.
+    pub(crate) before: Option<DateTime<Utc>>,
+}
+
+/// Collect the excisions requested by `[e :db/excise target]` assertions in the given trie.
+///
+/// The trie must already have been type checked.
+pub(crate) fn excisions<'schema>(aev_trie: &AEVTrie<'schema>) -> Vec<Excision> {
+    let mut excisions = vec![];
+    for (&(a, _), evs) in aev_trie {
+        if a != entids::DB_EXCISE {
+            continue;
+        }
+
+        for (&e, ars) in evs {
+            for target in ars.add.iter() {
+                let target = match *target {
+                    TypedValue::Ref(target) => target,
+                    _ => continue,
+                };
+
+                let attrs: BTreeSet<Entid> = added_values(aev_trie, entids::DB_EXCISE_ATTRS, e)
+                    .into_iter()
+                    .filter_map(|v| match v {
+                        TypedValue::Ref(a) => Some(a),
+                        _ => None,
+                    })
+                    .collect();
+                let before_tx = added_values(aev_trie, entids::DB_EXCISE_BEFORE_T, e)
+                    .into_iter()
+                    .filter_map(|v| match v {
+                        TypedValue::Ref(tx) => Some(tx),
+                        _ => None,
+                    })
+                    .next();
+                let before = added_values(aev_trie, entids::DB_EXCISE_BEFORE, e)
+                    .into_iter()
+                    .filter_map(|v| match v {
+                        TypedValue::Instant(instant) => Some(instant),
+                        _ => None,
+                    })
+                    .next();
+
+                excisions.push(Excision {
+                    entity: e,
+                    target,
+                    attrs: if attrs.is_empty() { None } else { Some(attrs) },
+                    before_tx,
+                    before,
+                });
+            }
+        }
+    }
+    excisions
+}
+
+/// Fail unless the partition that `target` belongs to allows excision.  If partitions overlap, the
+/// narrowest one containing `target` decides.
+///
+/// Entities with a `:db/ident` (including attributes) are never excisable: removing them would
+/// leave the schema referring to datoms that no longer exist.
+pub(crate) fn ensure_excision_allowed(
+    partition_map: &PartitionMap,
+    schema: &Schema,
+    target: Entid,
+) -> Result<()> {
+    let allowed = schema.get_ident(target).is_none()
+        && partition_map
+            .values()
+            .filter(|partition| partition.allows_entid(target))
+            .min_by_key(|partition| partition.end - partition.start)
+            .is_some_and(|partition| partition.allow_excision);
+    if !allowed {
+        bail!(DbErrorKind::ExcisionNotAllowed(target));
+    }
+    Ok(())
+}
+
+/// What `excise` removed.
+pub(crate) struct Excised {
+    /// The [e a v] datoms that were removed from the `datoms` materialized view, so that consumers
+    /// (like the attribute caches) can forget them.
+    pub(crate) datoms: Vec<(Entid, Entid, TypedValue)>,
+
+    /// The attributes of the datoms that were removed from the transaction log.
+    pub(crate) attrs: BTreeSet<Entid>,
+
+    /// How many assertions and retractions were removed from the transaction log.
+    pub(crate) count: usize,
+}
+
+/// Permanently remove the datoms matching `excision` from the store.
+pub(crate) fn excise(
+    conn: &rusqlite::Connection,
+    schema: &Schema,
+    excision: &Excision,
+) -> Result<Excised> {
+    let mut conditions = vec!["e = ?".to_string()];
+    let mut params: Vec<&dyn ToSql> = vec![&excision.target];
+
+    let attrs: Option<Vec<String>> = excision
+        .attrs
+        .as_ref()
+        .map(|attrs| attrs.iter().map(|a| a.to_string()).collect());
+    if let Some(ref attrs) = attrs {
+        conditions.push(format!("a IN ({})", attrs.join(", ")));
+    }
+
+    if let Some(ref before_tx) = excision.before_tx {
+        conditions.push("tx < ?".to_string());
+        params.push(before_tx);
+    }
+
+    let before = excision.before.map(|before| before.to_micros());
+    if let Some(ref before) = before {
+        conditions.push(format!(
+            "tx IN (SELECT e FROM datoms WHERE a = {} AND v < ?)",
+            entids::DB_TX_INSTANT
+        ));
+        params.push(before);
+    }
+
+    let conditions = conditions.join(" AND ");
+
+    // Collect what we're about to remove from the materialized view.
+    let s = format!(
+        "SELECT e, a, v, value_type_tag FROM all_datoms WHERE {}",
+        conditions
+    );
+    let mut stmt = conn.prepare(&s)?;
+    let excised: Result<Vec<(Entid, Entid, TypedValue)>> = stmt
+        .query_and_then(&params[..], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                TypedValue::from_sql_value_pair(row.get(2)?, row.get(3)?)?,
+            ))
+        })?
+        .collect();
+    let excised = excised?;
+
+    // Collect what we're about to remove from the log, in every timeline.
+    let s = format!(
+        "SELECT DISTINCT a FROM timelined_transactions WHERE {}",
+        conditions
+    );
+    let mut stmt = conn.prepare(&s)?;
+    let attrs: Result<BTreeSet<Entid>> = stmt
+        .query_and_then(&params[..], |row| -> Result<Entid> { Ok(row.get(0)?) })?
+        .collect();
+    let attrs = attrs?;
+
+    // Fulltext values are shared between datoms, so we can only remove those that nothing else
+    // refers to once we're done.
+    let fulltext_attrs: Vec<String> = schema
+        .attribute_map
+        .iter()
+        .filter(|(_, attribute)| attribute.fulltext)
+        .map(|(a, _)| a.to_string())
+        .collect();
+    let fulltext_rowids: Vec<i64> = if fulltext_attrs.is_empty() {
+        vec![]
+    } else {
+        let fulltext_attrs = fulltext_attrs.join(", ");
+        let s = format!(
+            "SELECT v FROM datoms WHERE {} AND a IN ({})
+             UNION
+             SELECT v FROM timelined_transactions WHERE {} AND a IN ({})",
+            conditions, fulltext_attrs, conditions, fulltext_attrs
+        );
+        let mut stmt = conn.prepare(&s)?;
+        let both: Vec<&dyn ToSql> = params.iter().chain(params.iter()).cloned().collect();
+        let rowids: Result<Vec<i64>> = stmt
+            .query_and_then(&both[..], |row| -> Result<i64> { Ok(row.get(0)?) })?
+            .collect();
+        rowids?
+    };
+
+    conn.execute(
+        &format!("DELETE FROM datoms WHERE {}", conditions),
+        &params[..],
+    )?;
+    let count = conn.execute(
+        &format!("DELETE FROM timelined_transactions WHERE {}", conditions),
+        &params[..],
+    )?;
+
+    if !fulltext_rowids.is_empty() {
+        let rowids: Vec<String> = fulltext_rowids.iter().map(|r| r.to_string()).collect();
+        let fulltext_attrs = fulltext_attrs.join(", ");
+        conn.execute(
+            &format!(
+                "DELETE FROM fulltext_values WHERE rowid IN ({})
+                 AND rowid NOT IN (SELECT v FROM datoms WHERE index_fulltext IS NOT 0)
+                 AND rowid NOT IN (SELECT v FROM timelined_transactions WHERE a IN ({}))",
+                rowids.join(", "),
+                fulltext_attrs
+            ),
+            rusqlite::params![],
+        )?;
+    }
+
+    Ok(Excised {
+        datoms: excised,
+        attrs,
+        count,
+    })
+}
+
+/// Record a summary of what was `excised` on the entity that requested `excision`, replacing
+/// anything the transaction asserted for the summary attributes.
+pub(crate) fn record_summary<'schema>(
+    aev_trie: &mut AEVTrie<'schema>,
+    schema: &'schema Schema,
+    excision: &Excision,
+    excised: &Excised,
+) -> Result<()> {
+    let attrs = excised.attrs.iter().map(|&a| TypedValue::Ref(a)).collect();
+    let count = once(TypedValue::Long(excised.count as i64)).collect();
+
+    let summary = [
+        (entids::DB_EXCISE_EXCISED_ATTRS, attrs),
+        (entids::DB_EXCISE_EXCISED_COUNT, count),
+    ];
+    for (a, values) in summary {
+        let ars = aev_trie
+            .entry((a, schema.require_attribute_for_entid(a)?))
+            .or_default()
+            .entry(excision.entity)
+            .or_default();
+        ars.add = values;
+        ars.retract.clear();
+    }
+    Ok(())
+}
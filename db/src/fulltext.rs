@@ -0,0 +1,83 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Fulltext values are stored once, in the plain `fulltext_values` table, and indexed by one FTS5
+//! table per tokenizer, like `fulltext_index_porter`.  Each index is an external-content index of
+//! `fulltext_values`, kept up to date by triggers.
+//!
+//! Every index holds every fulltext value, not just the values of attributes using its tokenizer.
+//! That way, changing an attribute's `:db/fulltextTokenizer` takes effect immediately, and a query
+//! can search several attributes with a single index.  Indexes are created the first time an
+//! attribute uses their tokenizer, and the default index always exists.
+
+use rusqlite;
+
+use core_traits::attribute::FulltextTokenizer;
+
+use db_traits::errors::{DbErrorKind, Result};
+
+/// The trigram tokenizer arrived in SQLite 3.34.0.
+const MIN_SQLITE_VERSION_FOR_TRIGRAM: i32 = 3_034_000;
+
+/// The FTS5 `tokenize` option for the given tokenizer.
+fn tokenize_option(tokenizer: FulltextTokenizer) -> &'static str {
+    match tokenizer {
+        FulltextTokenizer::Unicode61 => "unicode61 remove_diacritics 0",
+        FulltextTokenizer::Unaccented => "unicode61 remove_diacritics 2",
+        FulltextTokenizer::Porter => "porter unicode61 remove_diacritics 2",
+        FulltextTokenizer::Trigram => "trigram",
+    }
+}
+
+/// Create the index for the given tokenizer, and fill it with the existing fulltext values, if it
+/// doesn't already exist.
+pub(crate) fn ensure_fulltext_index(
+    conn: &rusqlite::Connection,
+    tokenizer: FulltextTokenizer,
+) -> Result<()> {
+    let table = tokenizer.index_table();
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)",
+        &[table],
+        |row| row.get(0),
+    )?;
+    if exists {
+        return Ok(());
+    }
+
+    if tokenizer == FulltextTokenizer::Trigram
+        && rusqlite::version_number() < MIN_SQLITE_VERSION_FOR_TRIGRAM
+    {
+        bail!(DbErrorKind::BadSchemaAssertion(format!(
+            ":db.fulltextTokenizer/trigram requires SQLite {}, but this is SQLite {}",
+            MIN_SQLITE_VERSION_FOR_TRIGRAM,
+            rusqlite::version_number()
+        )));
+    }
+
+    conn.execute_batch(&format!(
+        r#"CREATE VIRTUAL TABLE {table}
+             USING fts5(text, content='fulltext_values', content_rowid='id', tokenize='{tokenize}');
+           CREATE TRIGGER {table}_insert AFTER INSERT ON fulltext_values BEGIN
+             INSERT INTO {table} (rowid, text) VALUES (new.id, new.text);
+           END;
+           CREATE TRIGGER {table}_delete AFTER DELETE ON fulltext_values BEGIN
+             INSERT INTO {table} ({table}, rowid, text) VALUES ('delete', old.id, old.text);
+           END;
+           CREATE TRIGGER {table}_update AFTER UPDATE OF text ON fulltext_values BEGIN
+             INSERT INTO {table} ({table}, rowid, text) VALUES ('delete', old.id, old.text);
+             INSERT INTO {table} (rowid, text) VALUES (new.id, new.text);
+           END;
+           INSERT INTO {table} ({table}) VALUES ('rebuild');"#,
+        table = table,
+        tokenize = tokenize_option(tokenizer)
+    ))?;
+    Ok(())
+}
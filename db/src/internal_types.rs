@@ -132,6 +132,10 @@ pub type TypedValueOr<T> = Either<TypedValue, T>;
 pub type TempIdHandle = ValueRc<TempId>;
 pub type TempIdMap = HashMap<TempIdHandle, KnownEntid>;
 
+/// Map tempids to the names of the partitions they should be allocated in.  Tempids that aren't
+/// present are allocated in `:db.part/user`.
+pub type TempIdPartitions = BTreeMap<TempId, String>;
+
 pub type LookupRef = ValueRc<AVPair>;
 
 /// Internal representation of an entid on its way to resolution.  We either have the simple case (a
@@ -149,6 +153,20 @@ pub type TermWithTempIds = Term<KnownEntidOr<TempIdHandle>, TypedValueOr<TempIdH
 pub type TermWithoutTempIds = Term<KnownEntid, TypedValue>;
 pub type Population = Vec<TermWithTempIds>;
 
+/// A built-in transaction function that needs to consult the store, which it can only do once the
+/// lookup refs it mentions have been resolved.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub enum TxFunctionTerm<E, V> {
+    /// Like `[:db/retractEntity e]`.
+    RetractEntity(E),
+    /// Like `[:db/cas e a old new]`, where `old` is `None` if `e` must have no value for `a`.  The
+    /// new value is transacted as an ordinary `[:db/add e a new]`.
+    Cas(E, Entid, Option<V>),
+}
+
+pub type TxFunctionTermWithLookupRefs =
+    TxFunctionTerm<KnownEntidOr<LookupRefOrTempId>, TypedValueOr<LookupRefOrTempId>>;
+
 impl TermWithTempIds {
     // These have no tempids by definition, and just need to be unwrapped.  This operation might
     // also be called "lowering" or "level lowering", but the concept of "unwrapping" is common in
@@ -218,3 +236,13 @@ pub(crate) struct AddAndRetract {
 // checking more efficient.  BTree* for deterministic errors.
 pub(crate) type AEVTrie<'schema> =
     BTreeMap<(Entid, &'schema Attribute), BTreeMap<Entid, AddAndRetract>>;
+
+/// Collect the values asserted for `[e a _]` in the given trie.
+pub(crate) fn added_values(aev_trie: &AEVTrie, a: Entid, e: Entid) -> Vec<TypedValue> {
+    aev_trie
+        .iter()
+        .filter(|&(&(trie_a, _), _)| trie_a == a)
+        .filter_map(|(_, evs)| evs.get(&e))
+        .flat_map(|ars| ars.add.iter().cloned())
+        .collect()
+}
@@ -21,6 +21,7 @@ extern crate log;
 extern crate serde_derive;
 
 extern crate petgraph;
+extern crate regex;
 extern crate rusqlite;
 extern crate tabwriter;
 extern crate time;
@@ -48,8 +49,11 @@ mod bootstrap;
 pub mod cache;
 pub mod db;
 pub mod entids;
+mod excision;
+mod fulltext;
 pub mod internal_types; // pub because we need them for building entities programmatically.
 mod metadata;
+mod partitions;
 mod schema;
 pub mod timelines;
 mod tx;
@@ -77,11 +81,14 @@ pub use crate::db::{new_connection, TypedSQLValue};
 #[cfg(feature = "sqlcipher")]
 pub use db::{change_encryption_key, new_connection_with_key};
 
-pub use crate::watcher::TransactWatcher;
+pub use crate::watcher::{TransactWatcher, TxDataWatcher};
 
 pub use crate::tx::{transact, transact_terms};
 
-pub use crate::tx_observer::{InProgressObserverTransactWatcher, TxObservationService, TxObserver};
+pub use crate::tx_observer::{
+    DatomFilter, DatomObserver, DatomPattern, InProgressObserverTransactWatcher, ObservedTx,
+    TxObservationService, TxObserver,
+};
 
 pub use crate::types::{AttributeSet, Partition, PartitionMap, TransactableValue, DB};
 
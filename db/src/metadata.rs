@@ -58,6 +58,8 @@ pub enum AttributeAlteration {
     NoHistory,
     /// - change whether an attribute is treated as a component
     IsComponent,
+    /// - change how a fulltext attribute's values are tokenized
+    FulltextTokenizer,
 }
 
 /// An alteration to an ident.
@@ -86,6 +88,17 @@ impl MetadataReport {
     }
 }
 
+/// Map a `:db.fulltextTokenizer/*` entid to the tokenizer it names.
+fn tokenizer_for_entid(entid: Entid) -> Option<attribute::FulltextTokenizer> {
+    match entid {
+        entids::DB_FULLTEXT_TOKENIZER_UNICODE61 => Some(attribute::FulltextTokenizer::Unicode61),
+        entids::DB_FULLTEXT_TOKENIZER_UNACCENTED => Some(attribute::FulltextTokenizer::Unaccented),
+        entids::DB_FULLTEXT_TOKENIZER_PORTER => Some(attribute::FulltextTokenizer::Porter),
+        entids::DB_FULLTEXT_TOKENIZER_TRIGRAM => Some(attribute::FulltextTokenizer::Trigram),
+        _ => None,
+    }
+}
+
 /// Update an 'AttributeMap' in place given two sets of ident and attribute retractions, which
 /// together contain enough information to reason about a "schema retraction".
 ///
@@ -186,8 +199,9 @@ pub fn update_attribute_map_from_entid_triples(
             .entry(entid)
             .or_insert_with(|| attribute_builder_to_modify(entid, attribute_map));
         match attr {
-            // You can only retract :db/unique, :db/isComponent; all others must be altered instead
-            // of retracted, or are not allowed to change.
+            // You can only retract :db/unique, :db/isComponent, :db/index, and
+            // :db/fulltextTokenizer; all others must be altered instead of retracted, or are not
+            // allowed to change.
             entids::DB_IS_COMPONENT => {
                 match value {
                     &TypedValue::Boolean(v) if builder.component == Some(v) => {
@@ -218,9 +232,31 @@ pub fn update_attribute_map_from_entid_triples(
                 }
             },
 
+            // Retracting :db/fulltextTokenizer restores the default tokenizer.
+            entids::DB_FULLTEXT_TOKENIZER => {
+                match *value {
+                    TypedValue::Ref(t) if tokenizer_for_entid(t) == builder.fulltext_tokenizer => {
+                        builder.fulltext_tokenizer(attribute::FulltextTokenizer::default());
+                    },
+                    _ => {
+                        bail!(DbErrorKind::BadSchemaAssertion(format!("Attempted to retract :db/fulltextTokenizer with the wrong value {:?}.", value)));
+                    },
+                }
+            },
+
+            entids::DB_INDEX => {
+                match value {
+                    &TypedValue::Boolean(v) if builder.index == Some(v) => {
+                        builder.index(false);
+                    },
+                    v => {
+                        bail!(DbErrorKind::BadSchemaAssertion(format!("Attempted to retract :db/index with the wrong value {:?}.", v)));
+                    },
+                }
+            },
+
             entids::DB_VALUE_TYPE |
             entids::DB_CARDINALITY |
-            entids::DB_INDEX |
             entids::DB_FULLTEXT |
             entids::DB_NO_HISTORY => {
                 bail!(DbErrorKind::BadSchemaAssertion(format!("Retracting attribute {} for entity {} not permitted.", attr, entid)));
@@ -282,6 +318,16 @@ pub fn update_attribute_map_from_entid_triples(
                 }
             },
 
+            entids::DB_FULLTEXT_TOKENIZER => {
+                match *value {
+                    TypedValue::Ref(t) => match tokenizer_for_entid(t) {
+                        Some(tokenizer) => { builder.fulltext_tokenizer(tokenizer); },
+                        None => bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [... :db/fulltextTokenizer :db.fulltextTokenizer/*] but got [... :db/fulltextTokenizer {:?}]", value))),
+                    },
+                    _ => bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [... :db/fulltextTokenizer :db.fulltextTokenizer/*] but got [... :db/fulltextTokenizer {:?}]", value)))
+                }
+            },
+
             entids::DB_IS_COMPONENT => {
                 match *value {
                     TypedValue::Boolean(x) => { builder.component(x); },
@@ -331,6 +377,9 @@ pub fn update_attribute_map_from_entid_triples(
                         entid
                     )))?;
                 let mutations = builder.mutate(entry.get_mut());
+                if mutations.contains(&AttributeAlteration::FulltextTokenizer) {
+                    entry.get().validate(|| entid.to_string())?;
+                }
                 attributes_altered.insert(entid, mutations);
             }
         }
@@ -0,0 +1,210 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Applications can install their own partitions by transacting
+//!
+//! ```edn
+//! [{:db/id                       "p"
+//!   :db/ident                    :myapp.part/logs
+//!   :db.partition/start          1000000
+//!   :db.partition/end            1999999
+//!   :db.partition/allowExcision  true}           ; Optional: defaults to false.
+//!  [:db/add :db.part/db :db.install/partition "p"]]
+//! ```
+//!
+//! and then allocate entids in the new partition with `(tempid :myapp.part/logs)`.
+//!
+//! Partitions can't overlap.  Since the bootstrap partitions cover every entid, a new partition is
+//! carved out of the unallocated end of an existing partition: that partition's range is
+//! truncated to end just before the new partition starts.
+
+use std::collections::BTreeSet;
+
+use rusqlite;
+
+use core_traits::{Entid, TypedValue};
+
+use mentat_core::{HasSchema, Schema};
+
+use db_traits::errors::{DbErrorKind, Result};
+
+use crate::db;
+use crate::entids;
+use crate::internal_types::{added_values, AEVTrie};
+use crate::types::{Partition, PartitionMap};
+
+/// A request to install a new partition.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct PartitionInstallation {
+    pub(crate) name: String,
+    pub(crate) start: Entid,
+    pub(crate) end: Entid,
+    pub(crate) allow_excision: bool,
+}
+
+/// Collect the partitions installed by `[:db.part/db :db.install/partition p]` assertions in the
+/// given trie.
+///
+/// The partition `p` must be described in the same transaction, except for its `:db/ident`, which
+/// may already exist.  The trie must already have been type checked.
+pub(crate) fn installations(
+    aev_trie: &AEVTrie,
+    schema: &Schema,
+) -> Result<Vec<PartitionInstallation>> {
+    let mut installations = vec![];
+    for (&(a, _), evs) in aev_trie {
+        if a != entids::DB_INSTALL_PARTITION {
+            continue;
+        }
+
+        for (&e, ars) in evs {
+            if ars.add.is_empty() {
+                continue;
+            }
+            if e != entids::DB_PART_DB {
+                bail!(DbErrorKind::PartitionInstallationFailed(format!(
+                    "partitions must be installed with [:db.part/db :db.install/partition ...], not on entity {}",
+                    e
+                )));
+            }
+
+            for p in ars.add.iter() {
+                let p = match *p {
+                    TypedValue::Ref(p) => p,
+                    _ => continue,
+                };
+
+                let name = added_values(aev_trie, entids::DB_IDENT, p)
+                    .into_iter()
+                    .filter_map(|v| match v {
+                        TypedValue::Keyword(ident) => Some(ident.to_string()),
+                        _ => None,
+                    })
+                    .next()
+                    .or_else(|| schema.get_ident(p).map(|ident| ident.to_string()))
+                    .ok_or_else(|| {
+                        DbErrorKind::PartitionInstallationFailed(format!(
+                            "partition {} has no :db/ident",
+                            p
+                        ))
+                    })?;
+
+                let long = |a: Entid, attribute: &str| -> Result<Entid> {
+                    added_values(aev_trie, a, p)
+                        .into_iter()
+                        .filter_map(|v| match v {
+                            TypedValue::Long(x) => Some(x),
+                            _ => None,
+                        })
+                        .next()
+                        .ok_or_else(|| {
+                            DbErrorKind::PartitionInstallationFailed(format!(
+                                "{} has no {}",
+                                name, attribute
+                            ))
+                            .into()
+                        })
+                };
+                let start = long(entids::DB_PARTITION_START, ":db.partition/start")?;
+                let end = long(entids::DB_PARTITION_END, ":db.partition/end")?;
+                let allow_excision = added_values(aev_trie, entids::DB_PARTITION_ALLOW_EXCISION, p)
+                    .into_iter()
+                    .any(|v| v == TypedValue::Boolean(true));
+
+                installations.push(PartitionInstallation {
+                    name,
+                    start,
+                    end,
+                    allow_excision,
+                });
+            }
+        }
+    }
+    Ok(installations)
+}
+
+/// Add the given partition to `partition_map`, truncating the partition it's carved out of, and
+/// record the changes in the store.
+///
+/// Installing a partition that's already present with the same range is not an error: during
+/// sync, we adopt partition maps that already include the partitions being installed.
+pub(crate) fn install(
+    conn: &rusqlite::Connection,
+    partition_map: &mut PartitionMap,
+    installation: &PartitionInstallation,
+) -> Result<()> {
+    let PartitionInstallation {
+        ref name,
+        start,
+        end,
+        allow_excision,
+    } = *installation;
+
+    if let Some(existing) = partition_map.get(name) {
+        if existing.start != start
+            || existing.end != end
+            || existing.allow_excision != allow_excision
+        {
+            bail!(DbErrorKind::PartitionInstallationFailed(format!(
+                "{} is already installed with a different range",
+                name
+            )));
+        }
+    } else {
+        if start <= 0 || end < start {
+            bail!(DbErrorKind::PartitionInstallationFailed(format!(
+                "{} has an invalid range [{}, {}]",
+                name, start, end
+            )));
+        }
+
+        let overlapping: Vec<String> = partition_map
+            .iter()
+            .filter(|&(_, partition)| partition.start <= end && start <= partition.end)
+            .map(|(part, _)| part.clone())
+            .collect();
+        let parent = match overlapping.as_slice() {
+            [parent] => parent,
+            _ => bail!(DbErrorKind::PartitionInstallationFailed(format!(
+                "{} must lie within exactly one existing partition, but overlaps {:?}",
+                name, overlapping
+            ))),
+        };
+
+        let partition = partition_map
+            .get_mut(parent)
+            .expect("overlapping partition");
+        if start <= partition.next_entid() || end > partition.end {
+            bail!(DbErrorKind::PartitionInstallationFailed(format!(
+                "{} must lie within the unallocated entids [{}, {}] of {}",
+                name,
+                partition.next_entid() + 1,
+                partition.end,
+                parent
+            )));
+        }
+        partition.end = start - 1;
+
+        partition_map.insert(
+            name.clone(),
+            Partition::new(start, end, start, allow_excision),
+        );
+    }
+
+    // Only persist partitions the store already knows about, plus the new one.  In particular,
+    // this leaves alone any purely in-memory partitions that tests might add.
+    let known: BTreeSet<String> = db::read_known_parts(conn)?;
+    db::update_known_parts(
+        conn,
+        partition_map
+            .iter()
+            .filter(|&(part, _)| known.contains(part) || part == name),
+    )
+}
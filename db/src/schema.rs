@@ -56,6 +56,12 @@ impl AttributeValidation for Attribute {
                 ident()
             )))
         }
+        if !self.fulltext && self.fulltext_tokenizer != attribute::FulltextTokenizer::default() {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(
+                ":db/fulltextTokenizer without :db/fulltext true for entid: {}",
+                ident()
+            )))
+        }
         if self.component && self.value_type != ValueType::Ref {
             bail!(DbErrorKind::BadSchemaAssertion(format!(
                 ":db/isComponent true without :db/valueType :db.type/ref for entid: {}",
@@ -92,6 +98,7 @@ pub struct AttributeBuilder {
     pub unique: Option<Option<attribute::Unique>>,
     pub index: Option<bool>,
     pub fulltext: Option<bool>,
+    pub fulltext_tokenizer: Option<attribute::FulltextTokenizer>,
     pub component: Option<bool>,
     pub no_history: Option<bool>,
 }
@@ -112,7 +119,9 @@ impl AttributeBuilder {
         let mut ab = AttributeBuilder::default();
         ab.multival = Some(attribute.multival);
         ab.unique = Some(attribute.unique);
+        ab.index = Some(attribute.index);
         ab.component = Some(attribute.component);
+        ab.fulltext_tokenizer = Some(attribute.fulltext_tokenizer);
         ab
     }
 
@@ -152,6 +161,11 @@ impl AttributeBuilder {
         self
     }
 
+    pub fn fulltext_tokenizer(&mut self, tokenizer: attribute::FulltextTokenizer) -> &mut Self {
+        self.fulltext_tokenizer = Some(tokenizer);
+        self
+    }
+
     pub fn component(&mut self, component: bool) -> &mut Self {
         self.component = Some(component);
         self
@@ -193,6 +207,9 @@ impl AttributeBuilder {
         if let Some(fulltext) = self.fulltext {
             attribute.fulltext = fulltext;
         }
+        if let Some(fulltext_tokenizer) = self.fulltext_tokenizer {
+            attribute.fulltext_tokenizer = fulltext_tokenizer;
+        }
         if let Some(multival) = self.multival {
             attribute.multival = multival;
         }
@@ -249,6 +266,12 @@ impl AttributeBuilder {
                 mutations.push(AttributeAlteration::NoHistory);
             }
         }
+        if let Some(fulltext_tokenizer) = self.fulltext_tokenizer {
+            if fulltext_tokenizer != attribute.fulltext_tokenizer {
+                attribute.fulltext_tokenizer = fulltext_tokenizer;
+                mutations.push(AttributeAlteration::FulltextTokenizer);
+            }
+        }
 
         mutations
     }
@@ -415,6 +438,7 @@ mod test {
                 index: false,
                 value_type: ValueType::Boolean,
                 fulltext: false,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: None,
                 multival: false,
                 component: false,
@@ -430,6 +454,7 @@ mod test {
                 index: true,
                 value_type: ValueType::Long,
                 fulltext: false,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: Some(attribute::Unique::Value),
                 multival: false,
                 component: false,
@@ -445,6 +470,7 @@ mod test {
                 index: true,
                 value_type: ValueType::Ref,
                 fulltext: false,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: Some(attribute::Unique::Identity),
                 multival: false,
                 component: false,
@@ -460,6 +486,7 @@ mod test {
                 index: false,
                 value_type: ValueType::Ref,
                 fulltext: false,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: None,
                 multival: false,
                 component: true,
@@ -475,6 +502,7 @@ mod test {
                 index: true,
                 value_type: ValueType::String,
                 fulltext: true,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: None,
                 multival: false,
                 component: false,
@@ -498,6 +526,7 @@ mod test {
                 index: false,
                 value_type: ValueType::Boolean,
                 fulltext: false,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: Some(attribute::Unique::Value),
                 multival: false,
                 component: false,
@@ -528,6 +557,7 @@ mod test {
                 index: false,
                 value_type: ValueType::Long,
                 fulltext: false,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: Some(attribute::Unique::Identity),
                 multival: false,
                 component: false,
@@ -558,6 +588,7 @@ mod test {
                 index: false,
                 value_type: ValueType::Boolean,
                 fulltext: false,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: None,
                 multival: false,
                 component: true,
@@ -589,6 +620,7 @@ mod test {
                 index: false,
                 value_type: ValueType::String,
                 fulltext: true,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: None,
                 multival: false,
                 component: false,
@@ -618,6 +650,7 @@ mod test {
                 index: true,
                 value_type: ValueType::Long,
                 fulltext: true,
+                fulltext_tokenizer: attribute::FulltextTokenizer::default(),
                 unique: None,
                 multival: false,
                 component: false,
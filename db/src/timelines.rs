@@ -160,12 +160,14 @@ pub fn move_from_main_timeline(
     for tx_id in &txs_to_move {
         let reversed_terms = reversed_terms_for(conn, *tx_id)?;
 
-        // Rewind schema and datoms.
+        // Rewind schema and datoms. Transactions are rewound newest first, each against
+        // the schema left behind by rewinding the one after it.
+        let current_schema = last_schema.as_ref().unwrap_or(schema);
         let (report, _, new_schema, _) = transact_terms_with_action(
             conn,
             partition_map.clone(),
-            schema,
-            schema,
+            current_schema,
+            current_schema,
             NullWatcher(),
             reversed_terms.into_iter().map(|t| t.rewrap()),
             InternSet::new(),
@@ -182,7 +184,9 @@ pub fn move_from_main_timeline(
         // A quick workaround is to just remove the bad txInstant datom.
         // See test_clashing_tx_instants test case.
         remove_tx_from_datoms(conn, report.tx_id)?;
-        last_schema = new_schema;
+        if new_schema.is_some() {
+            last_schema = new_schema;
+        }
     }
 
     // Move transactions over to the target timeline.
@@ -652,6 +656,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_pop_schema_alterations() {
+        let mut conn = TestConn::default();
+        conn.sanitized_partition_map();
+
+        assert_transact!(
+            conn,
+            r#"
+            [{:db/ident :test/one :db/valueType :db.type/long :db/cardinality :db.cardinality/one}]
+        "#
+        );
+
+        let partition_map0 = conn.partition_map.clone();
+        let schema0 = conn.schema.clone();
+
+        // Each alteration is rewound against the schema left by rewinding the next one.
+        let report1 = assert_transact!(
+            conn,
+            "[[:db/add :test/one :db/cardinality :db.cardinality/many]]"
+        );
+        assert_transact!(conn, "[[:db/add :test/one :db/index true]]");
+        assert_transact!(
+            conn,
+            "[[:db/add :test/one :db/cardinality :db.cardinality/one]]"
+        );
+
+        let (new_schema, new_partition_map) = move_from_main_timeline(
+            &conn.sqlite,
+            &conn.schema,
+            conn.partition_map.clone(),
+            report1.tx_id..,
+            1,
+        )
+        .expect("moved alterations");
+        update_conn(&mut conn, &new_schema, &new_partition_map);
+
+        assert_eq!(conn.partition_map, partition_map0);
+        assert_eq!(conn.schema, schema0);
+    }
+
     #[test]
     fn test_pop_in_sequence() {
         let mut conn = TestConn::default();
@@ -52,14 +52,16 @@ use std::iter::once;
 use crate::db;
 use crate::db::MentatStoring;
 use crate::entids;
+use crate::excision;
 use crate::internal_types::{
     replace_lookup_ref, AEVTrie, AddAndRetract, KnownEntidOr, LookupRef, LookupRefOrTempId,
     TempIdHandle, TempIdMap, Term, TermWithTempIds, TermWithTempIdsAndLookupRefs,
-    TermWithoutTempIds, TypedValueOr,
+    TempIdPartitions, TermWithoutTempIds, TxFunctionTerm, TxFunctionTermWithLookupRefs,
+    TypedValueOr,
 };
 use db_traits::errors;
 use db_traits::errors::{DbErrorKind, Result};
-use edn::{InternSet, Keyword};
+use edn::{InternSet, Keyword, ValueRc};
 
 use mentat_core::util::Either;
 
@@ -68,6 +70,7 @@ use core_traits::{attribute, now, Attribute, Entid, KnownEntid, TypedValue, Valu
 use mentat_core::{DateTime, Schema, TxReport, Utc};
 
 use crate::metadata;
+use crate::partitions;
 use crate::schema::SchemaBuilding;
 use crate::tx_checking;
 use crate::types::{AVMap, AVPair, PartitionMap, TransactableValue};
@@ -235,13 +238,22 @@ where
     ///
     /// The `Term` instances produce share interned TempId and LookupRef handles, and we return the
     /// interned handle sets so that consumers can ensure all handles are used appropriately.
+    ///
+    /// Built-in transaction functions like `:db/retractEntity` need to consult the store before
+    /// they can be turned into `Term` instances; they're returned separately.
+    ///
+    /// Tempids like `(tempid :myapp.part/logs)` are rewritten into plain tempids; the partitions
+    /// they should be allocated in are returned separately, too.
+    #[allow(clippy::type_complexity)]
     fn entities_into_terms_with_temp_ids_and_lookup_refs<I, V: TransactableValue>(
         &self,
         entities: I,
     ) -> Result<(
         Vec<TermWithTempIdsAndLookupRefs>,
         InternSet<TempId>,
+        TempIdPartitions,
         InternSet<AVPair>,
+        Vec<TxFunctionTermWithLookupRefs>,
     )>
     where
         I: IntoIterator<Item = Entity<V>>,
@@ -252,6 +264,7 @@ where
             mentat_id_count: i64,
             tx_id: KnownEntid,
             temp_ids: InternSet<TempId>,
+            temp_id_partitions: TempIdPartitions,
             lookup_refs: InternSet<AVPair>,
         }
 
@@ -267,6 +280,7 @@ where
                     mentat_id_count: 0,
                     tx_id,
                     temp_ids: InternSet::new(),
+                    temp_id_partitions: TempIdPartitions::default(),
                     lookup_refs: InternSet::new(),
                 }
             }
@@ -309,6 +323,36 @@ where
                 Ok(self.lookup_refs.intern((lr_a, lr_typed_value)))
             }
 
+            /// Intern the given tempid, recording the partition it should be allocated in, if any.
+            fn intern_temp_id(&mut self, tempid: ValueRc<TempId>) -> Result<TempIdHandle> {
+                let (tempid, part) = match *tempid {
+                    TempId::InPartition(ref part, ref name) => {
+                        let tempid = match *name {
+                            Some(ref name) => TempId::External(name.clone()),
+                            None => {
+                                self.mentat_id_count += 1;
+                                TempId::Internal(self.mentat_id_count)
+                            }
+                        };
+                        (tempid, part.to_string())
+                    }
+                    _ => return Ok(self.temp_ids.intern(tempid)),
+                };
+
+                if !self.partition_map.contains_key(&part) {
+                    bail!(DbErrorKind::UnknownPartition(part));
+                }
+                if let Some(previous) = self.temp_id_partitions.insert(tempid.clone(), part.clone()) {
+                    if previous != part {
+                        bail!(DbErrorKind::NotYetImplemented(format!(
+                            "tempid {} cannot be allocated in both {} and {}",
+                            tempid, previous, part
+                        )));
+                    }
+                }
+                Ok(self.temp_ids.intern(tempid))
+            }
+
             /// Allocate private internal tempids reserved for Mentat.  Internal tempids just need to be
             /// unique within one transaction; they should never escape a transaction.
             fn allocate_mentat_id<W: TransactableValue>(&mut self) -> entmod::EntityPlace<W> {
@@ -330,7 +374,7 @@ where
                     }
 
                     entmod::EntityPlace::TempId(e) => Ok(Either::Right(LookupRefOrTempId::TempId(
-                        self.temp_ids.intern(e),
+                        self.intern_temp_id(e)?,
                     ))),
 
                     entmod::EntityPlace::LookupRef(ref lookup_ref) => Ok(Either::Right(
@@ -404,7 +448,7 @@ where
                                 Ok(Either::Left(KnownEntid(self.entity_a_into_term_a(entid)?))),
 
                             entmod::ValuePlace::TempId(tempid) =>
-                                Ok(Either::Right(LookupRefOrTempId::TempId(self.temp_ids.intern(tempid)))),
+                                Ok(Either::Right(LookupRefOrTempId::TempId(self.intern_temp_id(tempid)?))),
 
                             entmod::ValuePlace::LookupRef(ref lookup_ref) =>
                                 Ok(Either::Right(LookupRefOrTempId::LookupRef(self.intern_lookup_ref(lookup_ref)?))),
@@ -440,9 +484,57 @@ where
         deque.extend(entities);
 
         let mut terms: Vec<TermWithTempIdsAndLookupRefs> = Vec::with_capacity(deque.len());
+        let mut tx_functions: Vec<TxFunctionTermWithLookupRefs> = vec![];
 
         while let Some(entity) = deque.pop_front() {
             match entity {
+                Entity::RetractEntity { e } => {
+                    let e = in_process.entity_e_into_term_e(e)?;
+                    tx_functions.push(TxFunctionTerm::RetractEntity(e));
+                }
+
+                Entity::Cas { e, a, old, new } => {
+                    let AttributePlace::Entid(a) = a;
+                    let a = in_process.entity_a_into_term_a(a)?;
+                    let attribute = self.schema.require_attribute_for_entid(a)?;
+                    if attribute.multival {
+                        bail!(DbErrorKind::NotYetImplemented(format!(
+                            "Cannot use :db/cas for attribute {} that is not :db.cardinality :db.cardinality/one",
+                            a
+                        )));
+                    }
+
+                    let old = match old {
+                        None => None,
+                        Some(entmod::ValuePlace::Atom(v)) => Some(Either::Left(
+                            v.into_typed_value(self.schema, attribute.value_type)?,
+                        )),
+                        Some(entmod::ValuePlace::Entid(entid)) => Some(Either::Left(
+                            TypedValue::Ref(in_process.entity_a_into_term_a(entid)?),
+                        )),
+                        Some(entmod::ValuePlace::LookupRef(ref lookup_ref)) => {
+                            Some(Either::Right(LookupRefOrTempId::LookupRef(
+                                in_process.intern_lookup_ref(lookup_ref)?,
+                            )))
+                        }
+                        Some(_) => bail!(DbErrorKind::NotYetImplemented(format!(
+                            "Cannot compare a value that is not an atom, entid, or lookup ref in :db/cas for attribute {}",
+                            a
+                        ))),
+                    };
+
+                    let term_e = in_process.entity_e_into_term_e(e.clone())?;
+                    tx_functions.push(TxFunctionTerm::Cas(term_e, a, old));
+
+                    // The new value is asserted like any other.
+                    deque.push_front(Entity::AddOrRetract {
+                        op: OpType::Add,
+                        e,
+                        a: AttributePlace::Entid(entmod::EntidOrIdent::Entid(a)),
+                        v: new,
+                    });
+                }
+
                 Entity::MapNotation(mut map_notation) => {
                     // :db/id is optional; if it's not given, we generate a special internal tempid
                     // to use for upserting.  This tempid will not be reported in the TxReport.
@@ -503,7 +595,7 @@ where
                             )),
 
                             entmod::ValuePlace::TempId(tempid) => Either::Right(
-                                LookupRefOrTempId::TempId(in_process.temp_ids.intern(tempid)),
+                                LookupRefOrTempId::TempId(in_process.intern_temp_id(tempid)?),
                             ),
 
                             entmod::ValuePlace::LookupRef(ref lookup_ref) => {
@@ -646,7 +738,13 @@ where
                 }
             }
         }
-        Ok((terms, in_process.temp_ids, in_process.lookup_refs))
+        Ok((
+            terms,
+            in_process.temp_ids,
+            in_process.temp_id_partitions,
+            in_process.lookup_refs,
+            tx_functions,
+        ))
     }
 
     /// Pipeline stage 2: rewrite `Term` instances with lookup refs into `Term` instances without
@@ -677,6 +775,96 @@ where
             .collect::<Result<Vec<_>>>()
     }
 
+    /// Pipeline stage 2, continued: consult the store to turn built-in transaction functions into
+    /// `Term` instances, or to check that they hold.
+    ///
+    /// `[:db/retractEntity e]` retracts every datom about `e`, including those that refer to it, and
+    /// recursively retracts the entities that `e` refers to through `:db/isComponent` attributes.
+    /// `[:db/cas e a old new]` fails the transaction unless `e` currently has value `old` for `a`.
+    fn apply_tx_functions<I>(
+        &self,
+        lookup_ref_map: &AVMap,
+        tx_functions: I,
+    ) -> Result<Vec<TermWithTempIds>>
+    where
+        I: IntoIterator<Item = TxFunctionTermWithLookupRefs>,
+    {
+        let mut terms: Vec<TermWithTempIds> = vec![];
+
+        // An entity might be retracted more than once, directly or as a component.
+        let mut retracted: BTreeSet<Entid> = BTreeSet::default();
+
+        for tx_function in tx_functions {
+            match tx_function {
+                TxFunctionTerm::RetractEntity(e) => {
+                    let e = match replace_lookup_ref(lookup_ref_map, e, KnownEntid)? {
+                        Either::Left(KnownEntid(e)) => e,
+                        Either::Right(tempid) => bail!(DbErrorKind::NotYetImplemented(format!(
+                            "[:db/retractEntity ...] cannot retract tempid {}",
+                            tempid
+                        ))),
+                    };
+
+                    let mut pending = vec![e];
+                    while let Some(e) = pending.pop() {
+                        if !retracted.insert(e) {
+                            continue;
+                        }
+
+                        for (datom_e, a, v) in self.store.datoms_about_entity(e)? {
+                            // Component entities belong to the entity that refers to them.
+                            if datom_e == e {
+                                if let TypedValue::Ref(component) = v {
+                                    if self.schema.require_attribute_for_entid(a)?.component {
+                                        pending.push(component);
+                                    }
+                                }
+                            }
+                            terms.push(Term::AddOrRetract(
+                                OpType::Retract,
+                                Either::Left(KnownEntid(datom_e)),
+                                a,
+                                Either::Left(v),
+                            ));
+                        }
+                    }
+                }
+
+                TxFunctionTerm::Cas(e, a, old) => {
+                    let e = match replace_lookup_ref(lookup_ref_map, e, KnownEntid)? {
+                        Either::Left(KnownEntid(e)) => e,
+                        Either::Right(tempid) => bail!(DbErrorKind::NotYetImplemented(format!(
+                            "[:db/cas ...] cannot compare tempid {}",
+                            tempid
+                        ))),
+                    };
+                    let expected = match old {
+                        Some(old) => {
+                            match replace_lookup_ref(lookup_ref_map, old, TypedValue::Ref)? {
+                                Either::Left(v) => Some(v),
+                                Either::Right(_) => unreachable!(), // We never intern tempids for :db/cas.
+                            }
+                        }
+                        None => None,
+                    };
+
+                    // :db/cas only applies to :db.cardinality/one attributes.
+                    let found = self.store.values_for_ea(e, a)?.into_iter().next();
+                    if found != expected {
+                        bail!(DbErrorKind::CasFailed {
+                            e,
+                            a,
+                            expected,
+                            found,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(terms)
+    }
+
     /// Transact the given `entities` against the store.
     ///
     /// This approach is explained in https://github.com/mozilla/mentat/wiki/Transacting.
@@ -686,20 +874,27 @@ where
         I: IntoIterator<Item = Entity<V>>,
     {
         // Pipeline stage 1: entities -> terms with tempids and lookup refs.
-        let (terms_with_temp_ids_and_lookup_refs, tempid_set, lookup_ref_set) =
-            self.entities_into_terms_with_temp_ids_and_lookup_refs(entities)?;
+        let (
+            terms_with_temp_ids_and_lookup_refs,
+            tempid_set,
+            tempid_partitions,
+            lookup_ref_set,
+            tx_functions,
+        ) = self.entities_into_terms_with_temp_ids_and_lookup_refs(entities)?;
 
         // Pipeline stage 2: resolve lookup refs -> terms with tempids.
         let lookup_ref_avs: Vec<&(i64, TypedValue)> =
             lookup_ref_set.iter().map(|rc| &**rc).collect();
         let lookup_ref_map: AVMap = self.store.resolve_avs(&lookup_ref_avs[..])?;
 
-        let terms_with_temp_ids =
+        let mut terms_with_temp_ids =
             self.resolve_lookup_refs(&lookup_ref_map, terms_with_temp_ids_and_lookup_refs)?;
+        terms_with_temp_ids.extend(self.apply_tx_functions(&lookup_ref_map, tx_functions)?);
 
         self.transact_simple_terms_with_action(
             terms_with_temp_ids,
             tempid_set,
+            tempid_partitions,
             TransactorAction::MaterializeAndCommit,
         )
     }
@@ -715,6 +910,7 @@ where
         self.transact_simple_terms_with_action(
             terms,
             tempid_set,
+            TempIdPartitions::default(),
             TransactorAction::MaterializeAndCommit,
         )
     }
@@ -723,6 +919,7 @@ where
         &mut self,
         terms: I,
         tempid_set: InternSet<TempId>,
+        tempid_partitions: TempIdPartitions,
         action: TransactorAction,
     ) -> Result<TxReport>
     where
@@ -788,14 +985,47 @@ where
 
         debug!("unresolved tempids {:?}", unresolved_temp_ids);
 
-        // TODO: track partitions for temporary IDs.
-        let entids = self
-            .partition_map
-            .allocate_entids(":db.part/user", unresolved_temp_ids.len());
+        // Tempids that upsert together share an allocation index.  Each index is allocated in the
+        // partition its tempids name, or in :db.part/user if they don't name one.
+        let mut partitions_for_indices: BTreeMap<usize, &str> = BTreeMap::default();
+        for (tempid, &index) in &unresolved_temp_ids {
+            let part = partitions_for_indices.entry(index).or_insert(":db.part/user");
+            if let Some(named) = tempid_partitions.get(&**tempid) {
+                if *part != ":db.part/user" && *part != named {
+                    bail!(DbErrorKind::NotYetImplemented(format!(
+                        "tempid {} cannot be allocated in both {} and {}",
+                        tempid, part, named
+                    )));
+                }
+                *part = named.as_str();
+            }
+        }
+
+        let mut indices_by_partition: BTreeMap<&str, Vec<usize>> = BTreeMap::default();
+        for (index, part) in partitions_for_indices {
+            indices_by_partition
+                .entry(part)
+                .or_insert_with(Vec::new)
+                .push(index);
+        }
+
+        let mut entids_for_indices: BTreeMap<usize, Entid> = BTreeMap::default();
+        for (part, indices) in indices_by_partition {
+            let partition = self
+                .partition_map
+                .get(part)
+                .ok_or_else(|| DbErrorKind::UnknownPartition(part.to_string()))?;
+            if partition.next_entid() + (indices.len() as i64) - 1 > partition.end {
+                bail!(DbErrorKind::PartitionExhausted(part.to_string()));
+            }
+
+            let entids = self.partition_map.allocate_entids(part, indices.len());
+            entids_for_indices.extend(indices.into_iter().zip(entids));
+        }
 
         let temp_id_allocations = unresolved_temp_ids
             .into_iter()
-            .map(|(tempid, index)| (tempid, KnownEntid(entids.start + (index as i64))))
+            .map(|(tempid, index)| (tempid, KnownEntid(entids_for_indices[&index])))
             .collect();
 
         debug!("tempid allocations {:?}", temp_id_allocations);
@@ -833,6 +1063,7 @@ where
         let mut aev_trie = into_aev_trie(&self.schema, final_populations, inert_terms)?;
 
         let tx_instant;
+        let partition_installations;
         {
             // TODO: Don't use this block to scope borrowing the schema; instead, extract a helper function.
 
@@ -867,6 +1098,23 @@ where
                 ));
             }
 
+            // Excise before writing this transaction's datoms, so that the excision request
+            // itself survives in the log as an audit record of what was removed.
+            for excision in excision::excisions(&aev_trie) {
+                excision::ensure_excision_allowed(
+                    &self.partition_map,
+                    self.schema,
+                    excision.target,
+                )?;
+                let excised = excision::excise(self.store, self.schema, &excision)?;
+                excision::record_summary(&mut aev_trie, self.schema, &excision, &excised)?;
+                for (e, a, v) in excised.datoms {
+                    self.watcher.datom(OpType::Retract, e, a, &v);
+                }
+            }
+
+            partition_installations = partitions::installations(&aev_trie, self.schema)?;
+
             // Pipeline stage 4: final terms (after rewriting) -> DB insertions.
             // Collect into non_fts_*.
 
@@ -891,8 +1139,6 @@ where
                         .map(|v| (true, v))
                         .chain(ars.retract.into_iter().map(|v| (false, v)))
                     {
-                        let op = if added { OpType::Add } else { OpType::Retract };
-                        self.watcher.datom(op, e, a, &v);
                         queue.push((e, a, attribute, v, added));
                     }
                 }
@@ -927,6 +1173,14 @@ where
                     self.store.commit_mentat_transaction(self.tx_id)?;
                 }
             }
+
+            // Watchers see what the transaction did rather than what it asked for: assertions of
+            // datoms that are already present are dropped, and replacing a cardinality-one value
+            // retracts the old one.
+            for (e, a, v, added) in self.store.resolved_datoms()? {
+                let op = if added { OpType::Add } else { OpType::Retract };
+                self.watcher.datom(op, e, a, &v);
+            }
         }
 
         self.watcher.done(&self.tx_id, self.schema)?;
@@ -961,10 +1215,18 @@ where
             }
         }
 
+        for installation in partition_installations {
+            partitions::install(self.store, &mut self.partition_map, &installation)?;
+        }
+
         Ok(TxReport {
             tx_id: self.tx_id,
             tx_instant,
             tempids,
+            schema_changed: false,
+            tx_data: None,
+            schema_before: None,
+            schema_after: None,
         })
     }
 }
@@ -995,7 +1257,7 @@ where
 
 fn conclude_tx<W>(
     tx: Tx<W>,
-    report: TxReport,
+    mut report: TxReport,
 ) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
 where
     W: TransactWatcher,
@@ -1005,6 +1267,7 @@ where
         Cow::Borrowed(_) => None,
         Cow::Owned(next_schema) => Some(next_schema),
     };
+    report.schema_changed = next_schema.is_some();
     Ok((report, tx.partition_map, next_schema, tx.watcher))
 }
 
@@ -1074,7 +1337,12 @@ where
     W: TransactWatcher,
 {
     let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher)?;
-    let report = tx.transact_simple_terms_with_action(terms, tempid_set, action)?;
+    let report = tx.transact_simple_terms_with_action(
+        terms,
+        tempid_set,
+        TempIdPartitions::default(),
+        action,
+    )?;
     conclude_tx(tx, report)
 }
 
@@ -8,9 +8,15 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::collections::BTreeSet;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
 use std::sync::{Arc, Weak};
 
-use std::sync::mpsc::{channel, Receiver, RecvError, Sender};
+use std::sync::mpsc::{
+    channel, sync_channel, Receiver, RecvError, Sender, SyncSender, TrySendError,
+};
 
 use std::thread;
 
@@ -18,11 +24,12 @@ use indexmap::IndexMap;
 
 use core_traits::{Entid, TypedValue};
 
-use mentat_core::Schema;
+use mentat_core::{HasSchema, Schema, TxDatom};
 
 use edn::entities::OpType;
+use edn::query::{NonIntegerConstant, PatternNonValuePlace, PatternValuePlace, WhereClause};
 
-use db_traits::errors::Result;
+use db_traits::errors::{DbErrorKind, Result};
 
 use crate::types::AttributeSet;
 
@@ -60,23 +67,289 @@ impl TxObserver {
     }
 }
 
+/// One position of a `DatomPattern`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum DatomPlace {
+    /// `_`: matches anything.
+    Placeholder,
+    /// `?x`: matches anything, but every occurrence of the variable must match the same value.
+    /// Variables are kept by name, since `Variable` can't be shared between threads.
+    Variable(String),
+    /// A constant: matches any of the given values.  An integer in value position, for example,
+    /// matches both the `:db.type/long` and the `:db.type/ref` with that value.
+    Values(Vec<TypedValue>),
+}
+
+impl DatomPlace {
+    fn matches<'v>(
+        &'v self,
+        value: &'v TypedValue,
+        bindings: &mut Vec<(&'v str, &'v TypedValue)>,
+    ) -> bool {
+        match self {
+            DatomPlace::Placeholder => true,
+            DatomPlace::Values(values) => values.contains(value),
+            DatomPlace::Variable(var) => match bindings.iter().find(|(v, _)| *v == var.as_str()) {
+                Some((_, bound)) => *bound == value,
+                None => {
+                    bindings.push((var, value));
+                    true
+                }
+            },
+        }
+    }
+}
+
+/// A Datalog pattern, like `[?e :foo/bar "baz"]` or `[_ :foo/bar _ _ false]`, matched against
+/// individual transacted datoms.  Idents are resolved when the pattern is created, so a pattern
+/// naming vocabulary that doesn't exist yet can't be created.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DatomPattern {
+    e: DatomPlace,
+    a: DatomPlace,
+    v: DatomPlace,
+    tx: DatomPlace,
+    added: DatomPlace,
+}
+
+impl DatomPattern {
+    pub fn new(schema: &Schema, pattern: &str) -> Result<DatomPattern> {
+        let pattern = match edn::parse::pattern(pattern) {
+            Ok(WhereClause::Pattern(pattern)) => pattern,
+            Ok(_) => bail!(DbErrorKind::BadObserverPattern(pattern.to_string())),
+            Err(e) => bail!(DbErrorKind::BadObserverPattern(format!(
+                "{}: {}",
+                pattern, e
+            ))),
+        };
+
+        Ok(DatomPattern {
+            e: non_value_place(schema, pattern.entity)?,
+            a: non_value_place(schema, pattern.attribute)?,
+            v: value_place(schema, pattern.value)?,
+            tx: non_value_place(schema, pattern.tx)?,
+            added: value_place(schema, pattern.added)?,
+        })
+    }
+
+    pub fn matches(&self, tx_id: Entid, datom: &TxDatom) -> bool {
+        let e = TypedValue::Ref(datom.e);
+        let a = TypedValue::Ref(datom.a);
+        let tx = TypedValue::Ref(tx_id);
+        let added = TypedValue::Boolean(datom.added);
+
+        let mut bindings = Vec::new();
+        self.e.matches(&e, &mut bindings)
+            && self.a.matches(&a, &mut bindings)
+            && self.v.matches(&datom.v, &mut bindings)
+            && self.tx.matches(&tx, &mut bindings)
+            && self.added.matches(&added, &mut bindings)
+    }
+}
+
+fn non_value_place(schema: &Schema, place: PatternNonValuePlace) -> Result<DatomPlace> {
+    Ok(match place {
+        PatternNonValuePlace::Placeholder => DatomPlace::Placeholder,
+        PatternNonValuePlace::Variable(var) => DatomPlace::Variable(var.as_str().to_string()),
+        PatternNonValuePlace::Entid(e) => DatomPlace::Values(vec![TypedValue::Ref(e)]),
+        PatternNonValuePlace::Ident(ident) => match schema.get_entid(&ident) {
+            Some(e) => DatomPlace::Values(vec![TypedValue::Ref(e.into())]),
+            None => bail!(DbErrorKind::UnrecognizedIdent(ident.to_string())),
+        },
+    })
+}
+
+fn value_place(schema: &Schema, place: PatternValuePlace) -> Result<DatomPlace> {
+    Ok(match place {
+        PatternValuePlace::Placeholder => DatomPlace::Placeholder,
+        PatternValuePlace::Variable(var) => DatomPlace::Variable(var.as_str().to_string()),
+        PatternValuePlace::EntidOrInteger(i) => {
+            DatomPlace::Values(vec![TypedValue::Long(i), TypedValue::Ref(i)])
+        }
+        PatternValuePlace::IdentOrKeyword(ident) => {
+            let mut values = vec![TypedValue::Keyword(ident.clone())];
+            if let Some(e) = schema.get_entid(&ident) {
+                values.push(TypedValue::Ref(e.into()));
+            }
+            DatomPlace::Values(values)
+        }
+        PatternValuePlace::Constant(constant) => DatomPlace::Values(vec![match constant {
+            NonIntegerConstant::BigInteger(i) => bail!(DbErrorKind::BadObserverPattern(format!(
+                "big integers are not supported: {}",
+                i
+            ))),
+            NonIntegerConstant::Boolean(v) => TypedValue::Boolean(v),
+            NonIntegerConstant::Float(v) => TypedValue::Double(v),
+            NonIntegerConstant::Text(v) => v.into(),
+            NonIntegerConstant::Instant(v) => TypedValue::Instant(v),
+            NonIntegerConstant::Uuid(v) => TypedValue::Uuid(v),
+        }]),
+    })
+}
+
+/// Which transacted datoms a `DatomObserver` is interested in.  A datom must satisfy every
+/// constraint given: it must have one of the given entities, if any were given; one of the given
+/// attributes, if any were given; and match one of the given patterns, if any were given.  The
+/// default filter accepts every datom.
+#[derive(Clone, Debug, Default)]
+pub struct DatomFilter {
+    entities: BTreeSet<Entid>,
+    attributes: AttributeSet,
+    patterns: Vec<DatomPattern>,
+}
+
+impl DatomFilter {
+    pub fn new() -> DatomFilter {
+        DatomFilter::default()
+    }
+
+    pub fn entity(&mut self, e: Entid) -> &mut Self {
+        self.entities.insert(e);
+        self
+    }
+
+    pub fn attribute(&mut self, a: Entid) -> &mut Self {
+        self.attributes.insert(a);
+        self
+    }
+
+    pub fn pattern(&mut self, pattern: DatomPattern) -> &mut Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    pub fn matches(&self, tx_id: Entid, datom: &TxDatom) -> bool {
+        (self.entities.is_empty() || self.entities.contains(&datom.e))
+            && (self.attributes.is_empty() || self.attributes.contains(&datom.a))
+            && (self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(tx_id, datom)))
+    }
+}
+
+/// The datoms of one committed transaction that passed a `DatomObserver`'s filter.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ObservedTx {
+    pub tx_id: Entid,
+    pub datoms: Vec<TxDatom>,
+    /// How many transactions were dropped, because the channel was full, since the previous
+    /// `ObservedTx` was sent.  Always zero for an observer that waits for room.
+    pub missed: usize,
+}
+
+/// An observer that receives the datoms of committed transactions, as `ObservedTx` values, on a
+/// bounded channel.  Transactions with no datoms passing the filter aren't sent at all.
+///
+/// Delivery happens on the observation service's thread.  An observer made with `new` waits there
+/// for room in its channel, so the receiver sees every transaction, in order; while it waits,
+/// delivery to every other observer waits too, though transactions themselves never do.  An
+/// observer made with `dropping` never waits: a transaction that arrives while the channel is
+/// full is dropped, and counted in the `missed` of the next `ObservedTx` that fits.
+/// Dropping the receiver unregisters the observer.
+pub struct DatomObserver {
+    filter: DatomFilter,
+    sender: SyncSender<ObservedTx>,
+    blocking: bool,
+    disconnected: AtomicBool,
+    // Dropped since the last successful send.
+    missed: AtomicUsize,
+    // Dropped in total.
+    dropped: AtomicUsize,
+}
+
+impl DatomObserver {
+    /// Make an observer and the receiving end of its channel, which holds at most `capacity`
+    /// undelivered transactions.  Delivery waits for room in the channel.
+    pub fn new(filter: DatomFilter, capacity: usize) -> (DatomObserver, Receiver<ObservedTx>) {
+        DatomObserver::with_blocking(filter, capacity, true)
+    }
+
+    /// Like `new`, but transactions that arrive while the channel is full are dropped instead of
+    /// waited on.
+    pub fn dropping(filter: DatomFilter, capacity: usize) -> (DatomObserver, Receiver<ObservedTx>) {
+        DatomObserver::with_blocking(filter, capacity, false)
+    }
+
+    fn with_blocking(
+        filter: DatomFilter,
+        capacity: usize,
+        blocking: bool,
+    ) -> (DatomObserver, Receiver<ObservedTx>) {
+        let (sender, receiver) = sync_channel(capacity);
+        let observer = DatomObserver {
+            filter,
+            sender,
+            blocking,
+            disconnected: AtomicBool::new(false),
+            missed: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        };
+        (observer, receiver)
+    }
+
+    pub fn applicable_datoms(&self, tx_id: Entid, datoms: &[TxDatom]) -> Vec<TxDatom> {
+        datoms
+            .iter()
+            .filter(|datom| self.filter.matches(tx_id, datom))
+            .cloned()
+            .collect()
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Acquire)
+    }
+
+    /// How many transactions were dropped, in total, because the channel was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    fn notify(&self, tx_id: Entid, datoms: Vec<TxDatom>) {
+        // Only the observation thread sends, so the count can't change under us.
+        let observed = ObservedTx {
+            tx_id,
+            datoms,
+            missed: self.missed.load(Ordering::Acquire),
+        };
+        let sent = if self.blocking {
+            self.sender
+                .send(observed)
+                .map_err(|e| TrySendError::Disconnected(e.0))
+        } else {
+            self.sender.try_send(observed)
+        };
+        match sent {
+            Ok(()) => self.missed.store(0, Ordering::Release),
+            Err(TrySendError::Full(_)) => {
+                self.missed.fetch_add(1, Ordering::AcqRel);
+                self.dropped.fetch_add(1, Ordering::AcqRel);
+            }
+            Err(TrySendError::Disconnected(_)) => self.disconnected.store(true, Ordering::Release),
+        }
+    }
+}
+
 pub trait Command {
     fn execute(&mut self);
 }
 
 pub struct TxCommand {
     reports: IndexMap<Entid, AttributeSet>,
+    tx_datoms: IndexMap<Entid, Vec<TxDatom>>,
     observers: Weak<IndexMap<String, Arc<TxObserver>>>,
+    datom_observers: Weak<IndexMap<String, Arc<DatomObserver>>>,
 }
 
 impl TxCommand {
     fn new(
         observers: &Arc<IndexMap<String, Arc<TxObserver>>>,
+        datom_observers: &Arc<IndexMap<String, Arc<DatomObserver>>>,
         reports: IndexMap<Entid, AttributeSet>,
+        tx_datoms: IndexMap<Entid, Vec<TxDatom>>,
     ) -> Self {
         TxCommand {
             reports,
+            tx_datoms,
             observers: Arc::downgrade(observers),
+            datom_observers: Arc::downgrade(datom_observers),
         }
     }
 }
@@ -91,12 +364,27 @@ impl Command for TxCommand {
                 }
             }
         }
+
+        if let Some(datom_observers) = self.datom_observers.upgrade() {
+            for observer in datom_observers.values() {
+                for (tx_id, datoms) in self.tx_datoms.iter() {
+                    if observer.is_disconnected() {
+                        break;
+                    }
+                    let applicable_datoms = observer.applicable_datoms(*tx_id, datoms);
+                    if !applicable_datoms.is_empty() {
+                        observer.notify(*tx_id, applicable_datoms);
+                    }
+                }
+            }
+        }
     }
 }
 
 #[derive(Default)]
 pub struct TxObservationService {
     observers: Arc<IndexMap<String, Arc<TxObserver>>>,
+    datom_observers: Arc<IndexMap<String, Arc<DatomObserver>>>,
     executor: Option<Sender<Box<dyn Command + Send>>>,
 }
 
@@ -104,33 +392,72 @@ impl TxObservationService {
     pub fn new() -> Self {
         TxObservationService {
             observers: Arc::new(IndexMap::new()),
+            datom_observers: Arc::new(IndexMap::new()),
             executor: None,
         }
     }
 
     // For testing purposes
     pub fn is_registered(&self, key: &str) -> bool {
-        self.observers.contains_key(key)
+        self.observers.contains_key(key) || self.datom_observers.contains_key(key)
     }
 
+    /// Register `observer` as `key`, replacing any observer of either kind registered as `key`.
     pub fn register(&mut self, key: String, observer: Arc<TxObserver>) {
+        self.deregister(&key);
         Arc::make_mut(&mut self.observers).insert(key, observer);
     }
 
+    /// Register `observer` as `key`, replacing any observer of either kind registered as `key`.
+    pub fn register_datom_observer(&mut self, key: String, observer: Arc<DatomObserver>) {
+        self.deregister(&key);
+        Arc::make_mut(&mut self.datom_observers).insert(key, observer);
+    }
+
     pub fn deregister(&mut self, key: &str) {
-        Arc::make_mut(&mut self.observers).remove(key);
+        if self.observers.contains_key(key) {
+            Arc::make_mut(&mut self.observers).remove(key);
+        }
+        if self.datom_observers.contains_key(key) {
+            Arc::make_mut(&mut self.datom_observers).remove(key);
+        }
     }
 
     pub fn has_observers(&self) -> bool {
-        !self.observers.is_empty()
+        !self.observers.is_empty() || self.has_datom_observers()
     }
 
-    pub fn in_progress_did_commit(&mut self, txes: IndexMap<Entid, AttributeSet>) {
+    /// Whether transactions need to collect their datoms for `in_progress_did_commit`.
+    pub fn has_datom_observers(&self) -> bool {
+        !self.datom_observers.is_empty()
+    }
+
+    pub fn in_progress_did_commit(
+        &mut self,
+        txes: IndexMap<Entid, AttributeSet>,
+        tx_datoms: IndexMap<Entid, Vec<TxDatom>>,
+    ) {
+        // Forget observers whose receivers have gone away.
+        if self.datom_observers.values().any(|o| o.is_disconnected()) {
+            Arc::make_mut(&mut self.datom_observers).retain(|_, o| !o.is_disconnected());
+        }
+
         // Don't spawn a thread only to say nothing.
         if !self.has_observers() {
             return;
         }
 
+        let cmd = Box::new(TxCommand::new(
+            &self.observers,
+            &self.datom_observers,
+            txes,
+            tx_datoms,
+        ));
+        self.dispatch(cmd);
+    }
+
+    /// Execute `cmd` on the observation thread, after every command dispatched before it.
+    pub fn dispatch(&mut self, cmd: Box<dyn Command + Send>) {
         let executor = self.executor.get_or_insert_with(|| {
             #[allow(clippy::type_complexity)]
             let (tx, rx): (
@@ -146,7 +473,6 @@ impl TxObservationService {
             tx
         });
 
-        let cmd = Box::new(TxCommand::new(&self.observers, txes));
         executor.send(cmd).unwrap();
     }
 }
@@ -160,26 +486,49 @@ impl Drop for TxObservationService {
 #[derive(Default)]
 pub struct InProgressObserverTransactWatcher {
     collected_attributes: AttributeSet,
+    collected_datoms: Option<Vec<TxDatom>>,
     pub txes: IndexMap<Entid, AttributeSet>,
+    pub tx_datoms: IndexMap<Entid, Vec<TxDatom>>,
 }
 
 impl InProgressObserverTransactWatcher {
     pub fn new() -> InProgressObserverTransactWatcher {
         InProgressObserverTransactWatcher {
             collected_attributes: Default::default(),
+            collected_datoms: None,
             txes: Default::default(),
+            tx_datoms: Default::default(),
+        }
+    }
+
+    /// A watcher that also collects each transaction's datoms, for `DatomObserver`s.
+    pub fn collecting_datoms() -> InProgressObserverTransactWatcher {
+        InProgressObserverTransactWatcher {
+            collected_datoms: Some(Vec::new()),
+            ..InProgressObserverTransactWatcher::new()
         }
     }
 }
 
 impl TransactWatcher for InProgressObserverTransactWatcher {
-    fn datom(&mut self, _op: OpType, _e: Entid, a: Entid, _v: &TypedValue) {
+    fn datom(&mut self, op: OpType, e: Entid, a: Entid, v: &TypedValue) {
         self.collected_attributes.insert(a);
+        if let Some(ref mut datoms) = self.collected_datoms {
+            datoms.push(TxDatom {
+                e,
+                a,
+                v: v.clone(),
+                added: op == OpType::Add,
+            });
+        }
     }
 
     fn done(&mut self, t: &Entid, _schema: &Schema) -> Result<()> {
         let collected_attributes = ::std::mem::take(&mut self.collected_attributes);
         self.txes.insert(*t, collected_attributes);
+        if let Some(ref mut datoms) = self.collected_datoms {
+            self.tx_datoms.insert(*t, ::std::mem::take(datoms));
+        }
         Ok(())
     }
 }
@@ -17,9 +17,11 @@
 // - When observers are registered we want to flip some flags as writes occur so that we can
 //   notifying them outside the transaction.
 
+use std::sync::Arc;
+
 use core_traits::{Entid, TypedValue};
 
-use mentat_core::Schema;
+use mentat_core::{Schema, TxDatom, TxReport};
 
 use edn::entities::OpType;
 
@@ -44,3 +46,65 @@ impl TransactWatcher for NullWatcher {
         Ok(())
     }
 }
+
+/// Wraps another watcher, optionally recording the datoms of the transaction and the schema it was
+/// applied against, so that they can be included in its `TxReport` without reading them back.
+pub struct TxDataWatcher<W> {
+    inner: W,
+    recording: bool,
+    tx_data: Vec<TxDatom>,
+    schema_before: Option<Arc<Schema>>,
+}
+
+impl<W> TxDataWatcher<W>
+where
+    W: TransactWatcher,
+{
+    pub fn new(inner: W, recording: bool) -> TxDataWatcher<W> {
+        TxDataWatcher {
+            inner,
+            recording,
+            tx_data: vec![],
+            schema_before: None,
+        }
+    }
+
+    /// If recording, fill in the `tx_data` and schemas of the given report, where `next_schema` is
+    /// the schema returned by the transactor.  Returns the wrapped watcher.
+    pub fn complete(self, report: &mut TxReport, next_schema: Option<&Schema>) -> W {
+        if self.recording {
+            let schema_after = match (next_schema, &self.schema_before) {
+                (Some(next_schema), _) => Some(Arc::new(next_schema.clone())),
+                (None, schema_before) => schema_before.clone(),
+            };
+            report.tx_data = Some(self.tx_data);
+            report.schema_before = self.schema_before;
+            report.schema_after = schema_after;
+        }
+        self.inner
+    }
+}
+
+impl<W> TransactWatcher for TxDataWatcher<W>
+where
+    W: TransactWatcher,
+{
+    fn datom(&mut self, op: OpType, e: Entid, a: Entid, v: &TypedValue) {
+        if self.recording {
+            self.tx_data.push(TxDatom {
+                e,
+                a,
+                v: v.clone(),
+                added: op == OpType::Add,
+            });
+        }
+        self.inner.datom(op, e, a, v);
+    }
+
+    fn done(&mut self, t: &Entid, schema: &Schema) -> Result<()> {
+        if self.recording {
+            self.schema_before = Some(Arc::new(schema.clone()));
+        }
+        self.inner.done(t, schema)
+    }
+}
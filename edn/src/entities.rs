@@ -31,10 +31,15 @@ impl TransactableValueMarker for ValueAndSpan {}
 
 /// A tempid, either an external tempid given in a transaction (usually as an `Value::Text`),
 /// or an internal tempid allocated by Mentat itself.
+///
+/// A tempid can also name the partition its entid should be allocated in, like
+/// `(tempid :myapp.part/logs)` or `(tempid :myapp.part/logs "name")`.  The transactor rewrites
+/// these into external (when named) or internal (when anonymous) tempids.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
 pub enum TempId {
     External(String),
     Internal(i64),
+    InPartition(Keyword, Option<String>),
 }
 
 impl TempId {
@@ -42,6 +47,7 @@ impl TempId {
         match self {
             TempId::External(s) => Some(s),
             TempId::Internal(_) => None,
+            TempId::InPartition(_, s) => s,
         }
     }
 }
@@ -51,6 +57,8 @@ impl fmt::Display for TempId {
         match self {
             TempId::External(ref s) => write!(f, "{}", s),
             TempId::Internal(x) => write!(f, "<tempid {}>", x),
+            TempId::InPartition(ref part, None) => write!(f, "(tempid {})", part),
+            TempId::InPartition(ref part, Some(ref s)) => write!(f, "(tempid {} {:?})", part, s),
         }
     }
 }
@@ -238,4 +246,15 @@ pub enum Entity<V> {
     },
     // Like {:db/id "tempid" a1 v1 a2 v2}.
     MapNotation(MapNotation<V>),
+    // Like [:db/retractEntity e].
+    RetractEntity {
+        e: EntityPlace<V>,
+    },
+    // Like [:db/cas e a old new].  `old` is `None` (written `nil`) when `e` must have no `a`.
+    Cas {
+        e: EntityPlace<V>,
+        a: AttributePlace,
+        old: Option<ValuePlace<V>>,
+        new: ValuePlace<V>,
+    },
 }
@@ -183,7 +183,9 @@ peg::parser!(pub grammar parse() for str {
 
     rule symbol_namespace() = symbol_char_initial() symbol_char_subsequent()* (namespace_divider() symbol_char_subsequent()+)*
     rule symbol_name() = ( symbol_char_initial()+ symbol_char_subsequent()* )
-    rule plain_symbol_name() = symbol_name() / "..." / "."
+    // A symbol may begin with + or - so long as a digit doesn't follow; `/` alone is division.
+    rule function_name() = symbol_name() / sign() !digit() symbol_char_subsequent()* / "/"
+    rule plain_symbol_name() = function_name() / "..." / "."
 
     rule keyword_prefix() = ":"
 
@@ -288,6 +290,9 @@ peg::parser!(pub grammar parse() for str {
         = "(" __ "lookup-ref" __ a:(entid()) __ v:(value()) __ ")" { LookupRef { a: AttributePlace::Entid(a), v } }
         / expected!("lookup-ref")
 
+    rule tempid_in_partition() -> TempId
+        = "(" __ "tempid" __ part:raw_namespaced_keyword() __ name:raw_text()? __ ")" { TempId::InPartition(part, name) }
+
     rule tx_function() -> TxFunction
         = "(" __ n:$(symbol_name()) __ ")" { TxFunction { op: PlainSymbol::plain(n) } }
 
@@ -295,6 +300,7 @@ peg::parser!(pub grammar parse() for str {
         = v:raw_text() { EntityPlace::TempId(TempId::External(v).into()) }
         / v:entid() { EntityPlace::Entid(v) }
         / v:lookup_ref() { EntityPlace::LookupRef(v) }
+        / v:tempid_in_partition() { EntityPlace::TempId(v.into()) }
         / v:tx_function() { EntityPlace::TxFunction(v) }
 
     rule value_place_pair() -> (EntidOrIdent, ValuePlace<ValueAndSpan>)
@@ -305,13 +311,24 @@ peg::parser!(pub grammar parse() for str {
 
     rule value_place() -> ValuePlace<ValueAndSpan>
         = __ v:lookup_ref() __ { ValuePlace::LookupRef(v) }
+        / __ v:tempid_in_partition() __ { ValuePlace::TempId(v.into()) }
         / __ v:tx_function() __ { ValuePlace::TxFunction(v) }
         / __ "[" __ vs:(value_place()*) __ "]" __ { ValuePlace::Vector(vs) }
         / __ v:map_notation() __ { ValuePlace::MapNotation(v) }
         / __ v:atom() __ { ValuePlace::Atom(v) }
 
+    rule cas_old_value() -> Option<ValuePlace<ValueAndSpan>>
+        = v:value_place() {
+            match v {
+                ValuePlace::Atom(ValueAndSpan { inner: SpannedValue::Nil, .. }) => None,
+                v => Some(v),
+            }
+        }
+
     pub rule entity() -> Entity<ValueAndSpan>
-        = __ "[" __ op:(op()) __ e:(entity_place()) __ a:(forward_entid())  __ v:(value_place()) __  "]" __ { Entity::AddOrRetract { op, e, a: AttributePlace::Entid(a), v } }
+        = __ "[" __ ":db/retractEntity" __ e:(entity_place()) __ "]" __ { Entity::RetractEntity { e } }
+        / __ "[" __ ":db/cas" __ e:(entity_place()) __ a:(forward_entid()) __ old:(cas_old_value()) __ new:(value_place()) __ "]" __ { Entity::Cas { e, a: AttributePlace::Entid(a), old, new } }
+        / __ "[" __ op:(op()) __ e:(entity_place()) __ a:(forward_entid())  __ v:(value_place()) __  "]" __ { Entity::AddOrRetract { op, e, a: AttributePlace::Entid(a), v } }
         / __ "[" __ op:(op()) __ e:(value_place())  __ a:(backward_entid()) __ v:(entity_place()) __ "]" __ { Entity::AddOrRetract { op, e: v, a: AttributePlace::Entid(a), v: e } }
         / __ map:map_notation() __ { Entity::MapNotation(map) }
         / expected!("entity")
@@ -327,7 +344,7 @@ peg::parser!(pub grammar parse() for str {
     // whitespace-eating rule or an explicit whitespace eating `__`.
 
     rule query_function() -> query::QueryFunction
-        = __ n:$(symbol_name()) __ {? query::QueryFunction::from_symbol(&PlainSymbol::plain(n)).ok_or("expected query function") }
+        = __ n:$(function_name()) __ {? query::QueryFunction::from_symbol(&PlainSymbol::plain(n)).ok_or("expected query function") }
 
     rule fn_arg() -> query::FnArg
         = v:value() {? query::FnArg::from_value(&v).ok_or("expected query function argument") }
@@ -347,14 +364,50 @@ peg::parser!(pub grammar parse() for str {
 
     rule pull_attribute() -> query::PullAttributeSpec
         = __ "*" __ { query::PullAttributeSpec::Wildcard }
-        / __ k:raw_forward_namespaced_keyword() __ alias:(":as" __ alias:raw_forward_keyword() __ { alias })? {
-            let attribute = query::PullConcreteAttribute::Ident(::std::rc::Rc::new(k));
-            let alias = alias.map(::std::rc::Rc::new);
-            query::PullAttributeSpec::Attribute(
-                query::NamedPullAttribute {
-                    attribute,
-                    alias,
-                })
+        / __ "{" entries:pull_map_entry()+ "}" __ { query::PullAttributeSpec::PullMapSpec(entries) }
+        / pull_attribute_expr()
+
+    // An attribute, possibly reversed, with options: `:person/_friend :as :friended-by`,
+    // `[:person/friend :limit 5]`, or `[:person/age :default 0]`.
+    rule pull_attribute_expr() -> query::PullAttributeSpec
+        = __ "[" a:named_pull_attribute() ":limit" __ "nil" __ "]" __ { query::PullAttributeSpec::Attribute(a) }
+        / __ "[" a:named_pull_attribute() ":limit" __ n:raw_integer() __ "]" __ {?
+            if n > 0 {
+                Ok(query::PullAttributeSpec::LimitedAttribute(a, n as u64))
+            } else {
+                Err("expected positive integer")
+            }
+        }
+        / __ "[" a:named_pull_attribute() ":default" v:value() "]" __ {?
+            query::PullDefaultValue::from_value(&v)
+                .map(|d| query::PullAttributeSpec::DefaultedAttribute(a, d))
+                .ok_or("expected pull default value")
+        }
+        / __ "[" a:named_pull_attribute() "]" __ { query::PullAttributeSpec::Attribute(a) }
+        / a:named_pull_attribute() { query::PullAttributeSpec::Attribute(a) }
+
+    rule named_pull_attribute() -> query::NamedPullAttribute
+        = __ k:raw_namespaced_keyword() __ alias:(":as" __ alias:raw_forward_keyword() __ { alias })? {
+            let attribute = query::PullConcreteAttribute::Ident(ValueRc::new(k));
+            let alias = alias.map(ValueRc::new);
+            query::NamedPullAttribute {
+                attribute,
+                alias,
+            }
+        }
+
+    rule pull_map_entry() -> (query::PullAttributeSpec, query::PullMapValue)
+        = k:pull_attribute_expr() v:pull_map_value() { (k, v) }
+
+    rule pull_map_value() -> query::PullMapValue
+        = __ "[" patterns:pull_attribute()+ "]" __ { query::PullMapValue::Pattern(patterns) }
+        / __ "..." __ { query::PullMapValue::Recursion(None) }
+        / __ n:raw_integer() __ {?
+            if n > 0 {
+                Ok(query::PullMapValue::Recursion(Some(n as u64)))
+            } else {
+                Err("expected positive integer")
+            }
         }
 
     rule limit() -> query::Limit
@@ -379,17 +432,19 @@ peg::parser!(pub grammar parse() for str {
     rule pattern_non_value_place() -> query::PatternNonValuePlace
         = v:value() {? query::PatternNonValuePlace::from_value(&v).ok_or("expected pattern_non_value_place") }
 
-    rule pattern() -> query::WhereClause
+    pub rule pattern() -> query::WhereClause
         = __ "["
           src:src_var()?
           e:pattern_non_value_place()
           a:pattern_non_value_place()
           v:pattern_value_place()?
           tx:pattern_non_value_place()?
+          added:pattern_value_place()?
         "]" __
         {?
             let v = v.unwrap_or(query::PatternValuePlace::Placeholder);
             let tx = tx.unwrap_or(query::PatternNonValuePlace::Placeholder);
+            let added = added.unwrap_or(query::PatternValuePlace::Placeholder);
 
             // Pattern::new takes care of reversal of reversed
             // attributes: [?x :foo/_bar ?y] turns into
@@ -410,7 +465,10 @@ peg::parser!(pub grammar parse() for str {
             //
             // is nonsense. That leaves us with a nested optional, which we unwrap here.
             query::Pattern::new(src, e, a, v, tx)
-                .map(query::WhereClause::Pattern)
+                .map(|mut p| {
+                    p.added = added;
+                    query::WhereClause::Pattern(p)
+                })
                 .ok_or("expected pattern")
         }
 
@@ -484,6 +542,21 @@ peg::parser!(pub grammar parse() for str {
                 })
         }
 
+    rule rule_name() -> PlainSymbol
+        = __ n:$(symbol_name()) __ {?
+            let name = PlainSymbol::plain(n);
+            match n {
+                "and" | "or" | "or-join" | "not" | "not-join" => Err("expected rule name"),
+                _ if name.is_var_symbol() || name.is_src_symbol() => Err("expected rule name"),
+                _ => Ok(name),
+            }
+        }
+
+    rule rule_expr() -> query::WhereClause
+        = __ "(" name:rule_name() args:pattern_value_place()+ ")" __ {
+            query::WhereClause::RuleExpr(query::RuleExpr { name, args })
+        }
+
     rule where_clause() -> query::WhereClause
         // Right now we only support patterns and predicates. See #239 for more.
         = pattern()
@@ -494,10 +567,30 @@ peg::parser!(pub grammar parse() for str {
         / type_annotation()
         / pred()
         / where_fn()
+        / rule_expr()
+
+    rule rule_definition() -> query::Rule
+        = __ "[" __ "(" name:rule_name() vars:variable()+ ")" __ clauses:where_clause()+ "]" __ {?
+            let given = vars.len();
+            let unique: BTreeSet<&query::Variable> = vars.iter().collect();
+            if given != unique.len() {
+                Err("expected unique variables")
+            } else {
+                Ok(query::Rule { name, vars, clauses })
+            }
+        }
+
+    pub rule parse_rules() -> Vec<query::Rule>
+        = __ "[" rules:rule_definition()+ "]" __ { rules }
+
+    rule in_part() -> query::InPart
+        = v:variable() { query::InPart::Variable(v) }
+        / s:src_var() { query::InPart::Source(s) }
+        / __ "%" __ { query::InPart::Rules }
 
     rule query_part() -> query::QueryPart
         = __ ":find" fs:find_spec() { query::QueryPart::FindSpec(fs) }
-        / __ ":in" in_vars:variable()+ { query::QueryPart::InVars(in_vars) }
+        / __ ":in" in_parts:in_part()+ { query::QueryPart::InVars(in_parts) }
         / __ ":limit" l:limit() { query::QueryPart::Limit(l) }
         / __ ":order" os:order()+ { query::QueryPart::Order(os) }
         / __ ":where" ws:where_clause()+ { query::QueryPart::WhereClauses(ws) }
@@ -94,7 +94,11 @@ impl Value {
             Value::NamespacedSymbol(ref v) => pp.text(v.namespace()).append("/").append(v.name()),
             Value::PlainSymbol(ref v) => pp.text(v.to_string()),
             Value::Keyword(ref v) => pp.text(v.to_string()),
-            Value::Text(ref v) => pp.text("\"").append(v.as_str()).append("\""),
+            // Escape what the parser unescapes, so that the output reads back in.
+            Value::Text(ref v) => pp
+                .text("\"")
+                .append(v.replace('\\', "\\\\").replace('"', "\\\""))
+                .append("\""),
             Value::Uuid(ref u) => pp
                 .text("#uuid \"")
                 .append(u.to_hyphenated().to_string())
@@ -128,6 +132,16 @@ mod test {
         assert_eq!(data.to_pretty(40).unwrap(), "[[] () #{} {} \"\"]");
     }
 
+    #[test]
+    fn test_pp_text_escapes() {
+        let string = r#"["say \"hi\"" "C:\\"]"#;
+        let data = parse::value(string).unwrap().without_spans();
+
+        let pretty = data.to_pretty(40).unwrap();
+        assert_eq!(pretty, string);
+        assert_eq!(parse::value(&pretty).unwrap().without_spans(), data);
+    }
+
     #[test]
     fn test_vector() {
         let string = "[1 2 3 4 5 6]";
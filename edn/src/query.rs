@@ -44,7 +44,7 @@ pub use crate::{Keyword, PlainSymbol};
 pub type SrcVarName = String; // Do not include the required syntactic '$'.
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub struct Variable(pub Rc<PlainSymbol>);
+pub struct Variable(pub ValueRc<PlainSymbol>);
 
 impl Variable {
     pub fn as_str(&self) -> &str {
@@ -59,7 +59,7 @@ impl Variable {
     pub fn from_valid_name(name: &str) -> Variable {
         let s = PlainSymbol::plain(name);
         assert!(s.is_var_symbol());
-        Variable(Rc::new(s))
+        Variable(ValueRc::new(s))
     }
 }
 
@@ -83,7 +83,7 @@ impl FromValue<Variable> for Variable {
 impl Variable {
     pub fn from_rc(sym: Rc<PlainSymbol>) -> Option<Variable> {
         if sym.is_var_symbol() {
-            Some(Variable(sym))
+            Some(Variable(ValueRc::from_rc(sym)))
         } else {
             None
         }
@@ -92,7 +92,7 @@ impl Variable {
     /// TODO: intern strings. #398.
     pub fn from_symbol(sym: &PlainSymbol) -> Option<Variable> {
         if sym.is_var_symbol() {
-            Some(Variable(Rc::new(sym.clone())))
+            Some(Variable(ValueRc::new(sym.clone())))
         } else {
             None
         }
@@ -163,6 +163,15 @@ impl FromValue<SrcVar> for SrcVar {
     }
 }
 
+impl fmt::Display for SrcVar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SrcVar::DefaultSrc => write!(f, "$"),
+            SrcVar::NamedSrc(ref name) => write!(f, "${}", name),
+        }
+    }
+}
+
 impl SrcVar {
     pub fn from_symbol(sym: &PlainSymbol) -> Option<SrcVar> {
         if sym.is_src_symbol() {
@@ -207,6 +216,9 @@ pub enum FnArg {
     EntidOrInteger(i64),
     IdentOrKeyword(Keyword),
     Constant(NonIntegerConstant),
+    // Like `_`: any value will do.  Only some functions accept this, like `fulltext`, where it
+    // stands for any fulltext attribute.
+    Placeholder,
     // The collection values representable in EDN.  There's no advantage to destructuring up front,
     // since consumers will need to handle arbitrarily nested EDN themselves anyway.
     Vector(Vec<FnArg>),
@@ -221,6 +233,7 @@ impl FromValue<FnArg> for FnArg {
             PlainSymbol(ref x) if x.is_var_symbol() => {
                 Variable::from_symbol(x).map(FnArg::Variable)
             }
+            PlainSymbol(ref x) if x.0 == "_" => Some(FnArg::Placeholder),
             PlainSymbol(_) => None,
             Keyword(ref x) => Some(FnArg::IdentOrKeyword(x.clone())),
             Instant(x) => Some(FnArg::Constant(NonIntegerConstant::Instant(x))),
@@ -253,6 +266,7 @@ impl std::fmt::Display for FnArg {
             FnArg::EntidOrInteger(entid) => write!(f, "{}", entid),
             FnArg::IdentOrKeyword(ref kw) => write!(f, "{}", kw),
             FnArg::Constant(ref constant) => write!(f, "{:?}", constant),
+            FnArg::Placeholder => write!(f, "_"),
             FnArg::Vector(ref vec) => write!(f, "{:?}", vec),
         }
     }
@@ -452,23 +466,42 @@ impl PatternValuePlace {
     }
 }
 
-// Not yet used.
-// pub enum PullDefaultValue {
-//     EntidOrInteger(i64),
-//     IdentOrKeyword(Rc<Keyword>),
-//     Constant(NonIntegerConstant),
-// }
+/// The value to use for an attribute that an entity doesn't have: `[:person/age :default 0]`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PullDefaultValue {
+    EntidOrInteger(i64),
+    IdentOrKeyword(ValueRc<Keyword>),
+    Constant(NonIntegerConstant),
+}
+
+impl FromValue<PullDefaultValue> for PullDefaultValue {
+    fn from_value(v: &crate::ValueAndSpan) -> Option<PullDefaultValue> {
+        match v.inner {
+            // Unlike a pattern, a default can be any keyword at all.
+            crate::SpannedValue::Keyword(ref x) => {
+                Some(PullDefaultValue::IdentOrKeyword(ValueRc::new(x.clone())))
+            }
+            _ => match PatternValuePlace::from_value(v)? {
+                PatternValuePlace::EntidOrInteger(x) => Some(PullDefaultValue::EntidOrInteger(x)),
+                PatternValuePlace::Constant(x) => Some(PullDefaultValue::Constant(x)),
+                PatternValuePlace::Placeholder
+                | PatternValuePlace::Variable(_)
+                | PatternValuePlace::IdentOrKeyword(_) => None,
+            },
+        }
+    }
+}
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum PullConcreteAttribute {
-    Ident(Rc<Keyword>),
+    Ident(ValueRc<Keyword>),
     Entid(i64),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct NamedPullAttribute {
     pub attribute: PullConcreteAttribute,
-    pub alias: Option<Rc<Keyword>>,
+    pub alias: Option<ValueRc<Keyword>>,
 }
 
 impl From<PullConcreteAttribute> for NamedPullAttribute {
@@ -480,13 +513,25 @@ impl From<PullConcreteAttribute> for NamedPullAttribute {
     }
 }
 
+/// What to pull from the entities that an attribute in a map specification refers to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PullMapValue {
+    /// `{:person/friend [:person/name]}`.
+    Pattern(Vec<PullAttributeSpec>),
+
+    /// `{:person/friend ...}` or `{:person/friend 2}`: apply the enclosing pattern again, following
+    /// the attribute at most this many times. `None` follows it until we run out of entities.
+    Recursion(Option<u64>),
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum PullAttributeSpec {
     Wildcard,
     Attribute(NamedPullAttribute),
-    // PullMapSpec(Vec<…>),
-    // LimitedAttribute(NamedPullAttribute, u64),  // Limit nil => Attribute instead.
-    // DefaultedAttribute(NamedPullAttribute, PullDefaultValue),
+    /// Each key is an `Attribute`, `LimitedAttribute`, or `DefaultedAttribute`.
+    PullMapSpec(Vec<(PullAttributeSpec, PullMapValue)>),
+    LimitedAttribute(NamedPullAttribute, u64), // Limit nil => Attribute instead.
+    DefaultedAttribute(NamedPullAttribute, PullDefaultValue),
 }
 
 impl std::fmt::Display for PullConcreteAttribute {
@@ -508,11 +553,66 @@ impl std::fmt::Display for NamedPullAttribute {
     }
 }
 
+impl std::fmt::Display for PullDefaultValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PullDefaultValue::EntidOrInteger(i) => write!(f, "{}", i),
+            PullDefaultValue::IdentOrKeyword(ref k) => write!(f, "{}", k),
+            PullDefaultValue::Constant(ref c) => {
+                let value = match c.clone() {
+                    NonIntegerConstant::Boolean(v) => crate::Value::Boolean(v),
+                    NonIntegerConstant::BigInteger(v) => crate::Value::BigInteger(v),
+                    NonIntegerConstant::Float(v) => crate::Value::Float(v),
+                    NonIntegerConstant::Text(v) => crate::Value::Text(v.to_string()),
+                    NonIntegerConstant::Instant(v) => crate::Value::Instant(v),
+                    NonIntegerConstant::Uuid(v) => crate::Value::Uuid(v),
+                };
+                write!(f, "{}", value)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PullMapValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PullMapValue::Pattern(ref patterns) => {
+                write!(f, "[")?;
+                for (i, p) in patterns.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", p)?;
+                }
+                write!(f, "]")
+            }
+            PullMapValue::Recursion(None) => write!(f, "..."),
+            PullMapValue::Recursion(Some(n)) => write!(f, "{}", n),
+        }
+    }
+}
+
 impl std::fmt::Display for PullAttributeSpec {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             PullAttributeSpec::Wildcard => write!(f, "*"),
             PullAttributeSpec::Attribute(ref attr) => write!(f, "{}", attr),
+            PullAttributeSpec::PullMapSpec(ref entries) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{} {}", k, v)?;
+                }
+                write!(f, "}}")
+            }
+            PullAttributeSpec::LimitedAttribute(ref attr, limit) => {
+                write!(f, "[{} :limit {}]", attr, limit)
+            }
+            PullAttributeSpec::DefaultedAttribute(ref attr, ref default) => {
+                write!(f, "[{} :default {}]", attr, default)
+            }
         }
     }
 }
@@ -790,6 +890,9 @@ pub struct Pattern {
     pub attribute: PatternNonValuePlace,
     pub value: PatternValuePlace,
     pub tx: PatternNonValuePlace,
+    /// Whether the datom was asserted or retracted: `[?e ?a ?v ?tx ?added]`. Only history
+    /// sources contain retractions.
+    pub added: PatternValuePlace,
 }
 
 impl Pattern {
@@ -822,6 +925,7 @@ impl Pattern {
                         attribute: k.to_reversed().into(),
                         value: e_v,
                         tx,
+                        added: PatternValuePlace::Placeholder,
                     });
                 } else {
                     return None;
@@ -834,6 +938,7 @@ impl Pattern {
             attribute: a,
             value: v,
             tx,
+            added: PatternValuePlace::Placeholder,
         })
     }
 }
@@ -932,6 +1037,25 @@ pub struct TypeAnnotation {
     pub variable: Variable,
 }
 
+/// An invocation of a rule: `(ancestor ?x ?y)`. Arguments are matched positionally against the
+/// head of each of the rule's definitions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RuleExpr {
+    pub name: PlainSymbol,
+    pub args: Vec<PatternValuePlace>,
+}
+
+/// A single rule definition, `[(ancestor ?a ?b) [?a :foo/parent ?b]]`.
+///
+/// A rule may have several definitions sharing the same name and arity; an invocation of the rule
+/// matches if any of them do. A definition can invoke itself to express a recursive relation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    pub name: PlainSymbol,
+    pub vars: Vec<Variable>,
+    pub clauses: Vec<WhereClause>,
+}
+
 #[allow(dead_code)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum WhereClause {
@@ -939,7 +1063,7 @@ pub enum WhereClause {
     OrJoin(OrJoin),
     Pred(Predicate),
     WhereFn(WhereFn),
-    RuleExpr,
+    RuleExpr(RuleExpr),
     Pattern(Pattern),
     TypeAnnotation(TypeAnnotation),
 }
@@ -952,15 +1076,24 @@ pub struct ParsedQuery {
     pub with: Vec<Variable>,
     pub in_vars: Vec<Variable>,
     pub in_sources: BTreeSet<SrcVar>,
+    /// True if the query declares a rule set, `%`, in its `:in` clause.
+    pub in_rules: bool,
     pub limit: Limit,
     pub where_clauses: Vec<WhereClause>,
     pub order: Option<Vec<Order>>,
 }
 
+/// One of the inputs named in a query's `:in` clause.
+pub(crate) enum InPart {
+    Variable(Variable),
+    Source(SrcVar),
+    Rules,
+}
+
 pub(crate) enum QueryPart {
     FindSpec(FindSpec),
     WithVars(Vec<Variable>),
-    InVars(Vec<Variable>),
+    InVars(Vec<InPart>),
     Limit(Limit),
     WhereClauses(Vec<WhereClause>),
     Order(Vec<Order>),
@@ -978,7 +1111,7 @@ impl ParsedQuery {
     ) -> std::result::Result<ParsedQuery, &'static str> {
         let mut find_spec: Option<FindSpec> = None;
         let mut with: Option<Vec<Variable>> = None;
-        let mut in_vars: Option<Vec<Variable>> = None;
+        let mut in_parts: Option<Vec<InPart>> = None;
         let mut limit: Option<Limit> = None;
         let mut where_clauses: Option<Vec<WhereClause>> = None;
         let mut order: Option<Vec<Order>> = None;
@@ -998,10 +1131,10 @@ impl ParsedQuery {
                     with = Some(x)
                 }
                 QueryPart::InVars(x) => {
-                    if in_vars.is_some() {
+                    if in_parts.is_some() {
                         return Err("find query has repeated :in");
                     }
-                    in_vars = Some(x)
+                    in_parts = Some(x)
                 }
                 QueryPart::Limit(x) => {
                     if limit.is_some() {
@@ -1024,12 +1157,33 @@ impl ParsedQuery {
             }
         }
 
+        let mut in_vars: Vec<Variable> = Vec::new();
+        let mut in_sources: BTreeSet<SrcVar> = BTreeSet::default();
+        let mut in_rules = false;
+        for part in in_parts.unwrap_or_else(Vec::new) {
+            match part {
+                InPart::Variable(v) => in_vars.push(v),
+                InPart::Source(s) => {
+                    if !in_sources.insert(s) {
+                        return Err("find query has repeated source in :in");
+                    }
+                }
+                InPart::Rules => {
+                    if in_rules {
+                        return Err("find query has repeated % in :in");
+                    }
+                    in_rules = true;
+                }
+            }
+        }
+
         Ok(ParsedQuery {
             find_spec: find_spec.ok_or("expected :find")?,
             default_source: SrcVar::DefaultSrc,
             with: with.unwrap_or_else(Vec::new), //
-            in_vars: in_vars.unwrap_or_else(Vec::new),
-            in_sources: BTreeSet::default(),
+            in_vars,
+            in_sources,
+            in_rules,
             limit: limit.unwrap_or(Limit::None),
             where_clauses: where_clauses.ok_or("expected :where")?,
             order,
@@ -1086,7 +1240,7 @@ impl ContainsVariables for WhereClause {
             NotJoin(ref n) => n.accumulate_mentioned_variables(acc),
             WhereFn(ref f) => f.accumulate_mentioned_variables(acc),
             TypeAnnotation(ref a) => a.accumulate_mentioned_variables(acc),
-            RuleExpr => (),
+            RuleExpr(ref r) => r.accumulate_mentioned_variables(acc),
         }
     }
 }
@@ -1154,6 +1308,16 @@ impl ContainsVariables for Predicate {
     }
 }
 
+impl ContainsVariables for RuleExpr {
+    fn accumulate_mentioned_variables(&self, acc: &mut BTreeSet<Variable>) {
+        for arg in &self.args {
+            if let PatternValuePlace::Variable(ref v) = *arg {
+                acc_ref(acc, v)
+            }
+        }
+    }
+}
+
 impl ContainsVariables for TypeAnnotation {
     fn accumulate_mentioned_variables(&self, acc: &mut BTreeSet<Variable>) {
         acc_ref(acc, &self.variable);
@@ -1207,5 +1371,8 @@ impl ContainsVariables for Pattern {
         if let PatternNonValuePlace::Variable(ref v) = self.tx {
             acc_ref(acc, v)
         }
+        if let PatternValuePlace::Variable(ref v) = self.added {
+            acc_ref(acc, v)
+        }
     }
 }
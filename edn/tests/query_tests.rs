@@ -13,11 +13,13 @@ extern crate edn;
 use edn::{Keyword, PlainSymbol};
 
 use edn::query::{
-    Direction, Element, FindSpec, FnArg, Limit, NonIntegerConstant, OrJoin, OrWhereClause, Order,
-    Pattern, PatternNonValuePlace, PatternValuePlace, Predicate, UnifyVars, Variable, WhereClause,
+    Direction, Element, FindSpec, FnArg, Limit, NamedPullAttribute, NonIntegerConstant, OrJoin,
+    OrWhereClause, Order, Pattern, PatternNonValuePlace, PatternValuePlace, Predicate, Pull,
+    PullAttributeSpec, PullConcreteAttribute, PullDefaultValue, PullMapValue, Rule, RuleExpr,
+    SrcVar, UnifyVars, Variable, WhereClause,
 };
 
-use edn::parse::parse_query;
+use edn::parse::{parse_query, parse_rules};
 
 ///! N.B., parsing a query can be done without reference to a DB.
 ///! Processing the parsed query into something we can work with
@@ -42,6 +44,7 @@ fn can_parse_predicates() {
                 attribute: PatternNonValuePlace::Placeholder,
                 value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             }),
             WhereClause::Pred(Predicate {
                 operator: PlainSymbol::plain("<"),
@@ -74,6 +77,7 @@ fn can_parse_simple_or() {
                     attribute: PatternNonValuePlace::Placeholder,
                     value: PatternValuePlace::EntidOrInteger(10),
                     tx: PatternNonValuePlace::Placeholder,
+                    added: PatternValuePlace::Placeholder,
                 })),
                 OrWhereClause::Clause(WhereClause::Pattern(Pattern {
                     source: None,
@@ -81,6 +85,7 @@ fn can_parse_simple_or() {
                     attribute: PatternNonValuePlace::Placeholder,
                     value: PatternValuePlace::EntidOrInteger(15),
                     tx: PatternNonValuePlace::Placeholder,
+                    added: PatternValuePlace::Placeholder,
                 })),
             ],
         )),]
@@ -106,6 +111,7 @@ fn can_parse_unit_or_join() {
                 attribute: PatternNonValuePlace::Placeholder,
                 value: PatternValuePlace::EntidOrInteger(15),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             })),],
         )),]
     );
@@ -131,6 +137,7 @@ fn can_parse_simple_or_join() {
                     attribute: PatternNonValuePlace::Placeholder,
                     value: PatternValuePlace::EntidOrInteger(10),
                     tx: PatternNonValuePlace::Placeholder,
+                    added: PatternValuePlace::Placeholder,
                 })),
                 OrWhereClause::Clause(WhereClause::Pattern(Pattern {
                     source: None,
@@ -138,6 +145,7 @@ fn can_parse_simple_or_join() {
                     attribute: PatternNonValuePlace::Placeholder,
                     value: PatternValuePlace::EntidOrInteger(-15),
                     tx: PatternNonValuePlace::Placeholder,
+                    added: PatternValuePlace::Placeholder,
                 })),
             ],
         )),]
@@ -169,6 +177,7 @@ fn can_parse_simple_or_and_join() {
                     attribute: PatternNonValuePlace::Placeholder,
                     value: PatternValuePlace::EntidOrInteger(10),
                     tx: PatternNonValuePlace::Placeholder,
+                    added: PatternValuePlace::Placeholder,
                 })),
                 OrWhereClause::And(vec![
                     WhereClause::OrJoin(OrJoin::new(
@@ -182,6 +191,7 @@ fn can_parse_simple_or_and_join() {
                                 attribute: ident("foo", "bar"),
                                 value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             })),
                             OrWhereClause::Clause(WhereClause::Pattern(Pattern {
                                 source: None,
@@ -191,6 +201,7 @@ fn can_parse_simple_or_and_join() {
                                 attribute: ident("foo", "baz"),
                                 value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             })),
                         ],
                     )),
@@ -332,3 +343,185 @@ fn can_parse_exotic_whitespace() {
         )
     );
 }
+
+#[test]
+fn can_parse_in_sources_and_rules() {
+    let s = "[:find ?x :in $ % ?y :where [?x :foo/bar ?y]]";
+    let p = parse_query(s).expect("parsed");
+    assert_eq!(p.in_vars, vec![Variable::from_valid_name("?y")]);
+    assert_eq!(
+        p.in_sources.into_iter().collect::<Vec<_>>(),
+        vec![SrcVar::DefaultSrc]
+    );
+    assert!(p.in_rules);
+
+    assert!(
+        !parse_query("[:find ?x :in ?y :where [?x :foo/bar ?y]]")
+            .expect("parsed")
+            .in_rules
+    );
+
+    assert!(parse_query("[:find ?x :in % % :where [?x :foo/bar ?y]]").is_err());
+    assert!(parse_query("[:find ?x :in $ $ :where [?x :foo/bar ?y]]").is_err());
+}
+
+#[test]
+fn can_parse_history_pattern() {
+    let s = "[:find ?v ?added :in $history :where [$history ?x :foo/bar ?v ?tx ?added]]";
+    let p = parse_query(s).expect("parsed");
+    assert_eq!(
+        p.in_sources.into_iter().collect::<Vec<_>>(),
+        vec![SrcVar::NamedSrc("history".to_string())]
+    );
+    assert_eq!(
+        p.where_clauses,
+        vec![WhereClause::Pattern(Pattern {
+            source: Some(SrcVar::NamedSrc("history".to_string())),
+            entity: PatternNonValuePlace::Variable(Variable::from_valid_name("?x")),
+            attribute: PatternNonValuePlace::Ident(Keyword::namespaced("foo", "bar").into()),
+            value: PatternValuePlace::Variable(Variable::from_valid_name("?v")),
+            tx: PatternNonValuePlace::Variable(Variable::from_valid_name("?tx")),
+            added: PatternValuePlace::Variable(Variable::from_valid_name("?added")),
+        })]
+    );
+
+    assert!(parse_query("[:find ?x :where [?x :foo/bar ?v ?tx ?added ?extra]]").is_err());
+}
+
+#[test]
+fn can_parse_rule_expr() {
+    let s = r#"[:find ?x :in % :where (ancestor ?x _ :foo/bar "baz")]"#;
+    let p = parse_query(s).expect("parsed");
+    assert_eq!(
+        p.where_clauses,
+        vec![WhereClause::RuleExpr(RuleExpr {
+            name: PlainSymbol::plain("ancestor"),
+            args: vec![
+                PatternValuePlace::Variable(Variable::from_valid_name("?x")),
+                PatternValuePlace::Placeholder,
+                Keyword::namespaced("foo", "bar").into(),
+                PatternValuePlace::Constant("baz".to_string().into()),
+            ],
+        })]
+    );
+
+    // Reserved words aren't rule names.
+    assert!(parse_query("[:find ?x :where (and [?x :foo/bar _])]").is_err());
+}
+
+#[test]
+fn can_parse_rules() {
+    let s = r#"[[(ancestor ?c ?a) [?c :foo/parent ?a]]
+                [(ancestor ?c ?a) [?c :foo/parent ?p] (ancestor ?p ?a)]]"#;
+    let rules = parse_rules(s).expect("parsed");
+    let c = Variable::from_valid_name("?c");
+    let a = Variable::from_valid_name("?a");
+    let p = Variable::from_valid_name("?p");
+    let parent: PatternNonValuePlace = Keyword::namespaced("foo", "parent").into();
+    assert_eq!(
+        rules,
+        vec![
+            Rule {
+                name: PlainSymbol::plain("ancestor"),
+                vars: vec![c.clone(), a.clone()],
+                clauses: vec![WhereClause::Pattern(Pattern {
+                    source: None,
+                    entity: PatternNonValuePlace::Variable(c.clone()),
+                    attribute: parent.clone(),
+                    value: PatternValuePlace::Variable(a.clone()),
+                    tx: PatternNonValuePlace::Placeholder,
+                    added: PatternValuePlace::Placeholder,
+                })],
+            },
+            Rule {
+                name: PlainSymbol::plain("ancestor"),
+                vars: vec![c.clone(), a.clone()],
+                clauses: vec![
+                    WhereClause::Pattern(Pattern {
+                        source: None,
+                        entity: PatternNonValuePlace::Variable(c),
+                        attribute: parent,
+                        value: PatternValuePlace::Variable(p.clone()),
+                        tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
+                    }),
+                    WhereClause::RuleExpr(RuleExpr {
+                        name: PlainSymbol::plain("ancestor"),
+                        args: vec![
+                            PatternValuePlace::Variable(p),
+                            PatternValuePlace::Variable(a)
+                        ],
+                    }),
+                ],
+            },
+        ]
+    );
+
+    // Head variables must be unique.
+    assert!(parse_rules("[[(r ?a ?a) [?a :foo/parent ?a]]]").is_err());
+
+    // Rules must have a body.
+    assert!(parse_rules("[[(r ?a)]]").is_err());
+}
+
+#[test]
+fn can_parse_pull_expressions() {
+    let s = r#"[:find (pull ?p [:person/name
+                              [:person/age :default 0]
+                              [:person/friend :as :person/pal :limit 5]
+                              [:person/pet :limit nil]
+                              {:person/_parent [*]
+                               [:person/child :limit 2] ...
+                               :person/boss 3}])
+                :where [?p :person/name _]]"#;
+    let p = parse_query(s).expect("parsed");
+    let attribute = |ns: &str, name: &str| NamedPullAttribute {
+        attribute: PullConcreteAttribute::Ident(Keyword::namespaced(ns, name).into()),
+        alias: None,
+    };
+    let patterns = vec![
+        PullAttributeSpec::Attribute(attribute("person", "name")),
+        PullAttributeSpec::DefaultedAttribute(
+            attribute("person", "age"),
+            PullDefaultValue::EntidOrInteger(0),
+        ),
+        PullAttributeSpec::LimitedAttribute(
+            NamedPullAttribute {
+                alias: Some(Keyword::namespaced("person", "pal").into()),
+                ..attribute("person", "friend")
+            },
+            5,
+        ),
+        PullAttributeSpec::Attribute(attribute("person", "pet")),
+        PullAttributeSpec::PullMapSpec(vec![
+            (
+                PullAttributeSpec::Attribute(attribute("person", "_parent")),
+                PullMapValue::Pattern(vec![PullAttributeSpec::Wildcard]),
+            ),
+            (
+                PullAttributeSpec::LimitedAttribute(attribute("person", "child"), 2),
+                PullMapValue::Recursion(None),
+            ),
+            (
+                PullAttributeSpec::Attribute(attribute("person", "boss")),
+                PullMapValue::Recursion(Some(3)),
+            ),
+        ]),
+    ];
+    let pull = Element::Pull(Pull {
+        var: Variable::from_valid_name("?p"),
+        patterns,
+    });
+    assert_eq!(p.find_spec, FindSpec::FindRel(vec![pull.clone()]));
+
+    // Pull expressions print as they're written.
+    assert_eq!(
+        pull.to_string(),
+        "(pull ?p [ :person/name [:person/age :default 0] [:person/friend :as :person/pal :limit 5] \
+         :person/pet {:person/_parent [*] [:person/child :limit 2] ... :person/boss 3} ])"
+    );
+
+    // Map keys must be attributes, and recursion limits must be positive.
+    assert!(parse_query("[:find (pull ?p [{* [:person/name]}]) :where [?p _ _]]").is_err());
+    assert!(parse_query("[:find (pull ?p [{:person/friend 0}]) :where [?p _ _]]").is_err());
+}
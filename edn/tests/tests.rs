@@ -428,6 +428,11 @@ fn test_symbol() {
     assert_eq!(symbol("$").unwrap(), s_plain("$"));
     assert_eq!(symbol(".").unwrap(), s_plain("."));
     assert_eq!(symbol("...").unwrap(), s_plain("..."));
+    assert_eq!(symbol("+").unwrap(), s_plain("+"));
+    assert_eq!(symbol("-").unwrap(), s_plain("-"));
+    assert_eq!(symbol("->x").unwrap(), s_plain("->x"));
+    assert_eq!(symbol("/").unwrap(), s_plain("/"));
+    assert!(symbol("-1").is_err());
 
     assert_eq!(symbol("hello/world").unwrap(), s_ns("hello", "world"));
     assert_eq!(
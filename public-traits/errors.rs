@@ -53,6 +53,12 @@ pub enum MentatError {
     #[fail(display = "invalid argument name: '{}'", _0)]
     InvalidArgumentName(String),
 
+    #[fail(
+        display = "attached store {} doesn't agree with this store on the entid of {}",
+        _0, _1
+    )]
+    AttachedStoreSchemaMismatch(String, String),
+
     #[fail(display = "unknown attribute: '{}'", _0)]
     UnknownAttribute(String),
 
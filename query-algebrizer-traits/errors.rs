@@ -12,7 +12,10 @@ use std; // To refer to std::result::Result.
 
 use core_traits::{ValueType, ValueTypeSet};
 
-use edn::{query::PlainSymbol, ParseError};
+use edn::{
+    query::{PlainSymbol, SrcVar},
+    ParseError,
+};
 
 pub type Result<T> = std::result::Result<T, AlgebrizerError>;
 
@@ -31,6 +34,10 @@ pub enum BindingError {
     /// potentially erroneous) bindings.
     ExpectedBindRelOrBindColl,
 
+    /// Expected `?x` but got some other type of binding.  Functions like `+` and `str` produce
+    /// exactly one value.
+    ExpectedBindScalar,
+
     /// Expected `[?x1 … ?xN]` or `[[?x1 … ?xN]]` but got some other number of bindings.  Mentat is
     /// deliberately more strict than Datomic: we prefer placeholders to omission.
     InvalidNumberOfBindings {
@@ -90,6 +97,33 @@ pub enum AlgebrizerError {
     #[fail(display = "no function named {}", _0)]
     UnknownFunction(PlainSymbol),
 
+    #[fail(display = "no rule named {}", _0)]
+    UnknownRule(PlainSymbol),
+
+    #[fail(
+        display = "unsupported recursion in rule {}: only a single directly recursive definition is supported",
+        _0
+    )]
+    UnsupportedRuleRecursion(PlainSymbol),
+
+    #[fail(display = "source {} not present in :in", _0)]
+    UnknownSource(SrcVar),
+
+    #[fail(display = "nothing bound to source {}", _0)]
+    UnboundSource(SrcVar),
+
+    #[fail(
+        display = "relation bound to {} must have tuples of one length and one type per position",
+        _0
+    )]
+    InvalidRelation(SrcVar),
+
+    #[fail(
+        display = "{} can't search attributes with different fulltext tokenizers at once",
+        _0
+    )]
+    MixedFulltextTokenizers(PlainSymbol),
+
     #[fail(display = ":limit var {} not present in :in", _0)]
     UnknownLimitVar(PlainSymbol),
 
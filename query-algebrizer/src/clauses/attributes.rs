@@ -0,0 +1,150 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::BTreeSet;
+
+use core_traits::{Entid, TypedValue};
+
+use crate::clauses::ConjoiningClauses;
+
+use crate::types::{
+    Column, ColumnConstraint, ColumnConstraintOrAlternation, ColumnIntersection, ComputedTable,
+    DatomsColumn, DatomsTable, QualifiedAlias, QueryValue, SourceAlias, TableAlias,
+    TransactionsColumn,
+};
+
+/// Working out which attributes a query reads.
+impl ConjoiningClauses {
+    /// Add every attribute whose datoms this CC's tables can match to `attributes`. Returns
+    /// `false` if some table can match datoms of any attribute -- for example, a table for the
+    /// pattern `[?e ?a ?v]` -- in which case `attributes` is incomplete.
+    pub(crate) fn collect_referenced_attributes(&self, attributes: &mut BTreeSet<Entid>) -> bool {
+        // A query that can't match anything doesn't read anything. If a schema change makes it
+        // possible to match, the caller has to look again.
+        if self.is_known_empty() {
+            return true;
+        }
+
+        for SourceAlias(table, alias) in self.from.iter() {
+            match *table {
+                DatomsTable::Computed(index) => {
+                    if !self.computed_tables[index].collect_referenced_attributes(attributes) {
+                        return false;
+                    }
+                }
+
+                // Fulltext values are only ever matched alongside the datoms that refer to them.
+                // Rule working tables are filled by the rule's own arms, which are computed
+                // tables. Attached stores don't change through this store's transactions.
+                DatomsTable::FulltextValues(_) | DatomsTable::Rule | DatomsTable::Attached(_) => {}
+
+                DatomsTable::Datoms
+                | DatomsTable::FulltextDatoms
+                | DatomsTable::AllDatoms
+                | DatomsTable::Transactions
+                | DatomsTable::AsOf(_)
+                | DatomsTable::Since(_)
+                | DatomsTable::History => match constrained_attributes(&self.wheres, alias) {
+                    Some(constrained) => attributes.extend(constrained),
+                    None => return false,
+                },
+            }
+        }
+
+        collect_from_not_joins(&self.wheres, attributes)
+    }
+}
+
+impl ComputedTable {
+    fn collect_referenced_attributes(&self, attributes: &mut BTreeSet<Entid>) -> bool {
+        match *self {
+            ComputedTable::Subquery(ref cc) => cc.collect_referenced_attributes(attributes),
+            ComputedTable::Union { ref arms, .. } => arms
+                .iter()
+                .all(|cc| cc.collect_referenced_attributes(attributes)),
+            ComputedTable::RecursiveUnion {
+                ref base,
+                ref recursive,
+                ..
+            } => base
+                .iter()
+                .chain(recursive.iter())
+                .all(|cc| cc.collect_referenced_attributes(attributes)),
+            ComputedTable::NamedValues { .. } => true,
+        }
+    }
+}
+
+fn is_attribute_column(column: &Column) -> bool {
+    matches!(
+        *column,
+        Column::Fixed(DatomsColumn::Attribute)
+            | Column::Transactions(TransactionsColumn::Attribute)
+    )
+}
+
+/// The attributes that `intersection` restricts the table `alias` to, or `None` if it doesn't.
+/// Simple `or` clauses turn into an alternation of attributes, so look inside those, too.
+fn constrained_attributes(
+    intersection: &ColumnIntersection,
+    alias: &TableAlias,
+) -> Option<Vec<Entid>> {
+    for constraint in intersection.0.iter() {
+        if let ColumnConstraintOrAlternation::Constraint(ColumnConstraint::Equals(
+            QualifiedAlias(ref table, ref column),
+            ref value,
+        )) = *constraint
+        {
+            if table == alias && is_attribute_column(column) {
+                match *value {
+                    QueryValue::Entid(e) | QueryValue::TypedValue(TypedValue::Ref(e)) => {
+                        return Some(vec![e]);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    for constraint in intersection.0.iter() {
+        if let ColumnConstraintOrAlternation::Alternation(ref alternation) = *constraint {
+            if alternation.0.is_empty() {
+                continue;
+            }
+            let mut alternatives = Vec::new();
+            let all_constrained = alternation.0.iter().all(|arm| {
+                constrained_attributes(arm, alias)
+                    .map(|attributes| alternatives.extend(attributes))
+                    .is_some()
+            });
+            if all_constrained {
+                return Some(alternatives);
+            }
+        }
+    }
+
+    None
+}
+
+fn collect_from_not_joins(
+    intersection: &ColumnIntersection,
+    attributes: &mut BTreeSet<Entid>,
+) -> bool {
+    intersection.0.iter().all(|constraint| match *constraint {
+        ColumnConstraintOrAlternation::Constraint(ColumnConstraint::NotExists(ref table)) => {
+            table.collect_referenced_attributes(attributes)
+        }
+        ColumnConstraintOrAlternation::Constraint(_) => true,
+        ColumnConstraintOrAlternation::Alternation(ref alternation) => alternation
+            .0
+            .iter()
+            .all(|arm| collect_from_not_joins(arm, attributes)),
+    })
+}
@@ -0,0 +1,145 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use core_traits::{TypedValue, ValueTypeSet};
+
+use edn::query::{Binding, FnArg, PlainSymbol, Predicate, WhereFn};
+
+use crate::clauses::ConjoiningClauses;
+
+use query_algebrizer_traits::errors::{AlgebrizerError, BindingError, Result};
+
+use crate::types::{
+    Column, ColumnConstraint, ComputedValue, QualifiedAlias, QueryValue, ScalarFunction, TableAlias,
+};
+
+use crate::Known;
+
+/// Application of functions that SQLite evaluates for us.
+impl ConjoiningClauses {
+    /// Bind the result of an arithmetic or string function, like `[(str ?a ?b) ?s]`.
+    ///
+    /// The variable is bound to the SQL expression itself, so it can be projected, compared and
+    /// joined just like a column of a table.
+    pub(crate) fn apply_computed_binding(
+        &mut self,
+        known: Known,
+        function: ScalarFunction,
+        where_fn: WhereFn,
+    ) -> Result<()> {
+        let var = match where_fn.binding {
+            Binding::BindScalar(var) => var,
+            _ => bail!(AlgebrizerError::InvalidBinding(
+                where_fn.operator,
+                BindingError::ExpectedBindScalar
+            )),
+        };
+
+        let computed = self.computed_value(known, function, &where_fn.operator, where_fn.args)?;
+        if self.is_known_empty() {
+            return Ok(());
+        }
+
+        self.constrain_var_to_type(var.clone(), computed.value_type);
+
+        let column = Column::Computed(computed);
+        if function == ScalarFunction::Divide {
+            // SQLite divides by zero to produce NULL. That's no binding at all.
+            let qa = QualifiedAlias(TableAlias::new(), column.clone());
+            self.wheres.add_intersection(ColumnConstraint::NotNull(qa));
+        }
+        self.bind_column_to_var(known.schema, TableAlias::new(), column, var);
+        Ok(())
+    }
+
+    /// Apply a string predicate, like `[(starts-with? ?name "Al")]`.
+    pub(crate) fn apply_computed_predicate(
+        &mut self,
+        known: Known,
+        function: ScalarFunction,
+        predicate: Predicate,
+    ) -> Result<()> {
+        let computed = self.computed_value(known, function, &predicate.operator, predicate.args)?;
+        self.wheres
+            .add_intersection(ColumnConstraint::Holds(computed));
+        Ok(())
+    }
+
+    /// Check the number and types of the arguments to `function`, and resolve them to values.
+    fn computed_value(
+        &mut self,
+        known: Known,
+        function: ScalarFunction,
+        operator: &PlainSymbol,
+        args: Vec<FnArg>,
+    ) -> Result<ComputedValue> {
+        let (least, most) = function.arity();
+        if args.len() < least {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(
+                operator.clone(),
+                args.len(),
+                least
+            ));
+        }
+        if let Some(most) = most {
+            if args.len() > most {
+                bail!(AlgebrizerError::InvalidNumberOfArguments(
+                    operator.clone(),
+                    args.len(),
+                    most
+                ));
+            }
+        }
+
+        let mut values = Vec::with_capacity(args.len());
+        let mut types = Vec::with_capacity(args.len());
+        for (position, arg) in args.into_iter().enumerate() {
+            let supported_types = function.supported_types(position);
+            let arg_types = self
+                .potential_types(known.schema, &arg)?
+                .intersection(supported_types);
+            if arg_types.is_empty() {
+                bail!(AlgebrizerError::InvalidArgumentType(
+                    operator.clone(),
+                    supported_types,
+                    position
+                ));
+            }
+            values.push(self.resolve_computed_argument(arg, arg_types)?);
+            types.push(arg_types);
+        }
+
+        Ok(ComputedValue {
+            function,
+            value_type: function.result_type(&types),
+            args: values,
+        })
+    }
+
+    fn resolve_computed_argument(&mut self, arg: FnArg, types: ValueTypeSet) -> Result<QueryValue> {
+        match arg {
+            FnArg::IdentOrKeyword(keyword) => {
+                Ok(QueryValue::TypedValue(TypedValue::Keyword(keyword.into())))
+            }
+            FnArg::Variable(var) => {
+                if self.bound_value(&var).is_none() && !self.known_type_set(&var).is_subset(types) {
+                    if self.extracted_types.contains_key(&var) {
+                        // We won't know the type until we run the query, so make SQLite check it.
+                        self.add_type_requirement(var.clone(), types);
+                    } else {
+                        self.narrow_types_for_var(var.clone(), types);
+                    }
+                }
+                self.resolve_argument(FnArg::Variable(var))
+            }
+            arg => self.resolve_argument(arg),
+        }
+    }
+}
@@ -66,7 +66,9 @@ impl ValueTypes for FnArg {
             }
 
             // These don't make sense here. TODO: split FnArg into scalar and non-scalar…
-            &FnArg::Vector(_) | &FnArg::SrcVar(_) => bail!(AlgebrizerError::UnsupportedArgument),
+            &FnArg::Vector(_) | &FnArg::SrcVar(_) | &FnArg::Placeholder => {
+                bail!(AlgebrizerError::UnsupportedArgument)
+            }
 
             // These are all straightforward.
             &FnArg::Constant(NonIntegerConstant::Boolean(_)) => {
@@ -209,7 +211,9 @@ impl ConjoiningClauses {
             FnArg::Constant(NonIntegerConstant::BigInteger(_)) => unimplemented!(),
 
             // These don't make sense here.
-            FnArg::Vector(_) | FnArg::SrcVar(_) => bail!(AlgebrizerError::InvalidGroundConstant),
+            FnArg::Vector(_) | FnArg::SrcVar(_) | FnArg::Placeholder => {
+                bail!(AlgebrizerError::InvalidGroundConstant)
+            }
 
             // These are all straightforward.
             FnArg::Constant(NonIntegerConstant::Boolean(x)) => {
@@ -8,27 +8,53 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use core_traits::{TypedValue, ValueType};
+use core_traits::{Entid, TypedValue, ValueType};
 
-use mentat_core::HasSchema;
+use mentat_core::{HasSchema, Schema};
 
 use mentat_core::util::Either;
 
-use edn::query::{Binding, FnArg, NonIntegerConstant, SrcVar, VariableOrPlaceholder, WhereFn};
+use edn::query::{
+    Binding, FnArg, NonIntegerConstant, PlainSymbol, SrcVar, VariableOrPlaceholder, WhereFn,
+};
 
 use crate::clauses::ConjoiningClauses;
 
 use query_algebrizer_traits::errors::{AlgebrizerError, BindingError, Result};
 
 use crate::types::{
-    Column, ColumnConstraint, DatomsColumn, DatomsTable, EmptyBecause, FulltextColumn,
-    QualifiedAlias, QueryValue, SourceAlias,
+    Column, ColumnAlternation, ColumnConstraint, ColumnConstraintOrAlternation, ColumnIntersection,
+    DatomsColumn, DatomsTable, EmptyBecause, FulltextColumn, QualifiedAlias, QueryValue,
+    SourceAlias,
 };
 
 use crate::Known;
 
+/// Resolve a single attribute argument to `fulltext`, like `:foo/bar` or `65536`.
+///
+/// An unknown ident, or an entity that isn't present in the store, is likely enough to be a coding
+/// error that we choose to bail instead of marking the pattern as known-empty.
+fn resolve_attribute(schema: &Schema, operator: &PlainSymbol, arg: FnArg) -> Result<Entid> {
+    let a = match arg {
+        FnArg::IdentOrKeyword(i) => schema.get_entid(&i).map(|k| k.into()),
+        // Must be an entid.
+        FnArg::EntidOrInteger(e) => Some(e),
+        _ => None,
+    };
+    a.filter(|a| schema.attribute_for_entid(*a).is_some())
+        .ok_or_else(|| AlgebrizerError::InvalidArgument(operator.clone(), "attribute", 1))
+}
+
 impl ConjoiningClauses {
-    #[allow(unused_variables)]
+    /// Apply `[(fulltext $ a search) [[?e ?v ?tx ?score ?snippet ?highlight]]]`.
+    ///
+    /// The attribute `a` can be a single attribute; a vector of attributes, like `[:foo/bar
+    /// :foo/baz]`; `_`, for any fulltext attribute; or a variable.  A variable that isn't bound is
+    /// bound to the matching attribute.
+    ///
+    /// Every binding after `?e` is optional.  `?score` is the BM25 relevance of the match: higher
+    /// is more relevant.  `?snippet` is an excerpt of the matching text and `?highlight` is the
+    /// whole matching text; both surround the matching terms with `<b>` and `</b>`.
     pub(crate) fn apply_fulltext(&mut self, known: Known, where_fn: WhereFn) -> Result<()> {
         if where_fn.args.len() != 3 {
             bail!(AlgebrizerError::InvalidNumberOfArguments(
@@ -54,16 +80,16 @@ impl ConjoiningClauses {
             ));
         }
 
-        // We should have exactly four bindings. Destructure them now.
+        // We should have at most six bindings. Destructure them now.
         let bindings = match where_fn.binding {
             Binding::BindRel(bindings) => {
                 let bindings_count = bindings.len();
-                if bindings_count < 1 || bindings_count > 4 {
+                if !(1..=6).contains(&bindings_count) {
                     bail!(AlgebrizerError::InvalidBinding(
                         where_fn.operator.clone(),
                         BindingError::InvalidNumberOfBindings {
                             number: bindings.len(),
-                            expected: 4,
+                            expected: 6,
                         }
                     ));
                 }
@@ -87,6 +113,12 @@ impl ConjoiningClauses {
         let b_score = bindings
             .next()
             .unwrap_or(VariableOrPlaceholder::Placeholder);
+        let b_snippet = bindings
+            .next()
+            .unwrap_or(VariableOrPlaceholder::Placeholder);
+        let b_highlight = bindings
+            .next()
+            .unwrap_or(VariableOrPlaceholder::Placeholder);
 
         let mut args = where_fn.args.into_iter();
 
@@ -102,63 +134,121 @@ impl ConjoiningClauses {
 
         let schema = known.schema;
 
-        // TODO: accept placeholder and set of attributes.  Alternately, consider putting the search
-        // term before the attribute arguments and collect the (variadic) attributes into a set.
-        // let a: Entid = self.resolve_attribute_argument(&where_fn.operator, 1, args.next().unwrap())?;
-        //
-        // TODO: improve the expression of this matching, possibly by using attribute_for_* uniformly.
-        let a = match args.next().unwrap() {
-            FnArg::IdentOrKeyword(i) => schema.get_entid(&i).map(|k| k.into()),
-            // Must be an entid.
-            FnArg::EntidOrInteger(e) => Some(e),
+        // Every fulltext attribute, in case we're asked to search them all.
+        let all_fulltext = || -> Vec<Entid> {
+            schema
+                .attribute_map
+                .iter()
+                .filter(|(_, attribute)| attribute.fulltext)
+                .map(|(a, _)| *a)
+                .collect()
+        };
+
+        // The attributes to search, and the variable to bind to the matching attribute, if any.
+        let operator = &where_fn.operator;
+        let (attributes, b_attribute) = match args.next().unwrap() {
+            FnArg::Placeholder => (all_fulltext(), None),
+            FnArg::Vector(attributes) => {
+                let attributes: Result<Vec<Entid>> = attributes
+                    .into_iter()
+                    .map(|a| resolve_attribute(schema, operator, a))
+                    .collect();
+                (attributes?, None)
+            }
             FnArg::Variable(v) => {
                 // If it's already bound, then let's expand the variable.
-                // TODO: allow non-constant attributes.
                 match self.bound_value(&v) {
-                    Some(TypedValue::Ref(entid)) => Some(entid),
+                    Some(TypedValue::Ref(entid)) => (
+                        vec![resolve_attribute(
+                            schema,
+                            operator,
+                            FnArg::EntidOrInteger(entid),
+                        )?],
+                        None,
+                    ),
                     Some(tv) => bail!(AlgebrizerError::InputTypeDisagreement(
                         v.name(),
                         ValueType::Ref,
                         tv.value_type()
                     )),
-                    None => bail!(AlgebrizerError::UnboundVariable((*v.0).clone())),
+                    None if self.input_variables.contains(&v) => {
+                        // Sorry, we haven't implemented late binding.
+                        bail!(AlgebrizerError::UnboundVariable((*v.0).clone()))
+                    }
+                    // Search every fulltext attribute, and bind the variable to the one that
+                    // matched.
+                    None => (all_fulltext(), Some(v)),
                 }
             }
-            _ => None,
+            arg => (vec![resolve_attribute(schema, operator, arg)?], None),
         };
 
-        // An unknown ident, or an entity that isn't present in the store, or isn't a fulltext
-        // attribute, is likely enough to be a coding error that we choose to bail instead of
-        // marking the pattern as known-empty.
-        let op = where_fn.operator.clone(); //TODO(gburd): remove me...
-        let a = a.ok_or_else(move || AlgebrizerError::InvalidArgument(op, "attribute", 1))?;
-        let op = where_fn.operator.clone(); //TODO(gburd): remove me...
-        let attribute = schema
-            .attribute_for_entid(a)
+        let fulltext_attributes: Vec<Entid> = attributes
+            .iter()
             .cloned()
-            .ok_or_else(move || AlgebrizerError::InvalidArgument(op, "attribute", 1))?;
-
-        if !attribute.fulltext {
+            .filter(|a| {
+                schema
+                    .attribute_for_entid(*a)
+                    .is_some_and(|attribute| attribute.fulltext)
+            })
+            .collect();
+
+        if fulltext_attributes.is_empty() {
             // We can never get results from a non-fulltext attribute!
-            println!("Can't run fulltext on non-fulltext attribute {}.", a);
-            self.mark_known_empty(EmptyBecause::NonFulltextAttribute(a));
+            match attributes.first() {
+                Some(&a) => self.mark_known_empty(EmptyBecause::NonFulltextAttribute(a)),
+                None => self.mark_known_empty(EmptyBecause::NoFulltextAttributes),
+            }
             return Ok(());
         }
 
-        let fulltext_values_alias = self.next_alias_for_table(DatomsTable::FulltextValues);
+        // Every index holds every fulltext value, so we can search with whichever tokenizer the
+        // attributes share.  Attributes that don't share one would each match differently, so we
+        // refuse to pick one for them.
+        let mut tokenizers = fulltext_attributes.iter().filter_map(|a| {
+            schema
+                .attribute_for_entid(*a)
+                .map(|attribute| attribute.fulltext_tokenizer)
+        });
+        let tokenizer = tokenizers.next().unwrap_or_default();
+        if !tokenizers.all(|t| t == tokenizer) {
+            bail!(AlgebrizerError::MixedFulltextTokenizers(operator.clone()));
+        }
+
+        let fulltext_values_table = DatomsTable::FulltextValues(tokenizer);
+        let fulltext_values_alias = self.next_alias_for_table(fulltext_values_table.clone());
         let datoms_table_alias = self.next_alias_for_table(DatomsTable::Datoms);
 
-        // We do a fulltext lookup by joining the fulltext values table against datoms -- just
-        // like applying a pattern, but two tables contribute instead of one.
+        // We do a fulltext lookup by joining the fulltext index against datoms -- just like
+        // applying a pattern, but two tables contribute instead of one.
         self.from.push(SourceAlias(
-            DatomsTable::FulltextValues,
+            fulltext_values_table,
             fulltext_values_alias.clone(),
         ));
         self.from
             .push(SourceAlias(DatomsTable::Datoms, datoms_table_alias.clone()));
 
-        // TODO: constrain the type in the more general cases (e.g., `a` is a var).
-        self.constrain_attribute(datoms_table_alias.clone(), a);
+        // The datom's value is only a rowid into the fulltext values table if the attribute is a
+        // fulltext attribute, so we always constrain the attribute.
+        if let [a] = fulltext_attributes.as_slice() {
+            self.constrain_attribute(datoms_table_alias.clone(), *a);
+        } else {
+            let alternation = fulltext_attributes
+                .iter()
+                .map(|a| {
+                    ColumnIntersection::from(vec![ColumnConstraint::Equals(
+                        QualifiedAlias(
+                            datoms_table_alias.clone(),
+                            Column::Fixed(DatomsColumn::Attribute),
+                        ),
+                        QueryValue::Entid(*a),
+                    )])
+                })
+                .collect();
+            self.wheres.add(ColumnConstraintOrAlternation::Alternation(
+                ColumnAlternation(alternation),
+            ));
+        }
 
         // Join the datoms table to the fulltext values table.
         self.wheres.add_intersection(ColumnConstraint::Equals(
@@ -172,6 +262,21 @@ impl ConjoiningClauses {
             )),
         ));
 
+        if let Some(var) = b_attribute {
+            // Attributes must be refs.
+            self.constrain_var_to_type(var.clone(), ValueType::Ref);
+            if self.is_known_empty() {
+                return Ok(());
+            }
+
+            self.bind_column_to_var(
+                schema,
+                datoms_table_alias.clone(),
+                DatomsColumn::Attribute,
+                var,
+            );
+        }
+
         // `search` is either text or a variable.
         // If it's simple text, great.
         // If it's a variable, it'll be in one of three states:
@@ -264,7 +369,7 @@ impl ConjoiningClauses {
 
             self.bind_column_to_var(
                 schema,
-                fulltext_values_alias,
+                fulltext_values_alias.clone(),
                 Column::Fulltext(FulltextColumn::Text),
                 var.clone(),
             );
@@ -277,7 +382,12 @@ impl ConjoiningClauses {
                 return Ok(());
             }
 
-            self.bind_column_to_var(schema, datoms_table_alias, DatomsColumn::Tx, var.clone());
+            self.bind_column_to_var(
+                schema,
+                datoms_table_alias.clone(),
+                DatomsColumn::Tx,
+                var.clone(),
+            );
         }
 
         if let VariableOrPlaceholder::Variable(ref var) = b_score {
@@ -292,9 +402,32 @@ impl ConjoiningClauses {
                 ));
             }
 
-            // We bind the value ourselves. This handily takes care of substituting into existing uses.
-            // TODO: produce real scores using SQLite's matchinfo.
-            self.bind_value(var, TypedValue::Double(0.0.into()));
+            self.bind_column_to_var(
+                schema,
+                fulltext_values_alias.clone(),
+                Column::Fulltext(FulltextColumn::Score(tokenizer)),
+                var.clone(),
+            );
+        }
+
+        for (binding, column) in [
+            (b_snippet, FulltextColumn::Snippet(tokenizer)),
+            (b_highlight, FulltextColumn::Highlight(tokenizer)),
+        ] {
+            if let VariableOrPlaceholder::Variable(var) = binding {
+                // Snippets and highlights are strings.
+                self.constrain_var_to_type(var.clone(), ValueType::String);
+                if self.is_known_empty() {
+                    return Ok(());
+                }
+
+                self.bind_column_to_var(
+                    schema,
+                    fulltext_values_alias.clone(),
+                    Column::Fulltext(column),
+                    var,
+                );
+            }
         }
 
         Ok(())
@@ -305,6 +438,7 @@ impl ConjoiningClauses {
 mod testing {
     use super::*;
 
+    use core_traits::attribute::FulltextTokenizer;
     use core_traits::{Attribute, ValueType};
 
     use mentat_core::Schema;
@@ -407,7 +541,7 @@ mod testing {
         );
 
         let bindings = cc.column_bindings;
-        assert_eq!(bindings.len(), 3);
+        assert_eq!(bindings.len(), 4);
 
         assert_eq!(
             bindings
@@ -440,15 +574,18 @@ mod testing {
             )]
         );
 
-        // Score is a value binding.
-        let values = cc.value_bindings;
+        // SQLite computes the score of each match.
         assert_eq!(
-            values
+            bindings
                 .get(&Variable::from_valid_name("?score"))
                 .expect("column binding for ?score")
                 .clone(),
-            TypedValue::Double(0.0.into())
+            vec![QualifiedAlias(
+                "fulltext_values00".to_string(),
+                Column::Fulltext(FulltextColumn::Score(FulltextTokenizer::Unicode61))
+            )]
         );
+        assert!(cc.value_bindings.is_empty());
 
         let known_types = cc.known_types;
         assert_eq!(known_types.len(), 4);
@@ -29,7 +29,7 @@ impl ConjoiningClauses {
     /// the provided types.
     /// Construct a computed table to yield this relation.
     /// This function will panic if some invariants are not met.
-    fn collect_named_bindings<'s>(
+    pub(crate) fn collect_named_bindings<'s>(
         &mut self,
         schema: &'s Schema,
         names: Vec<Variable>,
@@ -51,7 +51,7 @@ impl ConjoiningClauses {
         };
 
         let table = self.computed_tables.push_computed(named_values);
-        let alias = self.next_alias_for_table(table);
+        let alias = self.next_alias_for_table(table.clone());
 
         // Stitch the computed table into column_bindings, so we get cross-linking.
         for (name, ty) in names.iter().zip(types.into_iter()) {
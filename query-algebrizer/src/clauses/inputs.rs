@@ -10,21 +10,69 @@
 
 use std::collections::BTreeMap;
 
-use core_traits::{TypedValue, ValueType};
+use core_traits::{Entid, TypedValue, ValueType};
 
-use edn::query::Variable;
+use mentat_core::ValueRc;
+
+use edn::query::{Rule, SrcVar, Variable};
 
 use query_algebrizer_traits::errors::{AlgebrizerError, Result};
 
+use crate::validate::validate_relation;
+
 /// Define the inputs to a query. This is in two parts: a set of values known now, and a set of
 /// types known now.
 /// The separate map of types is to allow queries to be algebrized without full knowledge of
 /// the bindings that will be used at execution time.
 /// When built correctly, `types` is guaranteed to contain the types of `values` -- use
 /// `QueryInputs::new` or `QueryInputs::with_values` to construct an instance.
+/// Rule definitions can be attached with `QueryInputs::with_rules`; they're available to queries
+/// that name `%` in their `:in` clause. Source variables are bound to views of the store, other
+/// stores, or in-memory relations with `QueryInputs::with_source`.
 pub struct QueryInputs {
     pub(crate) types: BTreeMap<Variable, ValueType>,
     pub(crate) values: BTreeMap<Variable, TypedValue>,
+    pub(crate) rules: Vec<Rule>,
+    pub(crate) sources: BTreeMap<SrcVar, QuerySource>,
+}
+
+/// A view of the store other than its current state. Patterns that name a source bound to one
+/// of these match against that view instead of the `datoms` table.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DatabaseView {
+    /// The store as it was immediately after the given transaction.
+    AsOf(Entid),
+
+    /// Only those current datoms that were asserted after the given transaction.
+    Since(Entid),
+
+    /// Every assertion and retraction ever made. Patterns can bind a fifth place, `?added`, to
+    /// tell the two apart.
+    History,
+}
+
+/// Something other than the current state of the store that a source variable can be bound to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum QuerySource {
+    /// A view of the store being queried.
+    View(DatabaseView),
+
+    /// Another Mentat store, attached to the querying connection under the given schema name.
+    /// Attributes are resolved against the querying store's schema, so the attached store must
+    /// share its attribute definitions -- for example, an older copy of the same store.
+    Attached(String),
+
+    /// A collection of tuples. Patterns against a relation match each tuple by position, and
+    /// may name fewer places than the tuples have. As in any pattern, constants in the first two
+    /// places must be entids or idents. Every tuple must be the same length, and all of the
+    /// values in each position must have the same type.
+    Relation(ValueRc<Vec<Vec<TypedValue>>>),
+}
+
+impl From<DatabaseView> for QuerySource {
+    fn from(view: DatabaseView) -> QuerySource {
+        QuerySource::View(view)
+    }
 }
 
 impl Default for QueryInputs {
@@ -32,6 +80,8 @@ impl Default for QueryInputs {
         QueryInputs {
             types: BTreeMap::default(),
             values: BTreeMap::default(),
+            rules: Vec::new(),
+            sources: BTreeMap::default(),
         }
     }
 }
@@ -46,6 +96,8 @@ impl QueryInputs {
         QueryInputs {
             types: types.into_iter().collect(),
             values: BTreeMap::default(),
+            rules: Vec::new(),
+            sources: BTreeMap::default(),
         }
     }
 
@@ -56,6 +108,8 @@ impl QueryInputs {
                 .map(|(var, val)| (var.clone(), val.value_type()))
                 .collect(),
             values,
+            rules: Vec::new(),
+            sources: BTreeMap::default(),
         }
     }
 
@@ -73,6 +127,45 @@ impl QueryInputs {
                 }
             }
         }
-        Ok(QueryInputs { types, values })
+        Ok(QueryInputs {
+            types,
+            values,
+            rules: Vec::new(),
+            sources: BTreeMap::default(),
+        })
+    }
+
+    /// Attach rule definitions to these inputs, replacing any already present.
+    pub fn with_rules(mut self, rules: Vec<Rule>) -> QueryInputs {
+        self.rules = rules;
+        self
+    }
+
+    /// Bind a source variable to a view of the store, another store, or a relation. Named
+    /// sources must also appear in the query's `:in` clause; binding the default source, `$`,
+    /// changes what every pattern without an explicit source matches against.
+    pub fn with_source<S: Into<QuerySource>>(mut self, source: SrcVar, bound: S) -> QueryInputs {
+        self.sources.insert(source, bound.into());
+        self
+    }
+
+    /// Bind a source variable to an in-memory collection of tuples. The tuples must all be the
+    /// same length, and each position must hold values of a single type.
+    pub fn with_relation(self, source: SrcVar, rows: Vec<Vec<TypedValue>>) -> Result<QueryInputs> {
+        validate_relation(&source, &rows)?;
+        Ok(self.with_source(source, QuerySource::Relation(ValueRc::new(rows))))
+    }
+
+    pub fn types(&self) -> &BTreeMap<Variable, ValueType> {
+        &self.types
+    }
+
+    pub fn values(&self) -> &BTreeMap<Variable, TypedValue> {
+        &self.values
+    }
+
+    /// Whether these inputs attach rules or bind sources, rather than only binding variables.
+    pub fn has_rules_or_sources(&self) -> bool {
+        !self.rules.is_empty() || !self.sources.is_empty()
     }
 }
@@ -16,13 +16,18 @@ use std::collections::btree_map::Entry;
 
 use std::fmt::{Debug, Formatter};
 
+use std::rc::Rc;
+
 use core_traits::{Attribute, Entid, KnownEntid, TypedValue, ValueType, ValueTypeSet};
 
-use mentat_core::{Cloned, HasSchema, Schema};
+use mentat_core::{Cloned, HasSchema, Schema, ValueRc};
 
 use mentat_core::counter::RcCounter;
 
-use edn::query::{Element, FindSpec, Keyword, PatternNonValuePlace, Pull, Variable, WhereClause};
+use edn::query::{
+    Element, FindSpec, Keyword, PatternNonValuePlace, PlainSymbol, Pull, Rule, SrcVar, Variable,
+    WhereClause,
+};
 
 use query_algebrizer_traits::errors::{AlgebrizerError, Result};
 
@@ -39,15 +44,21 @@ mod or;
 mod pattern;
 mod predicate;
 mod resolve;
+mod rules;
 
+mod attributes;
+mod computed;
 mod fulltext;
 mod ground;
+mod relation;
 mod tx_log_api;
 mod where_fn;
 
-use crate::validate::{validate_not_join, validate_or_join};
+use crate::validate::{
+    validate_not_join, validate_or_join, validate_pattern_source, validate_sources,
+};
 
-pub use self::inputs::QueryInputs;
+pub use self::inputs::{DatabaseView, QueryInputs, QuerySource};
 
 use crate::Known;
 
@@ -176,6 +187,16 @@ pub struct ConjoiningClauses {
 
     /// Map of variables to the set of type requirements we have for them.
     required_types: BTreeMap<Variable, ValueTypeSet>,
+
+    /// The rule definitions available to the query, keyed by name. Shared with nested CCs.
+    rules: Rc<BTreeMap<PlainSymbol, Vec<Rule>>>,
+
+    /// The rules currently being expanded. Used to detect recursion that we can't express.
+    rules_in_progress: BTreeSet<PlainSymbol>,
+
+    /// What each source variable is bound to. Patterns whose source isn't listed here match
+    /// against the current state of the store.
+    sources: BTreeMap<SrcVar, QuerySource>,
 }
 
 impl PartialEq for ConjoiningClauses {
@@ -227,6 +248,9 @@ impl Default for ConjoiningClauses {
             value_bindings: BTreeMap::new(),
             known_types: BTreeMap::new(),
             extracted_types: BTreeMap::new(),
+            rules: Rc::new(BTreeMap::new()),
+            rules_in_progress: BTreeSet::new(),
+            sources: BTreeMap::new(),
         }
     }
 }
@@ -273,15 +297,28 @@ impl ConjoiningClauses {
             Some(QueryInputs {
                 mut types,
                 mut values,
+                rules,
+                sources,
             }) => {
                 // Discard any bindings not mentioned in our :in clause.
                 types.keep_intersected_keys(&in_variables);
                 values.keep_intersected_keys(&in_variables);
 
+                // Group rule definitions by name: a rule can have several.
+                let mut rule_map: BTreeMap<PlainSymbol, Vec<Rule>> = BTreeMap::new();
+                for rule in rules.into_iter() {
+                    rule_map
+                        .entry(rule.name.clone())
+                        .or_insert_with(Vec::new)
+                        .push(rule);
+                }
+
                 let mut cc = ConjoiningClauses {
                     alias_counter,
                     input_variables: in_variables,
                     value_bindings: values,
+                    rules: Rc::new(rule_map),
+                    sources,
                     ..Default::default()
                 };
 
@@ -319,6 +356,9 @@ impl ConjoiningClauses {
             known_types: self.known_types.clone(),
             extracted_types: self.extracted_types.clone(),
             required_types: self.required_types.clone(),
+            rules: self.rules.clone(),
+            rules_in_progress: self.rules_in_progress.clone(),
+            sources: self.sources.clone(),
             ..Default::default()
         }
     }
@@ -334,6 +374,22 @@ impl ConjoiningClauses {
             known_types: self.known_types.with_intersected_keys(&vars),
             extracted_types: self.extracted_types.with_intersected_keys(&vars),
             required_types: self.required_types.with_intersected_keys(&vars),
+            rules: self.rules.clone(),
+            rules_in_progress: self.rules_in_progress.clone(),
+            sources: self.sources.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Make a new, empty CC that shares this CC's alias counter, rules, and sources, but none of
+    /// its variable associations. Rule bodies that are evaluated separately from their call site are
+    /// algebrized in such a scope.
+    fn make_rule_scope(&self) -> ConjoiningClauses {
+        ConjoiningClauses {
+            alias_counter: self.alias_counter.clone(),
+            rules: self.rules.clone(),
+            rules_in_progress: self.rules_in_progress.clone(),
+            sources: self.sources.clone(),
             ..Default::default()
         }
     }
@@ -431,6 +487,10 @@ impl ConjoiningClauses {
                     self.constrain_column_to_constant(table, column, bound_val);
                 }
 
+                Column::Computed(_) => {
+                    self.constrain_column_to_constant(table, column, bound_val);
+                }
+
                 Column::Fulltext(FulltextColumn::Rowid)
                 | Column::Fulltext(FulltextColumn::Text) => {
                     // We never expose `rowid` via queries.  We do expose `text`, but only
@@ -438,6 +498,12 @@ impl ConjoiningClauses {
                     unimplemented!()
                 }
 
+                Column::Fulltext(FulltextColumn::Score(_))
+                | Column::Fulltext(FulltextColumn::Snippet(_))
+                | Column::Fulltext(FulltextColumn::Highlight(_)) => {
+                    self.constrain_column_to_constant(table, column, bound_val);
+                }
+
                 Column::Fixed(DatomsColumn::ValueTypeTag) => {
                     // I'm pretty sure this is meaningless right now, because we will never bind
                     // a type tag to a variable -- there's no syntax for doing so.
@@ -885,9 +951,41 @@ impl ConjoiningClauses {
         }
     }
 
+    /// The tuples bound to a source, if that source is an in-memory relation.
+    pub(crate) fn relation_for_source(
+        &self,
+        source: &Option<SrcVar>,
+    ) -> Option<ValueRc<Vec<Vec<TypedValue>>>> {
+        let source = source.clone().unwrap_or(SrcVar::DefaultSrc);
+        match self.sources.get(&source) {
+            Some(QuerySource::Relation(rows)) => Some(rows.clone()),
+            _ => None,
+        }
+    }
+
+    /// A pattern against a view of the store, or against an attached store, uses that source's
+    /// table in place of whichever table the current state would use. These tables resolve
+    /// fulltext values themselves.
+    fn table_for_source(&self, source: &SrcVar, table: DatomsTable) -> DatomsTable {
+        match self.sources.get(source) {
+            None => table,
+            Some(QuerySource::View(DatabaseView::AsOf(tx))) => DatomsTable::AsOf(*tx),
+            Some(QuerySource::View(DatabaseView::Since(tx))) => DatomsTable::Since(*tx),
+            Some(QuerySource::View(DatabaseView::History)) => DatomsTable::History,
+            Some(QuerySource::Attached(name)) => DatomsTable::Attached(name.clone()),
+            Some(QuerySource::Relation(_)) => {
+                unreachable!("patterns against relations don't use tables")
+            }
+        }
+    }
+
     pub(crate) fn next_alias_for_table(&mut self, table: DatomsTable) -> TableAlias {
         match table {
             DatomsTable::Computed(u) => format!("{}{:02}", table.name(), u),
+            // Whichever index we search, it's an index of `fulltext_values`.
+            DatomsTable::FulltextValues(_) => {
+                format!("fulltext_values{:02}", self.alias_counter.next())
+            }
             _ => format!("{}{:02}", table.name(), self.alias_counter.next()),
         }
     }
@@ -902,10 +1000,11 @@ impl ConjoiningClauses {
         pattern: &'a EvolvedPattern,
     ) -> Option<SourceAlias> {
         self.table_for_places(schema, &pattern.attribute, &pattern.value)
+            .map(|table: DatomsTable| self.table_for_source(&pattern.source, table))
             .map_err(|reason| {
                 self.mark_known_empty(reason);
             })
-            .map(|table: DatomsTable| SourceAlias(table, self.next_alias_for_table(table)))
+            .map(|table: DatomsTable| SourceAlias(table.clone(), self.next_alias_for_table(table)))
             .ok()
     }
 
@@ -973,7 +1072,17 @@ impl ConjoiningClauses {
     ///    datoms12.e = datoms14.e
     /// ```
     pub(crate) fn expand_column_bindings(&mut self) {
-        for cols in self.column_bindings.values() {
+        for (var, cols) in self.column_bindings.iter() {
+            // An `:in` variable without a value is bound when the query is run.
+            if self.input_variables.contains(var) && !self.value_bindings.contains_key(var) {
+                if let Some(primary) = cols.first() {
+                    self.wheres.add_intersection(ColumnConstraint::Equals(
+                        primary.clone(),
+                        QueryValue::Input(var.clone()),
+                    ));
+                }
+            }
+
             if cols.len() > 1 {
                 let primary = &cols[0];
                 let secondaries = cols.iter().skip(1);
@@ -1141,8 +1250,8 @@ impl ConjoiningClauses {
                 }
 
                 // Patterns are common, so let's grab as much type information from
-                // them as we can.
-                WhereClause::Pattern(ref p) => {
+                // them as we can. The places of a relation's tuples can hold anything.
+                WhereClause::Pattern(ref p) if self.relation_for_source(&p.source).is_none() => {
                     self.mark_as_ref(&p.entity);
                     self.mark_as_ref(&p.attribute);
                     self.mark_as_ref(&p.tx);
@@ -1165,13 +1274,16 @@ impl ConjoiningClauses {
                 continue;
             }
             match clause {
-                WhereClause::Pattern(p) => match self.make_evolved_pattern(known, p) {
-                    PlaceOrEmpty::Place(evolved) => patterns.push_back(evolved),
-                    PlaceOrEmpty::Empty(because) => {
-                        self.mark_known_empty(because);
-                        return Ok(());
+                WhereClause::Pattern(p) if self.relation_for_source(&p.source).is_none() => {
+                    validate_pattern_source(&p, &self.sources)?;
+                    match self.make_evolved_pattern(known, p) {
+                        PlaceOrEmpty::Place(evolved) => patterns.push_back(evolved),
+                        PlaceOrEmpty::Empty(because) => {
+                            self.mark_known_empty(because);
+                            return Ok(());
+                        }
                     }
-                },
+                }
                 _ => {
                     if !patterns.is_empty() {
                         self.apply_evolved_patterns(known, patterns)?;
@@ -1187,12 +1299,10 @@ impl ConjoiningClauses {
     // This is here, rather than in `lib.rs`, because it's recursive: `or` can contain `or`,
     // and so on.
     pub(crate) fn apply_clause(&mut self, known: Known, where_clause: WhereClause) -> Result<()> {
+        validate_sources(&where_clause, &self.sources)?;
         match where_clause {
             WhereClause::Pattern(p) => {
-                match self.make_evolved_pattern(known, p) {
-                    PlaceOrEmpty::Place(evolved) => self.apply_pattern(known, evolved),
-                    PlaceOrEmpty::Empty(because) => self.mark_known_empty(because),
-                }
+                self.apply_parsed_pattern(known, p);
                 Ok(())
             }
             WhereClause::Pred(p) => self.apply_predicate(known, p),
@@ -1206,7 +1316,7 @@ impl ConjoiningClauses {
                 self.apply_not_join(known, n)
             }
             WhereClause::TypeAnnotation(anno) => self.apply_type_anno(&anno),
-            _ => unimplemented!(),
+            WhereClause::RuleExpr(r) => self.apply_rule_expr(known, r),
         }
     }
 }
@@ -169,6 +169,17 @@ impl ConjoiningClauses {
         assert!(or_join.is_fully_unified());
         assert!(or_join.clauses.len() >= 2);
 
+        // Patterns against a relation don't use a table at all, so they can't share one.
+        let uses_relation = or_join.clauses.iter().any(|clause| match clause {
+            OrWhereClause::Clause(WhereClause::Pattern(p)) => {
+                self.relation_for_source(&p.source).is_some()
+            }
+            _ => false,
+        });
+        if uses_relation {
+            return DeconstructedOrJoin::Complex(or_join);
+        }
+
         // We're going to collect into this.
         // If at any point we hit something that's not a suitable pattern, we'll
         // reconstruct and return a complex `OrJoin`.
@@ -219,7 +230,8 @@ impl ConjoiningClauses {
                                 _simply_matches_place(&template.entity, &p.entity) &&
                                 _simply_matches_place(&template.attribute, &p.attribute) &&
                                 _simply_matches_value_place(&template.value, &p.value) &&
-                                _simply_matches_place(&template.tx, &p.tx)
+                                _simply_matches_place(&template.tx, &p.tx) &&
+                                _simply_matches_value_place(&template.added, &p.added)
                         } else {
                             // No previous pattern.
                             true
@@ -228,7 +240,7 @@ impl ConjoiningClauses {
                         // All of our clauses that _do_ yield a table -- that are possible --
                         // must use the same table in order for this to be a simple `or`!
                         if same_shape {
-                            if expected_table == Some(table) {
+                            if expected_table.as_ref() == Some(&table) {
                                 patterns.push(p);
                                 continue;
                             }
@@ -292,14 +304,7 @@ impl ConjoiningClauses {
             }
             DeconstructedOrJoin::UnitPattern(pattern) => {
                 // Same, but simpler.
-                match self.make_evolved_pattern(known, pattern) {
-                    PlaceOrEmpty::Empty(e) => {
-                        self.mark_known_empty(e);
-                    }
-                    PlaceOrEmpty::Place(pattern) => {
-                        self.apply_pattern(known, pattern);
-                    }
-                };
+                self.apply_parsed_pattern(known, pattern);
                 Ok(())
             }
             DeconstructedOrJoin::Simple(patterns, mentioned_vars) => {
@@ -677,7 +682,7 @@ impl ConjoiningClauses {
             arms: acc,
         };
         let table = self.computed_tables.push_computed(union);
-        let alias = self.next_alias_for_table(table);
+        let alias = self.next_alias_for_table(table.clone());
 
         // Stitch the computed table into column_bindings, so we get cross-linking.
         let schema = known.schema;
@@ -21,8 +21,8 @@ use edn::query::{
 use crate::clauses::ConjoiningClauses;
 
 use crate::types::{
-    ColumnConstraint, DatomsColumn, EmptyBecause, EvolvedNonValuePlace, EvolvedPattern,
-    EvolvedValuePlace, PlaceOrEmpty, SourceAlias,
+    ColumnConstraint, DatomsColumn, DatomsTable, EmptyBecause, EvolvedNonValuePlace,
+    EvolvedPattern, EvolvedValuePlace, PlaceOrEmpty, SourceAlias, TransactionsColumn,
 };
 
 use crate::Known;
@@ -146,6 +146,16 @@ impl ConjoiningClauses {
                     if self.is_known_empty() {
                         return;
                     }
+                } else if self.input_variables.contains(v) && self.bound_value(v).is_none() {
+                    // An `:in` variable bound when the query is run gets the same type tag
+                    // restriction as a value supplied up front: see `EvolvedValuePlace::Value`.
+                    if let Some(input_type) = self.known_type(v) {
+                        self.wheres
+                            .add_intersection(ColumnConstraint::has_unit_type(
+                                col.clone(),
+                                input_type,
+                            ));
+                    }
                 }
 
                 self.bind_column_to_var(schema, col.clone(), DatomsColumn::Value, v.clone());
@@ -271,6 +281,65 @@ impl ConjoiningClauses {
                 self.constrain_column_to_entity(col.clone(), DatomsColumn::Tx, entid);
             }
         }
+
+        self.apply_added_place(known, &pattern.added, alias);
+    }
+
+    /// Views of the store have an `added` column. The current state of the store doesn't: every
+    /// datom in it is an assertion, so `?added` is simply `true`.
+    fn apply_added_place(&mut self, known: Known, added: &EvolvedValuePlace, alias: &SourceAlias) {
+        let has_added_column = matches!(
+            alias.0,
+            DatomsTable::AsOf(_) | DatomsTable::Since(_) | DatomsTable::History
+        );
+        match added {
+            EvolvedValuePlace::Placeholder => (),
+            EvolvedValuePlace::Variable(ref v) => {
+                if has_added_column {
+                    self.constrain_var_to_type(v.clone(), ValueType::Boolean);
+                    if self.is_known_empty() {
+                        return;
+                    }
+                    self.bind_column_to_var(
+                        known.schema,
+                        alias.1.clone(),
+                        TransactionsColumn::Added,
+                        v.clone(),
+                    );
+                } else {
+                    self.bind_value(v, TypedValue::Boolean(true));
+                }
+            }
+            EvolvedValuePlace::Value(TypedValue::Boolean(b)) => {
+                if has_added_column {
+                    self.constrain_column_to_constant(
+                        alias.1.clone(),
+                        TransactionsColumn::Added,
+                        TypedValue::Boolean(*b),
+                    );
+                } else if !b {
+                    self.mark_known_empty(EmptyBecause::RetractionOutsideHistory);
+                }
+            }
+            EvolvedValuePlace::Value(ref v) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(
+                    ValueType::Boolean,
+                    v.clone(),
+                ));
+            }
+            EvolvedValuePlace::Entid(e) | EvolvedValuePlace::EntidOrInteger(e) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(
+                    ValueType::Boolean,
+                    TypedValue::Ref(*e),
+                ));
+            }
+            EvolvedValuePlace::IdentOrKeyword(ref kw) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(
+                    ValueType::Boolean,
+                    TypedValue::Keyword(kw.clone()),
+                ));
+            }
+        }
     }
 
     fn reverse_lookup(
@@ -328,12 +397,14 @@ impl ConjoiningClauses {
     // TODO: use constant values -- extract transformation code from apply_pattern_clause_for_alias.
     // TODO: loop over all patterns until no more cache values apply?
     fn attempt_cache_lookup(&mut self, known: Known, pattern: &EvolvedPattern) -> bool {
-        // Precondition: default source. If it's not default, don't call this.
-        assert!(pattern.source == SrcVar::DefaultSrc);
+        // Precondition: the current state of the store. The cache knows nothing about views.
+        assert!(!self.sources.contains_key(&pattern.source));
 
         let schema = known.schema;
 
-        if pattern.tx != EvolvedNonValuePlace::Placeholder {
+        if pattern.tx != EvolvedNonValuePlace::Placeholder
+            || pattern.added != EvolvedValuePlace::Placeholder
+        {
             return false;
         }
 
@@ -570,11 +641,12 @@ impl ConjoiningClauses {
         known: Known,
         pattern: Pattern,
     ) -> PlaceOrEmpty<EvolvedPattern> {
-        let (e, a, v, tx, source) = (
+        let (e, a, v, tx, added, source) = (
             pattern.entity,
             pattern.attribute,
             pattern.value,
             pattern.tx,
+            pattern.added,
             pattern.source,
         );
         use self::PlaceOrEmpty::*;
@@ -586,13 +658,19 @@ impl ConjoiningClauses {
                     Empty(because) => Empty(because),
                     Place(v) => match self.make_evolved_tx(&known, tx) {
                         Empty(because) => Empty(because),
-                        Place(tx) => PlaceOrEmpty::Place(EvolvedPattern {
-                            source: source.unwrap_or(SrcVar::DefaultSrc),
-                            entity: e,
-                            attribute: a,
-                            value: v,
-                            tx,
-                        }),
+                        Place(tx) => {
+                            match self.make_evolved_value(&known, Some(ValueType::Boolean), added) {
+                                Empty(because) => Empty(because),
+                                Place(added) => PlaceOrEmpty::Place(EvolvedPattern {
+                                    source: source.unwrap_or(SrcVar::DefaultSrc),
+                                    entity: e,
+                                    attribute: a,
+                                    value: v,
+                                    tx,
+                                    added,
+                                }),
+                            }
+                        }
                     },
                 },
             },
@@ -610,6 +688,7 @@ impl ConjoiningClauses {
 
         let mut new_entity: Option<EvolvedNonValuePlace> = None;
         let mut new_value: Option<EvolvedValuePlace> = None;
+        let mut new_added: Option<EvolvedValuePlace> = None;
 
         match &pattern.entity {
             EvolvedNonValuePlace::Variable(ref var) => {
@@ -643,18 +722,33 @@ impl ConjoiningClauses {
             _ => (),
         }
 
+        match &pattern.added {
+            EvolvedValuePlace::Variable(ref var) => {
+                if let Some(tv) = self.bound_value(var) {
+                    new_added = Some(EvolvedValuePlace::Value(tv));
+                }
+            }
+            _ => (),
+        }
+
         if let Some(e) = new_entity {
             pattern.entity = e;
         }
         if let Some(v) = new_value {
             pattern.value = v;
         }
+        if let Some(added) = new_added {
+            pattern.added = added;
+        }
         Place(pattern)
     }
 
-    #[cfg(test)]
     pub(crate) fn apply_parsed_pattern(&mut self, known: Known, pattern: Pattern) {
         use self::PlaceOrEmpty::*;
+        if let Some(rows) = self.relation_for_source(&pattern.source) {
+            self.apply_relation_pattern(known, &pattern, &rows);
+            return;
+        }
         match self.make_evolved_pattern(known, pattern) {
             Empty(e) => self.mark_known_empty(e),
             Place(p) => self.apply_pattern(known, p),
@@ -662,12 +756,10 @@ impl ConjoiningClauses {
     }
 
     pub(crate) fn apply_pattern(&mut self, known: Known, pattern: EvolvedPattern) {
-        // For now we only support the default source.
-        if pattern.source != SrcVar::DefaultSrc {
-            unimplemented!();
-        }
-
-        if self.attempt_cache_lookup(known, &pattern) {
+        // Sources have been validated by this point: a source that isn't bound to a view is the
+        // default source, which might be served from the cache.
+        if !self.sources.contains_key(&pattern.source) && self.attempt_cache_lookup(known, &pattern)
+        {
             return;
         }
 
@@ -724,6 +816,7 @@ mod testing {
                 attribute: ident("foo", "bar"),
                 value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -746,6 +839,7 @@ mod testing {
                 attribute: ident("foo", "bar"),
                 value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -777,6 +871,7 @@ mod testing {
                 attribute: ident("foo", "bar"),
                 value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -828,6 +923,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Placeholder,
                 value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -893,6 +989,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Variable(a),
                 value: PatternValuePlace::Variable(v.clone()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -944,6 +1041,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Variable(a),
                 value: PatternValuePlace::Variable(v),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -972,6 +1070,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Variable(a),
                 value: PatternValuePlace::Variable(v),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -1012,6 +1111,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Placeholder,
                 value: PatternValuePlace::Constant("hello".into()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -1087,6 +1187,7 @@ mod testing {
                 attribute: ident("foo", "roz"),
                 value: PatternValuePlace::Constant("idgoeshere".into()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
         cc.apply_parsed_pattern(
@@ -1097,6 +1198,7 @@ mod testing {
                 attribute: ident("foo", "bar"),
                 value: PatternValuePlace::Variable(y),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -1183,6 +1285,7 @@ mod testing {
                 attribute: ident("foo", "bar"),
                 value: PatternValuePlace::Variable(y.clone()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -1243,6 +1346,7 @@ mod testing {
                 attribute: ident("foo", "bar"),
                 value: PatternValuePlace::Variable(y),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -1288,6 +1392,7 @@ mod testing {
                 attribute: ident("foo", "bar"),
                 value: PatternValuePlace::Variable(y),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -1334,6 +1439,7 @@ mod testing {
                 attribute: ident("foo", "roz"),
                 value: PatternValuePlace::Variable(y.clone()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
         cc.apply_parsed_pattern(
@@ -1344,6 +1450,7 @@ mod testing {
                 attribute: ident("foo", "bar"),
                 value: PatternValuePlace::Variable(y.clone()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
@@ -1385,6 +1492,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Variable(y.clone()),
                 value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
         cc.apply_parsed_pattern(
@@ -1395,6 +1503,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Variable(y),
                 value: PatternValuePlace::Variable(x.clone()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
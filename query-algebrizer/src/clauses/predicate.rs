@@ -20,7 +20,7 @@ use crate::clauses::convert::ValueTypes;
 
 use query_algebrizer_traits::errors::{AlgebrizerError, Result};
 
-use crate::types::{ColumnConstraint, EmptyBecause, Inequality, QueryValue};
+use crate::types::{ColumnConstraint, EmptyBecause, Inequality, QueryValue, ScalarFunction};
 
 use crate::Known;
 
@@ -29,20 +29,25 @@ impl ConjoiningClauses {
     /// There are several kinds of predicates in our Datalog:
     /// - A limited set of binary comparison operators: < > <= >= !=.
     ///   These are converted into SQLite binary comparisons and some type constraints.
-    /// - In the future, some predicates that are implemented via function calls in SQLite.
+    /// - String predicates like `starts-with?` and `re-find`, which are evaluated by SQLite.
     ///
-    /// At present we have implemented only the five built-in comparison binary operators.
+    /// At present we have implemented only these and the built-in comparison binary operators.
     pub(crate) fn apply_predicate(&mut self, known: Known, predicate: Predicate) -> Result<()> {
         // Because we'll be growing the set of built-in predicates, handling each differently,
         // and ultimately allowing user-specified predicates, we match on the predicate name first.
         if let Some(op) = Inequality::from_datalog_operator(predicate.operator.0.as_str()) {
             self.apply_inequality(known, op, predicate)
+        } else if let Some(function) =
+            ScalarFunction::from_datalog_operator(predicate.operator.0.as_str())
+                .filter(|f| f.is_predicate())
+        {
+            self.apply_computed_predicate(known, function, predicate)
         } else {
             bail!(AlgebrizerError::UnknownFunction(predicate.operator.clone()))
         }
     }
 
-    fn potential_types(&self, schema: &Schema, fn_arg: &FnArg) -> Result<ValueTypeSet> {
+    pub(crate) fn potential_types(&self, schema: &Schema, fn_arg: &FnArg) -> Result<ValueTypeSet> {
         match fn_arg {
             FnArg::Variable(ref v) => Ok(self.known_type_set(v)),
             _ => fn_arg.potential_types(schema),
@@ -239,6 +244,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Placeholder,
                 value: PatternValuePlace::Variable(y.clone()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
         assert!(!cc.is_known_empty());
@@ -323,6 +329,7 @@ mod testing {
                 attribute: PatternNonValuePlace::Placeholder,
                 value: PatternValuePlace::Variable(y.clone()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
         assert!(!cc.is_known_empty());
@@ -352,6 +359,7 @@ mod testing {
                 attribute: ident("foo", "roz"),
                 value: PatternValuePlace::Variable(y.clone()),
                 tx: PatternNonValuePlace::Placeholder,
+                added: PatternValuePlace::Placeholder,
             },
         );
 
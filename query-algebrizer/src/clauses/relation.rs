@@ -0,0 +1,173 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use core_traits::TypedValue;
+
+use mentat_core::HasSchema;
+
+use edn::query::{Keyword, Pattern, PatternNonValuePlace, PatternValuePlace, SrcVar, Variable};
+
+use crate::clauses::pattern::into_typed_value;
+
+use crate::clauses::ConjoiningClauses;
+
+use crate::types::EmptyBecause;
+
+use crate::Known;
+
+/// What a single place in a pattern asks of the corresponding position in a tuple.
+enum Term {
+    Any,
+    Bind(Variable),
+    OneOf(Vec<TypedValue>),
+}
+
+impl Term {
+    fn for_ident(known: &Known, ident: &Keyword) -> Term {
+        // An ident might be stored as a keyword or, resolved, as an entity.
+        let mut candidates = vec![TypedValue::Keyword(ident.clone().into())];
+        if let Some(entid) = known.schema.get_entid(ident) {
+            candidates.push(TypedValue::Ref(entid.0));
+        }
+        Term::OneOf(candidates)
+    }
+
+    fn for_integer(i: i64) -> Term {
+        Term::OneOf(vec![TypedValue::Ref(i), TypedValue::Long(i)])
+    }
+
+    fn from_non_value_place(known: &Known, place: &PatternNonValuePlace) -> Term {
+        match *place {
+            PatternNonValuePlace::Placeholder => Term::Any,
+            PatternNonValuePlace::Variable(ref v) => Term::Bind(v.clone()),
+            PatternNonValuePlace::Entid(e) => Term::for_integer(e),
+            PatternNonValuePlace::Ident(ref kw) => Term::for_ident(known, kw),
+        }
+    }
+
+    fn from_value_place(known: &Known, place: &PatternValuePlace) -> Term {
+        match *place {
+            PatternValuePlace::Placeholder => Term::Any,
+            PatternValuePlace::Variable(ref v) => Term::Bind(v.clone()),
+            PatternValuePlace::EntidOrInteger(i) => Term::for_integer(i),
+            PatternValuePlace::IdentOrKeyword(ref kw) => Term::for_ident(known, kw),
+            PatternValuePlace::Constant(ref c) => Term::OneOf(vec![into_typed_value(c.clone())]),
+        }
+    }
+}
+
+impl ConjoiningClauses {
+    /// Match a pattern against an in-memory relation. The relation is known now, so rather than
+    /// asking SQLite to do the work we filter its tuples here and bind the surviving values just
+    /// as `ground` would.
+    pub(crate) fn apply_relation_pattern(
+        &mut self,
+        known: Known,
+        pattern: &Pattern,
+        rows: &[Vec<TypedValue>],
+    ) {
+        if self.is_known_empty() {
+            return;
+        }
+
+        let terms = vec![
+            Term::from_non_value_place(&known, &pattern.entity),
+            Term::from_non_value_place(&known, &pattern.attribute),
+            Term::from_value_place(&known, &pattern.value),
+            Term::from_non_value_place(&known, &pattern.tx),
+            Term::from_value_place(&known, &pattern.added),
+        ];
+
+        // The variables that will take their values from the relation, each with the position
+        // that supplies it. Variables that are already bound to a value only filter.
+        let mut names: Vec<(Variable, usize)> = Vec::new();
+        for (i, term) in terms.iter().enumerate() {
+            if let Term::Bind(ref var) = *term {
+                if self.bound_value(var).is_none() && !names.iter().any(|(v, _)| v == var) {
+                    names.push((var.clone(), i));
+                }
+            }
+        }
+
+        let matching: Vec<&Vec<TypedValue>> = rows
+            .iter()
+            .filter(|row| self.tuple_matches(&terms, row))
+            .collect();
+
+        if matching.is_empty() {
+            let source = pattern.source.clone().unwrap_or(SrcVar::DefaultSrc);
+            self.mark_known_empty(EmptyBecause::NoMatchingTuples(source));
+            return;
+        }
+
+        if names.is_empty() {
+            // Some tuple matches, and that's all we needed to know.
+            return;
+        }
+
+        if matching.len() == 1 {
+            for (var, i) in names {
+                self.bind_value(&var, matching[0][i].clone());
+            }
+            return;
+        }
+
+        // `algebrize_with_inputs` rejects relations that aren't rectangular or that mix types
+        // within a position, so the first tuple tells us the type of each column.
+        let types = names
+            .iter()
+            .map(|&(_, i)| matching[0][i].value_type())
+            .collect();
+        let values = matching
+            .iter()
+            .flat_map(|row| names.iter().map(move |&(_, i)| row[i].clone()))
+            .collect();
+        let names = names.into_iter().map(|(var, _)| var).collect();
+        self.collect_named_bindings(known.schema, names, types, values);
+    }
+
+    fn tuple_matches(&self, terms: &[Term], row: &[TypedValue]) -> bool {
+        let mut seen: Vec<(&Variable, &TypedValue)> = Vec::new();
+        for (i, term) in terms.iter().enumerate() {
+            let value = match (term, row.get(i)) {
+                (Term::Any, _) => continue,
+                // The pattern names a place that this tuple doesn't have.
+                (_, None) => return false,
+                (_, Some(value)) => value,
+            };
+            match *term {
+                Term::Any => unreachable!(),
+                Term::OneOf(ref candidates) => {
+                    if !candidates.contains(value) {
+                        return false;
+                    }
+                }
+                Term::Bind(ref var) => {
+                    if !self.known_type_set(var).contains(value.value_type()) {
+                        return false;
+                    }
+                    if let Some(bound) = self.bound_value(var) {
+                        if bound != *value {
+                            return false;
+                        }
+                    }
+                    if let Some(&(_, previous)) = seen.iter().find(|(v, _)| *v == var) {
+                        if previous != value {
+                            return false;
+                        }
+                    } else {
+                        seen.push((var, value));
+                    }
+                }
+            }
+        }
+        true
+    }
+}
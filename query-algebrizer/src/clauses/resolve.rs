@@ -12,7 +12,7 @@ use core_traits::{TypedValue, ValueType};
 
 use mentat_core::{HasSchema, Schema};
 
-use edn::query::{FnArg, NonIntegerConstant, PlainSymbol};
+use edn::query::{FnArg, NonIntegerConstant, PlainSymbol, Variable};
 
 use crate::clauses::ConjoiningClauses;
 
@@ -22,6 +22,18 @@ use crate::types::{EmptyBecause, QueryValue};
 
 /// Argument resolution.
 impl ConjoiningClauses {
+    /// Turn a variable without a value into a `QueryValue`: the first column it's bound to, or,
+    /// for an `:in` variable of known type that isn't bound to a column, the input itself.
+    fn resolve_variable(&self, var: &Variable) -> Result<QueryValue> {
+        if let Some(col) = self.column_bindings.get(var).and_then(|cols| cols.first()) {
+            return Ok(QueryValue::Column(col.clone()));
+        }
+        if self.input_variables.contains(var) && self.known_type(var).is_some() {
+            return Ok(QueryValue::Input(var.clone()));
+        }
+        bail!(AlgebrizerError::UnboundVariable(var.name()))
+    }
+
     /// Take a function argument and turn it into a `QueryValue` suitable for use in a concrete
     /// constraint.
     /// Additionally, do two things:
@@ -45,10 +57,7 @@ impl ConjoiningClauses {
                     }
                 } else {
                     self.constrain_var_to_numeric(var.clone());
-                    self.column_bindings
-                        .get(&var)
-                        .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
-                        .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()))
+                    self.resolve_variable(&var)
                 }
             },
             // Can't be an entid.
@@ -60,6 +69,7 @@ impl ConjoiningClauses {
             Constant(NonIntegerConstant::Uuid(_)) |
             Constant(NonIntegerConstant::Instant(_)) |        // Instants are covered below.
             Constant(NonIntegerConstant::BigInteger(_)) |
+            Placeholder |
             Vector(_) => {
                 self.mark_known_empty(EmptyBecause::NonNumericArgument);
                 bail!(AlgebrizerError::InvalidArgument(function.clone(), "numeric", position))
@@ -86,10 +96,7 @@ impl ConjoiningClauses {
                 )),
                 None => {
                     self.constrain_var_to_type(var.clone(), ValueType::Instant);
-                    self.column_bindings
-                        .get(&var)
-                        .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
-                        .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()))
+                    self.resolve_variable(&var)
                 }
             },
             Constant(NonIntegerConstant::Instant(v)) => {
@@ -105,6 +112,7 @@ impl ConjoiningClauses {
             | Constant(NonIntegerConstant::Text(_))
             | Constant(NonIntegerConstant::Uuid(_))
             | Constant(NonIntegerConstant::BigInteger(_))
+            | Placeholder
             | Vector(_) => {
                 self.mark_known_empty(EmptyBecause::NonInstantArgument);
                 bail!(AlgebrizerError::InvalidArgumentType(
@@ -133,10 +141,7 @@ impl ConjoiningClauses {
                     // Incorrect types will be handled by the constraint, above.
                     Ok(QueryValue::Entid(e))
                 } else {
-                    self.column_bindings
-                        .get(&var)
-                        .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
-                        .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()))
+                    self.resolve_variable(&var)
                 }
             }
             EntidOrInteger(i) => Ok(QueryValue::TypedValue(TypedValue::Ref(i))),
@@ -151,6 +156,7 @@ impl ConjoiningClauses {
             | Constant(NonIntegerConstant::Instant(_))
             | Constant(NonIntegerConstant::BigInteger(_))
             | SrcVar(_)
+            | Placeholder
             | Vector(_) => {
                 self.mark_known_empty(EmptyBecause::NonEntityArgument);
                 bail!(AlgebrizerError::InvalidArgumentType(
@@ -178,17 +184,12 @@ impl ConjoiningClauses {
 
     /// Take a function argument and turn it into a `QueryValue` suitable for use in a concrete
     /// constraint.
-    #[allow(dead_code)]
-    fn resolve_argument(&self, arg: FnArg) -> Result<QueryValue> {
+    pub(crate) fn resolve_argument(&self, arg: FnArg) -> Result<QueryValue> {
         use self::FnArg::*;
         match arg {
             FnArg::Variable(var) => match self.bound_value(&var) {
                 Some(v) => Ok(QueryValue::TypedValue(v)),
-                None => self
-                    .column_bindings
-                    .get(&var)
-                    .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
-                    .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name())),
+                None => self.resolve_variable(&var),
             },
             EntidOrInteger(i) => Ok(QueryValue::PrimitiveLong(i)),
             IdentOrKeyword(_) => unimplemented!(), // TODO
@@ -208,7 +209,7 @@ impl ConjoiningClauses {
                 Ok(QueryValue::TypedValue(TypedValue::Instant(u)))
             }
             Constant(NonIntegerConstant::BigInteger(_)) => unimplemented!(),
-            SrcVar(_) => unimplemented!(),
+            SrcVar(_) | Placeholder => bail!(AlgebrizerError::UnsupportedArgument),
             Vector(_) => unimplemented!(), // TODO
         }
     }
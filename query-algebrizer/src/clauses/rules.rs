@@ -0,0 +1,485 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use core_traits::ValueTypeSet;
+
+use mentat_core::counter::RcCounter;
+
+use edn::query::{
+    Binding, FnArg, NotJoin, OrJoin, OrWhereClause, Pattern, PatternNonValuePlace,
+    PatternValuePlace, PlainSymbol, Predicate, Rule, RuleExpr, TypeAnnotation, UnifyVars, Variable,
+    VariableOrPlaceholder, WhereClause, WhereFn,
+};
+
+use query_algebrizer_traits::errors::{AlgebrizerError, Result};
+
+use crate::clauses::{ConjoiningClauses, PushComputed};
+
+use crate::types::{
+    ComputedTable, DatomsTable, EmptyBecause, PlaceOrEmpty, QualifiedAlias, SourceAlias,
+    VariableColumn,
+};
+
+use crate::validate::validate_or_join;
+
+use crate::Known;
+
+/// Return a variable named after `var` that can't collide with any variable in the query:
+/// `#` can't appear in a parsed variable name.
+fn fresh_variable(counter: &RcCounter, var: &Variable) -> Variable {
+    Variable::from_valid_name(&format!("{}#{:02}", var.as_str(), counter.next()))
+}
+
+/// Count the invocations of the named rule at the top level of a rule definition.
+fn self_calls(name: &PlainSymbol, rule: &Rule) -> usize {
+    rule.clauses
+        .iter()
+        .filter(|clause| matches!(clause, WhereClause::RuleExpr(r) if &r.name == name))
+        .count()
+}
+
+/// Rewrites the clauses of a rule definition for a particular use of that definition.
+///
+/// The variables in the head of the definition are replaced by the variables supplied at the
+/// call site. Every other variable is local to the definition, and so is given a fresh name.
+struct RuleScope {
+    mapping: BTreeMap<Variable, Variable>,
+    counter: RcCounter,
+}
+
+impl RuleScope {
+    fn new(head: &[Variable], targets: &[Variable], counter: RcCounter) -> RuleScope {
+        RuleScope {
+            mapping: head.iter().cloned().zip(targets.iter().cloned()).collect(),
+            counter,
+        }
+    }
+
+    fn var(&mut self, var: Variable) -> Variable {
+        if let Some(existing) = self.mapping.get(&var) {
+            return existing.clone();
+        }
+        let fresh = fresh_variable(&self.counter, &var);
+        self.mapping.insert(var, fresh.clone());
+        fresh
+    }
+
+    fn non_value_place(&mut self, place: PatternNonValuePlace) -> PatternNonValuePlace {
+        match place {
+            PatternNonValuePlace::Variable(v) => PatternNonValuePlace::Variable(self.var(v)),
+            place => place,
+        }
+    }
+
+    fn value_place(&mut self, place: PatternValuePlace) -> PatternValuePlace {
+        match place {
+            PatternValuePlace::Variable(v) => PatternValuePlace::Variable(self.var(v)),
+            place => place,
+        }
+    }
+
+    fn fn_arg(&mut self, arg: FnArg) -> FnArg {
+        match arg {
+            FnArg::Variable(v) => FnArg::Variable(self.var(v)),
+            FnArg::Vector(args) => {
+                FnArg::Vector(args.into_iter().map(|a| self.fn_arg(a)).collect())
+            }
+            arg => arg,
+        }
+    }
+
+    fn variable_or_placeholder(&mut self, place: VariableOrPlaceholder) -> VariableOrPlaceholder {
+        match place {
+            VariableOrPlaceholder::Variable(v) => VariableOrPlaceholder::Variable(self.var(v)),
+            VariableOrPlaceholder::Placeholder => VariableOrPlaceholder::Placeholder,
+        }
+    }
+
+    fn binding(&mut self, binding: Binding) -> Binding {
+        match binding {
+            Binding::BindScalar(v) => Binding::BindScalar(self.var(v)),
+            Binding::BindColl(v) => Binding::BindColl(self.var(v)),
+            Binding::BindRel(vs) => Binding::BindRel(
+                vs.into_iter()
+                    .map(|v| self.variable_or_placeholder(v))
+                    .collect(),
+            ),
+            Binding::BindTuple(vs) => Binding::BindTuple(
+                vs.into_iter()
+                    .map(|v| self.variable_or_placeholder(v))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn unify_vars(&mut self, unify_vars: UnifyVars) -> UnifyVars {
+        match unify_vars {
+            UnifyVars::Implicit => UnifyVars::Implicit,
+            UnifyVars::Explicit(vs) => {
+                UnifyVars::Explicit(vs.into_iter().map(|v| self.var(v)).collect())
+            }
+        }
+    }
+
+    fn clauses(&mut self, clauses: Vec<WhereClause>) -> Vec<WhereClause> {
+        clauses.into_iter().map(|c| self.clause(c)).collect()
+    }
+
+    fn clause(&mut self, clause: WhereClause) -> WhereClause {
+        match clause {
+            WhereClause::Pattern(p) => WhereClause::Pattern(Pattern {
+                source: p.source,
+                entity: self.non_value_place(p.entity),
+                attribute: self.non_value_place(p.attribute),
+                value: self.value_place(p.value),
+                tx: self.non_value_place(p.tx),
+                added: self.value_place(p.added),
+            }),
+            WhereClause::Pred(p) => WhereClause::Pred(Predicate {
+                operator: p.operator,
+                args: p.args.into_iter().map(|a| self.fn_arg(a)).collect(),
+            }),
+            WhereClause::WhereFn(f) => WhereClause::WhereFn(WhereFn {
+                operator: f.operator,
+                args: f.args.into_iter().map(|a| self.fn_arg(a)).collect(),
+                binding: self.binding(f.binding),
+            }),
+            WhereClause::OrJoin(o) => {
+                let (clauses, unify_vars, _) = o.dismember();
+                let unify_vars = self.unify_vars(unify_vars);
+                let clauses = clauses
+                    .into_iter()
+                    .map(|c| match c {
+                        OrWhereClause::Clause(c) => OrWhereClause::Clause(self.clause(c)),
+                        OrWhereClause::And(cs) => OrWhereClause::And(self.clauses(cs)),
+                    })
+                    .collect();
+                WhereClause::OrJoin(OrJoin::new(unify_vars, clauses))
+            }
+            WhereClause::NotJoin(n) => {
+                let unify_vars = self.unify_vars(n.unify_vars);
+                WhereClause::NotJoin(NotJoin::new(unify_vars, self.clauses(n.clauses)))
+            }
+            WhereClause::TypeAnnotation(a) => WhereClause::TypeAnnotation(TypeAnnotation {
+                value_type: a.value_type,
+                variable: self.var(a.variable),
+            }),
+            WhereClause::RuleExpr(r) => WhereClause::RuleExpr(RuleExpr {
+                name: r.name,
+                args: r.args.into_iter().map(|a| self.value_place(a)).collect(),
+            }),
+        }
+    }
+}
+
+/// Union the types of each column across each arm of a rule.
+fn column_types<'a, I>(columns: &[Variable], arms: I) -> BTreeMap<Variable, ValueTypeSet>
+where
+    I: IntoIterator<Item = &'a ConjoiningClauses>,
+{
+    let mut types: BTreeMap<Variable, ValueTypeSet> = columns
+        .iter()
+        .map(|c| (c.clone(), ValueTypeSet::none()))
+        .collect();
+    for cc in arms {
+        for (column, acc) in types.iter_mut() {
+            *acc = acc.union(cc.known_type_set(column));
+        }
+    }
+    types
+}
+
+impl ConjoiningClauses {
+    /// Apply an invocation of a rule, like `(ancestor ?x ?y)`.
+    ///
+    /// A rule with a single definition is simply inlined. A rule with several definitions is
+    /// equivalent to an `or-join` on its arguments. A rule that refers to itself is evaluated to
+    /// a fixpoint as a recursive common table expression.
+    pub(crate) fn apply_rule_expr(&mut self, known: Known, expr: RuleExpr) -> Result<()> {
+        let RuleExpr { name, args } = expr;
+
+        // We can only express direct recursion, which is handled below without re-entering here.
+        if self.rules_in_progress.contains(&name) {
+            bail!(AlgebrizerError::UnsupportedRuleRecursion(name));
+        }
+
+        let definitions = match self.rules.get(&name) {
+            Some(definitions) => definitions.clone(),
+            None => bail!(AlgebrizerError::UnknownRule(name)),
+        };
+
+        if let Some(definition) = definitions.iter().find(|d| d.vars.len() != args.len()) {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(
+                name,
+                args.len(),
+                definition.vars.len()
+            ));
+        }
+
+        // Every argument becomes a variable. Constants are grounded after the rule is applied,
+        // at which point we know enough about the types involved to interpret them.
+        let (vars, grounds) = self.variables_for_rule_args(args);
+
+        let recursive = definitions.iter().any(|d| self_calls(&name, d) > 0);
+
+        self.rules_in_progress.insert(name.clone());
+        if recursive {
+            self.apply_recursive_rule(known, &name, definitions, vars)?;
+        } else {
+            self.apply_rule_definitions(known, definitions, vars)?;
+        }
+        self.rules_in_progress.remove(&name);
+
+        for ground in grounds.into_iter() {
+            self.apply_clause(known, ground)?;
+        }
+        Ok(())
+    }
+
+    /// Turn the arguments to a rule into variables, returning those variables and any `ground`
+    /// clauses needed to bind constant arguments.
+    fn variables_for_rule_args(
+        &self,
+        args: Vec<PatternValuePlace>,
+    ) -> (Vec<Variable>, Vec<WhereClause>) {
+        let placeholder = Variable::from_valid_name("?_");
+        let mut grounds = Vec::new();
+        let vars = args
+            .into_iter()
+            .map(|arg| {
+                let constant = match arg {
+                    PatternValuePlace::Variable(v) => return v,
+                    PatternValuePlace::Placeholder => {
+                        return fresh_variable(&self.alias_counter, &placeholder)
+                    }
+                    PatternValuePlace::EntidOrInteger(i) => FnArg::EntidOrInteger(i),
+                    PatternValuePlace::IdentOrKeyword(k) => FnArg::IdentOrKeyword((*k).clone()),
+                    PatternValuePlace::Constant(c) => FnArg::Constant(c),
+                };
+                let var = fresh_variable(&self.alias_counter, &placeholder);
+                grounds.push(WhereClause::WhereFn(WhereFn {
+                    operator: PlainSymbol::plain("ground"),
+                    args: vec![constant],
+                    binding: Binding::BindScalar(var.clone()),
+                }));
+                var
+            })
+            .collect();
+        (vars, grounds)
+    }
+
+    /// Apply the definitions of a non-recursive rule directly to this CC.
+    fn apply_rule_definitions(
+        &mut self,
+        known: Known,
+        definitions: Vec<Rule>,
+        vars: Vec<Variable>,
+    ) -> Result<()> {
+        let mut arms: Vec<Vec<WhereClause>> = definitions
+            .into_iter()
+            .map(|d| RuleScope::new(&d.vars, &vars, self.alias_counter.clone()).clauses(d.clauses))
+            .collect();
+
+        if arms.len() == 1 {
+            return self.apply_clauses(known, arms.pop().unwrap());
+        }
+
+        let or_join = OrJoin::new(
+            UnifyVars::Explicit(vars.into_iter().collect()),
+            arms.into_iter().map(OrWhereClause::And).collect(),
+        );
+        validate_or_join(&or_join)?;
+        self.apply_or_join(known, or_join)
+    }
+
+    /// Finish algebrizing one arm of a recursive rule.
+    fn complete_rule_arm(
+        mut cc: ConjoiningClauses,
+        columns: &[Variable],
+        head: &[Variable],
+    ) -> Result<PlaceOrEmpty<ConjoiningClauses>> {
+        if cc.is_known_empty() {
+            return Ok(PlaceOrEmpty::Empty(cc.empty_because.unwrap()));
+        }
+        cc.expand_column_bindings();
+        cc.prune_extracted_types();
+        cc.process_required_types()?;
+        if let Some(because) = cc.empty_because {
+            return Ok(PlaceOrEmpty::Empty(because));
+        }
+
+        // Every arm must produce a value for every column.
+        for (column, var) in columns.iter().zip(head.iter()) {
+            if !cc.is_value_bound(column) && !cc.column_bindings.contains_key(column) {
+                bail!(AlgebrizerError::UnboundVariable(var.name()));
+            }
+        }
+        Ok(PlaceOrEmpty::Place(cc))
+    }
+
+    /// Algebrize the recursive definition of a rule: the call to the rule itself is replaced by
+    /// a reference to the rows computed so far, described by `types`.
+    fn recursive_rule_arm(
+        &self,
+        known: Known,
+        columns: &[Variable],
+        types: &BTreeMap<Variable, ValueTypeSet>,
+        call: Vec<PatternValuePlace>,
+        clauses: Vec<WhereClause>,
+    ) -> Result<ConjoiningClauses> {
+        let mut cc = self.make_rule_scope();
+        let (vars, grounds) = cc.variables_for_rule_args(call);
+
+        let alias = cc.next_alias_for_table(DatomsTable::Rule);
+        for (column, var) in columns.iter().zip(vars) {
+            cc.bind_column_to_var(
+                known.schema,
+                alias.clone(),
+                VariableColumn::Variable(column.clone()),
+                var.clone(),
+            );
+            let column_types = types[column];
+            if !column_types.is_unit() {
+                cc.extracted_types.entry(var.clone()).or_insert_with(|| {
+                    QualifiedAlias::new(
+                        alias.clone(),
+                        VariableColumn::VariableTypeTag(column.clone()),
+                    )
+                });
+            }
+            cc.narrow_types_for_var(var, column_types);
+        }
+        cc.from.push(SourceAlias(DatomsTable::Rule, alias));
+
+        cc.apply_clauses(known, clauses)?;
+        for ground in grounds.into_iter() {
+            cc.apply_clause(known, ground)?;
+        }
+        Ok(cc)
+    }
+
+    /// Apply a rule with one directly recursive definition. The rule is computed in isolation --
+    /// the `base` definitions seed the relation, and the recursive definition extends it until
+    /// nothing changes -- and the result joined against the call's arguments.
+    fn apply_recursive_rule(
+        &mut self,
+        known: Known,
+        name: &PlainSymbol,
+        definitions: Vec<Rule>,
+        vars: Vec<Variable>,
+    ) -> Result<()> {
+        // Each definition projects one column per argument. These names are shared by every arm.
+        let columns: Vec<Variable> = (0..vars.len())
+            .map(|i| Variable::from_valid_name(&format!("?{}#{}", name, i)))
+            .collect();
+
+        let mut base: Vec<(Vec<WhereClause>, Vec<Variable>)> = Vec::new();
+        let mut recursive: Vec<(RuleExpr, Vec<WhereClause>, Vec<Variable>)> = Vec::new();
+        for definition in definitions.into_iter() {
+            let calls = self_calls(name, &definition);
+            let mut clauses =
+                RuleScope::new(&definition.vars, &columns, self.alias_counter.clone())
+                    .clauses(definition.clauses);
+            match calls {
+                0 => base.push((clauses, definition.vars)),
+                1 => {
+                    let position = clauses
+                        .iter()
+                        .position(|c| matches!(c, WhereClause::RuleExpr(r) if &r.name == name))
+                        .expect("a recursive call");
+                    if let WhereClause::RuleExpr(call) = clauses.remove(position) {
+                        recursive.push((call, clauses, definition.vars));
+                    }
+                }
+                _ => bail!(AlgebrizerError::UnsupportedRuleRecursion(name.clone())),
+            }
+        }
+
+        // SQLite allows only a single recursive arm in a recursive CTE, and the recursion has to
+        // start somewhere.
+        if base.is_empty() || recursive.len() != 1 {
+            bail!(AlgebrizerError::UnsupportedRuleRecursion(name.clone()));
+        }
+        let (call, body, head) = recursive.pop().unwrap();
+
+        let mut base_arms = Vec::with_capacity(base.len());
+        let mut empty_because: Option<EmptyBecause> = None;
+        for (clauses, head) in base.into_iter() {
+            let mut cc = self.make_rule_scope();
+            cc.apply_clauses(known, clauses)?;
+            match ConjoiningClauses::complete_rule_arm(cc, &columns, &head)? {
+                PlaceOrEmpty::Place(arm) => base_arms.push(arm),
+                PlaceOrEmpty::Empty(because) => empty_because = Some(because),
+            }
+        }
+
+        if base_arms.is_empty() {
+            // Nothing to recurse from.
+            self.mark_known_empty(empty_because.expect("empty for a reason"));
+            return Ok(());
+        }
+
+        // The recursive arm reads back rows that it produced itself, so the types of its columns
+        // are a fixpoint, too. Type sets only grow, so this terminates quickly.
+        let mut types = column_types(&columns, base_arms.iter());
+        let recursive_arms = loop {
+            let cc =
+                self.recursive_rule_arm(known, &columns, &types, call.args.clone(), body.clone())?;
+            match ConjoiningClauses::complete_rule_arm(cc, &columns, &head)? {
+                PlaceOrEmpty::Empty(_) => break vec![],
+                PlaceOrEmpty::Place(arm) => {
+                    let grown = column_types(&columns, base_arms.iter().chain(Some(&arm)));
+                    if grown == types {
+                        break vec![arm];
+                    }
+                    types = grown;
+                }
+            }
+        };
+
+        let projection: BTreeSet<Variable> = columns.iter().cloned().collect();
+        let type_extraction: BTreeSet<Variable> = columns
+            .iter()
+            .filter(|c| !types[c].is_unit())
+            .cloned()
+            .collect();
+
+        let table = self
+            .computed_tables
+            .push_computed(ComputedTable::RecursiveUnion {
+                projection,
+                type_extraction: type_extraction.clone(),
+                base: base_arms,
+                recursive: recursive_arms,
+            });
+        let alias = self.next_alias_for_table(table.clone());
+
+        // Stitch the computed table into our column bindings.
+        for (column, var) in columns.into_iter().zip(vars) {
+            self.bind_column_to_var(
+                known.schema,
+                alias.clone(),
+                VariableColumn::Variable(column.clone()),
+                var.clone(),
+            );
+            let column_types = types[&column];
+            if type_extraction.contains(&column) {
+                self.extracted_types.entry(var.clone()).or_insert_with(|| {
+                    QualifiedAlias::new(alias.clone(), VariableColumn::VariableTypeTag(column))
+                });
+            }
+            self.narrow_types_for_var(var, column_types);
+        }
+        self.from.push(SourceAlias(table, alias));
+        Ok(())
+    }
+}
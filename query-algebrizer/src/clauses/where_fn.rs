@@ -12,6 +12,8 @@ use edn::query::WhereFn;
 
 use crate::clauses::ConjoiningClauses;
 
+use crate::types::ScalarFunction;
+
 use query_algebrizer_traits::errors::{AlgebrizerError, Result};
 
 use crate::Known;
@@ -21,7 +23,8 @@ impl ConjoiningClauses {
     /// There are several kinds of functions binding variables in our Datalog:
     /// - A set of functions like `ground`, fulltext` and `get-else` that are translated into SQL
     ///   `VALUES`, `MATCH`, or `JOIN`, yielding bindings.
-    /// - In the future, some functions that are implemented via function calls in SQLite.
+    /// - Arithmetic and string functions like `+` and `str` that are evaluated by SQLite, binding
+    ///   a variable to the resulting expression.
     ///
     /// At present we have implemented only a limited selection of functions.
     pub(crate) fn apply_where_fn(&mut self, known: Known, where_fn: WhereFn) -> Result<()> {
@@ -32,7 +35,12 @@ impl ConjoiningClauses {
             "ground" => self.apply_ground(known, where_fn),
             "tx-data" => self.apply_tx_data(known, where_fn),
             "tx-ids" => self.apply_tx_ids(known, where_fn),
-            _ => bail!(AlgebrizerError::UnknownFunction(where_fn.operator.clone())),
+            op => match ScalarFunction::from_datalog_operator(op) {
+                Some(function) if !function.is_predicate() => {
+                    self.apply_computed_binding(known, function, where_fn)
+                }
+                _ => bail!(AlgebrizerError::UnknownFunction(where_fn.operator.clone())),
+            },
         }
     }
 }
@@ -18,7 +18,7 @@ extern crate query_algebrizer_traits;
 
 use std::collections::BTreeSet;
 use std::ops::Sub;
-use std::rc::Rc;
+use std::sync::Arc;
 
 mod clauses;
 mod types;
@@ -26,15 +26,20 @@ mod validate;
 
 use core_traits::{Entid, TypedValue, ValueType};
 
-use mentat_core::{parse_query, CachedAttributes, Schema};
+use mentat_core::{parse_query, parse_rules, CachedAttributes, HasSchema, Schema};
 
 use mentat_core::counter::RcCounter;
 
-use edn::query::{Element, FindSpec, Limit, Order, ParsedQuery, SrcVar, Variable, WhereClause};
+use edn::query::{
+    Element, FindSpec, FnArg, Limit, NamedPullAttribute, Order, ParsedQuery, PullAttributeSpec,
+    PullConcreteAttribute, PullMapValue, Rule, SrcVar, Variable, WhereClause,
+};
 
 use query_algebrizer_traits::errors::{AlgebrizerError, Result};
 
-pub use crate::clauses::{QueryInputs, VariableBindings};
+pub use crate::clauses::{DatabaseView, QueryInputs, QuerySource, VariableBindings};
+
+use crate::validate::validate_relation;
 
 pub use crate::types::{EmptyBecause, FindQuery};
 
@@ -137,7 +142,7 @@ impl<'s, 'c> Known<'s, 'c> {
 #[derive(Debug)]
 pub struct AlgebraicQuery {
     default_source: SrcVar,
-    pub find_spec: Rc<FindSpec>,
+    pub find_spec: Arc<FindSpec>,
     has_aggregates: bool,
 
     /// The set of variables that the caller wishes to be used for grouping when aggregating.
@@ -174,9 +179,11 @@ impl AlgebraicQuery {
                 self.cc.is_value_bound(var)
             }
 
-            // For now, we pretend that aggregate functions are never fully bound:
-            // we don't statically compute them, even if we know the value of the var.
-            &Element::Aggregate(ref _fn) => false,
+            // An aggregate over a single bound variable can be computed statically.
+            &Element::Aggregate(ref agg) => match agg.args.as_slice() {
+                [FnArg::Variable(ref var)] => self.cc.is_value_bound(var),
+                _ => false,
+            },
         })
     }
 
@@ -193,6 +200,66 @@ impl AlgebraicQuery {
             .input_variables
             .sub(&self.cc.value_bound_variable_set())
     }
+
+    /// Return the attributes whose datoms can change this query's results, or `None` if datoms of
+    /// any attribute can: for instance, if a pattern doesn't name its attribute, or a pull
+    /// expression uses a wildcard. A change to the schema can change any query's results.
+    pub fn referenced_attributes(&self, schema: &Schema) -> Option<BTreeSet<Entid>> {
+        let mut attributes = BTreeSet::new();
+        if !self.cc.collect_referenced_attributes(&mut attributes) {
+            return None;
+        }
+        if self.is_known_empty() {
+            return Some(attributes);
+        }
+        for element in self.find_spec.columns() {
+            if let Element::Pull(ref pull) = *element {
+                if !collect_pulled_attributes(schema, &pull.patterns, &mut attributes) {
+                    return None;
+                }
+            }
+        }
+        Some(attributes)
+    }
+}
+
+fn collect_pulled_attributes(
+    schema: &Schema,
+    patterns: &[PullAttributeSpec],
+    attributes: &mut BTreeSet<Entid>,
+) -> bool {
+    // Unknown attributes can't be pulled, so they don't matter.
+    let resolve = |named: &NamedPullAttribute| match named.attribute {
+        PullConcreteAttribute::Entid(e) => Some(e),
+        PullConcreteAttribute::Ident(ref ident) => {
+            let ident = ident.unreversed().unwrap_or_else(|| (**ident).clone());
+            schema.get_entid(&ident).map(|e| e.into())
+        }
+    };
+
+    for pattern in patterns {
+        match *pattern {
+            PullAttributeSpec::Wildcard => return false,
+            PullAttributeSpec::Attribute(ref named)
+            | PullAttributeSpec::LimitedAttribute(ref named, _)
+            | PullAttributeSpec::DefaultedAttribute(ref named, _) => {
+                attributes.extend(resolve(named));
+            }
+            PullAttributeSpec::PullMapSpec(ref entries) => {
+                for (key, value) in entries.iter() {
+                    if !collect_pulled_attributes(schema, ::std::slice::from_ref(key), attributes) {
+                        return false;
+                    }
+                    if let PullMapValue::Pattern(nested) = value {
+                        if !collect_pulled_attributes(schema, nested, attributes) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    true
 }
 
 pub fn algebrize_with_counter(
@@ -302,8 +369,29 @@ pub fn algebrize_with_inputs(
     known: Known,
     parsed: FindQuery,
     counter: usize,
-    inputs: QueryInputs,
+    mut inputs: QueryInputs,
 ) -> Result<AlgebraicQuery> {
+    // Rules are only visible to queries that ask for them with `%`.
+    if !parsed.in_rules {
+        inputs.rules.clear();
+    }
+
+    // Likewise, a bound source is only visible if it's named in `:in`, and every source named
+    // there must be bound. The default source is always available.
+    inputs
+        .sources
+        .retain(|source, _| *source == SrcVar::DefaultSrc || parsed.in_sources.contains(source));
+    for source in parsed.in_sources.iter() {
+        if *source != SrcVar::DefaultSrc && !inputs.sources.contains_key(source) {
+            bail!(AlgebrizerError::UnboundSource(source.clone()));
+        }
+    }
+    for (source, bound) in inputs.sources.iter() {
+        if let QuerySource::Relation(ref rows) = *bound {
+            validate_relation(source, rows)?;
+        }
+    }
+
     let alias_counter = RcCounter::with_initial(counter);
     let mut cc =
         ConjoiningClauses::with_inputs_and_alias_counter(parsed.in_vars, inputs, alias_counter);
@@ -334,7 +422,7 @@ pub fn algebrize_with_inputs(
     };
     let q = AlgebraicQuery {
         default_source: parsed.default_source,
-        find_spec: Rc::new(parsed.find_spec),
+        find_spec: Arc::new(parsed.find_spec),
         has_aggregates: false, // TODO: we don't parse them yet.
         with: parsed.with,
         named_projection: extra_vars,
@@ -351,8 +439,8 @@ pub use crate::clauses::ConjoiningClauses;
 
 pub use crate::types::{
     Column, ColumnAlternation, ColumnConstraint, ColumnConstraintOrAlternation, ColumnIntersection,
-    ColumnName, ComputedTable, DatomsColumn, DatomsTable, FulltextColumn, OrderBy, QualifiedAlias,
-    QueryValue, SourceAlias, TableAlias, VariableColumn,
+    ColumnName, ComputedTable, ComputedValue, DatomsColumn, DatomsTable, FulltextColumn, OrderBy,
+    QualifiedAlias, QueryValue, ScalarFunction, SourceAlias, TableAlias, VariableColumn,
 };
 
 impl FindQuery {
@@ -363,6 +451,7 @@ impl FindQuery {
             with: BTreeSet::default(),
             in_vars: BTreeSet::default(),
             in_sources: BTreeSet::default(),
+            in_rules: false,
             limit: Limit::None,
             where_clauses,
             order: None,
@@ -407,6 +496,7 @@ impl FindQuery {
             with,
             in_vars,
             in_sources: parsed.in_sources,
+            in_rules: parsed.in_rules,
             limit: parsed.limit,
             where_clauses: parsed.where_clauses,
             order: parsed.order,
@@ -419,3 +509,9 @@ pub fn parse_find_string(string: &str) -> Result<FindQuery> {
         .map_err(|e| e.into())
         .and_then(FindQuery::from_parsed_query)
 }
+
+/// Parse a set of rule definitions, `[[(name ?a ?b) clause…] …]`, for use with
+/// `QueryInputs::with_rules`.
+pub fn parse_rules_string(string: &str) -> Result<Vec<Rule>> {
+    parse_rules(string).map_err(|e| e.into())
+}
@@ -11,6 +11,7 @@
 use std::collections::BTreeSet;
 use std::fmt::{Debug, Formatter};
 
+use core_traits::attribute::FulltextTokenizer;
 use core_traits::{Entid, TypedValue, ValueType, ValueTypeSet};
 
 use mentat_core::ValueRc;
@@ -19,14 +20,20 @@ use edn::query::{Direction, FindSpec, Keyword, Limit, Order, SrcVar, Variable, W
 
 /// This enum models the fixed set of default tables we have -- two
 /// tables and two views -- and computed tables defined in the enclosing CC.
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub enum DatomsTable {
-    Datoms,          // The non-fulltext datoms table.
-    FulltextValues,  // The virtual table mapping IDs to strings.
-    FulltextDatoms,  // The fulltext-datoms view.
-    AllDatoms,       // Fulltext and non-fulltext datoms.
-    Computed(usize), // A computed table, tracked elsewhere in the query.
-    Transactions,    // The transactions table, which makes the tx-data log API efficient.
+    Datoms, // The non-fulltext datoms table.
+    // The fulltext index with the given tokenizer.
+    FulltextValues(FulltextTokenizer),
+    FulltextDatoms,   // The fulltext-datoms view.
+    AllDatoms,        // Fulltext and non-fulltext datoms.
+    Computed(usize),  // A computed table, tracked elsewhere in the query.
+    Transactions,     // The transactions table, which makes the tx-data log API efficient.
+    Rule,             // The working table of the enclosing recursive rule.
+    AsOf(Entid),      // The store as it was immediately after the given transaction.
+    Since(Entid),     // Current datoms asserted after the given transaction.
+    History,          // Every assertion and retraction, from the transaction log.
+    Attached(String), // All datoms of another store attached to the connection under this name.
 }
 
 /// A source of rows that isn't a named table -- typically a subquery or union.
@@ -42,17 +49,30 @@ pub enum ComputedTable {
         names: Vec<Variable>,
         values: Vec<TypedValue>,
     },
+    /// A recursive rule, computed to a fixpoint. The `base` arms are evaluated first; the
+    /// `recursive` arm refers to the rows accumulated so far via `DatomsTable::Rule`.
+    RecursiveUnion {
+        projection: BTreeSet<Variable>,
+        type_extraction: BTreeSet<Variable>,
+        base: Vec<crate::clauses::ConjoiningClauses>,
+        recursive: Vec<crate::clauses::ConjoiningClauses>,
+    },
 }
 
 impl DatomsTable {
     pub fn name(&self) -> &'static str {
         match *self {
             DatomsTable::Datoms => "datoms",
-            DatomsTable::FulltextValues => "fulltext_values",
+            DatomsTable::FulltextValues(tokenizer) => tokenizer.index_table(),
             DatomsTable::FulltextDatoms => "fulltext_datoms",
             DatomsTable::AllDatoms => "all_datoms",
             DatomsTable::Computed(_) => "c",
             DatomsTable::Transactions => "transactions",
+            DatomsTable::Rule => "rule",
+            DatomsTable::AsOf(_) => "as_of",
+            DatomsTable::Since(_) => "since",
+            DatomsTable::History => "history",
+            DatomsTable::Attached(_) => "attached",
         }
     }
 }
@@ -72,10 +92,16 @@ pub enum DatomsColumn {
 }
 
 /// One of the named columns of our fulltext values table.
+///
+/// `Score`, `Snippet`, and `Highlight` aren't stored: SQLite computes them for each match, using
+/// the index for the given tokenizer.
 #[derive(PartialEq, Eq, Clone)]
 pub enum FulltextColumn {
     Rowid,
     Text,
+    Score(FulltextTokenizer),
+    Snippet(FulltextTokenizer),
+    Highlight(FulltextTokenizer),
 }
 
 /// One of the named columns of our transactions table.
@@ -101,6 +127,7 @@ pub enum Column {
     Fulltext(FulltextColumn),
     Variable(VariableColumn),
     Transactions(TransactionsColumn),
+    Computed(ComputedValue),
 }
 
 impl From<DatomsColumn> for Column {
@@ -182,6 +209,7 @@ impl Debug for Column {
             Column::Fulltext(ref c) => c.fmt(f),
             Column::Variable(ref v) => v.fmt(f),
             Column::Transactions(ref t) => t.fmt(f),
+            Column::Computed(ref c) => c.fmt(f),
         }
     }
 }
@@ -192,6 +220,9 @@ impl FulltextColumn {
         match *self {
             Rowid => "rowid",
             Text => "text",
+            Score(_) => "score",
+            Snippet(_) => "snippet",
+            Highlight(_) => "highlight",
         }
     }
 }
@@ -261,7 +292,11 @@ pub struct QualifiedAlias(pub TableAlias, pub Column);
 
 impl Debug for QualifiedAlias {
     fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
-        write!(f, "{}.{:?}", self.0, self.1)
+        match self.1 {
+            // Computed values don't live in any table.
+            Column::Computed(ref c) => c.fmt(f),
+            _ => write!(f, "{}.{:?}", self.0, self.1),
+        }
     }
 }
 
@@ -276,6 +311,7 @@ impl QualifiedAlias {
             Column::Fulltext(_) => None,
             Column::Variable(_) => None,
             Column::Transactions(ref c) => c.associated_type_tag_column().map(Column::Transactions),
+            Column::Computed(_) => None,
         }
         .map(|d| QualifiedAlias(self.0.clone(), d))
     }
@@ -292,6 +328,10 @@ pub enum QueryValue {
     // cannot be a boolean, so `datoms00.value_type_tag` must be in the set `#{0, 4, 5}`.
     // Note that `5 = 5.0` in SQLite, and we preserve that here.
     PrimitiveLong(i64),
+
+    /// The value of an `:in` variable that's bound when the query is run, not when it's
+    /// algebrized. The variable's type is always known.
+    Input(Variable),
 }
 
 impl Debug for QueryValue {
@@ -302,6 +342,7 @@ impl Debug for QueryValue {
             Entid(ref entid) => write!(f, "entity({:?})", entid),
             TypedValue(ref typed_value) => write!(f, "value({:?})", typed_value),
             PrimitiveLong(value) => write!(f, "primitive({:?})", value),
+            Input(ref var) => write!(f, "input({:?})", var),
         }
     }
 }
@@ -407,6 +448,153 @@ impl Debug for Inequality {
     }
 }
 
+/// The functions that SQLite computes on our behalf: arithmetic and string functions that bind
+/// a variable, such as `[(+ ?a ?b) ?c]`, and string predicates, such as `[(includes? ?s "x")]`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ScalarFunction {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+
+    Str,
+    Subs,
+    LowerCase,
+    UpperCase,
+
+    // Predicates.
+    StartsWith,
+    EndsWith,
+    Includes,
+    ReFind,
+}
+
+impl ScalarFunction {
+    pub fn from_datalog_operator(s: &str) -> Option<ScalarFunction> {
+        use self::ScalarFunction::*;
+        match s {
+            "+" => Some(Add),
+            "-" => Some(Subtract),
+            "*" => Some(Multiply),
+            "/" => Some(Divide),
+
+            "str" => Some(Str),
+            "subs" => Some(Subs),
+            "lower-case" => Some(LowerCase),
+            "upper-case" => Some(UpperCase),
+
+            "starts-with?" => Some(StartsWith),
+            "ends-with?" => Some(EndsWith),
+            "includes?" => Some(Includes),
+            "re-find" => Some(ReFind),
+            _ => None,
+        }
+    }
+
+    pub fn to_datalog_operator(self) -> &'static str {
+        use self::ScalarFunction::*;
+        match self {
+            Add => "+",
+            Subtract => "-",
+            Multiply => "*",
+            Divide => "/",
+
+            Str => "str",
+            Subs => "subs",
+            LowerCase => "lower-case",
+            UpperCase => "upper-case",
+
+            StartsWith => "starts-with?",
+            EndsWith => "ends-with?",
+            Includes => "includes?",
+            ReFind => "re-find",
+        }
+    }
+
+    /// Predicates filter rows; every other function binds a variable.
+    pub fn is_predicate(self) -> bool {
+        use self::ScalarFunction::*;
+        matches!(self, StartsWith | EndsWith | Includes | ReFind)
+    }
+
+    /// The least and, if there is one, the greatest number of arguments this function accepts.
+    pub fn arity(self) -> (usize, Option<usize>) {
+        use self::ScalarFunction::*;
+        match self {
+            Add | Subtract | Multiply | Divide | Str => (1, None),
+            Subs => (2, Some(3)),
+            LowerCase | UpperCase => (1, Some(1)),
+            StartsWith | EndsWith | Includes | ReFind => (2, Some(2)),
+        }
+    }
+
+    /// The types this function accepts as its argument in `position`.
+    pub fn supported_types(self, position: usize) -> ValueTypeSet {
+        use self::ScalarFunction::*;
+        match self {
+            Add | Subtract | Multiply | Divide => ValueTypeSet::of_numeric_types(),
+
+            // These are the types whose SQLite representation reads just as it prints.
+            Str => {
+                let mut ts = ValueTypeSet::of_numeric_types();
+                ts.insert(ValueType::String);
+                ts.insert(ValueType::Keyword);
+                ts
+            }
+
+            Subs if position > 0 => ValueTypeSet::of_one(ValueType::Long),
+
+            Subs | LowerCase | UpperCase | StartsWith | EndsWith | Includes | ReFind => {
+                ValueTypeSet::of_one(ValueType::String)
+            }
+        }
+    }
+
+    /// The type of value this function produces, given the possible types of its arguments.
+    /// Arithmetic yields a `Long` only if every argument is known to be a `Long`; division
+    /// always yields a `Double`.
+    pub fn result_type(self, argument_types: &[ValueTypeSet]) -> ValueType {
+        use self::ScalarFunction::*;
+        match self {
+            Add | Subtract | Multiply => {
+                let long = ValueTypeSet::of_one(ValueType::Long);
+                if argument_types.iter().all(|ts| *ts == long) {
+                    ValueType::Long
+                } else {
+                    ValueType::Double
+                }
+            }
+            Divide => ValueType::Double,
+            StartsWith | EndsWith | Includes | ReFind => ValueType::Boolean,
+            Str | Subs | LowerCase | UpperCase => ValueType::String,
+        }
+    }
+}
+
+impl Debug for ScalarFunction {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        f.write_str(self.to_datalog_operator())
+    }
+}
+
+/// The application of a `ScalarFunction` to its arguments, which SQLite evaluates for each row.
+#[derive(PartialEq, Eq, Clone)]
+pub struct ComputedValue {
+    pub function: ScalarFunction,
+    pub args: Vec<QueryValue>,
+    pub value_type: ValueType,
+}
+
+impl Debug for ComputedValue {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        write!(f, "({:?}", self.function)?;
+        for arg in self.args.iter() {
+            write!(f, " {:?}", arg)?;
+        }
+        write!(f, ")")
+    }
+}
+
 #[derive(PartialEq, Eq)]
 pub enum ColumnConstraint {
     Equals(QualifiedAlias, QueryValue),
@@ -422,6 +610,10 @@ pub enum ColumnConstraint {
     },
     NotExists(ComputedTable),
     Matches(QualifiedAlias, QueryValue),
+    /// A string predicate, such as `starts-with?`, that must hold.
+    Holds(ComputedValue),
+    /// Some computed values, like the quotient of a division by zero, are `NULL`.
+    NotNull(QualifiedAlias),
 }
 
 impl ColumnConstraint {
@@ -574,6 +766,8 @@ impl Debug for ColumnConstraint {
                 write!(f, "1)")
             }
             NotExists(ref ct) => write!(f, "NOT EXISTS {:?}", ct),
+            Holds(ref c) => write!(f, "{:?}", c),
+            NotNull(ref qa) => write!(f, "{:?} IS NOT NULL", qa),
         }
     }
 }
@@ -613,11 +807,14 @@ pub enum EmptyBecause {
     NonEntityArgument,
     NonStringFulltextValue,
     NonFulltextAttribute(Entid),
+    NoFulltextAttributes,
     UnresolvedIdent(Keyword),
     InvalidAttributeIdent(Keyword),
     InvalidAttributeEntid(Entid),
     InvalidBinding(Column, TypedValue),
     ValueTypeMismatch(ValueType, TypedValue),
+    RetractionOutsideHistory,
+    NoMatchingTuples(SrcVar),
     AttributeLookupFailed, // Catch-all, because the table lookup code is lazy. TODO
 }
 
@@ -669,6 +866,7 @@ impl Debug for EmptyBecause {
             InvalidAttributeIdent(ref kw) => write!(f, "{} does not name an attribute", kw),
             InvalidAttributeEntid(entid) => write!(f, "{} is not an attribute", entid),
             NonFulltextAttribute(entid) => write!(f, "{} is not a fulltext attribute", entid),
+            NoFulltextAttributes => write!(f, "There are no fulltext attributes"),
             InvalidBinding(ref column, ref tv) => {
                 write!(f, "{:?} cannot name column {:?}", tv, column)
             }
@@ -677,6 +875,10 @@ impl Debug for EmptyBecause {
                 "Type mismatch: {:?} doesn't match attribute type {:?}",
                 typed_value, value_type
             ),
+            RetractionOutsideHistory => {
+                write!(f, "Only history sources contain retractions")
+            }
+            NoMatchingTuples(ref source) => write!(f, "No tuple in {} matches", source),
             AttributeLookupFailed => write!(f, "Attribute lookup failed"),
         }
     }
@@ -694,6 +896,7 @@ pub struct FindQuery {
     pub with: BTreeSet<Variable>,
     pub in_vars: BTreeSet<Variable>,
     pub in_sources: BTreeSet<SrcVar>,
+    pub in_rules: bool,
     pub limit: Limit,
     pub where_clauses: Vec<WhereClause>,
     pub order: Option<Vec<Order>>,
@@ -739,4 +942,5 @@ pub struct EvolvedPattern {
     pub attribute: EvolvedNonValuePlace,
     pub value: EvolvedValuePlace,
     pub tx: EvolvedNonValuePlace,
+    pub added: EvolvedValuePlace,
 }
@@ -8,12 +8,19 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
-use edn::query::{ContainsVariables, NotJoin, OrJoin, UnifyVars, Variable};
+use core_traits::TypedValue;
+
+use edn::query::{
+    ContainsVariables, NotJoin, OrJoin, OrWhereClause, Pattern, SrcVar, UnifyVars, Variable,
+    WhereClause,
+};
 
 use query_algebrizer_traits::errors::{AlgebrizerError, Result};
 
+use crate::clauses::QuerySource;
+
 /// In an `or` expression, every mentioned var is considered 'free'.
 /// In an `or-join` expression, every var in the var list is 'required'.
 ///
@@ -81,6 +88,70 @@ pub(crate) fn validate_not_join(not_join: &NotJoin) -> Result<()> {
     }
 }
 
+/// Every pattern must match against the default source or a source that's been bound.
+/// Sources are bound only if they're named in `:in`, so this also catches sources that aren't.
+pub(crate) fn validate_pattern_source(
+    pattern: &Pattern,
+    sources: &BTreeMap<SrcVar, QuerySource>,
+) -> Result<()> {
+    match pattern.source {
+        Some(ref source @ SrcVar::NamedSrc(_)) if !sources.contains_key(source) => {
+            bail!(AlgebrizerError::UnknownSource(source.clone()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Apply `validate_pattern_source` to every pattern in the clause, including those nested in
+/// `or` and `not`.
+pub(crate) fn validate_sources(
+    clause: &WhereClause,
+    sources: &BTreeMap<SrcVar, QuerySource>,
+) -> Result<()> {
+    match clause {
+        WhereClause::Pattern(ref p) => validate_pattern_source(p, sources),
+        WhereClause::OrJoin(ref o) => {
+            for clause in o.clauses.iter() {
+                match clause {
+                    OrWhereClause::Clause(ref clause) => validate_sources(clause, sources)?,
+                    OrWhereClause::And(ref clauses) => {
+                        for clause in clauses.iter() {
+                            validate_sources(clause, sources)?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+        WhereClause::NotJoin(ref n) => {
+            for clause in n.clauses.iter() {
+                validate_sources(clause, sources)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A relation's tuples must all be the same length, and each position must hold values of a
+/// single type.
+pub(crate) fn validate_relation(source: &SrcVar, rows: &[Vec<TypedValue>]) -> Result<()> {
+    if let Some(first) = rows.first() {
+        for row in rows.iter() {
+            let rectangular = row.len() == first.len();
+            if !rectangular
+                || row
+                    .iter()
+                    .zip(first.iter())
+                    .any(|(a, b)| a.value_type() != b.value_type())
+            {
+                bail!(AlgebrizerError::InvalidRelation(source.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     extern crate edn;
@@ -144,6 +215,7 @@ mod tests {
                         attribute: ident("artist", "type"),
                         value: value_ident("artist.type", "group"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }))
                 );
                 assert_eq!(
@@ -157,6 +229,7 @@ mod tests {
                             attribute: ident("artist", "type"),
                             value: value_ident("artist.type", "person"),
                             tx: PatternNonValuePlace::Placeholder,
+                            added: PatternValuePlace::Placeholder,
                         }),
                         WhereClause::Pattern(Pattern {
                             source: None,
@@ -166,6 +239,7 @@ mod tests {
                             attribute: ident("artist", "gender"),
                             value: value_ident("artist.gender", "female"),
                             tx: PatternNonValuePlace::Placeholder,
+                            added: PatternValuePlace::Placeholder,
                         }),
                     ])
                 );
@@ -221,6 +295,7 @@ mod tests {
                         attribute: ident("artist", "type"),
                         value: value_ident("artist.type", "group"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }))
                 );
                 assert_eq!(
@@ -234,6 +309,7 @@ mod tests {
                             attribute: ident("artist", "type"),
                             value: PatternValuePlace::Variable(Variable::from_valid_name("?type")),
                             tx: PatternNonValuePlace::Placeholder,
+                            added: PatternValuePlace::Placeholder,
                         }),
                         WhereClause::Pattern(Pattern {
                             source: None,
@@ -243,6 +319,7 @@ mod tests {
                             attribute: ident("artist", "role"),
                             value: value_ident("artist.role", "parody"),
                             tx: PatternNonValuePlace::Placeholder,
+                            added: PatternValuePlace::Placeholder,
                         }),
                     ])
                 );
@@ -298,6 +375,7 @@ mod tests {
                         attribute: artist_country.clone(),
                         value: value_ident("country", "CA"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     })
                 );
                 assert_eq!(
@@ -308,6 +386,7 @@ mod tests {
                         attribute: artist_country,
                         value: value_ident("country", "GB"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     })
                 );
             }
@@ -342,6 +421,7 @@ mod tests {
                         attribute: ident("release", "artists"),
                         value: artist,
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     })
                 );
                 assert_eq!(
@@ -352,6 +432,7 @@ mod tests {
                         attribute: ident("release", "year"),
                         value: PatternValuePlace::EntidOrInteger(1970),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     })
                 );
             }
@@ -0,0 +1,98 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate core_traits;
+extern crate edn;
+extern crate mentat_core;
+extern crate mentat_query_algebrizer;
+extern crate query_algebrizer_traits;
+
+mod utils;
+
+use std::collections::BTreeSet;
+
+use core_traits::{Entid, ValueType};
+
+use mentat_core::Schema;
+
+use mentat_query_algebrizer::{algebrize, parse_find_string, Known};
+
+use crate::utils::SchemaBuilder;
+
+// :foo/name is 65, :foo/age is 66, and :foo/friend is 67.
+fn prepopulated_schema() -> Schema {
+    SchemaBuilder::new()
+        .define_simple_attr("foo", "name", ValueType::String, false)
+        .define_simple_attr("foo", "age", ValueType::Long, false)
+        .define_simple_attr("foo", "friend", ValueType::Ref, true)
+        .schema
+}
+
+fn referenced_attributes(schema: &Schema, query: &str) -> Option<BTreeSet<Entid>> {
+    let parsed = parse_find_string(query).expect("query input to have parsed");
+    algebrize(Known::for_schema(schema), parsed)
+        .expect("algebrizing to have succeeded")
+        .referenced_attributes(schema)
+}
+
+fn set(entids: &[Entid]) -> Option<BTreeSet<Entid>> {
+    Some(entids.iter().cloned().collect())
+}
+
+#[test]
+fn test_pattern_attributes() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find ?x ?n :where [?x :foo/name ?n] [?x :foo/age 30]]"#;
+    assert_eq!(referenced_attributes(&schema, query), set(&[65, 66]));
+
+    let query = r#"[:find ?n :where [?x :foo/_friend ?y] [?y :foo/name ?n]]"#;
+    assert_eq!(referenced_attributes(&schema, query), set(&[65, 67]));
+
+    // Any attribute can match an unnamed attribute.
+    let query = r#"[:find ?x :where [?x ?a "Bob"]]"#;
+    assert_eq!(referenced_attributes(&schema, query), None);
+
+    // A query that can't match anything doesn't depend on anything.
+    let query = r#"[:find ?x :where [?x :foo/unknown "Bob"]]"#;
+    assert_eq!(referenced_attributes(&schema, query), set(&[]));
+}
+
+#[test]
+fn test_or_and_not_attributes() {
+    let schema = prepopulated_schema();
+
+    // A simple `or` becomes an alternation of attributes on a single table.
+    let query = r#"[:find ?x :where (or [?x :foo/name "Bob"] [?x :foo/age 30])]"#;
+    assert_eq!(referenced_attributes(&schema, query), set(&[65, 66]));
+
+    // A complex `or` becomes a union.
+    let query = r#"[:find ?x :where (or (and [?x :foo/name "Bob"] [?x :foo/age 30])
+                                       [?x :foo/friend 99])]"#;
+    assert_eq!(referenced_attributes(&schema, query), set(&[65, 66, 67]));
+
+    let query = r#"[:find ?x :where [?x :foo/name _] (not [?x :foo/age 30])]"#;
+    assert_eq!(referenced_attributes(&schema, query), set(&[65, 66]));
+
+    let query = r#"[:find ?x :where [?x :foo/name _] (not-join [?x] [?x ?a 30])]"#;
+    assert_eq!(referenced_attributes(&schema, query), None);
+}
+
+#[test]
+fn test_pull_attributes() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find (pull ?x [:foo/age {:foo/_friend [:foo/name]}])
+                    :where [?x :foo/name "Bob"]]"#;
+    assert_eq!(referenced_attributes(&schema, query), set(&[65, 66, 67]));
+
+    let query = r#"[:find (pull ?x [*]) :where [?x :foo/name "Bob"]]"#;
+    assert_eq!(referenced_attributes(&schema, query), None);
+}
@@ -0,0 +1,183 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate core_traits;
+extern crate edn;
+extern crate mentat_core;
+extern crate mentat_query_algebrizer;
+extern crate query_algebrizer_traits;
+
+mod utils;
+
+use core_traits::{ValueType, ValueTypeSet};
+
+use mentat_core::Schema;
+
+use edn::query::{PlainSymbol, Variable};
+
+use query_algebrizer_traits::errors::{AlgebrizerError, BindingError};
+
+use mentat_query_algebrizer::{Column, Known, ScalarFunction};
+
+use crate::utils::{alg, bails, SchemaBuilder};
+
+fn prepopulated_schema() -> Schema {
+    SchemaBuilder::new()
+        .define_simple_attr("foo", "name", ValueType::String, false)
+        .define_simple_attr("foo", "age", ValueType::Long, false)
+        .define_simple_attr("foo", "height", ValueType::Double, false)
+        .schema
+}
+
+fn var(name: &str) -> Variable {
+    Variable::from_valid_name(name)
+}
+
+#[test]
+fn test_arithmetic_result_types() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    let query = r#"[:find ?y :where [?x :foo/age ?a] [(+ ?a 1) ?y]]"#;
+    let cc = alg(known, query);
+    assert_eq!(cc.known_type(&var("?y")), Some(ValueType::Long));
+    match cc.column_bindings.get(&var("?y")).map(|cols| &cols[0].1) {
+        Some(Column::Computed(computed)) => {
+            assert_eq!(computed.function, ScalarFunction::Add);
+            assert_eq!(computed.args.len(), 2);
+        }
+        bound => panic!("?y should be computed, not {:?}", bound),
+    }
+
+    let query = r#"[:find ?y :where [?x :foo/age ?a] [?x :foo/height ?h] [(* ?a ?h) ?y]]"#;
+    let cc = alg(known, query);
+    assert_eq!(cc.known_type(&var("?y")), Some(ValueType::Double));
+
+    // Division always produces a double.
+    let query = r#"[:find ?y :where [?x :foo/age ?a] [(/ ?a 2) ?y]]"#;
+    let cc = alg(known, query);
+    assert_eq!(cc.known_type(&var("?y")), Some(ValueType::Double));
+
+    // A value of unknown type becomes numeric.
+    let query = r#"[:find ?y :where [?x _ ?v] [(- ?v) ?y]]"#;
+    let cc = alg(known, query);
+    assert_eq!(cc.known_type(&var("?y")), Some(ValueType::Double));
+}
+
+#[test]
+fn test_string_functions() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    let query = r#"[:find ?s :where [?x :foo/name ?n] [?x :foo/age ?a] [(str ?n " is " ?a) ?s]]"#;
+    let cc = alg(known, query);
+    assert_eq!(cc.known_type(&var("?s")), Some(ValueType::String));
+
+    let query = r#"[:find ?p :where [?x :foo/name ?n] [(lower-case ?n) ?l] [(subs ?l 0 3) ?p]]"#;
+    let cc = alg(known, query);
+    assert_eq!(cc.known_type(&var("?p")), Some(ValueType::String));
+    assert!(!cc.is_known_empty());
+
+    // The result of a function can be joined like any other value.
+    let query = r#"[:find ?x :where [?x :foo/name ?n] [(upper-case ?n) ?u] [?y :foo/name ?u]]"#;
+    let cc = alg(known, query);
+    assert!(!cc.is_known_empty());
+    assert_eq!(cc.column_bindings.get(&var("?u")).map(|c| c.len()), Some(2));
+
+    // A string function can't produce a number.
+    let query = r#"[:find ?x :where [?x :foo/name ?n] [(lower-case ?n) ?a] [?x :foo/age ?a]]"#;
+    let cc = alg(known, query);
+    assert!(cc.is_known_empty());
+}
+
+#[test]
+fn test_function_argument_types() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    let query = r#"[:find ?y :where [?x :foo/name ?n] [(+ ?n 1) ?y]]"#;
+    assert_eq!(
+        bails(known, query),
+        AlgebrizerError::InvalidArgumentType(
+            PlainSymbol::plain("+"),
+            ValueTypeSet::of_numeric_types(),
+            0
+        )
+    );
+
+    let query = r#"[:find ?x :where [?x :foo/age ?a] [(starts-with? ?a "1")]]"#;
+    assert_eq!(
+        bails(known, query),
+        AlgebrizerError::InvalidArgumentType(
+            PlainSymbol::plain("starts-with?"),
+            ValueTypeSet::of_one(ValueType::String),
+            0
+        )
+    );
+
+    let query = r#"[:find ?p :where [?x :foo/name ?n] [(subs ?n "a") ?p]]"#;
+    assert_eq!(
+        bails(known, query),
+        AlgebrizerError::InvalidArgumentType(
+            PlainSymbol::plain("subs"),
+            ValueTypeSet::of_one(ValueType::Long),
+            1
+        )
+    );
+
+    let query = r#"[:find ?l :where [?x :foo/name ?n] [(lower-case ?n ?n) ?l]]"#;
+    assert_eq!(
+        bails(known, query),
+        AlgebrizerError::InvalidNumberOfArguments(PlainSymbol::plain("lower-case"), 2, 1)
+    );
+
+    let query = r#"[:find ?l :where [?x :foo/name ?n] [(lower-case ?n) [?l ...]]]"#;
+    assert_eq!(
+        bails(known, query),
+        AlgebrizerError::InvalidBinding(
+            PlainSymbol::plain("lower-case"),
+            BindingError::ExpectedBindScalar
+        )
+    );
+
+    // Predicates don't bind, and functions aren't predicates.
+    let query = r#"[:find ?x :where [?x :foo/name ?n] [(includes? ?n "a") ?b]]"#;
+    assert_eq!(
+        bails(known, query),
+        AlgebrizerError::UnknownFunction(PlainSymbol::plain("includes?"))
+    );
+    let query = r#"[:find ?x :where [?x :foo/name ?n] [(lower-case ?n)]]"#;
+    assert_eq!(
+        bails(known, query),
+        AlgebrizerError::UnknownFunction(PlainSymbol::plain("lower-case"))
+    );
+}
+
+#[test]
+fn test_string_predicates() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    for predicate in &["starts-with?", "ends-with?", "includes?"] {
+        let query = format!(
+            r#"[:find ?x :where [?x :foo/name ?n] [({} ?n "a")]]"#,
+            predicate
+        );
+        let cc = alg(known, query.as_str());
+        assert!(!cc.is_known_empty());
+        assert_eq!(cc.wheres.len(), 2);
+    }
+
+    // An attribute-less value must turn out to be a string.
+    let query = r#"[:find ?x :where [?x _ ?v] [(re-find "^A" ?v)]]"#;
+    let cc = alg(known, query);
+    assert!(!cc.is_known_empty());
+    assert_eq!(cc.known_type(&var("?v")), Some(ValueType::String));
+}
@@ -0,0 +1,154 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate core_traits;
+extern crate edn;
+extern crate mentat_core;
+extern crate mentat_query_algebrizer;
+extern crate query_algebrizer_traits;
+
+mod utils;
+
+use core_traits::{TypedValue, ValueType};
+
+use mentat_core::{Schema, ValueRc};
+
+use edn::query::{SrcVar, Variable};
+
+use query_algebrizer_traits::errors::AlgebrizerError;
+
+use mentat_query_algebrizer::{
+    ComputedTable, DatomsTable, EmptyBecause, Known, QueryInputs, QuerySource,
+};
+
+use crate::utils::{alg_with_inputs, bails_with_inputs, SchemaBuilder};
+
+fn prepopulated_schema() -> Schema {
+    SchemaBuilder::new()
+        .define_simple_attr("foo", "name", ValueType::String, false)
+        .define_simple_attr("foo", "age", ValueType::Long, false)
+        .schema
+}
+
+fn people() -> QueryInputs {
+    QueryInputs::default()
+        .with_relation(
+            SrcVar::NamedSrc("people".to_string()),
+            vec![
+                vec![TypedValue::typed_string("Alice"), TypedValue::Long(30)],
+                vec![TypedValue::typed_string("Bob"), TypedValue::Long(40)],
+            ],
+        )
+        .expect("valid relation")
+}
+
+#[test]
+fn test_relation_yields_named_values() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?x :in $ $people :where [$people ?n ?a] [?x :foo/name ?n]]"#;
+    let cc = alg_with_inputs(known, query, people());
+    assert!(!cc.is_known_empty());
+    match cc.computed_tables.as_slice() {
+        [ComputedTable::NamedValues { names, values }] => {
+            assert_eq!(
+                names,
+                &vec![
+                    Variable::from_valid_name("?n"),
+                    Variable::from_valid_name("?a")
+                ]
+            );
+            assert_eq!(values.len(), 4);
+        }
+        tables => panic!("unexpected computed tables {:?}", tables),
+    }
+    assert_eq!(
+        cc.known_type(&Variable::from_valid_name("?a")),
+        Some(ValueType::Long)
+    );
+}
+
+#[test]
+fn test_relation_filters_constants() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    // Only one tuple matches, so its value is bound directly.
+    let query = r#"[:find ?n :in $people :where [$people ?n 40]]"#;
+    let cc = alg_with_inputs(known, query, people());
+    assert!(!cc.is_known_empty());
+    assert!(cc.computed_tables.is_empty());
+    assert_eq!(
+        cc.bound_value(&Variable::from_valid_name("?n")),
+        Some(TypedValue::typed_string("Bob"))
+    );
+
+    let query = r#"[:find ?n :in $people :where [$people ?n 50]]"#;
+    let cc = alg_with_inputs(known, query, people());
+    assert!(cc.is_known_empty());
+    assert_eq!(
+        cc.empty_because,
+        Some(EmptyBecause::NoMatchingTuples(SrcVar::NamedSrc(
+            "people".to_string()
+        )))
+    );
+
+    // The tuples have no third place.
+    let query = r#"[:find ?n :in $people :where [$people ?n _ ?x]]"#;
+    let cc = alg_with_inputs(known, query, people());
+    assert!(cc.is_known_empty());
+}
+
+#[test]
+fn test_invalid_relation() {
+    let source = SrcVar::NamedSrc("people".to_string());
+    let ragged = vec![
+        vec![TypedValue::typed_string("Alice")],
+        vec![TypedValue::typed_string("Bob"), TypedValue::Long(40)],
+    ];
+    let mixed = vec![
+        vec![TypedValue::typed_string("Alice")],
+        vec![TypedValue::Long(40)],
+    ];
+
+    // Relations are checked when they're bound…
+    for rows in [ragged.clone(), mixed.clone()] {
+        let e = QueryInputs::default()
+            .with_relation(source.clone(), rows)
+            .err()
+            .expect("invalid relation");
+        assert_eq!(e, AlgebrizerError::InvalidRelation(source.clone()));
+    }
+
+    // … and when they're used, however they were bound.
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?n :in $people :where [$people ?n _]]"#;
+    for rows in [ragged, mixed] {
+        let inputs = QueryInputs::default()
+            .with_source(source.clone(), QuerySource::Relation(ValueRc::new(rows)));
+        let e = bails_with_inputs(known, query, inputs);
+        assert_eq!(e, AlgebrizerError::InvalidRelation(source.clone()));
+    }
+}
+
+#[test]
+fn test_attached_source() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?n :in $ $other :where [$other ?x :foo/name ?n]]"#;
+    let inputs = QueryInputs::default().with_source(
+        SrcVar::NamedSrc("other".to_string()),
+        QuerySource::Attached("backup".to_string()),
+    );
+    let cc = alg_with_inputs(known, query, inputs);
+    assert!(!cc.is_known_empty());
+    assert_eq!(cc.from[0].0, DatomsTable::Attached("backup".to_string()));
+}
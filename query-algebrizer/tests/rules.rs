@@ -0,0 +1,196 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate core_traits;
+extern crate edn;
+extern crate mentat_core;
+extern crate mentat_query_algebrizer;
+extern crate query_algebrizer_traits;
+
+mod utils;
+
+use core_traits::{ValueType, ValueTypeSet};
+
+use mentat_core::Schema;
+
+use edn::query::{PlainSymbol, Variable};
+
+use query_algebrizer_traits::errors::AlgebrizerError;
+
+use mentat_query_algebrizer::{parse_rules_string, ComputedTable, Known, QueryInputs};
+
+use crate::utils::{alg_with_inputs, bails_with_inputs, SchemaBuilder};
+
+fn prepopulated_schema() -> Schema {
+    SchemaBuilder::new()
+        .define_simple_attr("foo", "name", ValueType::String, false)
+        .define_simple_attr("foo", "age", ValueType::Long, false)
+        .define_simple_attr("foo", "parent", ValueType::Ref, false)
+        .schema
+}
+
+fn rules(rules: &str) -> QueryInputs {
+    QueryInputs::default().with_rules(parse_rules_string(rules).expect("parsed rules"))
+}
+
+#[test]
+fn test_single_definition_is_inlined() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?n :in % :where (named ?x ?n)]"#;
+    let cc = alg_with_inputs(known, query, rules("[[(named ?p ?n) [?p :foo/name ?n]]]"));
+    assert!(!cc.is_known_empty());
+    assert!(cc.computed_tables.is_empty());
+    assert_eq!(
+        cc.known_type(&Variable::from_valid_name("?n")),
+        Some(ValueType::String)
+    );
+    assert_eq!(
+        cc.known_type(&Variable::from_valid_name("?x")),
+        Some(ValueType::Ref)
+    );
+}
+
+#[test]
+fn test_several_definitions_are_unioned() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?v :in % :where (prop ?x ?v)]"#;
+    let cc = alg_with_inputs(
+        known,
+        query,
+        rules(
+            r#"[[(prop ?e ?v) [?e :foo/name ?v]]
+                [(prop ?e ?v) [?e :foo/age ?v]]]"#,
+        ),
+    );
+    assert!(!cc.is_known_empty());
+    match cc.computed_tables.as_slice() {
+        [ComputedTable::Union { arms, .. }] => assert_eq!(arms.len(), 2),
+        tables => panic!("unexpected computed tables {:?}", tables),
+    }
+    assert_eq!(
+        cc.known_type_set(&Variable::from_valid_name("?v")),
+        ValueTypeSet::of_one(ValueType::String).union(ValueTypeSet::of_one(ValueType::Long))
+    );
+}
+
+#[test]
+fn test_recursive_rule() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?a :in % :where (ancestor ?c ?a) [?c :foo/name "x"]]"#;
+    let cc = alg_with_inputs(
+        known,
+        query,
+        rules(
+            r#"[[(ancestor ?c ?a) [?c :foo/parent ?a]]
+                [(ancestor ?c ?a) [?c :foo/parent ?p] (ancestor ?p ?a)]]"#,
+        ),
+    );
+    assert!(!cc.is_known_empty());
+    match cc.computed_tables.as_slice() {
+        [ComputedTable::RecursiveUnion {
+            type_extraction,
+            base,
+            recursive,
+            ..
+        }] => {
+            assert!(type_extraction.is_empty());
+            assert_eq!(base.len(), 1);
+            assert_eq!(recursive.len(), 1);
+        }
+        tables => panic!("unexpected computed tables {:?}", tables),
+    }
+    assert_eq!(
+        cc.known_type(&Variable::from_valid_name("?a")),
+        Some(ValueType::Ref)
+    );
+}
+
+#[test]
+fn test_rule_known_empty() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?x :in % :where (aged ?x "ten")]"#;
+    let cc = alg_with_inputs(known, query, rules("[[(aged ?p ?a) [?p :foo/age ?a]]]"));
+    assert!(cc.is_known_empty());
+}
+
+#[test]
+fn test_rules_require_percent() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?n :where (named ?x ?n)]"#;
+    let e = bails_with_inputs(known, query, rules("[[(named ?p ?n) [?p :foo/name ?n]]]"));
+    assert_eq!(e, AlgebrizerError::UnknownRule(PlainSymbol::plain("named")));
+}
+
+#[test]
+fn test_rule_arity() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?n :in % :where (named ?n)]"#;
+    let e = bails_with_inputs(known, query, rules("[[(named ?p ?n) [?p :foo/name ?n]]]"));
+    assert_eq!(
+        e,
+        AlgebrizerError::InvalidNumberOfArguments(PlainSymbol::plain("named"), 1, 2)
+    );
+}
+
+#[test]
+fn test_unsupported_recursion() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?a :in % :where (ping ?c ?a)]"#;
+
+    // Mutual recursion.
+    let e = bails_with_inputs(
+        known,
+        query,
+        rules(
+            r#"[[(ping ?c ?a) [?c :foo/parent ?p] (pong ?p ?a)]
+                [(pong ?c ?a) [?c :foo/parent ?p] (ping ?p ?a)]]"#,
+        ),
+    );
+    assert_eq!(
+        e,
+        AlgebrizerError::UnsupportedRuleRecursion(PlainSymbol::plain("ping"))
+    );
+
+    // Recursion that isn't at the top level of a definition.
+    let e = bails_with_inputs(
+        known,
+        query,
+        rules(
+            r#"[[(ping ?c ?a) [?c :foo/parent ?a]]
+                [(ping ?c ?a) (or (ping ?a ?c) [?c :foo/name ?a])]]"#,
+        ),
+    );
+    assert_eq!(
+        e,
+        AlgebrizerError::UnsupportedRuleRecursion(PlainSymbol::plain("ping"))
+    );
+
+    // More than one recursive definition.
+    let e = bails_with_inputs(
+        known,
+        query,
+        rules(
+            r#"[[(ping ?c ?a) [?c :foo/parent ?a]]
+                [(ping ?c ?a) [?c :foo/parent ?p] (ping ?p ?a)]
+                [(ping ?c ?a) [?a :foo/parent ?p] (ping ?c ?p)]]"#,
+        ),
+    );
+    assert_eq!(
+        e,
+        AlgebrizerError::UnsupportedRuleRecursion(PlainSymbol::plain("ping"))
+    );
+}
@@ -0,0 +1,129 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate core_traits;
+extern crate edn;
+extern crate mentat_core;
+extern crate mentat_query_algebrizer;
+extern crate query_algebrizer_traits;
+
+mod utils;
+
+use core_traits::ValueType;
+
+use mentat_core::Schema;
+
+use edn::query::{SrcVar, Variable};
+
+use query_algebrizer_traits::errors::AlgebrizerError;
+
+use mentat_query_algebrizer::{DatabaseView, DatomsTable, EmptyBecause, Known, QueryInputs};
+
+use crate::utils::{alg_with_inputs, bails_with_inputs, SchemaBuilder};
+
+fn prepopulated_schema() -> Schema {
+    SchemaBuilder::new()
+        .define_simple_attr("foo", "name", ValueType::String, false)
+        .define_simple_attr("foo", "age", ValueType::Long, false)
+        .schema
+}
+
+fn named(name: &str) -> SrcVar {
+    SrcVar::NamedSrc(name.to_string())
+}
+
+fn viewed(source: SrcVar, view: DatabaseView) -> QueryInputs {
+    QueryInputs::default().with_source(source, view)
+}
+
+#[test]
+fn test_view_tables() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    let query = r#"[:find ?n :in $history :where [$history ?x :foo/name ?n]]"#;
+    let cc = alg_with_inputs(
+        known,
+        query,
+        viewed(named("history"), DatabaseView::History),
+    );
+    assert!(!cc.is_known_empty());
+    assert_eq!(cc.from[0].0, DatomsTable::History);
+
+    let query = r#"[:find ?n :in $ :where [?x :foo/name ?n]]"#;
+    let cc = alg_with_inputs(
+        known,
+        query,
+        viewed(SrcVar::DefaultSrc, DatabaseView::AsOf(1000)),
+    );
+    assert_eq!(cc.from[0].0, DatomsTable::AsOf(1000));
+
+    let query = r#"[:find ?n :where [?x :foo/name ?n]]"#;
+    let cc = alg_with_inputs(
+        known,
+        query,
+        viewed(SrcVar::DefaultSrc, DatabaseView::Since(1000)),
+    );
+    assert_eq!(cc.from[0].0, DatomsTable::Since(1000));
+}
+
+#[test]
+fn test_added_on_current_source() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    let query = r#"[:find ?added :where [?x :foo/name _ _ ?added]]"#;
+    let cc = alg_with_inputs(known, query, QueryInputs::default());
+    assert!(!cc.is_known_empty());
+    assert_eq!(
+        cc.known_type(&Variable::from_valid_name("?added")),
+        Some(ValueType::Boolean)
+    );
+
+    let query = r#"[:find ?x :where [?x :foo/name _ _ false]]"#;
+    let cc = alg_with_inputs(known, query, QueryInputs::default());
+    assert!(cc.is_known_empty());
+    assert_eq!(
+        cc.empty_because,
+        Some(EmptyBecause::RetractionOutsideHistory)
+    );
+
+    // `false` is fine when reading history.
+    let query = r#"[:find ?x :in $h :where [$h ?x :foo/name _ _ false]]"#;
+    let cc = alg_with_inputs(known, query, viewed(named("h"), DatabaseView::History));
+    assert!(!cc.is_known_empty());
+
+    // The added place only accepts booleans.
+    let query = r#"[:find ?x :where [?x :foo/name _ _ 5]]"#;
+    let cc = alg_with_inputs(known, query, QueryInputs::default());
+    assert!(cc.is_known_empty());
+}
+
+#[test]
+fn test_unknown_source() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?n :where [$history ?x :foo/name ?n]]"#;
+    let e = bails_with_inputs(
+        known,
+        query,
+        viewed(named("history"), DatabaseView::History),
+    );
+    assert_eq!(e, AlgebrizerError::UnknownSource(named("history")));
+}
+
+#[test]
+fn test_unbound_source() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let query = r#"[:find ?n :in $history :where [$history ?x :foo/name ?n]]"#;
+    let e = bails_with_inputs(known, query, QueryInputs::default());
+    assert_eq!(e, AlgebrizerError::UnboundSource(named("history")));
+}
@@ -8,9 +8,13 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use core_traits::{ValueType, ValueTypeSet};
+use std::cmp::Ordering;
 
-use edn::query::{Aggregate, QueryFunction, Variable};
+use rand::seq::SliceRandom;
+
+use core_traits::{Binding, TypedValue, ValueType, ValueTypeSet};
+
+use edn::query::{Aggregate, FnArg, QueryFunction, Variable};
 
 use mentat_query_algebrizer::{ColumnName, ConjoiningClauses, VariableColumn};
 
@@ -22,20 +26,80 @@ use crate::errors::{ProjectorError, Result};
 pub enum SimpleAggregationOp {
     Avg,
     Count,
+    CountDistinct,
+    Distinct,
     Max,
+    MaxN(usize),
+    Median,
     Min,
+    MinN(usize),
+    Sample(usize),
+    Stddev,
     Sum,
+    Variance,
 }
 
 impl SimpleAggregationOp {
-    pub fn to_sql(self) -> &'static str {
+    /// The name of this aggregate in a query.
+    pub fn name(self) -> &'static str {
         use self::SimpleAggregationOp::*;
         match self {
             Avg => "avg",
             Count => "count",
-            Max => "max",
-            Min => "min",
+            CountDistinct => "count-distinct",
+            Distinct => "distinct",
+            Max | MaxN(_) => "max",
+            Median => "median",
+            Min | MinN(_) => "min",
+            Sample(_) => "sample",
+            Stddev => "stddev",
             Sum => "sum",
+            Variance => "variance",
+        }
+    }
+
+    /// Return `true` if SQLite can compute this aggregate for us. The rest are computed as we
+    /// project rows.
+    pub fn is_sql(self) -> bool {
+        use self::SimpleAggregationOp::*;
+        match self {
+            Avg | Count | CountDistinct | Max | Min | Sum => true,
+            Distinct | MaxN(_) | Median | MinN(_) | Sample(_) | Stddev | Variance => false,
+        }
+    }
+
+    /// Return `true` if this aggregate yields a collection of values rather than a single value.
+    pub fn is_collection(self) -> bool {
+        use self::SimpleAggregationOp::*;
+        match self {
+            Distinct | MaxN(_) | MinN(_) | Sample(_) => true,
+            Avg | Count | CountDistinct | Max | Median | Min | Stddev | Sum | Variance => false,
+        }
+    }
+
+    /// The SQL expression that computes this aggregate over `arg`, if SQLite can compute it.
+    pub fn to_sql_expression<F>(self, arg: F) -> Option<Expression>
+    where
+        F: Fn() -> ColumnOrExpression,
+    {
+        use self::SimpleAggregationOp::*;
+        let unary = |sql_op, arg| Expression::Unary { sql_op, arg };
+        match self {
+            Avg => Some(unary("avg", arg())),
+            Count => Some(unary("count", arg())),
+            CountDistinct => Some(Expression::Distinct {
+                sql_op: "count",
+                arg: arg(),
+            }),
+            Max => Some(unary("max", arg())),
+            Min => Some(unary("min", arg())),
+            Sum => Some(unary("sum", arg())),
+
+            // `avg(x * x) - avg(x) * avg(x)` loses everything to cancellation when the values
+            // are large and close together, so we compute the variance in two passes ourselves.
+            Stddev | Variance => None,
+
+            Distinct | MaxN(_) | Median | MinN(_) | Sample(_) => None,
         }
     }
 
@@ -43,9 +107,24 @@ impl SimpleAggregationOp {
         match function.0.name() {
             "avg" => Some(SimpleAggregationOp::Avg),
             "count" => Some(SimpleAggregationOp::Count),
+            "count-distinct" => Some(SimpleAggregationOp::CountDistinct),
+            "distinct" => Some(SimpleAggregationOp::Distinct),
             "max" => Some(SimpleAggregationOp::Max),
+            "median" => Some(SimpleAggregationOp::Median),
             "min" => Some(SimpleAggregationOp::Min),
+            "stddev" => Some(SimpleAggregationOp::Stddev),
             "sum" => Some(SimpleAggregationOp::Sum),
+            "variance" => Some(SimpleAggregationOp::Variance),
+            _ => None,
+        }
+    }
+
+    /// Aggregates like `(max 3 ?x)` that take a count as well as a variable.
+    fn for_function_with_count(function: &QueryFunction, n: usize) -> Option<SimpleAggregationOp> {
+        match function.0.name() {
+            "max" => Some(SimpleAggregationOp::MaxN(n)),
+            "min" => Some(SimpleAggregationOp::MinN(n)),
+            "sample" => Some(SimpleAggregationOp::Sample(n)),
             _ => None,
         }
     }
@@ -56,7 +135,8 @@ impl SimpleAggregationOp {
     /// to take `Sum` of `{Instant}`, valid to take (lexicographic) `Max` of `{String}`,
     /// but invalid to take `Max` of `{Uuid, String}`.
     ///
-    /// The returned type is the type of the result of the aggregation.
+    /// The returned type is the type of the result of the aggregation. For aggregates that
+    /// collect values, it's the type of those values.
     pub fn is_applicable_to_types(self, possibilities: ValueTypeSet) -> Result<ValueType> {
         use self::SimpleAggregationOp::*;
         if possibilities.is_empty() {
@@ -65,10 +145,10 @@ impl SimpleAggregationOp {
 
         match self {
             // One can always count results.
-            Count => Ok(ValueType::Long),
+            Count | CountDistinct => Ok(ValueType::Long),
 
             // Only numeric types can be averaged or summed.
-            Avg => {
+            Avg | Median | Stddev | Variance => {
                 if possibilities.is_only_numeric() {
                     // The mean of a set of numeric values will always, for our purposes, be a double.
                     Ok(ValueType::Double)
@@ -95,7 +175,19 @@ impl SimpleAggregationOp {
                 }
             }
 
-            Max | Min => {
+            // Collecting values of mixed types isn't supported.
+            Distinct | Sample(_) => {
+                if possibilities.is_unit() {
+                    Ok(possibilities.exemplar().expect("a type"))
+                } else {
+                    bail!(ProjectorError::CannotApplyAggregateOperationToTypes(
+                        self,
+                        possibilities
+                    ))
+                }
+            }
+
+            Max | MaxN(_) | Min | MinN(_) => {
                 if possibilities.is_unit() {
                     use self::ValueType::*;
                     let the_type = possibilities.exemplar().expect("a type");
@@ -138,6 +230,127 @@ impl SimpleAggregationOp {
             }
         }
     }
+
+    /// Compute this aggregate over `values` ourselves, rather than asking SQLite to do so.
+    /// Returns `None` when the result is `nil`, as it is for the `max` of no values.
+    pub fn aggregate(self, mut values: Vec<TypedValue>) -> Option<Binding> {
+        use self::SimpleAggregationOp::*;
+        let collection = |values: Vec<TypedValue>| {
+            Some(Binding::Vec(
+                values
+                    .into_iter()
+                    .map(Binding::Scalar)
+                    .collect::<Vec<_>>()
+                    .into(),
+            ))
+        };
+        match self {
+            Count => Some(TypedValue::Long(values.len() as i64).into()),
+            CountDistinct => {
+                values.sort();
+                values.dedup();
+                Some(TypedValue::Long(values.len() as i64).into())
+            }
+            Sum => Some(sum(&values).into()),
+            Avg => mean(&values).map(|m| m.into()),
+            Max | Min => {
+                let mixed = values.iter().any(|v| v.value_type() == ValueType::Double);
+                let extreme = if self == Max {
+                    values.into_iter().max_by(compare)
+                } else {
+                    values.into_iter().min_by(compare)
+                };
+                // Just as in SQL, a `Long` is returned as a `Double` if there are both.
+                extreme.map(|v| match v {
+                    TypedValue::Long(l) if mixed => (l as f64).into(),
+                    v => v.into(),
+                })
+            }
+            Median => {
+                values.sort_by(compare);
+                let mid = values.len() / 2;
+                match values.len() {
+                    0 => None,
+                    n if n % 2 == 1 => as_double(&values[mid]).map(|m| m.into()),
+                    _ => mean(&values[mid - 1..=mid]).map(|m| m.into()),
+                }
+            }
+            Variance => variance(&values).map(|v| v.into()),
+            Stddev => variance(&values).map(|v| v.sqrt().into()),
+            Distinct => {
+                values.sort_by(compare);
+                values.dedup();
+                collection(values)
+            }
+            Sample(n) => {
+                values.sort();
+                values.dedup();
+                let sample = values
+                    .choose_multiple(&mut rand::thread_rng(), n)
+                    .cloned()
+                    .collect();
+                collection(sample)
+            }
+            MaxN(n) => {
+                values.sort_by(|a, b| compare(b, a));
+                values.truncate(n);
+                collection(values)
+            }
+            MinN(n) => {
+                values.sort_by(compare);
+                values.truncate(n);
+                collection(values)
+            }
+        }
+    }
+}
+
+fn as_double(value: &TypedValue) -> Option<f64> {
+    match *value {
+        TypedValue::Long(l) => Some(l as f64),
+        TypedValue::Double(d) => Some(d.into_inner()),
+        _ => None,
+    }
+}
+
+/// Order values as SQLite would: numbers by magnitude, whatever their type, and other values by
+/// their natural order.
+fn compare(a: &TypedValue, b: &TypedValue) -> Ordering {
+    match (as_double(a), as_double(b)) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b),
+    }
+}
+
+fn sum(values: &[TypedValue]) -> TypedValue {
+    let longs = values.iter().try_fold(0i64, |acc, v| match *v {
+        TypedValue::Long(l) => acc.checked_add(l),
+        _ => None,
+    });
+    match longs {
+        Some(total) => TypedValue::Long(total),
+        // Doubles, or longs that overflowed.
+        None => values.iter().filter_map(as_double).sum::<f64>().into(),
+    }
+}
+
+fn mean(values: &[TypedValue]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let total: f64 = values.iter().filter_map(as_double).sum();
+    Some(total / values.len() as f64)
+}
+
+/// The population variance.
+fn variance(values: &[TypedValue]) -> Option<f64> {
+    let m = mean(values)?;
+    let squares: f64 = values
+        .iter()
+        .filter_map(as_double)
+        .map(|v| (v - m) * (v - m))
+        .sum();
+    Some(squares / values.len() as f64)
 }
 
 pub struct SimpleAggregate {
@@ -147,14 +360,20 @@ pub struct SimpleAggregate {
 
 impl SimpleAggregate {
     pub fn column_name(&self) -> Name {
-        format!("({} {})", self.op.to_sql(), self.var.name())
+        use self::SimpleAggregationOp::*;
+        match self.op {
+            MaxN(n) | MinN(n) | Sample(n) => {
+                format!("({} {} {})", self.op.name(), n, self.var.name())
+            }
+            _ => format!("({} {})", self.op.name(), self.var.name()),
+        }
     }
 
     pub fn use_static_value(&self) -> bool {
         use self::SimpleAggregationOp::*;
         match self.op {
             Avg | Max | Min => true,
-            Count | Sum => false,
+            _ => false,
         }
     }
 
@@ -162,10 +381,33 @@ impl SimpleAggregate {
     pub fn is_nullable(&self) -> bool {
         use self::SimpleAggregationOp::*;
         match self.op {
-            Avg | Max | Min => true,
-            Count | Sum => false,
+            Avg | Max | Median | Min | Stddev | Variance => true,
+            Count | CountDistinct | Distinct | MaxN(_) | MinN(_) | Sample(_) | Sum => false,
         }
     }
+
+    /// The result of this aggregate over no rows at all: counts and `sum` are zero, collections
+    /// are empty, and the others are `nil`, represented here as `None`. `sum` has the type it
+    /// would have if we ran it.
+    pub fn value_over_no_rows(&self, known_types: ValueTypeSet) -> Option<Binding> {
+        match self.op {
+            SimpleAggregationOp::Sum => match self.op.is_applicable_to_types(known_types) {
+                Ok(ValueType::Double) => Some(TypedValue::Double(0.0.into()).into()),
+                _ => Some(TypedValue::Long(0).into()),
+            },
+            op => op.aggregate(vec![]),
+        }
+    }
+
+    /// The result of this aggregate over a single row in which the variable is bound to `value`.
+    pub fn value_over_one_row(&self, value: TypedValue) -> Result<Binding> {
+        self.op
+            .is_applicable_to_types(ValueTypeSet::of_one(value.value_type()))?;
+        Ok(self
+            .op
+            .aggregate(vec![value])
+            .expect("every aggregate of one value to have a value"))
+    }
 }
 
 pub trait SimpleAggregation {
@@ -174,12 +416,17 @@ pub trait SimpleAggregation {
 
 impl SimpleAggregation for Aggregate {
     fn to_simple(&self) -> Option<SimpleAggregate> {
-        if self.args.len() != 1 {
-            return None;
-        }
-        self.args[0].as_variable().and_then(|v| {
-            SimpleAggregationOp::for_function(&self.func)
-                .map(|op| SimpleAggregate { op, var: v.clone() })
+        let (op, var) = match self.args.as_slice() {
+            [FnArg::Variable(ref var)] => (SimpleAggregationOp::for_function(&self.func)?, var),
+            [FnArg::EntidOrInteger(n), FnArg::Variable(ref var)] if *n >= 0 => (
+                SimpleAggregationOp::for_function_with_count(&self.func, *n as usize)?,
+                var,
+            ),
+            _ => return None,
+        };
+        Some(SimpleAggregate {
+            op,
+            var: var.clone(),
         })
     }
 }
@@ -194,35 +441,28 @@ pub fn projected_column_for_simple_aggregate(
 ) -> Result<(ProjectedColumn, ValueType)> {
     let known_types = cc.known_type_set(&simple.var);
     let return_type = simple.op.is_applicable_to_types(known_types)?;
-    let projected_column_or_expression = if let Some(value) = cc.bound_value(&simple.var) {
-        // Oh, we already know the value!
-        if simple.use_static_value() {
-            // We can statically compute the aggregate result for some operators -- not count or
-            // sum, but avg/max/min are OK.
-            ColumnOrExpression::Value(value)
-        } else {
-            let expression = Expression::Unary {
-                sql_op: simple.op.to_sql(),
-                arg: ColumnOrExpression::Value(value),
+    let bound_value = cc.bound_value(&simple.var);
+    let projected_column_or_expression = match bound_value {
+        // Oh, we already know the value! We can statically compute the aggregate result for some
+        // operators -- not count or sum, but avg/max/min are OK.
+        Some(ref value) if simple.use_static_value() => ColumnOrExpression::Value(value.clone()),
+        _ => {
+            // The common case is that the values are bound during execution.
+            let arg = || match bound_value {
+                Some(ref value) => ColumnOrExpression::Value(value.clone()),
+                None => ColumnOrExpression::ExistingColumn(
+                    VariableColumn::Variable(simple.var.clone()).column_name(),
+                ),
             };
+            let expression = simple.op.to_sql_expression(arg).ok_or_else(|| {
+                ProjectorError::NotYetImplemented(format!("{} in SQL", simple.op.name()))
+            })?;
             if simple.is_nullable() {
                 ColumnOrExpression::NullableAggregate(Box::new(expression), return_type)
             } else {
                 ColumnOrExpression::Expression(Box::new(expression), return_type)
             }
         }
-    } else {
-        // The common case: the values are bound during execution.
-        let name = VariableColumn::Variable(simple.var.clone()).column_name();
-        let expression = Expression::Unary {
-            sql_op: simple.op.to_sql(),
-            arg: ColumnOrExpression::ExistingColumn(name),
-        };
-        if simple.is_nullable() {
-            ColumnOrExpression::NullableAggregate(Box::new(expression), return_type)
-        } else {
-            ColumnOrExpression::Expression(Box::new(expression), return_type)
-        }
     };
     Ok((
         ProjectedColumn(projected_column_or_expression, simple.column_name()),
@@ -8,10 +8,12 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+extern crate chrono;
 extern crate failure;
 
 extern crate indexmap;
 extern crate rusqlite;
+extern crate serde_json;
 
 extern crate db_traits;
 extern crate edn;
@@ -29,7 +31,7 @@ use std::collections::BTreeSet;
 
 use std::iter;
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use rusqlite::{Row, Rows};
 
@@ -41,9 +43,9 @@ use mentat_core::util::Either;
 
 use mentat_db::TypedSQLValue;
 
-use edn::query::{Element, FindSpec, Limit, Variable};
+use edn::query::{Aggregate, Element, FindSpec, Limit, Variable};
 
-use mentat_query_algebrizer::{AlgebraicQuery, VariableBindings};
+use mentat_query_algebrizer::{AlgebraicQuery, ConjoiningClauses, VariableBindings};
 
 use mentat_query_sql::{GroupBy, Projection};
 
@@ -51,29 +53,36 @@ pub mod translate;
 
 mod binding_tuple;
 pub use crate::binding_tuple::BindingTuple;
+mod output;
 mod project;
 mod projectors;
 mod pull;
 mod relresult;
 
-use crate::project::{project_elements, ProjectedElements};
+use crate::project::{
+    needs_post_aggregation, project_elements, project_elements_for_post_aggregation,
+    ProjectedElements,
+};
 
 pub use crate::project::projected_column_for_var;
 
 pub use crate::projectors::{ConstantProjector, Projector};
 
 use crate::projectors::{
-    CollProjector, CollTwoStagePullProjector, RelProjector, RelTwoStagePullProjector,
-    ScalarProjector, ScalarTwoStagePullProjector, TupleProjector, TupleTwoStagePullProjector,
+    AggregatingProjector, CollProjector, CollTwoStagePullProjector, RelProjector,
+    RelTwoStagePullProjector, ScalarProjector, ScalarTwoStagePullProjector, TupleProjector,
+    TupleTwoStagePullProjector,
 };
 
 pub use crate::relresult::{RelResult, StructuredRelResult};
 
+use query_projector_traits::aggregates::SimpleAggregation;
+
 use query_projector_traits::errors::{ProjectorError, Result};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct QueryOutput {
-    pub spec: Rc<FindSpec>,
+    pub spec: Arc<FindSpec>,
     pub results: QueryResults,
 }
 
@@ -113,7 +122,7 @@ impl QueryOutput {
         self.results.is_empty()
     }
 
-    pub fn empty(spec: &Rc<FindSpec>) -> QueryOutput {
+    pub fn empty(spec: &Arc<FindSpec>) -> QueryOutput {
         use self::FindSpec::*;
         let results = match **spec {
             FindScalar(_) => QueryResults::Scalar(None),
@@ -127,17 +136,16 @@ impl QueryOutput {
         }
     }
 
-    pub fn from_constants(spec: &Rc<FindSpec>, bindings: VariableBindings) -> QueryResults {
+    pub fn from_constants(spec: &Arc<FindSpec>, bindings: VariableBindings) -> Result<QueryResults> {
         use self::FindSpec::*;
-        match **spec {
+        Ok(match **spec {
             FindScalar(Element::Variable(ref var))
             | FindScalar(Element::Corresponding(ref var)) => {
                 let val = bindings.get(var).cloned().map(|v| v.into());
                 QueryResults::Scalar(val)
             }
-            FindScalar(Element::Aggregate(ref _agg)) => {
-                // TODO: static aggregates.
-                unimplemented!();
+            FindScalar(Element::Aggregate(ref agg)) => {
+                QueryResults::Scalar(Some(static_aggregate(agg, &bindings)?))
             }
             FindScalar(Element::Pull(ref _pull)) => {
                 // TODO: static pull.
@@ -147,22 +155,20 @@ impl QueryOutput {
                 let values = elements
                     .iter()
                     .map(|e| match *e {
-                        Element::Variable(ref var) | Element::Corresponding(ref var) => bindings
-                            .get(var)
-                            .cloned()
-                            .expect("every var to have a binding")
-                            .into(),
+                        Element::Variable(ref var) | Element::Corresponding(ref var) => {
+                            Ok(bindings
+                                .get(var)
+                                .cloned()
+                                .expect("every var to have a binding")
+                                .into())
+                        }
                         Element::Pull(ref _pull) => {
                             // TODO: static pull.
                             unreachable!();
                         }
-                        Element::Aggregate(ref _agg) => {
-                            // TODO: static computation of aggregates, then
-                            // implement the condition in `is_fully_bound`.
-                            unreachable!();
-                        }
+                        Element::Aggregate(ref agg) => static_aggregate(agg, &bindings),
                     })
-                    .collect();
+                    .collect::<Result<Vec<Binding>>>()?;
                 QueryResults::Tuple(Some(values))
             }
             FindColl(Element::Variable(ref var)) | FindColl(Element::Corresponding(ref var)) => {
@@ -177,37 +183,63 @@ impl QueryOutput {
                 // TODO: static pull.
                 unimplemented!();
             }
-            FindColl(Element::Aggregate(ref _agg)) => {
-                // Does it even make sense to write
-                // [:find [(max ?x) ...] :where [_ :foo/bar ?x]]
-                // ?
-                // TODO
-                unimplemented!();
+            FindColl(Element::Aggregate(ref agg)) => {
+                QueryResults::Coll(vec![static_aggregate(agg, &bindings)?])
             }
             FindRel(ref elements) => {
                 let width = elements.len();
                 let values = elements
                     .iter()
                     .map(|e| match *e {
-                        Element::Variable(ref var) | Element::Corresponding(ref var) => bindings
-                            .get(var)
-                            .cloned()
-                            .expect("every var to have a binding")
-                            .into(),
+                        Element::Variable(ref var) | Element::Corresponding(ref var) => {
+                            Ok(bindings
+                                .get(var)
+                                .cloned()
+                                .expect("every var to have a binding")
+                                .into())
+                        }
                         Element::Pull(ref _pull) => {
                             // TODO: static pull.
                             unreachable!();
                         }
-                        Element::Aggregate(ref _agg) => {
-                            // TODO: static computation of aggregates, then
-                            // implement the condition in `is_fully_bound`.
-                            unreachable!();
-                        }
+                        Element::Aggregate(ref agg) => static_aggregate(agg, &bindings),
                     })
-                    .collect();
+                    .collect::<Result<Vec<Binding>>>()?;
                 QueryResults::Rel(RelResult { width, values })
             }
+        })
+    }
+
+    /// The results of a query that can't match anything. Usually that's nothing at all, but
+    /// ungrouped aggregates still produce a row: `count` and `sum` are zero. If any aggregate in
+    /// that row is `nil`, there's no row, just as when the query runs.
+    pub fn from_no_rows(spec: &Arc<FindSpec>, cc: &ConjoiningClauses) -> Result<QueryResults> {
+        use self::FindSpec::*;
+        let mut values: Vec<Binding> = Vec::new();
+        for element in spec.columns() {
+            match *element {
+                Element::Aggregate(ref agg) => {
+                    let simple = agg.to_simple().ok_or_else(|| {
+                        ProjectorError::NotYetImplemented("complex aggregates".into())
+                    })?;
+                    match simple.value_over_no_rows(cc.known_type_set(&simple.var)) {
+                        Some(value) => values.push(value.into()),
+                        None => return Ok(QueryOutput::empty_factory(spec)()),
+                    }
+                }
+                // Grouped by a variable, so there are no groups.
+                _ => return Ok(QueryOutput::empty_factory(spec)()),
+            }
         }
+        Ok(match **spec {
+            FindScalar(_) => QueryResults::Scalar(values.pop()),
+            FindTuple(_) => QueryResults::Tuple(Some(values)),
+            FindColl(_) => QueryResults::Coll(values),
+            FindRel(ref es) => QueryResults::Rel(RelResult {
+                width: es.len(),
+                values,
+            }),
+        })
     }
 
     pub fn into_scalar(self) -> Result<Option<Binding>> {
@@ -239,6 +271,18 @@ impl QueryOutput {
     }
 }
 
+/// Statically compute an aggregate over a single row of known values.
+fn static_aggregate(agg: &Aggregate, bindings: &VariableBindings) -> Result<Binding> {
+    let simple = agg
+        .to_simple()
+        .ok_or_else(|| ProjectorError::NotYetImplemented("complex aggregates".into()))?;
+    let value = bindings
+        .get(&simple.var)
+        .cloned()
+        .expect("every var to have a binding");
+    Ok(simple.value_over_one_row(value)?.into())
+}
+
 impl QueryResults {
     pub fn len(&self) -> usize {
         use crate::QueryResults::*;
@@ -318,6 +362,7 @@ impl QueryResults {
 }
 
 type Index = usize; // See rusqlite::RowIndex.
+#[derive(Clone, Copy)]
 enum TypedIndex {
     Known(Index, ValueTypeTag),
     Unknown(Index, Index),
@@ -339,21 +384,22 @@ impl TypedIndex {
     /// This function will return a runtime error if the type tag is unknown, or the value is
     /// otherwise not convertible by the DB layer.
     fn lookup<'a>(&self, row: &Row<'a>) -> Result<Binding> {
+        self.lookup_value(row).map(|v| v.into())
+    }
+
+    /// Like `lookup`, but yielding the `TypedValue` itself.
+    fn lookup_value<'a>(&self, row: &Row<'a>) -> Result<TypedValue> {
         use crate::TypedIndex::*;
 
         match *self {
             Known(value_index, value_type) => {
                 let v: rusqlite::types::Value = row.get(value_index).unwrap();
-                TypedValue::from_sql_value_pair(v, value_type)
-                    .map(|v| v.into())
-                    .map_err(|e| e.into())
+                TypedValue::from_sql_value_pair(v, value_type).map_err(|e| e.into())
             }
             Unknown(value_index, type_index) => {
                 let v: rusqlite::types::Value = row.get(value_index).unwrap();
                 let value_type_tag: i32 = row.get(type_index).unwrap();
-                TypedValue::from_sql_value_pair(v, value_type_tag)
-                    .map(|v| v.into())
-                    .map_err(|e| e.into())
+                TypedValue::from_sql_value_pair(v, value_type_tag).map_err(|e| e.into())
             }
         }
     }
@@ -386,6 +432,10 @@ pub struct CombinedProjection {
 
     // A list of column names to use as a GROUP BY clause.
     pub group_by_cols: Vec<GroupBy>,
+
+    /// True if the Datalog projector groups and aggregates rows itself. It then also applies any
+    /// limit, so the SQL query must return every row.
+    pub post_aggregate: bool,
 }
 
 impl CombinedProjection {
@@ -422,7 +472,12 @@ pub fn query_projection(
     use self::FindSpec::*;
 
     let spec = query.find_spec.clone();
-    if query.is_fully_unit_bound() {
+    if query.is_known_empty() {
+        // Do a few gyrations to produce empty results of the right kind for the query.
+        let results = QueryOutput::from_no_rows(&spec, &query.cc)?;
+        let f = Box::new(move || results.clone());
+        Ok(Either::Left(ConstantProjector::new(spec, f)))
+    } else if query.is_fully_unit_bound() {
         // Do a few gyrations to produce empty results of the right kind for the query.
 
         let variables: BTreeSet<Variable> = spec
@@ -436,24 +491,30 @@ pub fn query_projection(
                 Element::Pull(_) => {
                     unreachable!();
                 }
-                Element::Aggregate(ref _agg) => {
-                    // TODO: static computation of aggregates, then
-                    // implement the condition in `is_fully_bound`.
-                    unreachable!();
-                }
+
+                // Only aggregates over a single variable are fully bound.
+                Element::Aggregate(ref agg) => agg.args[0]
+                    .as_variable()
+                    .cloned()
+                    .expect("fully bound aggregate"),
             })
             .collect();
 
-        // TODO: error handling
-        let results = QueryOutput::from_constants(&spec, query.cc.value_bindings(&variables));
+        let results = QueryOutput::from_constants(&spec, query.cc.value_bindings(&variables))?;
         let f = Box::new(move || results.clone());
 
         Ok(Either::Left(ConstantProjector::new(spec, f)))
-    } else if query.is_known_empty() {
-        // Do a few gyrations to produce empty results of the right kind for the query.
-        let empty = QueryOutput::empty_factory(&spec);
-        Ok(Either::Left(ConstantProjector::new(spec, empty)))
     } else {
+        if query.find_spec.columns().any(needs_post_aggregation) {
+            let column_count = query.find_spec.expected_column_count();
+            let elements = project_elements_for_post_aggregation(
+                column_count,
+                query.find_spec.columns(),
+                query,
+            )?;
+            return AggregatingProjector::combine(spec, elements, &query.limit).map(Either::Right);
+        }
+
         match *query.find_spec {
             FindColl(ref element) => {
                 let elements = project_elements(1, iter::once(element), query)?;
@@ -504,7 +565,7 @@ pub fn query_projection(
 #[test]
 fn test_into_tuple() {
     let query_output = QueryOutput {
-        spec: Rc::new(FindSpec::FindTuple(vec![
+        spec: Arc::new(FindSpec::FindTuple(vec![
             Element::Variable(Variable::from_valid_name("?x")),
             Element::Variable(Variable::from_valid_name("?y")),
         ])),
@@ -533,7 +594,7 @@ fn test_into_tuple() {
     }
 
     let query_output = QueryOutput {
-        spec: Rc::new(FindSpec::FindTuple(vec![
+        spec: Arc::new(FindSpec::FindTuple(vec![
             Element::Variable(Variable::from_valid_name("?x")),
             Element::Variable(Variable::from_valid_name("?y")),
         ])),
@@ -0,0 +1,186 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Query results in formats that other tools can read: JSON, CSV, and EDN.
+//!
+//! Results keep their shape in JSON and EDN: a scalar is a single value, or null if there wasn't
+//! one; a tuple is an array, or null; a collection is an array of values; and a relation is an
+//! array of rows, each an array of values. CSV has a header row naming the find spec's elements,
+//! then a row for each result.
+//!
+//! JSON can't represent every Mentat value directly, so values are mapped like this:
+//!
+//! - refs and longs are numbers.
+//! - doubles are numbers, except that NaN and the infinities are null.
+//! - booleans are booleans.
+//! - strings are strings.
+//! - keywords are strings with a leading colon, like `":person/name"`.
+//! - instants are RFC 3339 strings in UTC, like `"2018-01-01T12:34:56.789Z"`.
+//! - UUIDs are hyphenated strings.
+//! - pulled entities are objects whose keys are attribute keywords, like `":person/name"`.
+//!
+//! CSV cells hold the same text as the JSON strings and numbers, without quotes unless a cell
+//! needs them, and are empty where JSON has a null double. Pulled entities are written to a cell as
+//! JSON.
+
+use std::collections::BTreeMap;
+
+use chrono::SecondsFormat;
+
+use serde_json;
+
+use core_traits::{Binding, TypedValue};
+
+use mentat_db::TypedSQLValue;
+
+use edn;
+
+use crate::{QueryOutput, QueryResults};
+
+impl QueryResults {
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value::{Array, Null};
+        match *self {
+            QueryResults::Scalar(ref v) => v.as_ref().map_or(Null, binding_to_json),
+            QueryResults::Tuple(ref v) => v.as_ref().map_or(Null, |row| row_to_json(row)),
+            QueryResults::Coll(ref v) => row_to_json(v),
+            QueryResults::Rel(ref rel) => Array(rel.rows().map(row_to_json).collect()),
+        }
+    }
+
+    pub fn to_edn(&self) -> edn::Value {
+        use edn::Value::{Nil, Vector};
+        match *self {
+            QueryResults::Scalar(ref v) => v.as_ref().map_or(Nil, binding_to_edn),
+            QueryResults::Tuple(ref v) => v.as_ref().map_or(Nil, |row| row_to_edn(row)),
+            QueryResults::Coll(ref v) => row_to_edn(v),
+            QueryResults::Rel(ref rel) => Vector(rel.rows().map(row_to_edn).collect()),
+        }
+    }
+
+    fn csv_rows(&self) -> Vec<&[Binding]> {
+        match *self {
+            QueryResults::Scalar(ref v) => v.iter().map(std::slice::from_ref).collect(),
+            QueryResults::Tuple(ref v) => v.iter().map(|row| row.as_slice()).collect(),
+            QueryResults::Coll(ref v) => v.iter().map(std::slice::from_ref).collect(),
+            QueryResults::Rel(ref rel) => rel.rows().collect(),
+        }
+    }
+}
+
+impl QueryOutput {
+    pub fn to_json(&self) -> serde_json::Value {
+        self.results.to_json()
+    }
+
+    pub fn to_edn(&self) -> edn::Value {
+        self.results.to_edn()
+    }
+
+    /// The results as CSV, with a header row naming the columns of the find spec. Each line,
+    /// including the last, ends with a newline.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        let header: Vec<String> = self.spec.columns().map(|e| e.to_string()).collect();
+        push_csv_line(&mut out, header.iter().map(|h| h.as_str()));
+        for row in self.results.csv_rows() {
+            let cells: Vec<String> = row.iter().map(binding_to_csv).collect();
+            push_csv_line(&mut out, cells.iter().map(|c| c.as_str()));
+        }
+        out
+    }
+}
+
+fn row_to_json(row: &[Binding]) -> serde_json::Value {
+    serde_json::Value::Array(row.iter().map(binding_to_json).collect())
+}
+
+fn binding_to_json(binding: &Binding) -> serde_json::Value {
+    match *binding {
+        Binding::Scalar(ref v) => value_to_json(v),
+        Binding::Vec(ref v) => row_to_json(v),
+        Binding::Map(ref m) => serde_json::Value::Object(
+            m.iter()
+                .map(|(k, v)| (k.to_string(), binding_to_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+fn value_to_json(value: &TypedValue) -> serde_json::Value {
+    use serde_json::Value;
+    match *value {
+        TypedValue::Ref(e) => Value::from(e),
+        TypedValue::Long(l) => Value::from(l),
+        // Non-finite doubles become null.
+        TypedValue::Double(d) => Value::from(d.into_inner()),
+        TypedValue::Boolean(b) => Value::Bool(b),
+        _ => Value::String(value_to_text(value)),
+    }
+}
+
+/// Scalar values as they appear in JSON strings and CSV cells.
+fn value_to_text(value: &TypedValue) -> String {
+    match *value {
+        TypedValue::Ref(e) => e.to_string(),
+        TypedValue::Long(l) => l.to_string(),
+        // Formatted as JSON numbers are. Non-finite doubles, which JSON can't hold, are empty.
+        TypedValue::Double(d) if !d.into_inner().is_finite() => String::new(),
+        TypedValue::Double(d) => serde_json::Value::from(d.into_inner()).to_string(),
+        TypedValue::Boolean(b) => b.to_string(),
+        TypedValue::String(ref s) => s.to_string(),
+        TypedValue::Keyword(ref k) => k.to_string(),
+        TypedValue::Instant(ref i) => i.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        TypedValue::Uuid(ref u) => u.to_hyphenated().to_string(),
+    }
+}
+
+fn row_to_edn(row: &[Binding]) -> edn::Value {
+    edn::Value::Vector(row.iter().map(binding_to_edn).collect())
+}
+
+fn binding_to_edn(binding: &Binding) -> edn::Value {
+    match *binding {
+        Binding::Scalar(ref v) => v.to_edn_value_pair().0,
+        Binding::Vec(ref v) => row_to_edn(v),
+        Binding::Map(ref m) => edn::Value::Map(
+            m.iter()
+                .map(|(k, v)| (edn::Value::Keyword((**k).clone()), binding_to_edn(v)))
+                .collect::<BTreeMap<_, _>>(),
+        ),
+    }
+}
+
+fn binding_to_csv(binding: &Binding) -> String {
+    match *binding {
+        Binding::Scalar(ref v) => value_to_text(v),
+        _ => binding_to_json(binding).to_string(),
+    }
+}
+
+/// Cells are quoted, as RFC 4180 describes, when they contain a comma, a quote, or a line break.
+fn push_csv_line<'a, I>(out: &mut String, cells: I)
+where
+    I: Iterator<Item = &'a str>,
+{
+    for (i, cell) in cells.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if cell.contains(&[',', '"', '\n', '\r'][..]) {
+            out.push('"');
+            out.push_str(&cell.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(cell);
+        }
+    }
+    out.push('\n');
+}
@@ -8,7 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use indexmap::IndexSet;
 
@@ -27,7 +27,7 @@ use mentat_query_algebrizer::{
 use mentat_query_sql::{ColumnOrExpression, GroupBy, Name, ProjectedColumn, Projection};
 
 use query_projector_traits::aggregates::{
-    projected_column_for_simple_aggregate, SimpleAggregation,
+    projected_column_for_simple_aggregate, SimpleAggregation, SimpleAggregationOp,
 };
 
 use query_projector_traits::errors::{ProjectorError, Result};
@@ -55,6 +55,17 @@ pub(crate) struct ProjectedElements {
     // it would be more efficient to combine them.
     pub pulls: Vec<PullTemplate>,
     pub group_by: Vec<GroupBy>,
+
+    // If we aggregate rows ourselves, what to do with each template's values.
+    pub post_aggregates: Option<Vec<PostAggregate>>,
+}
+
+/// When SQLite can't compute one of a query's aggregates, we compute all of them as we project
+/// rows. Each output column is then either one of the values by which rows are grouped, or an
+/// aggregate over the values of a column.
+pub(crate) enum PostAggregate {
+    Group,
+    Aggregate(SimpleAggregationOp),
 }
 
 impl ProjectedElements {
@@ -69,6 +80,7 @@ impl ProjectedElements {
             datalog_projector: projector,
             distinct,
             group_by_cols: self.group_by,
+            post_aggregate: self.post_aggregates.is_some(),
         })
     }
 
@@ -85,6 +97,14 @@ impl ProjectedElements {
         ::std::mem::swap(&mut out, &mut self.pulls);
         out
     }
+
+    // This leaves an empty list behind, so we still know to aggregate rows ourselves.
+    pub(crate) fn take_post_aggregates(&mut self) -> Vec<PostAggregate> {
+        self.post_aggregates
+            .as_mut()
+            .map(::std::mem::take)
+            .unwrap_or_default()
+    }
 }
 
 fn candidate_type_column(
@@ -302,7 +322,7 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
                         Max | Min => {
                             min_max_count += 1;
                         }
-                        Avg | Count | Sum => (),
+                        _ => (),
                     }
 
                     // When we encounter a simple aggregate -- one in which the aggregation can be
@@ -413,6 +433,7 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
             templates,
             pulls,
             group_by: vec![],
+            post_aggregates: None,
         });
     }
 
@@ -523,5 +544,100 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
         templates,
         pulls,
         group_by,
+        post_aggregates: None,
+    })
+}
+
+pub(crate) fn needs_post_aggregation(element: &Element) -> bool {
+    match *element {
+        Element::Aggregate(ref a) => a.to_simple().is_some_and(|simple| !simple.op.is_sql()),
+        _ => false,
+    }
+}
+
+/// Like `project_elements`, but for queries with an aggregate that SQLite can't compute. We
+/// project the distinct bindings of every variable we need and leave grouping, aggregation, and
+/// limiting to the projector.
+pub(crate) fn project_elements_for_post_aggregation<'a, I: IntoIterator<Item = &'a Element>>(
+    count: usize,
+    elements: I,
+    query: &AlgebraicQuery,
+) -> Result<ProjectedElements> {
+    let mut projection = Vec::with_capacity(count + 2);
+    let mut projected: BTreeMap<Variable, TypedIndex> = BTreeMap::new();
+    let mut i: usize = 0;
+
+    let mut project_var = |var: &Variable| -> Result<TypedIndex> {
+        if let Some(index) = projected.get(var) {
+            return Ok(*index);
+        }
+        let (projected_column, type_set) = projected_column_for_var(var, &query.cc)?;
+        projection.push(projected_column);
+        let index = if let Some(tag) = type_set.unique_type_tag() {
+            i += 1; // We used one SQL column.
+            TypedIndex::Known(i - 1, tag)
+        } else {
+            let (type_column, type_name) = candidate_type_column(&query.cc, var)?;
+            projection.push(ProjectedColumn(type_column, type_name));
+            i += 2; // We used two SQL columns.
+            TypedIndex::Unknown(i - 2, i - 1)
+        };
+        projected.insert(var.clone(), index);
+        Ok(index)
+    };
+
+    let mut templates = Vec::with_capacity(count);
+    let mut post_aggregates = Vec::with_capacity(count);
+    let mut grouped = BTreeSet::new();
+    for e in elements {
+        match *e {
+            Element::Variable(ref var) => {
+                if !grouped.insert(var.clone()) {
+                    bail!(ProjectorError::InvalidProjection(format!(
+                        "Duplicate variable {} in query.",
+                        var
+                    )));
+                }
+                templates.push(project_var(var)?);
+                post_aggregates.push(PostAggregate::Group);
+            }
+            Element::Aggregate(ref a) => {
+                let simple = a.to_simple().ok_or_else(|| {
+                    ProjectorError::NotYetImplemented("complex aggregates".into())
+                })?;
+                simple
+                    .op
+                    .is_applicable_to_types(query.cc.known_type_set(&simple.var))?;
+                templates.push(project_var(&simple.var)?);
+                post_aggregates.push(PostAggregate::Aggregate(simple.op));
+            }
+            Element::Corresponding(_) => {
+                bail!(ProjectorError::NotYetImplemented(
+                    "`the` with aggregates computed outside SQL".into()
+                ));
+            }
+            Element::Pull(_) => {
+                bail!(ProjectorError::NotYetImplemented(
+                    "pull with aggregates computed outside SQL".into()
+                ));
+            }
+        }
+    }
+
+    // Variables used for ordering, and `:with` variables, distinguish rows without appearing in
+    // the output.
+    for var in query.named_projection.iter().chain(query.with.iter()) {
+        if !query.cc.is_value_bound(var) {
+            project_var(var)?;
+        }
+    }
+
+    Ok(ProjectedElements {
+        sql_projection: Projection::Columns(projection),
+        pre_aggregate_projection: None,
+        templates,
+        pulls: vec![],
+        group_by: vec![],
+        post_aggregates: Some(post_aggregates),
     })
 }
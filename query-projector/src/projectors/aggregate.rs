@@ -0,0 +1,149 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+
+use core_traits::TypedValue;
+
+use edn::query::Limit;
+
+use crate::project::PostAggregate;
+use crate::{
+    rusqlite, Binding, CombinedProjection, Element, FindSpec, ProjectedElements, QueryOutput,
+    QueryResults, RelResult, Rows, Schema, TypedIndex,
+};
+
+use query_projector_traits::errors::{ProjectorError, Result};
+
+use super::Projector;
+
+/// An aggregating projector groups rows by the values of their non-aggregate columns, and
+/// computes each aggregate over a group's values. We use it for aggregates that SQLite can't
+/// compute for us; it works for any find spec.
+pub(crate) struct AggregatingProjector {
+    spec: Arc<FindSpec>,
+    templates: Vec<TypedIndex>,
+    post_aggregates: Vec<PostAggregate>,
+    limit: Option<usize>,
+}
+
+impl AggregatingProjector {
+    pub(crate) fn combine(
+        spec: Arc<FindSpec>,
+        mut elements: ProjectedElements,
+        limit: &Limit,
+    ) -> Result<CombinedProjection> {
+        // The SQL query returns rows, not groups, so we can't let it apply the limit.
+        let limit = match *limit {
+            Limit::None => None,
+            Limit::Fixed(n) => Some(n as usize),
+            Limit::Variable(ref var) => bail!(ProjectorError::NotYetImplemented(format!(
+                "unbound limit {} with aggregates computed outside SQL",
+                var
+            ))),
+        };
+        let projector = Box::new(AggregatingProjector {
+            spec,
+            templates: elements.take_templates(),
+            post_aggregates: elements.take_post_aggregates(),
+            limit,
+        });
+
+        // Aggregates are computed over distinct bindings.
+        elements.combine(projector, true)
+    }
+
+    /// Compute the output row for a group. As in SQL, we reject a row if any of its aggregates
+    /// is `nil`.
+    fn aggregate_group(
+        &self,
+        key: Vec<TypedValue>,
+        inputs: Vec<Vec<TypedValue>>,
+    ) -> Option<Vec<Binding>> {
+        let mut key = key.into_iter();
+        let mut inputs = inputs.into_iter();
+        self.post_aggregates
+            .iter()
+            .map(|post| match *post {
+                PostAggregate::Group => key.next().map(Binding::Scalar),
+                PostAggregate::Aggregate(op) => op.aggregate(inputs.next().unwrap_or_default()),
+            })
+            .collect()
+    }
+}
+
+impl Projector for AggregatingProjector {
+    fn project<'stmt, 's>(
+        &self,
+        _schema: &Schema,
+        _sqlite: &'s rusqlite::Connection,
+        mut rows: Rows<'stmt>,
+    ) -> Result<QueryOutput> {
+        let aggregate_count = self
+            .post_aggregates
+            .iter()
+            .filter(|post| matches!(post, PostAggregate::Aggregate(_)))
+            .count();
+
+        // Keep groups in the order in which we first see them, which respects any ordering.
+        let mut groups: IndexMap<Vec<TypedValue>, Vec<Vec<TypedValue>>> = IndexMap::new();
+        while let Some(row) = rows.next().unwrap() {
+            let mut key = Vec::new();
+            let mut values = Vec::with_capacity(aggregate_count);
+            for (template, post) in self.templates.iter().zip(self.post_aggregates.iter()) {
+                let value = template.lookup_value(row)?;
+                match *post {
+                    PostAggregate::Group => key.push(value),
+                    PostAggregate::Aggregate(_) => values.push(value),
+                }
+            }
+            let group = groups
+                .entry(key)
+                .or_insert_with(|| vec![vec![]; aggregate_count]);
+            for (inputs, value) in group.iter_mut().zip(values) {
+                inputs.push(value);
+            }
+        }
+
+        // Just like SQL, if we don't group at all then aggregating nothing still yields a row.
+        if groups.is_empty() && aggregate_count == self.post_aggregates.len() {
+            groups.insert(vec![], vec![vec![]; aggregate_count]);
+        }
+
+        let mut results = groups
+            .into_iter()
+            .filter_map(|(key, inputs)| self.aggregate_group(key, inputs))
+            .take(self.limit.unwrap_or(usize::MAX));
+
+        let results = match *self.spec {
+            FindSpec::FindScalar(_) => {
+                QueryResults::Scalar(results.next().and_then(|row| row.into_iter().next()))
+            }
+            FindSpec::FindTuple(_) => QueryResults::Tuple(results.next()),
+            FindSpec::FindColl(_) => {
+                QueryResults::Coll(results.filter_map(|row| row.into_iter().next()).collect())
+            }
+            FindSpec::FindRel(_) => QueryResults::Rel(RelResult {
+                width: self.templates.len(),
+                values: results.flatten().collect(),
+            }),
+        };
+        Ok(QueryOutput {
+            spec: self.spec.clone(),
+            results,
+        })
+    }
+
+    fn columns<'s>(&'s self) -> Box<dyn Iterator<Item = &'s Element> + 's> {
+        self.spec.columns()
+    }
+}
@@ -8,7 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::{rusqlite, Element, FindSpec, QueryOutput, QueryResults, Rows, Schema};
 
@@ -19,14 +19,14 @@ use super::Projector;
 /// A projector that produces a `QueryResult` containing fixed data.
 /// Takes a boxed function that should return an empty result set of the desired type.
 pub struct ConstantProjector {
-    spec: Rc<FindSpec>,
-    results_factory: Box<dyn Fn() -> QueryResults>,
+    spec: Arc<FindSpec>,
+    results_factory: Box<dyn Fn() -> QueryResults + Send>,
 }
 
 impl ConstantProjector {
     pub fn new(
-        spec: Rc<FindSpec>,
-        results_factory: Box<dyn Fn() -> QueryResults>,
+        spec: Arc<FindSpec>,
+        results_factory: Box<dyn Fn() -> QueryResults + Send>,
     ) -> ConstantProjector {
         ConstantProjector {
             spec,
@@ -12,7 +12,7 @@ use super::{rusqlite, Element, QueryOutput, Rows, Schema};
 
 use query_projector_traits::errors::Result;
 
-pub trait Projector {
+pub trait Projector: Send {
     fn project<'stmt, 's>(
         &self,
         schema: &Schema,
@@ -22,12 +22,15 @@ pub trait Projector {
     fn columns<'s>(&'s self) -> Box<dyn Iterator<Item = &Element> + 's>;
 }
 
+mod aggregate;
 mod constant;
 mod pull_two_stage;
 mod simple;
 
 pub use self::constant::ConstantProjector;
 
+pub(crate) use self::aggregate::AggregatingProjector;
+
 pub(crate) use self::simple::{CollProjector, RelProjector, ScalarProjector, TupleProjector};
 
 pub(crate) use self::pull_two_stage::{
@@ -8,7 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use std::iter::once;
 
@@ -28,7 +28,7 @@ use query_projector_traits::errors::Result;
 use super::Projector;
 
 pub(crate) struct ScalarTwoStagePullProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     puller: Puller,
 }
 
@@ -38,7 +38,7 @@ pub(crate) struct ScalarTwoStagePullProjector {
 impl ScalarTwoStagePullProjector {
     fn with_template(
         schema: &Schema,
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         pull: PullOperation,
     ) -> Result<ScalarTwoStagePullProjector> {
         Ok(ScalarTwoStagePullProjector {
@@ -49,7 +49,7 @@ impl ScalarTwoStagePullProjector {
 
     pub(crate) fn combine(
         schema: &Schema,
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         mut elements: ProjectedElements,
     ) -> Result<CombinedProjection> {
         let pull = elements.pulls.pop().expect("Expected a single pull");
@@ -97,7 +97,7 @@ impl Projector for ScalarTwoStagePullProjector {
 
 /// A tuple projector produces a single vector. It's the single-result version of rel.
 pub(crate) struct TupleTwoStagePullProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     len: usize,
     templates: Vec<TypedIndex>,
     pulls: Vec<PullTemplate>,
@@ -105,7 +105,7 @@ pub(crate) struct TupleTwoStagePullProjector {
 
 impl TupleTwoStagePullProjector {
     fn with_templates(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         len: usize,
         templates: Vec<TypedIndex>,
         pulls: Vec<PullTemplate>,
@@ -131,7 +131,7 @@ impl TupleTwoStagePullProjector {
     }
 
     pub(crate) fn combine(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         column_count: usize,
         mut elements: ProjectedElements,
     ) -> Result<CombinedProjection> {
@@ -201,7 +201,7 @@ impl Projector for TupleTwoStagePullProjector {
 /// Each column in each stride is the result of taking one or two columns from
 /// the `Row`: one for the value and optionally one for the type tag.
 pub(crate) struct RelTwoStagePullProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     len: usize,
     templates: Vec<TypedIndex>,
     pulls: Vec<PullTemplate>,
@@ -209,7 +209,7 @@ pub(crate) struct RelTwoStagePullProjector {
 
 impl RelTwoStagePullProjector {
     fn with_templates(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         len: usize,
         templates: Vec<TypedIndex>,
         pulls: Vec<PullTemplate>,
@@ -237,7 +237,7 @@ impl RelTwoStagePullProjector {
     }
 
     pub(crate) fn combine(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         column_count: usize,
         mut elements: ProjectedElements,
     ) -> Result<CombinedProjection> {
@@ -314,17 +314,17 @@ impl Projector for RelTwoStagePullProjector {
 /// A coll projector produces a vector of values.
 /// Each value is sourced from the same column.
 pub(crate) struct CollTwoStagePullProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     pull: PullOperation,
 }
 
 impl CollTwoStagePullProjector {
-    fn with_pull(spec: Rc<FindSpec>, pull: PullOperation) -> CollTwoStagePullProjector {
+    fn with_pull(spec: Arc<FindSpec>, pull: PullOperation) -> CollTwoStagePullProjector {
         CollTwoStagePullProjector { spec, pull }
     }
 
     pub(crate) fn combine(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         mut elements: ProjectedElements,
     ) -> Result<CombinedProjection> {
         let pull = elements.pulls.pop().expect("Expected a single pull");
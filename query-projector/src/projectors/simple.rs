@@ -8,7 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::{
     rusqlite, Binding, CombinedProjection, Element, FindSpec, ProjectedElements, QueryOutput,
@@ -20,17 +20,17 @@ use query_projector_traits::errors::Result;
 use super::Projector;
 
 pub(crate) struct ScalarProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     template: TypedIndex,
 }
 
 impl ScalarProjector {
-    fn with_template(spec: Rc<FindSpec>, template: TypedIndex) -> ScalarProjector {
+    fn with_template(spec: Arc<FindSpec>, template: TypedIndex) -> ScalarProjector {
         ScalarProjector { spec, template }
     }
 
     pub(crate) fn combine(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         mut elements: ProjectedElements,
     ) -> Result<CombinedProjection> {
         let template = elements
@@ -70,14 +70,14 @@ impl Projector for ScalarProjector {
 
 /// A tuple projector produces a single vector. It's the single-result version of rel.
 pub(crate) struct TupleProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     len: usize,
     templates: Vec<TypedIndex>,
 }
 
 impl TupleProjector {
     fn with_templates(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         len: usize,
         templates: Vec<TypedIndex>,
     ) -> TupleProjector {
@@ -101,7 +101,7 @@ impl TupleProjector {
     }
 
     pub(crate) fn combine(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         column_count: usize,
         mut elements: ProjectedElements,
     ) -> Result<CombinedProjection> {
@@ -145,13 +145,13 @@ impl Projector for TupleProjector {
 /// Each column in each stride is the result of taking one or two columns from
 /// the `Row`: one for the value and optionally one for the type tag.
 pub(crate) struct RelProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     len: usize,
     templates: Vec<TypedIndex>,
 }
 
 impl RelProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>) -> RelProjector {
+    fn with_templates(spec: Arc<FindSpec>, len: usize, templates: Vec<TypedIndex>) -> RelProjector {
         RelProjector {
             spec,
             len,
@@ -174,7 +174,7 @@ impl RelProjector {
     }
 
     pub(crate) fn combine(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         column_count: usize,
         mut elements: ProjectedElements,
     ) -> Result<CombinedProjection> {
@@ -226,17 +226,17 @@ impl Projector for RelProjector {
 /// A coll projector produces a vector of values.
 /// Each value is sourced from the same column.
 pub(crate) struct CollProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     template: TypedIndex,
 }
 
 impl CollProjector {
-    fn with_template(spec: Rc<FindSpec>, template: TypedIndex) -> CollProjector {
+    fn with_template(spec: Arc<FindSpec>, template: TypedIndex) -> CollProjector {
         CollProjector { spec, template }
     }
 
     pub(crate) fn combine(
-        spec: Rc<FindSpec>,
+        spec: Arc<FindSpec>,
         mut elements: ProjectedElements,
     ) -> Result<CombinedProjection> {
         let template = elements
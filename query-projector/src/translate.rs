@@ -14,7 +14,7 @@ use mentat_core::{SQLTypeAffinity, SQLValueType, SQLValueTypeSet, Schema, ValueT
 
 use mentat_core::util::Either;
 
-use edn::query::Limit;
+use edn::query::{Limit, Variable};
 
 use mentat_query_algebrizer::{
     AlgebraicQuery, ColumnAlternation, ColumnConstraint, ColumnConstraintOrAlternation,
@@ -31,7 +31,7 @@ use mentat_query_sql::{
     SelectQuery, TableList, TableOrSubquery, Values,
 };
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use super::Result;
 
@@ -138,6 +138,10 @@ impl ToConstraint for ColumnConstraint {
                 Constraint::equal(left.to_column(), right.to_column())
             }
 
+            Equals(qa, QueryValue::Input(var)) => {
+                Constraint::equal(qa.to_column(), ColumnOrExpression::Input(var))
+            }
+
             Equals(qa, QueryValue::PrimitiveLong(value)) => {
                 let tag_column = qa
                     .for_associated_type_tag()
@@ -227,6 +231,12 @@ impl ToConstraint for ColumnConstraint {
                 let subquery = table_for_computed(computed_table, TableAlias::new());
                 Constraint::NotExists { subquery }
             }
+
+            Holds(computed) => computed.into(),
+
+            NotNull(qa) => Constraint::IsNotNull {
+                value: qa.to_column(),
+            },
         }
     }
 }
@@ -258,63 +268,86 @@ impl<T> ConsumableVec<T> {
     }
 }
 
+/// Turn one arm of a union into a subquery. Every arm must project the same columns with the same
+/// names. The values we project might be fixed or they might be columns.
+fn select_for_union_arm(
+    cc: ConjoiningClauses,
+    projection: &BTreeSet<Variable>,
+    type_extraction: &BTreeSet<Variable>,
+) -> SelectQuery {
+    // We're going to end up with the variables being projected and also some
+    // type tag columns.
+    let mut columns: Vec<ProjectedColumn> =
+        Vec::with_capacity(projection.len() + type_extraction.len());
+
+    // For each variable, find out which column it maps to within this arm, and
+    // project it as the variable name.
+    // E.g., SELECT datoms03.v AS `?x`.
+    for var in projection.iter() {
+        // TODO: chain results out.
+        let (projected_column, type_set) =
+            projected_column_for_var(var, &cc).expect("every var to be bound");
+        columns.push(projected_column);
+
+        // Similarly, project type tags if they're not known conclusively in the
+        // outer query.
+        // Assumption: we'll never need to project a tag without projecting the value of a variable.
+        if type_extraction.contains(var) {
+            let expression = if let Some(tag) = type_set.unique_type_tag() {
+                // If we know the type for sure, just project the constant.
+                // SELECT datoms03.v AS `?x`, 10 AS `?x_value_type_tag`
+                ColumnOrExpression::Integer(tag)
+            } else {
+                // Otherwise, we'll have an established type binding! This'll be
+                // either a datoms table or, recursively, a subquery. Project
+                // this:
+                // SELECT datoms03.v AS `?x`,
+                //        datoms03.value_type_tag AS `?x_value_type_tag`
+                let extract = cc
+                    .extracted_types
+                    .get(var)
+                    .expect("Expected variable to have a known type, or an extracted type");
+                ColumnOrExpression::Column(extract.clone())
+            };
+            let type_column = VariableColumn::VariableTypeTag(var.clone());
+            let proj = ProjectedColumn(expression, type_column.column_name());
+            columns.push(proj);
+        }
+    }
+
+    // Each arm simply turns into a subquery.
+    // The SQL translation will stuff "UNION" between each arm.
+    let projection = Projection::Columns(columns);
+    cc_to_select_query(projection, cc, false, vec![], None, Limit::None)
+}
+
 fn table_for_computed(computed: ComputedTable, alias: TableAlias) -> TableOrSubquery {
     match computed {
         ComputedTable::Union {
             projection,
             type_extraction,
             arms,
-        } => {
-            // The projection list for each CC must have the same shape and the same names.
-            // The values we project might be fixed or they might be columns.
-            TableOrSubquery::Union(
-                arms.into_iter()
-                    .map(|cc| {
-                        // We're going to end up with the variables being projected and also some
-                        // type tag columns.
-                        let mut columns: Vec<ProjectedColumn> = Vec::with_capacity(projection.len() + type_extraction.len());
-
-                        // For each variable, find out which column it maps to within this arm, and
-                        // project it as the variable name.
-                        // E.g., SELECT datoms03.v AS `?x`.
-                        for var in projection.iter() {
-                            // TODO: chain results out.
-                            let (projected_column, type_set) = projected_column_for_var(var, &cc).expect("every var to be bound");
-                            columns.push(projected_column);
-
-                            // Similarly, project type tags if they're not known conclusively in the
-                            // outer query.
-                            // Assumption: we'll never need to project a tag without projecting the value of a variable.
-                            if type_extraction.contains(var) {
-                                let expression =
-                                    if let Some(tag) = type_set.unique_type_tag() {
-                                        // If we know the type for sure, just project the constant.
-                                        // SELECT datoms03.v AS `?x`, 10 AS `?x_value_type_tag`
-                                        ColumnOrExpression::Integer(tag)
-                                    } else {
-                                        // Otherwise, we'll have an established type binding! This'll be
-                                        // either a datoms table or, recursively, a subquery. Project
-                                        // this:
-                                        // SELECT datoms03.v AS `?x`,
-                                        //        datoms03.value_type_tag AS `?x_value_type_tag`
-                                        let extract = cc.extracted_types
-                                                        .get(var)
-                                                        .expect("Expected variable to have a known type, or an extracted type");
-                                        ColumnOrExpression::Column(extract.clone())
-                                    };
-                                let type_column = VariableColumn::VariableTypeTag(var.clone());
-                                let proj = ProjectedColumn(expression, type_column.column_name());
-                                columns.push(proj);
-                            }
-                        }
-
-                        // Each arm simply turns into a subquery.
-                        // The SQL translation will stuff "UNION" between each arm.
-                        let projection = Projection::Columns(columns);
-                        cc_to_select_query(projection, cc, false, vec![], None, Limit::None)
-                  }).collect(),
-                alias)
-        }
+        } => TableOrSubquery::Union(
+            arms.into_iter()
+                .map(|cc| select_for_union_arm(cc, &projection, &type_extraction))
+                .collect(),
+            alias,
+        ),
+        ComputedTable::RecursiveUnion {
+            projection,
+            type_extraction,
+            base,
+            recursive,
+        } => TableOrSubquery::RecursiveUnion(
+            base.into_iter()
+                .map(|cc| select_for_union_arm(cc, &projection, &type_extraction))
+                .collect(),
+            recursive
+                .into_iter()
+                .map(|cc| select_for_union_arm(cc, &projection, &type_extraction))
+                .collect(),
+            alias,
+        ),
         ComputedTable::Subquery(subquery) => {
             TableOrSubquery::Subquery(Box::new(cc_to_exists(*subquery)))
         }
@@ -477,7 +510,7 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
 /// Consume a provided `AlgebraicQuery` to yield a new
 /// `ProjectedSelect`.
 pub fn query_to_select(schema: &Schema, query: AlgebraicQuery) -> Result<ProjectedSelect> {
-    // TODO: we can't pass `query.limit` here if we aggregate during projection.
+    // We can't pass `query.limit` here if we aggregate during projection.
     // SQL-based aggregation -- `SELECT SUM(datoms00.e)` -- is fine.
     query_projection(schema, &query).map(|e| match e {
         Either::Left(constant) => ProjectedSelect::Constant(constant),
@@ -487,7 +520,13 @@ pub fn query_to_select(schema: &Schema, query: AlgebraicQuery) -> Result<Project
             datalog_projector,
             distinct,
             group_by_cols,
+            post_aggregate,
         }) => {
+            let limit = if post_aggregate {
+                Limit::None
+            } else {
+                query.limit
+            };
             ProjectedSelect::Query {
                 query: match pre_aggregate_projection {
                     // If we know we need a nested query for aggregation, build that first.
@@ -498,7 +537,7 @@ pub fn query_to_select(schema: &Schema, query: AlgebraicQuery) -> Result<Project
                             distinct,
                             group_by_cols,
                             query.order,
-                            query.limit,
+                            limit,
                         );
                         Box::new(re_project(inner, sql_projection)) // outer
                     }
@@ -508,7 +547,7 @@ pub fn query_to_select(schema: &Schema, query: AlgebraicQuery) -> Result<Project
                         distinct,
                         group_by_cols,
                         query.order,
-                        query.limit,
+                        limit,
                     )),
                 },
                 projector: datalog_projector,
@@ -17,16 +17,15 @@ extern crate mentat_sql;
 
 use std::collections::BTreeMap;
 
-use std::rc::Rc;
 
 use edn::query::{FindSpec, Keyword, Variable};
 
 use core_traits::{Attribute, Entid, TypedValue, ValueType};
 
-use mentat_core::Schema;
+use mentat_core::{Schema, ValueRc};
 
 use mentat_query_algebrizer::{
-    algebrize, algebrize_with_inputs, parse_find_string, Known, QueryInputs,
+    algebrize, algebrize_with_inputs, parse_find_string, parse_rules_string, Known, QueryInputs,
 };
 
 use mentat_query_projector::ConstantProjector;
@@ -143,10 +142,13 @@ fn prepopulated_schema() -> Schema {
     prepopulated_typed_schema(ValueType::String)
 }
 
-fn make_arg(name: &'static str, value: &'static str) -> (String, Rc<rusqlite::types::Value>) {
+fn make_arg(
+    name: &'static str,
+    value: &'static str,
+) -> (String, ValueRc<rusqlite::types::Value>) {
     (
         name.to_string(),
-        Rc::new(rusqlite::types::Value::Text(value.to_string())),
+        ValueRc::new(rusqlite::types::Value::Text(value.to_string())),
     )
 }
 
@@ -219,6 +221,38 @@ fn test_unbound_variable_limit() {
     assert_eq!(args, vec![make_arg("$v0", "yyy")]);
 }
 
+#[test]
+fn test_late_bound_inputs() {
+    // We only know the type of `?name`, so we compare against an argument bound later.
+    let schema = prepopulated_schema();
+    let query = r#"[:find ?x :in ?name :where [?x :foo/bar ?name]]"#;
+    let inputs = QueryInputs::with_type_sequence(vec![(var!(?name), ValueType::String)]);
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT `datoms00`.e AS `?x` \
+         FROM `datoms` AS `datoms00` \
+         WHERE `datoms00`.a = 99 AND `datoms00`.v = $iname"
+    );
+    assert_eq!(args, vec![]);
+
+    // Inputs that aren't bound to a column can still be used as arguments.
+    let schema = prepopulated_typed_schema(ValueType::Long);
+    let query = r#"[:find ?x :in ?min-y :where [?x :foo/bar ?y] [(> ?y ?min-y)]]"#;
+    let inputs = QueryInputs::with_type_sequence(vec![(
+        Variable::from_valid_name("?min-y"),
+        ValueType::Long,
+    )]);
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT `datoms00`.e AS `?x` \
+         FROM `datoms` AS `datoms00` \
+         WHERE `datoms00`.a = 99 AND `datoms00`.v > $imin_y"
+    );
+    assert_eq!(args, vec![]);
+}
+
 #[test]
 fn test_bound_variable_limit() {
     let schema = prepopulated_schema();
@@ -270,10 +304,9 @@ fn test_bound_variable_limit_affects_types() {
     let select = query_to_select(&schema, algebrized).expect("query to translate");
     let SQLQuery { sql, args } = query_to_sql(select);
 
-    // TODO: this query isn't actually correct -- we don't yet algebrize for variables that are
-    // specified in `:in` but not provided at algebrizing time. But it shows what we care about
-    // at the moment: we don't project a type column, because we know it's a Long.
-    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `datoms00`.v AS `?limit` FROM `datoms` AS `datoms00` LIMIT $ilimit");
+    // We don't project a type column, because we know it's a Long, but we do check the type tag,
+    // because the attribute is unknown. The value is bound when the query is run.
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `datoms00`.v AS `?limit` FROM `datoms` AS `datoms00` WHERE (`datoms00`.value_type_tag = 5) AND `datoms00`.v = $ilimit LIMIT $ilimit");
     assert_eq!(args, vec![]);
 }
 
@@ -1094,8 +1127,8 @@ fn test_fulltext() {
         "SELECT DISTINCT `datoms01`.e AS `?entity`, \
          `fulltext_values00`.text AS `?value`, \
          `datoms01`.tx AS `?tx`, \
-         0e0 AS `?score` \
-         FROM `fulltext_values` AS `fulltext_values00`, \
+         -bm25(`fulltext_values00`.`fulltext_index_unicode61`) AS `?score` \
+         FROM `fulltext_index_unicode61` AS `fulltext_values00`, \
          `datoms` AS `datoms01` \
          WHERE `datoms01`.a = 100 \
          AND `datoms01`.v = `fulltext_values00`.rowid \
@@ -1111,7 +1144,7 @@ fn test_fulltext() {
         "SELECT DISTINCT `datoms01`.e AS `?entity`, \
          `fulltext_values00`.text AS `?value`, \
          `datoms01`.tx AS `?tx` \
-         FROM `fulltext_values` AS `fulltext_values00`, \
+         FROM `fulltext_index_unicode61` AS `fulltext_values00`, \
          `datoms` AS `datoms01` \
          WHERE `datoms01`.a = 100 \
          AND `datoms01`.v = `fulltext_values00`.rowid \
@@ -1127,7 +1160,7 @@ fn test_fulltext() {
         "SELECT DISTINCT `datoms01`.e AS `?entity`, \
          `fulltext_values00`.text AS `?value`, \
          `datoms01`.tx AS `?tx` \
-         FROM `fulltext_values` AS `fulltext_values00`, \
+         FROM `fulltext_index_unicode61` AS `fulltext_values00`, \
          `datoms` AS `datoms01` \
          WHERE `datoms01`.a = 100 \
          AND `datoms01`.v = `fulltext_values00`.rowid \
@@ -1142,15 +1175,15 @@ fn test_fulltext() {
         "SELECT DISTINCT `datoms01`.e AS `?entity`, \
          `fulltext_values00`.text AS `?value`, \
          `datoms01`.tx AS `?tx` \
-         FROM `fulltext_values` AS `fulltext_values00`, \
+         FROM `fulltext_index_unicode61` AS `fulltext_values00`, \
          `datoms` AS `datoms01`, \
          `datoms` AS `datoms02` \
          WHERE `datoms01`.a = 100 \
          AND `datoms01`.v = `fulltext_values00`.rowid \
          AND `fulltext_values00`.text MATCH $v0 \
          AND `datoms02`.a = 99 \
-         AND `datoms02`.v = 0e0 \
-         AND `datoms01`.e = `datoms02`.e"
+         AND `datoms01`.e = `datoms02`.e \
+         AND -bm25(`fulltext_values00`.`fulltext_index_unicode61`) = `datoms02`.v"
     );
     assert_eq!(args, vec![make_arg("$v0", "needle"),]);
 
@@ -1162,16 +1195,74 @@ fn test_fulltext() {
          `fulltext_values01`.text AS `?value`, \
          `datoms02`.tx AS `?tx` \
          FROM `datoms` AS `datoms00`, \
-         `fulltext_values` AS `fulltext_values01`, \
+         `fulltext_index_unicode61` AS `fulltext_values01`, \
          `datoms` AS `datoms02` \
          WHERE `datoms00`.a = 99 \
          AND `datoms02`.a = 100 \
          AND `datoms02`.v = `fulltext_values01`.rowid \
          AND `fulltext_values01`.text MATCH $v0 \
-         AND `datoms00`.v = 0e0 \
-         AND `datoms00`.e = `datoms02`.e"
+         AND `datoms00`.e = `datoms02`.e \
+         AND `datoms00`.v = -bm25(`fulltext_values01`.`fulltext_index_unicode61`)"
+    );
+    assert_eq!(args, vec![make_arg("$v0", "needle"),]);
+}
+
+#[test]
+fn test_fulltext_attributes() {
+    let mut schema = prepopulated_schema();
+    associate_ident(&mut schema, Keyword::namespaced("foo", "title"), 101);
+    add_attribute(
+        &mut schema,
+        101,
+        Attribute {
+            value_type: ValueType::String,
+            fulltext: true,
+            ..Default::default()
+        },
+    );
+
+    // Several attributes at once.
+    let query = r#"[:find ?entity :where [(fulltext $ [:foo/fts :foo/title] "needle") [[?entity]]]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT `datoms01`.e AS `?entity` \
+         FROM `fulltext_index_unicode61` AS `fulltext_values00`, \
+         `datoms` AS `datoms01` \
+         WHERE ((`datoms01`.a = 100) OR (`datoms01`.a = 101)) \
+         AND `datoms01`.v = `fulltext_values00`.rowid \
+         AND `fulltext_values00`.text MATCH $v0"
     );
     assert_eq!(args, vec![make_arg("$v0", "needle"),]);
+
+    // Any fulltext attribute, binding the attribute that matched.
+    let query = r#"[:find ?entity ?a :where [(fulltext $ ?a "needle") [[?entity]]]]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT `datoms01`.e AS `?entity`, \
+         `datoms01`.a AS `?a` \
+         FROM `fulltext_index_unicode61` AS `fulltext_values00`, \
+         `datoms` AS `datoms01` \
+         WHERE ((`datoms01`.a = 100) OR (`datoms01`.a = 101)) \
+         AND `datoms01`.v = `fulltext_values00`.rowid \
+         AND `fulltext_values00`.text MATCH $v0"
+    );
+
+    // Any fulltext attribute, with a snippet and a highlight.
+    let query = r#"[:find ?entity ?snippet ?highlight :where [(fulltext $ _ "needle") [[?entity _ _ _ ?snippet ?highlight]]]]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT `datoms01`.e AS `?entity`, \
+         snippet(`fulltext_values00`.`fulltext_index_unicode61`, 0, '<b>', '</b>', '...', 15) AS `?snippet`, \
+         highlight(`fulltext_values00`.`fulltext_index_unicode61`, 0, '<b>', '</b>') AS `?highlight` \
+         FROM `fulltext_index_unicode61` AS `fulltext_values00`, \
+         `datoms` AS `datoms01` \
+         WHERE ((`datoms01`.a = 100) OR (`datoms01`.a = 101)) \
+         AND `datoms01`.v = `fulltext_values00`.rowid \
+         AND `fulltext_values00`.text MATCH $v0"
+    );
 }
 
 #[test]
@@ -1186,17 +1277,18 @@ fn test_fulltext_inputs() {
     types.insert(Variable::from_valid_name("?entity"), ValueType::Ref);
     let inputs = QueryInputs::new(types, BTreeMap::default()).expect("valid inputs");
 
-    // Without binding the value. q_once will err if you try this!
+    // Without binding the value, which is then bound when the query is run.
     let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
     assert_eq!(
         sql,
         "SELECT DISTINCT `fulltext_values00`.text AS `?val` \
          FROM \
-         `fulltext_values` AS `fulltext_values00`, \
+         `fulltext_index_unicode61` AS `fulltext_values00`, \
          `datoms` AS `datoms01` \
          WHERE `datoms01`.a = 100 \
          AND `datoms01`.v = `fulltext_values00`.rowid \
-         AND `fulltext_values00`.text MATCH $v0"
+         AND `fulltext_values00`.text MATCH $v0 \
+         AND `datoms01`.e = $ientity"
     );
     assert_eq!(args, vec![make_arg("$v0", "hello"),]);
 
@@ -1210,7 +1302,7 @@ fn test_fulltext_inputs() {
         sql,
         "SELECT DISTINCT `fulltext_values00`.text AS `?val` \
          FROM \
-         `fulltext_values` AS `fulltext_values00`, \
+         `fulltext_index_unicode61` AS `fulltext_values00`, \
          `datoms` AS `datoms01` \
          WHERE `datoms01`.a = 100 \
          AND `datoms01`.v = `fulltext_values00`.rowid \
@@ -1231,7 +1323,7 @@ fn test_fulltext_inputs() {
     assert_eq!(
         sql,
         "SELECT 111 AS `?entity` FROM \
-         `fulltext_values` AS `fulltext_values00`, \
+         `fulltext_index_unicode61` AS `fulltext_values00`, \
          `datoms` AS `datoms01` \
          WHERE `datoms01`.a = 100 \
          AND `datoms01`.v = `fulltext_values00`.rowid \
@@ -1258,7 +1350,7 @@ fn test_fulltext_inputs() {
          `fulltext_values00`.text AS `?value`, \
          `datoms02`.v AS `?friend` \
          FROM \
-         `fulltext_values` AS `fulltext_values00`, \
+         `fulltext_index_unicode61` AS `fulltext_values00`, \
          `datoms` AS `datoms01`, \
          `datoms` AS `datoms02` \
          WHERE `datoms01`.a = 100 \
@@ -1436,6 +1528,66 @@ fn test_project_the() {
     assert_eq!(args, vec![]);
 }
 
+#[test]
+fn test_complex_aggregates() {
+    let schema = prepopulated_typed_schema(ValueType::Long);
+
+    let query = r#"[:find (count-distinct ?t) (avg ?t)
+                    :where
+                    [?e :foo/bar ?t]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT * \
+         FROM \
+         (SELECT count(DISTINCT `?t`) AS `(count-distinct ?t)`, \
+         avg(`?t`) AS `(avg ?t)` \
+         FROM \
+         (SELECT DISTINCT \
+         `datoms00`.v AS `?t` \
+         FROM `datoms` AS `datoms00` \
+         WHERE `datoms00`.a = 99)) \
+         WHERE `(avg ?t)` IS NOT NULL"
+    );
+    assert_eq!(args, vec![]);
+
+    // A variance computed by SQLite would lose precision, so we compute it as we project rows.
+    let query = r#"[:find (count-distinct ?t) (variance ?t)
+                    :where
+                    [?e :foo/bar ?t]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT \
+         `datoms00`.v AS `?t` \
+         FROM `datoms` AS `datoms00` \
+         WHERE `datoms00`.a = 99"
+    );
+    assert_eq!(args, vec![]);
+
+    // SQLite can't compute a median, so we project the rows to aggregate, and limit the groups
+    // ourselves.
+    let query = r#"[:find ?e (median ?t) (count ?t)
+                    :with ?x
+                    :limit 2
+                    :where
+                    [?e :foo/bar ?t]
+                    [?x :foo/bar ?t]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT \
+         `datoms00`.e AS `?e`, \
+         `datoms00`.v AS `?t`, \
+         `datoms01`.e AS `?x` \
+         FROM `datoms` AS `datoms00`, `datoms` AS `datoms01` \
+         WHERE `datoms00`.a = 99 \
+         AND `datoms01`.a = 99 \
+         AND `datoms00`.v = `datoms01`.v"
+    );
+    assert_eq!(args, vec![]);
+}
+
 #[test]
 fn test_tx_before_and_after() {
     let schema = prepopulated_typed_schema(ValueType::Long);
@@ -1581,3 +1733,79 @@ fn test_tx_data() {
     );
     assert_eq!(args, vec![]);
 }
+
+#[test]
+fn test_recursive_rule() {
+    let schema = prepopulated_typed_schema(ValueType::Ref);
+    let rules = parse_rules_string(
+        r#"[[(reach ?x ?y) [?x :foo/bar ?y]]
+            [(reach ?x ?y) (reach ?x ?z) [?z :foo/bar ?y]]]"#,
+    )
+    .expect("parsed rules");
+    let query = r#"[:find ?y :in % :where (reach 65536 ?y)]"#;
+    let SQLQuery { sql, args } =
+        translate_with_inputs(&schema, query, QueryInputs::default().with_rules(rules));
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT `c00`.`?reach#1` AS `?y` FROM \
+         (WITH RECURSIVE `rule` AS (\
+         SELECT `datoms02`.e AS `?reach#0`, `datoms02`.v AS `?reach#1` \
+         FROM `datoms` AS `datoms02` \
+         WHERE `datoms02`.a = 99 \
+         UNION \
+         SELECT `rule03`.`?reach#0` AS `?reach#0`, `datoms04`.v AS `?reach#1` \
+         FROM `rule` AS `rule03`, `datoms` AS `datoms04` \
+         WHERE `datoms04`.a = 99 AND `rule03`.`?reach#1` = `datoms04`.e) \
+         SELECT * FROM `rule`) AS `c00` \
+         WHERE `c00`.`?reach#0` = 65536"
+    );
+    assert_eq!(args, vec![]);
+}
+
+#[test]
+fn test_computed_bindings_and_predicates() {
+    let schema = prepopulated_typed_schema(ValueType::Long);
+    let query = r#"[:find ?x ?y :where [?x :foo/bar ?v] [(+ ?v 1) ?y]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT `datoms00`.e AS `?x`, (`datoms00`.v + 1) AS `?y` \
+         FROM `datoms` AS `datoms00` \
+         WHERE `datoms00`.a = 99"
+    );
+    assert_eq!(args, vec![]);
+
+    // Division always yields a double, and dividing by zero yields nothing.
+    let query = r#"[:find ?y :where [_ :foo/bar ?v] [(/ ?v 2) ?y]]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT ((`datoms00`.v * 1e0) / 2) AS `?y` \
+         FROM `datoms` AS `datoms00` \
+         WHERE `datoms00`.a = 99 \
+         AND ((`datoms00`.v * 1e0) / 2) IS NOT NULL"
+    );
+
+    let schema = prepopulated_schema();
+    let query = r#"[:find ?p :where [_ :foo/bar ?s] [(subs ?s 1 3) ?p] [(starts-with? ?s "a")]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT substr(`datoms00`.v, (1 + 1), (3 - 1)) AS `?p` \
+         FROM `datoms` AS `datoms00` \
+         WHERE `datoms00`.a = 99 \
+         AND substr(`datoms00`.v, 1, length($v0)) = $v0"
+    );
+    assert_eq!(args, vec![make_arg("$v0", "a")]);
+
+    let query = r#"[:find ?x :where [?x :foo/bar ?s] [(re-find "^a.c$" ?s)]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(
+        sql,
+        "SELECT DISTINCT `datoms00`.e AS `?x` \
+         FROM `datoms` AS `datoms00` \
+         WHERE `datoms00`.a = 99 \
+         AND `datoms00`.v REGEXP $v0"
+    );
+    assert_eq!(args, vec![make_arg("$v0", "^a.c$")]);
+}
@@ -24,6 +24,15 @@ pub enum PullError {
     #[fail(display = ":db/id repeated")]
     RepeatedDbId,
 
+    #[fail(display = "{} can't name an attribute in a pull map", _0)]
+    InvalidMapKey(String),
+
+    #[fail(display = "can't pull reverse attribute {}: not a ref", _0)]
+    NonRefReverseAttribute(String),
+
+    #[fail(display = "unsupported pull default {}", _0)]
+    UnsupportedDefault(String),
+
     #[fail(display = "{}", _0)]
     DbError(#[cause] DbError),
 }
@@ -60,6 +60,7 @@ extern crate failure;
 extern crate rusqlite;
 
 extern crate core_traits;
+extern crate db_traits;
 extern crate edn;
 extern crate mentat_core;
 extern crate mentat_db;
@@ -69,13 +70,18 @@ use std::collections::{BTreeMap, BTreeSet};
 
 use std::iter::once;
 
-use core_traits::{Binding, Entid, StructuredMap, TypedValue};
+use core_traits::{Binding, Entid, StructuredMap, TypedValue, ValueType};
 
 use mentat_core::{Cloned, HasSchema, Keyword, Schema, ValueRc};
 
 use mentat_db::cache;
 
-use edn::query::{NamedPullAttribute, PullAttributeSpec, PullConcreteAttribute};
+use db_traits::errors::DbError;
+
+use edn::query::{
+    NamedPullAttribute, NonIntegerConstant, PullAttributeSpec, PullConcreteAttribute,
+    PullDefaultValue, PullMapValue,
+};
 
 use query_pull_traits::errors::{PullError, Result};
 
@@ -124,11 +130,40 @@ where
     Puller::prepare(schema, attrs)?.pull(schema, db, entities)
 }
 
+/// What to do with the entities that a pulled attribute refers to.
+enum Nested {
+    /// Pull these attributes from each of them.
+    Pattern(Box<Puller>),
+
+    /// Pull the enclosing pattern from each of them, following this attribute at most this many
+    /// times in total.
+    Recursion(Option<u64>),
+
+    /// The attribute is a component, and its values are part of the entity: pull everything.
+    Component,
+}
+
+struct PulledAttribute {
+    entid: Entid,
+
+    // The keyword to use in the output.
+    name: ValueRc<Keyword>,
+
+    // `:person/_friend` fetches the entities that refer to each entity, rather than the reverse.
+    reverse: bool,
+
+    // The reverse of a component attribute has at most one value.
+    component: bool,
+
+    limit: Option<usize>,
+    default: Option<TypedValue>,
+    nested: Option<Nested>,
+}
+
 /// A `Puller` constructs on demand a map from a provided set of entity IDs to a set of structured maps.
 pub struct Puller {
-    // The domain of this map is the set of attributes to fetch.
-    // The range is the set of aliases to use in the output.
-    attributes: BTreeMap<Entid, ValueRc<Keyword>>,
+    // The attributes to fetch, in the order in which they were requested.
+    attributes: Vec<PulledAttribute>,
     attribute_spec: cache::AttributeSpec,
 
     // If this is set, each pulled entity is contributed to its own output map, labeled with this
@@ -139,70 +174,284 @@ pub struct Puller {
     db_id_alias: Option<ValueRc<Keyword>>,
 }
 
+fn db_id() -> ValueRc<Keyword> {
+    ValueRc::new(Keyword::namespaced("db", "id"))
+}
+
+/// The map that stands in for an entity that recursion has already pulled: `{:db/id 1234}`.
+fn entity_reference(e: Entid) -> ValueRc<StructuredMap> {
+    let mut m = StructuredMap::default();
+    m.insert(db_id(), Binding::Scalar(TypedValue::Ref(e)));
+    ValueRc::new(m)
+}
+
+fn default_value(
+    schema: &Schema,
+    value_type: ValueType,
+    default: &PullDefaultValue,
+) -> Result<TypedValue> {
+    Ok(match *default {
+        PullDefaultValue::EntidOrInteger(i) => match value_type {
+            ValueType::Ref => TypedValue::Ref(i),
+            ValueType::Double => TypedValue::Double((i as f64).into()),
+            _ => TypedValue::Long(i),
+        },
+        PullDefaultValue::IdentOrKeyword(ref k) => match schema.get_entid(k) {
+            Some(entid) if value_type == ValueType::Ref => TypedValue::Ref(entid.into()),
+            _ => TypedValue::Keyword(k.to_value_rc()),
+        },
+        PullDefaultValue::Constant(ref c) => match c.clone() {
+            NonIntegerConstant::Boolean(v) => TypedValue::Boolean(v),
+            NonIntegerConstant::Float(v) => TypedValue::Double(v),
+            NonIntegerConstant::Text(v) => v.into(),
+            NonIntegerConstant::Instant(v) => TypedValue::Instant(v),
+            NonIntegerConstant::Uuid(v) => TypedValue::Uuid(v),
+            NonIntegerConstant::BigInteger(_) => {
+                return Err(PullError::UnsupportedDefault(default.to_string()))
+            }
+        },
+    })
+}
+
+/// Collect the entities that these bindings refer to.
+fn referenced_entities<'a, I>(bindings: I) -> BTreeSet<Entid>
+where
+    I: Iterator<Item = &'a Binding>,
+{
+    let mut entities = BTreeSet::new();
+    for binding in bindings {
+        match *binding {
+            Binding::Scalar(TypedValue::Ref(e)) => {
+                entities.insert(e);
+            }
+            Binding::Vec(ref vs) => entities.extend(referenced_entities(vs.iter())),
+            _ => {}
+        }
+    }
+    entities
+}
+
+/// Replace each entity reference in `binding` with the map pulled for that entity.
+fn nest(binding: Binding, pulled: &PullResults) -> Binding {
+    match binding {
+        Binding::Scalar(TypedValue::Ref(e)) => Binding::Map(
+            pulled
+                .get(&e)
+                .cloned()
+                .unwrap_or_else(|| ValueRc::new(StructuredMap::default())),
+        ),
+        Binding::Vec(vs) => Binding::Vec(ValueRc::new(
+            vs.iter().cloned().map(|b| nest(b, pulled)).collect(),
+        )),
+        binding => binding,
+    }
+}
+
+/// Find the entities that refer to `entities` through `attribute`.
+fn reverse_bindings(
+    db: &rusqlite::Connection,
+    attribute: &PulledAttribute,
+    entities: &[Entid],
+) -> Result<BTreeMap<Entid, Binding>> {
+    if entities.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let ids: Vec<String> = entities.iter().map(|e| e.to_string()).collect();
+    let sql = format!(
+        "SELECT v, e FROM datoms WHERE a = {} AND v IN ({}) ORDER BY v ASC, e ASC",
+        attribute.entid,
+        ids.join(", ")
+    );
+    let mut stmt = db.prepare(&sql).map_err(DbError::from)?;
+    let rows = stmt
+        .query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((row.get::<_, Entid>(0)?, row.get::<_, Entid>(1)?))
+        })
+        .map_err(DbError::from)?;
+
+    let mut referrers: BTreeMap<Entid, Vec<Binding>> = BTreeMap::new();
+    for row in rows {
+        let (v, e) = row.map_err(DbError::from)?;
+        referrers
+            .entry(v)
+            .or_insert_with(Vec::new)
+            .push(TypedValue::Ref(e).into());
+    }
+
+    Ok(referrers
+        .into_iter()
+        .map(|(v, mut es)| {
+            let binding = if attribute.component {
+                es.swap_remove(0)
+            } else {
+                Binding::Vec(ValueRc::new(es))
+            };
+            (v, binding)
+        })
+        .collect())
+}
+
 impl Puller {
     pub fn prepare(schema: &Schema, attributes: Vec<PullAttributeSpec>) -> Result<Puller> {
-        // TODO: eventually this entry point will handle aliasing and that kind of
-        // thing. For now it's just a convenience.
-
-        let lookup_name = |i: &Entid| {
-            // In the unlikely event that we have an attribute with no name, we bail.
-            schema
-                .get_ident(*i)
-                .map(|ident| ValueRc::new(ident.clone()))
-                .ok_or_else(|| PullError::UnnamedAttribute(*i))
+        let mut puller = Puller {
+            attributes: Vec::new(),
+            attribute_spec: cache::AttributeSpec::specified(&BTreeSet::new(), schema),
+            db_id_alias: None,
         };
 
-        let mut names: BTreeMap<Entid, ValueRc<Keyword>> = Default::default();
-        let mut attrs: BTreeSet<Entid> = Default::default();
-        let db_id = ::std::rc::Rc::new(Keyword::namespaced("db", "id"));
-        let mut db_id_alias = None;
-
-        for attr in attributes.iter() {
+        let mut wildcard = false;
+        for attr in attributes.into_iter() {
             match attr {
                 PullAttributeSpec::Wildcard => {
-                    let attribute_ids = schema.attribute_map.keys();
-                    for id in attribute_ids {
-                        names.insert(*id, lookup_name(id)?);
-                        attrs.insert(*id);
-                    }
-                    break;
+                    wildcard = true;
                 }
-                PullAttributeSpec::Attribute(NamedPullAttribute {
-                    ref attribute,
-                    ref alias,
-                }) => {
-                    let alias = alias.as_ref().map(|ref r| r.to_value_rc());
-                    match attribute {
-                        // Handle :db/id.
-                        PullConcreteAttribute::Ident(ref i) if i.as_ref() == db_id.as_ref() => {
-                            // We only allow :db/id once.
-                            if db_id_alias.is_some() {
-                                return Err(PullError::RepeatedDbId);
-                            }
-                            db_id_alias = Some(alias.unwrap_or_else(|| db_id.to_value_rc()));
-                        }
-                        PullConcreteAttribute::Ident(ref i) => {
-                            if let Some(entid) = schema.get_entid(i) {
-                                let name = alias.unwrap_or_else(|| i.to_value_rc());
-                                names.insert(entid.into(), name);
-                                attrs.insert(entid.into());
+                PullAttributeSpec::PullMapSpec(entries) => {
+                    for (key, value) in entries.into_iter() {
+                        let nested = match value {
+                            PullMapValue::Pattern(patterns) => {
+                                Nested::Pattern(Box::new(Puller::prepare(schema, patterns)?))
                             }
-                        }
-                        PullConcreteAttribute::Entid(ref entid) => {
-                            let name = alias.map(Ok).unwrap_or_else(|| lookup_name(entid))?;
-                            names.insert(*entid, name);
-                            attrs.insert(*entid);
-                        }
+                            PullMapValue::Recursion(limit) => Nested::Recursion(limit),
+                        };
+                        puller.add_attribute(schema, key, Some(nested))?;
                     }
                 }
+                attr => {
+                    puller.add_attribute(schema, attr, None)?;
+                }
             }
         }
 
-        Ok(Puller {
-            attributes: names,
-            attribute_spec: cache::AttributeSpec::specified(&attrs, schema),
-            db_id_alias,
-        })
+        if wildcard {
+            // Attributes that were named explicitly keep their options.
+            for (entid, attribute) in schema.attribute_map.iter() {
+                if puller
+                    .attributes
+                    .iter()
+                    .any(|a| !a.reverse && a.entid == *entid)
+                {
+                    continue;
+                }
+                let name = schema
+                    .get_ident(*entid)
+                    .map(|ident| ValueRc::new(ident.clone()))
+                    .ok_or_else(|| PullError::UnnamedAttribute(*entid))?;
+                puller.attributes.push(PulledAttribute {
+                    entid: *entid,
+                    name,
+                    reverse: false,
+                    component: attribute.component,
+                    limit: None,
+                    default: None,
+                    nested: if attribute.component {
+                        Some(Nested::Component)
+                    } else {
+                        None
+                    },
+                });
+            }
+        }
+
+        let forward: BTreeSet<Entid> = puller
+            .attributes
+            .iter()
+            .filter(|a| !a.reverse)
+            .map(|a| a.entid)
+            .collect();
+        puller.attribute_spec = cache::AttributeSpec::specified(&forward, schema);
+        Ok(puller)
+    }
+
+    fn add_attribute(
+        &mut self,
+        schema: &Schema,
+        spec: PullAttributeSpec,
+        nested: Option<Nested>,
+    ) -> Result<()> {
+        let (named, limit, default) = match spec {
+            PullAttributeSpec::Attribute(named) => (named, None, None),
+            PullAttributeSpec::LimitedAttribute(named, limit) => (named, Some(limit), None),
+            PullAttributeSpec::DefaultedAttribute(named, default) => (named, None, Some(default)),
+            spec @ PullAttributeSpec::Wildcard | spec @ PullAttributeSpec::PullMapSpec(_) => {
+                return Err(PullError::InvalidMapKey(spec.to_string()));
+            }
+        };
+
+        let NamedPullAttribute { attribute, alias } = named;
+        let alias = alias.map(|r| r.to_value_rc());
+        let (entid, reverse, name) = match attribute {
+            // Handle :db/id.
+            PullConcreteAttribute::Ident(ref i) if i.as_ref() == db_id().as_ref() => {
+                // We only allow :db/id once.
+                if self.db_id_alias.is_some() {
+                    return Err(PullError::RepeatedDbId);
+                }
+                self.db_id_alias = Some(alias.unwrap_or_else(db_id));
+                return Ok(());
+            }
+            PullConcreteAttribute::Ident(ref i) => {
+                let reverse = i.is_backward();
+                let forward = if reverse {
+                    i.to_reversed()
+                } else {
+                    (**i).clone()
+                };
+                match schema.get_entid(&forward) {
+                    Some(entid) => (
+                        entid.into(),
+                        reverse,
+                        alias.unwrap_or_else(|| i.to_value_rc()),
+                    ),
+                    None => return Ok(()),
+                }
+            }
+            PullConcreteAttribute::Entid(entid) => {
+                // In the unlikely event that we have an attribute with no name, we bail.
+                let name = match alias {
+                    Some(alias) => alias,
+                    None => schema
+                        .get_ident(entid)
+                        .map(|ident| ValueRc::new(ident.clone()))
+                        .ok_or_else(|| PullError::UnnamedAttribute(entid))?,
+                };
+                (entid, false, name)
+            }
+        };
+
+        let attribute = match schema.attribute_for_entid(entid) {
+            Some(attribute) => attribute,
+            None => return Ok(()),
+        };
+        if reverse && attribute.value_type != ValueType::Ref {
+            return Err(PullError::NonRefReverseAttribute(name.to_string()));
+        }
+
+        let default = match default {
+            Some(ref default) => Some(default_value(schema, attribute.value_type, default)?),
+            None => None,
+        };
+
+        // Component entities are part of the entity that refers to them, so we pull them whole
+        // unless we're told otherwise.
+        let nested = nested.or_else(|| {
+            if attribute.component && !reverse {
+                Some(Nested::Component)
+            } else {
+                None
+            }
+        });
+
+        self.attributes.push(PulledAttribute {
+            entid,
+            name,
+            reverse,
+            component: attribute.component,
+            limit: limit.map(|l| l as usize),
+            default,
+            nested,
+        });
+        Ok(())
     }
 
     pub fn pull<E>(
@@ -214,60 +463,152 @@ impl Puller {
     where
         E: IntoIterator<Item = Entid>,
     {
+        let entities: Vec<Entid> = entities.into_iter().collect();
+        self.pull_entities(schema, db, &entities, &BTreeMap::new(), &BTreeSet::new())
+    }
+
+    /// Pull one layer of entities, then recursively pull the entities they refer to.
+    ///
+    /// `depths` maps the index of each recursive attribute that we're following to the number of
+    /// times we may still follow it. `seen` holds the entities pulled by enclosing layers of
+    /// recursion, which are not pulled again: cycles end in `{:db/id 1234}`.
+    fn pull_entities(
+        &self,
+        schema: &Schema,
+        db: &rusqlite::Connection,
+        entities: &[Entid],
+        depths: &BTreeMap<usize, Option<u64>>,
+        seen: &BTreeSet<Entid>,
+    ) -> Result<PullResults> {
+        if entities.is_empty() {
+            return Ok(PullResults::new());
+        }
+
         // We implement pull by:
-        // - Generating `AttributeCaches` for the provided attributes and entities.
-        //   TODO: it would be nice to invert the cache as we build it, rather than have to invert it here.
-        // - Recursing. (TODO: we'll need AttributeCaches to not overwrite in case of recursion! And
-        //   ideally not do excess work when some entity/attribute pairs are known.)
+        // - Generating `AttributeCaches` for the provided forward attributes and entities, and
+        //   querying directly for reverse attributes.
+        // - Pulling the entities referred to by nested attributes, a layer at a time.
         // - Building a structure by walking the pull expression with the caches.
-        // TODO: limits.
 
         // Build a cache for these attributes and entities.
         // TODO: use the store's existing cache!
-        let entities: Vec<Entid> = entities.into_iter().collect();
         let caches = cache::AttributeCaches::make_cache_for_entities_and_attributes(
             schema,
             db,
             self.attribute_spec.clone(),
-            &entities,
+            entities,
         )?;
 
+        let mut seen_here = seen.clone();
+        seen_here.extend(entities.iter().cloned());
+
         // Now construct the appropriate result format.
-        // TODO: should we walk `e` then `a`, or `a` then `e`? Possibly the right answer
-        // is just to collect differently!
-        let mut maps = BTreeMap::new();
+        let mut maps: BTreeMap<Entid, StructuredMap> = BTreeMap::new();
 
         // Collect :db/id if requested.
         if let Some(ref alias) = self.db_id_alias {
             for e in entities.iter() {
-                let r = maps
-                    .entry(*e)
-                    .or_insert_with(|| ValueRc::new(StructuredMap::default()));
-                let m = ValueRc::get_mut(r).unwrap();
-                m.insert(alias.clone(), Binding::Scalar(TypedValue::Ref(*e)));
+                maps.entry(*e)
+                    .or_insert_with(StructuredMap::default)
+                    .insert(alias.clone(), Binding::Scalar(TypedValue::Ref(*e)));
             }
         }
 
-        for (name, cache) in self.attributes.iter().filter_map(|(a, name)| {
-            caches
-                .forward_attribute_cache_for_attribute(schema, *a)
-                .map(|cache| (name.clone(), cache))
-        }) {
-            for e in entities.iter() {
-                if let Some(binding) = cache.binding_for_e(*e) {
-                    let r = maps
-                        .entry(*e)
-                        .or_insert_with(|| ValueRc::new(StructuredMap::default()));
-
-                    // Get into the inner map so we can accumulate a value.
-                    // We can unwrap here because we created all of these maps…
-                    let m = ValueRc::get_mut(r).unwrap();
+        for (index, attribute) in self.attributes.iter().enumerate() {
+            let mut values: BTreeMap<Entid, Binding> = if attribute.reverse {
+                reverse_bindings(db, attribute, entities)?
+            } else {
+                match caches.forward_attribute_cache_for_attribute(schema, attribute.entid) {
+                    Some(cache) => entities
+                        .iter()
+                        .filter_map(|e| cache.binding_for_e(*e).map(|b| (*e, b)))
+                        .collect(),
+                    None => BTreeMap::new(),
+                }
+            };
 
-                    m.insert(name.clone(), binding);
+            if let Some(limit) = attribute.limit {
+                for binding in values.values_mut() {
+                    if let Binding::Vec(ref mut vs) = *binding {
+                        if vs.len() > limit {
+                            *vs = ValueRc::new(vs.iter().take(limit).cloned().collect());
+                        }
+                    }
                 }
             }
+
+            if let Some(ref nested) = attribute.nested {
+                let referenced = referenced_entities(values.values());
+                let pulled = match *nested {
+                    Nested::Pattern(ref puller) => {
+                        let referenced: Vec<Entid> = referenced.iter().cloned().collect();
+                        puller.pull_entities(
+                            schema,
+                            db,
+                            &referenced,
+                            &BTreeMap::new(),
+                            &BTreeSet::new(),
+                        )?
+                    }
+                    Nested::Recursion(limit) => {
+                        let remaining = depths.get(&index).cloned().unwrap_or(limit);
+                        if remaining == Some(0) {
+                            // We've followed this attribute as far as we were asked to.
+                            continue;
+                        }
+                        let mut depths = depths.clone();
+                        depths.insert(index, remaining.map(|n| n - 1));
+                        let fresh: Vec<Entid> =
+                            referenced.difference(&seen_here).cloned().collect();
+                        let mut pulled =
+                            self.pull_entities(schema, db, &fresh, &depths, &seen_here)?;
+                        for e in referenced.intersection(&seen_here) {
+                            pulled.insert(*e, entity_reference(*e));
+                        }
+                        pulled
+                    }
+                    Nested::Component => {
+                        let everything =
+                            Puller::prepare(schema, vec![PullAttributeSpec::Wildcard])?;
+                        let fresh: Vec<Entid> =
+                            referenced.difference(&seen_here).cloned().collect();
+                        let mut pulled = everything.pull_entities(
+                            schema,
+                            db,
+                            &fresh,
+                            &BTreeMap::new(),
+                            &seen_here,
+                        )?;
+                        for e in referenced.intersection(&seen_here) {
+                            pulled.insert(*e, entity_reference(*e));
+                        }
+                        pulled
+                    }
+                };
+
+                values = values
+                    .into_iter()
+                    .map(|(e, binding)| (e, nest(binding, &pulled)))
+                    .collect();
+            }
+
+            for e in entities.iter() {
+                let binding = match values.remove(e) {
+                    Some(binding) => binding,
+                    None => match attribute.default {
+                        Some(ref default) => Binding::Scalar(default.clone()),
+                        None => continue,
+                    },
+                };
+                maps.entry(*e)
+                    .or_insert_with(StructuredMap::default)
+                    .insert(attribute.name.clone(), binding);
+            }
         }
 
-        Ok(maps)
+        Ok(maps
+            .into_iter()
+            .map(|(e, m)| (e, ValueRc::new(m)))
+            .collect())
     }
 }
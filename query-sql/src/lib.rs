@@ -20,12 +20,13 @@ use std::boxed::Box;
 
 use core_traits::{Entid, TypedValue, ValueType};
 
-use mentat_core::SQLTypeAffinity;
+use mentat_core::{SQLTypeAffinity, SQLValueType};
 
 use edn::query::{Direction, Limit, Variable};
 
 use mentat_query_algebrizer::{
-    Column, OrderBy, QualifiedAlias, QueryValue, SourceAlias, TableAlias, VariableColumn,
+    Column, ComputedValue, DatomsTable, FulltextColumn, OrderBy, QualifiedAlias, QueryValue,
+    ScalarFunction, SourceAlias, TableAlias, VariableColumn,
 };
 
 use sql_traits::errors::{BuildQueryResult, SQLError};
@@ -48,6 +49,8 @@ pub enum ColumnOrExpression {
     Integer(i32), // We use these for type codes etc.
     Long(i64),
     Value(TypedValue),
+    /// An `:in` variable bound when the query is run.
+    Input(Variable),
     // Some aggregates (`min`, `max`, `avg`) can be over 0 rows, and therefore can be `NULL`; that
     // needs special treatment.
     NullableAggregate(Box<Expression>, ValueType), // Track the return type.
@@ -59,6 +62,21 @@ pub enum Expression {
         sql_op: &'static str,
         arg: ColumnOrExpression,
     },
+    /// An aggregate over the distinct values of its argument, like `count(DISTINCT x)`.
+    Distinct {
+        sql_op: &'static str,
+        arg: ColumnOrExpression,
+    },
+    Binary {
+        sql_op: &'static str,
+        left: ColumnOrExpression,
+        right: ColumnOrExpression,
+    },
+    /// A call to a function of any number of arguments, like `substr(x, 1, 3)`.
+    Function {
+        sql_op: &'static str,
+        args: Vec<ColumnOrExpression>,
+    },
 }
 
 /// `QueryValue` and `ColumnOrExpression` are almost identical… merge somehow?
@@ -69,6 +87,7 @@ impl From<QueryValue> for ColumnOrExpression {
             QueryValue::Entid(e) => ColumnOrExpression::Entid(e),
             QueryValue::PrimitiveLong(v) => ColumnOrExpression::Long(v),
             QueryValue::TypedValue(v) => ColumnOrExpression::Value(v),
+            QueryValue::Input(var) => ColumnOrExpression::Input(var),
         }
     }
 }
@@ -133,6 +152,17 @@ pub enum Constraint {
     },
 }
 
+impl From<ComputedValue> for Constraint {
+    fn from(computed: ComputedValue) -> Constraint {
+        let (op, left, right) = predicate_parts(&computed);
+        Constraint::Infix {
+            op: Op(op),
+            left,
+            right,
+        }
+    }
+}
+
 impl Constraint {
     pub fn not_equal(left: ColumnOrExpression, right: ColumnOrExpression) -> Constraint {
         Constraint::Infix {
@@ -184,6 +214,9 @@ pub struct Join {
 pub enum TableOrSubquery {
     Table(SourceAlias),
     Union(Vec<SelectQuery>, TableAlias),
+    /// A recursive CTE with its base and recursive arms, wrapped so it can be joined like a table:
+    /// `(WITH RECURSIVE rule AS (base UNION … UNION recursive) SELECT * FROM rule) AS alias`.
+    RecursiveUnion(Vec<SelectQuery>, Vec<SelectQuery>, TableAlias),
     Subquery(Box<SelectQuery>),
     Values(Values, TableAlias),
 }
@@ -239,6 +272,172 @@ fn push_column(qb: &mut dyn QueryBuilder, col: &Column) -> BuildQueryResult {
             qb.push_sql(d.as_str());
             Ok(())
         }
+        Column::Computed(ref c) => computed_column(c).push_sql(qb),
+    }
+}
+
+fn expression(expression: Expression, value_type: ValueType) -> ColumnOrExpression {
+    ColumnOrExpression::Expression(Box::new(expression), value_type)
+}
+
+fn binary(
+    sql_op: &'static str,
+    left: ColumnOrExpression,
+    right: ColumnOrExpression,
+    value_type: ValueType,
+) -> ColumnOrExpression {
+    expression(
+        Expression::Binary {
+            sql_op,
+            left,
+            right,
+        },
+        value_type,
+    )
+}
+
+fn function(
+    sql_op: &'static str,
+    args: Vec<ColumnOrExpression>,
+    value_type: ValueType,
+) -> ColumnOrExpression {
+    expression(Expression::Function { sql_op, args }, value_type)
+}
+
+fn length(arg: ColumnOrExpression) -> ColumnOrExpression {
+    expression(
+        Expression::Unary {
+            sql_op: "length",
+            arg,
+        },
+        ValueType::Long,
+    )
+}
+
+/// The SQLite expression that computes the value of a function call in a query.
+pub fn computed_column(computed: &ComputedValue) -> ColumnOrExpression {
+    use self::ScalarFunction::*;
+    let value_type = computed.value_type;
+    let arg = |i: usize| ColumnOrExpression::from(computed.args[i].clone());
+    let mut args = computed
+        .args
+        .iter()
+        .cloned()
+        .map(ColumnOrExpression::from)
+        .collect::<Vec<_>>();
+
+    match computed.function {
+        Add | Subtract | Multiply | Divide => {
+            let sql_op = match computed.function {
+                Add => "+",
+                Subtract => "-",
+                Multiply => "*",
+                _ => "/",
+            };
+
+            // As in Clojure, `(- x)` is a negation and `(/ x)` a reciprocal.
+            if args.len() == 1 {
+                match computed.function {
+                    Subtract => args.insert(0, ColumnOrExpression::Long(0)),
+                    Divide => args.insert(0, ColumnOrExpression::Long(1)),
+                    _ => {}
+                }
+            }
+
+            let mut args = args.into_iter();
+            let mut left = args.next().expect("arithmetic takes at least one argument");
+            if value_type == ValueType::Double {
+                // SQLite keeps integer arithmetic integral. We promised a double.
+                left = binary(
+                    "*",
+                    left,
+                    ColumnOrExpression::Value(TypedValue::Double(1.0.into())),
+                    value_type,
+                );
+            }
+            args.fold(left, |left, right| binary(sql_op, left, right, value_type))
+        }
+
+        Str => {
+            // Concatenating with the empty string makes a lone number into text.
+            if args.len() == 1 {
+                args.insert(0, ColumnOrExpression::Value(TypedValue::typed_string("")));
+            }
+            let mut args = args.into_iter();
+            let first = args.next().expect("str takes at least one argument");
+            args.fold(first, |left, right| binary("||", left, right, value_type))
+        }
+
+        Subs => {
+            // Our indices are zero-based and exclusive of the end; SQLite's `substr` takes a
+            // one-based start and a length.
+            let mut sql_args = vec![
+                arg(0),
+                binary("+", arg(1), ColumnOrExpression::Long(1), ValueType::Long),
+            ];
+            if computed.args.len() == 3 {
+                sql_args.push(binary("-", arg(2), arg(1), ValueType::Long));
+            }
+            function("substr", sql_args, value_type)
+        }
+
+        LowerCase | UpperCase => expression(
+            Expression::Unary {
+                sql_op: if computed.function == LowerCase {
+                    "lower"
+                } else {
+                    "upper"
+                },
+                arg: arg(0),
+            },
+            value_type,
+        ),
+
+        StartsWith | EndsWith | Includes | ReFind => {
+            let (sql_op, left, right) = predicate_parts(computed);
+            binary(sql_op, left, right, value_type)
+        }
+    }
+}
+
+/// A string predicate is a comparison: an operator and its two sides.
+fn predicate_parts(
+    computed: &ComputedValue,
+) -> (&'static str, ColumnOrExpression, ColumnOrExpression) {
+    use self::ScalarFunction::*;
+    let arg = |i: usize| ColumnOrExpression::from(computed.args[i].clone());
+    match computed.function {
+        StartsWith => (
+            "=",
+            function(
+                "substr",
+                vec![arg(0), ColumnOrExpression::Long(1), length(arg(1))],
+                ValueType::String,
+            ),
+            arg(1),
+        ),
+        EndsWith => {
+            // `substr(s, length(s) - length(p) + 1)` is the last `length(p)` characters of `s`.
+            let start = binary(
+                "+",
+                binary("-", length(arg(0)), length(arg(1)), ValueType::Long),
+                ColumnOrExpression::Long(1),
+                ValueType::Long,
+            );
+            (
+                "=",
+                function("substr", vec![arg(0), start], ValueType::String),
+                arg(1),
+            )
+        }
+        Includes => (
+            ">",
+            function("instr", vec![arg(0), arg(1)], ValueType::Long),
+            ColumnOrExpression::Long(0),
+        ),
+        // `s REGEXP p` calls the `regexp(p, s)` function that we register on each connection.
+        ReFind => ("REGEXP", arg(1), arg(0)),
+        _ => panic!("{:?} is not a predicate", computed.function),
     }
 }
 
@@ -264,6 +463,7 @@ impl QueryFragment for ColumnOrExpression {
                 Ok(())
             }
             Value(ref v) => out.push_typed_value(v),
+            Input(ref var) => out.push_bind_param(&input_parameter_name(var)),
             NullableAggregate(ref e, _) | &Expression(ref e, _) => e.push_sql(out),
         }
     }
@@ -282,6 +482,34 @@ impl QueryFragment for Expression {
                 out.push_sql(")");
                 Ok(())
             }
+            Expression::Distinct { sql_op, ref arg } => {
+                out.push_sql(sql_op);
+                out.push_sql("(DISTINCT ");
+                arg.push_sql(out)?;
+                out.push_sql(")");
+                Ok(())
+            }
+            Expression::Binary {
+                sql_op,
+                ref left,
+                ref right,
+            } => {
+                out.push_sql("(");
+                left.push_sql(out)?;
+                out.push_sql(" ");
+                out.push_sql(sql_op);
+                out.push_sql(" ");
+                right.push_sql(out)?;
+                out.push_sql(")");
+                Ok(())
+            }
+            Expression::Function { sql_op, ref args } => {
+                out.push_sql(sql_op);
+                out.push_sql("(");
+                interpose!(arg, args, { arg.push_sql(out)? }, { out.push_sql(", ") });
+                out.push_sql(")");
+                Ok(())
+            }
         }
     }
 }
@@ -414,15 +642,102 @@ impl QueryFragment for JoinOp {
 
 // We don't own QualifiedAlias or QueryFragment, so we can't implement the trait.
 fn qualified_alias_push_sql(out: &mut dyn QueryBuilder, qa: &QualifiedAlias) -> BuildQueryResult {
+    match qa.1 {
+        // Computed values don't belong to any table.
+        Column::Computed(_) => return push_column(out, &qa.1),
+        Column::Fulltext(FulltextColumn::Score(_))
+        | Column::Fulltext(FulltextColumn::Snippet(_))
+        | Column::Fulltext(FulltextColumn::Highlight(_)) => {
+            return fulltext_function_push_sql(out, qa.0.as_str(), &qa.1)
+        }
+        _ => {}
+    }
     out.push_identifier(qa.0.as_str())?;
     out.push_sql(".");
     push_column(out, &qa.1)
 }
 
+/// Scores, snippets, and highlights are computed by FTS5's auxiliary functions, which take the
+/// hidden column named for the fulltext index: like `fulltext_values00.fulltext_index_porter`.
+///
+/// FTS5's `bm25` is lower for more relevant matches; we negate it so that higher is better.
+fn fulltext_function_push_sql(
+    out: &mut dyn QueryBuilder,
+    alias: &str,
+    column: &Column,
+) -> BuildQueryResult {
+    let (function, tokenizer, rest) = match *column {
+        Column::Fulltext(FulltextColumn::Score(tokenizer)) => ("-bm25(", tokenizer, ")"),
+        Column::Fulltext(FulltextColumn::Snippet(tokenizer)) => {
+            ("snippet(", tokenizer, ", 0, '<b>', '</b>', '...', 15)")
+        }
+        Column::Fulltext(FulltextColumn::Highlight(tokenizer)) => {
+            ("highlight(", tokenizer, ", 0, '<b>', '</b>')")
+        }
+        _ => unreachable!("{:?} is not computed by a fulltext function", column),
+    };
+    out.push_sql(function);
+    out.push_identifier(alias)?;
+    out.push_sql(".");
+    out.push_identifier(DatomsTable::FulltextValues(tokenizer).name())?;
+    out.push_sql(rest);
+    Ok(())
+}
+
+/// The value of a datom in the transaction log. The log stores fulltext values as rowids into
+/// `fulltext_values`; like `fulltext_datoms`, we resolve them to their text.
+fn logged_value_sql() -> String {
+    format!(
+        "CASE WHEN value_type_tag = {} AND typeof(v) = 'integer' \
+         THEN (SELECT text FROM fulltext_values WHERE rowid = v) \
+         ELSE v END",
+        ValueType::String.value_type_tag()
+    )
+}
+
+/// Views of the store are subqueries shaped like `datoms`, plus an `added` column.
+fn view_sql(table: &DatomsTable) -> Option<String> {
+    match *table {
+        // A datom is present as of `tx` if it was asserted at or before `tx` and not retracted
+        // between that assertion and `tx`.
+        DatomsTable::AsOf(tx) => Some(format!(
+            "SELECT e, a, {v} AS v, tx, value_type_tag, added FROM transactions AS t \
+             WHERE tx <= {tx} AND added = 1 AND NOT EXISTS \
+             (SELECT 1 FROM transactions AS r \
+             WHERE r.e = t.e AND r.a = t.a AND r.v = t.v AND r.value_type_tag = t.value_type_tag \
+             AND r.tx > t.tx AND r.tx <= {tx} AND r.added = 0)",
+            v = logged_value_sql(),
+            tx = tx
+        )),
+        DatomsTable::Since(tx) => Some(format!(
+            "SELECT e, a, v, tx, value_type_tag, 1 AS added FROM all_datoms WHERE tx > {}",
+            tx
+        )),
+        DatomsTable::History => Some(format!(
+            "SELECT e, a, {} AS v, tx, value_type_tag, added FROM transactions",
+            logged_value_sql()
+        )),
+        _ => None,
+    }
+}
+
 // We don't own SourceAlias or QueryFragment, so we can't implement the trait.
 fn source_alias_push_sql(out: &mut dyn QueryBuilder, sa: &SourceAlias) -> BuildQueryResult {
     let &SourceAlias(ref table, ref alias) = sa;
-    out.push_identifier(table.name())?;
+    if let Some(view) = view_sql(table) {
+        out.push_sql("(");
+        out.push_sql(&view);
+        out.push_sql(")");
+    } else if let DatomsTable::Attached(ref schema) = *table {
+        // An attached store has the same layout as ours, so its `all_datoms` view looks just
+        // like the tables we'd otherwise use. Attaching checks that it gives our idents the same
+        // entids, so the attribute entids we constrain on mean the same thing there.
+        out.push_identifier(schema)?;
+        out.push_sql(".");
+        out.push_identifier(DatomsTable::AllDatoms.name())?;
+    } else {
+        out.push_identifier(table.name())?;
+    }
     out.push_sql(" AS ");
     out.push_identifier(alias.as_str())
 }
@@ -459,6 +774,21 @@ impl QueryFragment for TableOrSubquery {
                 out.push_sql(") AS ");
                 out.push_identifier(table_alias.as_str())
             }
+            RecursiveUnion(ref base, ref recursive, ref table_alias) => {
+                out.push_sql("(WITH RECURSIVE ");
+                out.push_identifier(DatomsTable::Rule.name())?;
+                out.push_sql(" AS (");
+                interpose_iter!(
+                    subquery,
+                    base.iter().chain(recursive.iter()),
+                    { subquery.push_sql(out)? },
+                    { out.push_sql(" UNION ") }
+                );
+                out.push_sql(") SELECT * FROM ");
+                out.push_identifier(DatomsTable::Rule.name())?;
+                out.push_sql(") AS ");
+                out.push_identifier(table_alias.as_str())
+            }
             Subquery(ref subquery) => {
                 out.push_sql("(");
                 subquery.push_sql(out)?;
@@ -561,6 +891,12 @@ fn format_select_var(var: &str) -> String {
     once('i').chain(replaced_iter).collect()
 }
 
+/// The name of the SQL parameter that an `:in` variable bound when the query runs is passed as,
+/// without the leading `$`.
+pub fn input_parameter_name(var: &Variable) -> String {
+    format_select_var(var.as_str())
+}
+
 impl SelectQuery {
     fn push_variable_param(&self, var: &Variable, out: &mut dyn QueryBuilder) -> BuildQueryResult {
         let bind_param = format_select_var(var.as_str());
@@ -646,7 +982,8 @@ impl SelectQuery {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::rc::Rc;
+
+    use mentat_core::ValueRc;
 
     use mentat_query_algebrizer::{Column, DatomsColumn, DatomsTable, FulltextColumn};
 
@@ -720,6 +1057,29 @@ mod tests {
         assert_eq!("((123 = 456 AND 789 = 246))", build(&c));
     }
 
+    #[test]
+    fn test_aggregate_expressions() {
+        let column = || ColumnOrExpression::ExistingColumn("?x".to_string());
+        let distinct = Expression::Distinct {
+            sql_op: "count",
+            arg: column(),
+        };
+        assert_eq!("count(DISTINCT `?x`)", build(&distinct));
+
+        let squares = Expression::Unary {
+            sql_op: "avg",
+            arg: ColumnOrExpression::Expression(
+                Box::new(Expression::Binary {
+                    sql_op: "*",
+                    left: column(),
+                    right: column(),
+                }),
+                ValueType::Double,
+            ),
+        };
+        assert_eq!("avg((`?x` * `?x`))", build(&squares));
+    }
+
     #[test]
     fn test_unnamed_values() {
         let build = |len, values| build(&Values::Unnamed(len, values));
@@ -795,7 +1155,7 @@ mod tests {
         assert_eq!(
             vec![(
                 "$v0".to_string(),
-                Rc::new(rusqlite::types::Value::Text("needle".to_string()))
+                ValueRc::new(rusqlite::types::Value::Text("needle".to_string()))
             )],
             q.args
         );
@@ -17,8 +17,6 @@ extern crate core_traits;
 extern crate mentat_core;
 extern crate sql_traits;
 
-use std::rc::Rc;
-
 use std::collections::HashMap;
 
 use ordered_float::OrderedFloat;
@@ -37,7 +35,7 @@ pub struct SQLQuery {
     pub sql: String,
 
     /// These will eventually perhaps be rusqlite `ToSql` instances.
-    pub args: Vec<(String, Rc<rusqlite::types::Value>)>,
+    pub args: Vec<(String, ValueRc<rusqlite::types::Value>)>,
 }
 
 /// Gratefully based on Diesel's QueryBuilder trait:
@@ -86,7 +84,7 @@ pub struct SQLiteQueryBuilder {
     // in order to dedupe. We'll add these to the regular argument vector later.
     byte_args: HashMap<Vec<u8>, String>, // From value to argument name.
     string_args: HashMap<ValueRc<String>, String>, // From value to argument name.
-    args: Vec<(String, Rc<rusqlite::types::Value>)>, // (arg, value).
+    args: Vec<(String, ValueRc<rusqlite::types::Value>)>, // (arg, value).
 }
 
 impl SQLiteQueryBuilder {
@@ -112,7 +110,7 @@ impl SQLiteQueryBuilder {
         arg
     }
 
-    fn push_static_arg(&mut self, val: Rc<rusqlite::types::Value>) {
+    fn push_static_arg(&mut self, val: ValueRc<rusqlite::types::Value>) {
         // TODO: intern these, too.
         let arg = self.next_argument_name();
         self.push_named_arg(arg.as_str());
@@ -178,7 +176,7 @@ impl QueryBuilder for SQLiteQueryBuilder {
             }
             Keyword(ref s) => {
                 // TODO: intern.
-                let v = Rc::new(rusqlite::types::Value::Text(s.as_ref().to_string()));
+                let v = ValueRc::new(rusqlite::types::Value::Text(s.as_ref().to_string()));
                 self.push_static_arg(v);
             }
         }
@@ -219,13 +217,13 @@ impl QueryBuilder for SQLiteQueryBuilder {
         let string_args = self.string_args.into_iter().map(|(val, arg)| {
             (
                 arg,
-                Rc::new(rusqlite::types::Value::Text(val.as_ref().clone())),
+                ValueRc::new(rusqlite::types::Value::Text(val.as_ref().clone())),
             )
         });
         let byte_args = self
             .byte_args
             .into_iter()
-            .map(|(val, arg)| (arg, Rc::new(rusqlite::types::Value::Blob(val))));
+            .map(|(val, arg)| (arg, ValueRc::new(rusqlite::types::Value::Blob(val))));
 
         args.extend(string_args);
         args.extend(byte_args);
@@ -243,8 +241,8 @@ impl QueryBuilder for SQLiteQueryBuilder {
 mod tests {
     use super::*;
 
-    fn string_arg(s: &str) -> Rc<rusqlite::types::Value> {
-        Rc::new(rusqlite::types::Value::Text(s.to_string()))
+    fn string_arg(s: &str) -> ValueRc<rusqlite::types::Value> {
+        ValueRc::new(rusqlite::types::Value::Text(s.to_string()))
     }
 
     #[test]
@@ -26,18 +26,22 @@ use mentat_db::cache::{InProgressSQLiteAttributeCache, SQLiteAttributeCache};
 
 use mentat_db::db;
 use mentat_db::{
-    InProgressObserverTransactWatcher, PartitionMap, TxObservationService, TxObserver,
+    DatomObserver, InProgressObserverTransactWatcher, PartitionMap, TxObservationService,
+    TxObserver,
 };
 
 use mentat_query_pull::{pull_attributes_for_entities, pull_attributes_for_entity};
 
-use mentat_transaction::{CacheAction, CacheDirection, InProgress, InProgressRead, Metadata};
+use mentat_transaction::{
+    CacheAction, CacheDirection, InProgress, InProgressRead, LiveQueryReceiver, Metadata,
+    QueryPlanCache, QueryPlanCacheStats,
+};
 
 use public_traits::errors::{MentatError, Result};
 
 use mentat_transaction::query::{
-    lookup_value_for_attribute, lookup_values_for_attribute, q_explain, q_once, q_prepare,
-    q_uncached, Known, PreparedResult, QueryExplanation, QueryInputs, QueryOutput,
+    lookup_value_for_attribute, lookup_values_for_attribute, q_explain, q_prepare, q_uncached,
+    Known, PreparedResult, QueryExplanation, QueryInputs, QueryOutput, Variable,
 };
 
 /// A mutable, safe reference to the current Mentat store.
@@ -60,10 +64,11 @@ pub struct Conn {
     metadata: Mutex<Metadata>,
 
     // TODO: maintain set of change listeners or handles to transaction report queues. #298.
-
-    // TODO: maintain cache of query plans that could be shared across threads and invalidated when
-    // the schema changes. #315.
     pub(crate) tx_observer_service: Mutex<TxObservationService>,
+
+    /// Plans for queries run through `q_once`, shared by every thread using this `Conn`. Always
+    /// taken after `metadata`, never before.
+    query_plans: Mutex<QueryPlanCache>,
 }
 
 impl Conn {
@@ -77,6 +82,7 @@ impl Conn {
                 Default::default(),
             )),
             tx_observer_service: Mutex::new(TxObservationService::new()),
+            query_plans: Mutex::new(QueryPlanCache::default()),
         }
     }
 
@@ -114,6 +120,7 @@ impl Conn {
     }
 
     /// Query the Mentat store, using the given connection and the current metadata.
+    /// The query's plan is cached, and reused until the schema or the attribute cache changes.
     pub fn q_once<T>(
         &self,
         sqlite: &rusqlite::Connection,
@@ -125,8 +132,23 @@ impl Conn {
     {
         // Doesn't clone, unlike `current_schema`.
         let metadata = self.metadata.lock().unwrap();
-        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
-        q_once(sqlite, known, query, inputs)
+        self.query_plans.lock().unwrap().q_once(
+            sqlite,
+            &metadata.schema,
+            &metadata.attribute_cache,
+            metadata.generation,
+            query,
+            inputs,
+        )
+    }
+
+    pub fn query_plan_cache_stats(&self) -> QueryPlanCacheStats {
+        self.query_plans.lock().unwrap().stats()
+    }
+
+    /// Change how many query plans `q_once` keeps. Zero turns plan caching off.
+    pub fn set_query_plan_cache_capacity(&self, capacity: usize) {
+        self.query_plans.lock().unwrap().set_capacity(capacity);
     }
 
     /// Query the Mentat store, using the given connection and the current metadata,
@@ -248,6 +270,18 @@ impl Conn {
             )
         };
 
+        // Only collect datoms when somebody will look at them.
+        let tx_observer_watcher = if self
+            .tx_observer_service
+            .lock()
+            .unwrap()
+            .has_datom_observers()
+        {
+            InProgressObserverTransactWatcher::collecting_datoms()
+        } else {
+            InProgressObserverTransactWatcher::new()
+        };
+
         Ok(InProgress {
             mutex: &self.metadata,
             transaction: tx,
@@ -256,8 +290,9 @@ impl Conn {
             schema: (*current_schema).clone(),
             cache: InProgressSQLiteAttributeCache::from_cache(cache_cow),
             use_caching: true,
+            report_tx_data: false,
             tx_observer: &self.tx_observer_service,
-            tx_observer_watcher: InProgressObserverTransactWatcher::new(),
+            tx_observer_watcher,
         })
     }
 
@@ -342,6 +377,9 @@ impl Conn {
                 .into();
         }
 
+        // Plans might have answered parts of queries from the cache.
+        self.query_plans.lock().unwrap().invalidate();
+
         let cache = &mut metadata.attribute_cache;
         match cache_action {
             CacheAction::Register => match cache_direction {
@@ -364,9 +402,50 @@ impl Conn {
             .register(key, observer);
     }
 
+    /// Register an observer that receives the datoms of committed transactions.  See
+    /// `DatomObserver`.
+    pub fn register_datom_observer(&mut self, key: String, observer: Arc<DatomObserver>) {
+        self.tx_observer_service
+            .lock()
+            .unwrap()
+            .register_datom_observer(key, observer);
+    }
+
     pub fn unregister_observer(&mut self, key: &str) {
         self.tx_observer_service.lock().unwrap().deregister(key);
     }
+
+    /// Run `query`, using the given connection and the current metadata, and register it as a
+    /// live query named `key`. Returns its results and a channel of `LiveQueryUpdate`s, one for
+    /// each later commit that changes them, or the error if re-running the query fails. Those
+    /// commits wait for the query to re-run. See `LiveQueries::register`.
+    pub fn register_live_query(
+        &mut self,
+        sqlite: &rusqlite::Connection,
+        key: String,
+        query: &str,
+        inputs: Vec<(Variable, TypedValue)>,
+        capacity: usize,
+    ) -> Result<(QueryOutput, LiveQueryReceiver)> {
+        let mut metadata = self.metadata.lock().unwrap();
+        let metadata = &mut *metadata;
+        let known = Known::new(&metadata.schema, Some(&metadata.attribute_cache));
+        metadata
+            .live_queries
+            .register(sqlite, known, key, query, inputs, capacity)
+    }
+
+    pub fn unregister_live_query(&mut self, key: &str) {
+        self.metadata.lock().unwrap().live_queries.unregister(key);
+    }
+
+    pub fn is_registered_as_live_query(&self, key: &str) -> bool {
+        self.metadata
+            .lock()
+            .unwrap()
+            .live_queries
+            .is_registered(key)
+    }
 }
 
 #[cfg(test)]
@@ -377,7 +456,7 @@ mod tests {
 
     use core_traits::{Binding, TypedValue};
 
-    use mentat_core::CachedAttributes;
+    use mentat_core::{CachedAttributes, TxDatom};
 
     use mentat_transaction::query::Variable;
 
@@ -504,6 +583,144 @@ mod tests {
         assert_eq!(tempid_offset + 3, tempid_offset_after);
     }
 
+    #[test]
+    fn test_tx_report_tx_data() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        // Reports don't carry the transaction's data unless it's asked for.
+        let report = conn
+            .transact(
+                &mut sqlite,
+                "[{:db/ident :foo/bar :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+                  {:db/ident :foo/text :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true :db/index true}]",
+            )
+            .expect("transacted schema");
+        assert!(report.schema_changed);
+        assert_eq!(report.tx_data, None);
+        assert_eq!(report.schema_before, None);
+        assert_eq!(report.schema_after, None);
+
+        let schema = conn.current_schema();
+        let foo_bar = schema.get_entid(&kw!(:foo/bar)).expect(":foo/bar").0;
+        let tx_instant = schema
+            .get_entid(&kw!(:db/txInstant))
+            .expect(":db/txInstant")
+            .0;
+
+        let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun");
+        in_progress.report_tx_data(true);
+
+        let report = in_progress
+            .transact(r#"[[:db/add "e" :foo/bar 1]]"#)
+            .expect("transacted data");
+        let e = report.tempids["e"];
+        assert!(!report.schema_changed);
+        assert_eq!(
+            report.tx_data.expect("tx_data"),
+            vec![
+                TxDatom {
+                    e: report.tx_id,
+                    a: tx_instant,
+                    v: TypedValue::Instant(report.tx_instant),
+                    added: true,
+                },
+                TxDatom {
+                    e,
+                    a: foo_bar,
+                    v: TypedValue::Long(1),
+                    added: true,
+                },
+            ]
+        );
+        assert_eq!(report.schema_before.as_ref(), Some(&schema));
+        assert_eq!(report.schema_after.as_ref(), Some(&schema));
+
+        // Replacing a cardinality-one value retracts the old one.
+        let report = in_progress
+            .transact(format!("[[:db/add {} :foo/bar 2]]", e))
+            .expect("transacted replacement");
+        let instant = TxDatom {
+            e: report.tx_id,
+            a: tx_instant,
+            v: TypedValue::Instant(report.tx_instant),
+            added: true,
+        };
+        assert_eq!(
+            report.tx_data.expect("tx_data"),
+            vec![
+                instant,
+                TxDatom {
+                    e,
+                    a: foo_bar,
+                    v: TypedValue::Long(2),
+                    added: true,
+                },
+                TxDatom {
+                    e,
+                    a: foo_bar,
+                    v: TypedValue::Long(1),
+                    added: false,
+                },
+            ]
+        );
+
+        // Asserting a datom that's already present changes nothing.
+        let report = in_progress
+            .transact(format!("[[:db/add {} :foo/bar 2]]", e))
+            .expect("transacted repeat");
+        assert_eq!(report.tx_data.expect("tx_data").len(), 1);
+
+        let report = in_progress
+            .transact(format!("[[:db/retract {} :foo/bar 2]]", e))
+            .expect("transacted retraction");
+        assert!(report.tx_data.expect("tx_data").contains(&TxDatom {
+            e,
+            a: foo_bar,
+            v: TypedValue::Long(2),
+            added: false,
+        }));
+
+        // Fulltext values are reported as strings.
+        let foo_text = schema.get_entid(&kw!(:foo/text)).expect(":foo/text").0;
+        in_progress
+            .transact(format!(r#"[[:db/add {} :foo/text "old"]]"#, e))
+            .expect("transacted text");
+        let report = in_progress
+            .transact(format!(r#"[[:db/add {} :foo/text "new"]]"#, e))
+            .expect("transacted replacement text");
+        let tx_data = report.tx_data.expect("tx_data");
+        assert_eq!(
+            &tx_data[1..],
+            &[
+                TxDatom {
+                    e,
+                    a: foo_text,
+                    v: TypedValue::typed_string("new"),
+                    added: true,
+                },
+                TxDatom {
+                    e,
+                    a: foo_text,
+                    v: TypedValue::typed_string("old"),
+                    added: false,
+                },
+            ]
+        );
+
+        // Schema changes report both schemas.
+        let report = in_progress
+            .transact("[[:db/add :foo/bar :db/index true]]")
+            .expect("transacted schema");
+        assert!(report.schema_changed);
+        let schema_before = report.schema_before.expect("schema_before");
+        let schema_after = report.schema_after.expect("schema_after");
+        assert_eq!(schema_before, schema);
+        assert!(!schema_before.attribute_for_entid(foo_bar).unwrap().index);
+        assert!(schema_after.attribute_for_entid(foo_bar).unwrap().index);
+        assert_eq!(*schema_after, in_progress.schema);
+    }
+
     #[test]
     fn test_simple_prepared_query() {
         let mut c = db::new_connection("").expect("Couldn't open conn.");
@@ -560,6 +777,83 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_prepared_query_with_late_bound_inputs() {
+        let mut c = db::new_connection("").expect("Couldn't open conn.");
+        let mut conn = Conn::connect(&mut c).expect("Couldn't open DB.");
+        conn.transact(
+            &mut c,
+            r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#,
+        )
+        .expect("successful transaction");
+        conn.transact(
+            &mut c,
+            r#"[{:foo/name "Alice" :foo/age 30}
+                {:foo/name "Bob" :foo/age 40}
+                {:foo/name "Carol" :foo/age 50}]"#,
+        )
+        .expect("successful transaction");
+
+        let name = Variable::from_valid_name("?name");
+        let min = Variable::from_valid_name("?min");
+        let read = conn.begin_read(&mut c).expect("read");
+        let mut prepared = read
+            .q_prepare(
+                r#"[:find [?n ...]
+                    :in ?name ?min
+                    :where [?x :foo/name ?name] [?y :foo/age ?a] [(> ?a ?min)] [?y :foo/name ?n]
+                    :order ?n]"#,
+                QueryInputs::with_type_sequence(vec![
+                    (name.clone(), ValueType::String),
+                    (min.clone(), ValueType::Long),
+                ]),
+            )
+            .expect("prepare succeeded");
+
+        let mut names_older_than = |age: i64| {
+            let inputs = QueryInputs::with_value_sequence(vec![
+                (name.clone(), TypedValue::typed_string("Alice")),
+                (min.clone(), TypedValue::Long(age)),
+            ]);
+            prepared.run(inputs).into_coll_result().expect("results")
+        };
+        let strings = |names: &[&str]| -> Vec<Binding> {
+            names
+                .iter()
+                .map(|n| TypedValue::typed_string(*n).into())
+                .collect()
+        };
+        assert_eq!(names_older_than(35), strings(&["Bob", "Carol"]));
+        assert_eq!(names_older_than(45), strings(&["Carol"]));
+        assert_eq!(names_older_than(55), strings(&[]));
+
+        // Every input has to be bound, and to a value of the right type.
+        let inputs = QueryInputs::with_value_sequence(vec![(min.clone(), TypedValue::Long(1))]);
+        match prepared.run(inputs).expect_err("unbound ?name") {
+            MentatError::UnboundVariables(names) => {
+                assert_eq!(names, vec!["?name".to_string()].into_iter().collect())
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        let inputs = QueryInputs::with_value_sequence(vec![
+            (name.clone(), TypedValue::Long(1)),
+            (min.clone(), TypedValue::Long(1)),
+        ]);
+        assert!(prepared.run(inputs).is_err());
+
+        // Inputs need types to be bound later, declared or inferred.
+        let untyped = read.q_prepare(r#"[:find ?x :in ?name :where [?x _ ?name]]"#, None);
+        match untyped.map(|_| ()).expect_err("untyped ?name") {
+            MentatError::UnboundVariables(names) => {
+                assert_eq!(names, vec!["?name".to_string()].into_iter().collect())
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
     #[test]
     fn test_compound_rollback() {
         let mut sqlite = db::new_connection("").unwrap();
@@ -902,4 +1196,222 @@ mod tests {
         assert!(conn.current_cache().is_attribute_cached_forward(db_ident));
         assert!(conn.current_cache().is_attribute_cached_forward(db_type));
     }
+
+    #[test]
+    fn test_query_plan_cache() {
+        // Plans are shared between threads.
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Conn>();
+
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        conn.transact(
+            &mut sqlite,
+            r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#,
+        )
+        .expect("transacted schema");
+        conn.transact(
+            &mut sqlite,
+            r#"[{:foo/name "Alice" :foo/age 30} {:foo/name "Bob" :foo/age 40}]"#,
+        )
+        .expect("transacted data");
+
+        let query = r#"[:find ?n . :in ?age :where [?x :foo/age ?age] [?x :foo/name ?n]]"#;
+        let name_for_age = |conn: &Conn, sqlite: &rusqlite::Connection, age: i64| {
+            let inputs = QueryInputs::with_value_sequence(vec![(
+                Variable::from_valid_name("?age"),
+                TypedValue::Long(age),
+            )]);
+            conn.q_once(sqlite, query, inputs)
+                .into_scalar_result()
+                .expect("query")
+        };
+
+        let alice = Some(TypedValue::typed_string("Alice").into());
+        let bob = Some(TypedValue::typed_string("Bob").into());
+
+        assert_eq!(name_for_age(&conn, &sqlite, 30), alice);
+        assert_eq!(name_for_age(&conn, &sqlite, 30), alice);
+
+        // Input values are bound when the plan runs, so one plan serves every age.
+        assert_eq!(name_for_age(&conn, &sqlite, 40), bob);
+        assert_eq!(name_for_age(&conn, &sqlite, 60), None);
+
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (3, 1, 1));
+
+        // Without cached attributes, plans don't depend on the data.
+        conn.transact(&mut sqlite, r#"[{:foo/name "Carol" :foo/age 50}]"#)
+            .expect("transacted data");
+        assert_eq!(
+            name_for_age(&conn, &sqlite, 50),
+            Some(TypedValue::typed_string("Carol").into())
+        );
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.invalidations), (4, 1, 0));
+
+        // Changing the schema throws every plan away.
+        conn.transact(
+            &mut sqlite,
+            r#"[{:db/ident :foo/email :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#,
+        )
+        .expect("transacted schema");
+        assert_eq!(name_for_age(&conn, &sqlite, 30), alice);
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!(
+            (stats.hits, stats.misses, stats.invalidations, stats.entries),
+            (4, 2, 1, 1)
+        );
+
+        // The least recently used plan goes first.
+        let age_for_name = |conn: &Conn, sqlite: &rusqlite::Connection, name: &str| {
+            let inputs = QueryInputs::with_value_sequence(vec![(
+                Variable::from_valid_name("?name"),
+                TypedValue::typed_string(name),
+            )]);
+            conn.q_once(
+                sqlite,
+                r#"[:find ?age . :in ?name :where [(ground ?name) ?n] [?x :foo/name ?n] [?x :foo/age ?age]]"#,
+                inputs,
+            )
+            .into_scalar_result()
+            .expect("query")
+        };
+        conn.set_query_plan_cache_capacity(2);
+        assert_eq!(name_for_age(&conn, &sqlite, 40), bob);
+        assert_eq!(
+            age_for_name(&conn, &sqlite, "Alice"),
+            Some(TypedValue::Long(30).into())
+        );
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (5, 3, 2));
+        assert_eq!(name_for_age(&conn, &sqlite, 40), bob);
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (5, 4, 2));
+
+        // Without a capacity, nothing is cached.
+        conn.set_query_plan_cache_capacity(0);
+        assert_eq!(name_for_age(&conn, &sqlite, 40), bob);
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (5, 4, 0));
+    }
+
+    #[test]
+    fn test_query_plan_cache_needs_values() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        conn.transact(
+            &mut sqlite,
+            r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/nick :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#,
+        )
+        .expect("transacted schema");
+        conn.transact(
+            &mut sqlite,
+            r#"[{:foo/name "Alice" :foo/nick "Al"} {:foo/name "Bob" :foo/nick "Bo"}]"#,
+        )
+        .expect("transacted data");
+
+        // `ground` needs a constant, so these plans are keyed by the input's value.
+        let query = r#"[:find ?nick . :in ?name :where [(ground ?name) ?n] [?x :foo/name ?n] [?x :foo/nick ?nick]]"#;
+        let nick_for_name = |conn: &Conn, sqlite: &rusqlite::Connection, name: &str| {
+            let inputs = QueryInputs::with_value_sequence(vec![(
+                Variable::from_valid_name("?name"),
+                TypedValue::typed_string(name),
+            )]);
+            conn.q_once(sqlite, query, inputs)
+                .into_scalar_result()
+                .expect("query")
+        };
+
+        let al = Some(TypedValue::typed_string("Al").into());
+        let bo = Some(TypedValue::typed_string("Bo").into());
+        assert_eq!(nick_for_name(&conn, &sqlite, "Alice"), al);
+        assert_eq!(nick_for_name(&conn, &sqlite, "Bob"), bo);
+        assert_eq!(nick_for_name(&conn, &sqlite, "Alice"), al);
+        assert_eq!(nick_for_name(&conn, &sqlite, "Bob"), bo);
+
+        // One entry says the query needs values, and there's one plan for each value.
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 2, 3));
+    }
+
+    #[test]
+    fn test_query_plan_cache_with_cached_attributes() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        conn.transact(
+            &mut sqlite,
+            r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#,
+        )
+        .expect("transacted schema");
+        let report = conn
+            .transact(&mut sqlite, r#"[{:db/id "a" :foo/name "Alice"}]"#)
+            .expect("transacted data");
+        let alice = *report.tempids.get("a").expect("alice");
+
+        let schema = conn.current_schema();
+        conn.cache(
+            &mut sqlite,
+            &schema,
+            &kw!(:foo/name),
+            CacheDirection::Forward,
+            CacheAction::Register,
+        )
+        .expect("cached :foo/name");
+
+        // With `:foo/name` cached, the value is baked into the plan.
+        let query = format!("[:find ?n . :where [{} :foo/name ?n]]", alice);
+        let name = |conn: &Conn, sqlite: &rusqlite::Connection| {
+            conn.q_once(sqlite, query.as_str(), None)
+                .into_scalar_result()
+                .expect("query")
+        };
+        assert_eq!(
+            name(&conn, &sqlite),
+            Some(TypedValue::typed_string("Alice").into())
+        );
+        assert_eq!(
+            name(&conn, &sqlite),
+            Some(TypedValue::typed_string("Alice").into())
+        );
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+
+        // So the plan doesn't survive a commit.
+        conn.transact(
+            &mut sqlite,
+            format!(r#"[[:db/add {} :foo/name "Alicia"]]"#, alice),
+        )
+        .expect("transacted data");
+        assert_eq!(
+            name(&conn, &sqlite),
+            Some(TypedValue::typed_string("Alicia").into())
+        );
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.invalidations), (1, 2, 1));
+
+        // Nor a change to the cached attributes.
+        conn.cache(
+            &mut sqlite,
+            &schema,
+            &kw!(:foo/name),
+            CacheDirection::Forward,
+            CacheAction::Deregister,
+        )
+        .expect("uncached :foo/name");
+        assert_eq!(
+            name(&conn, &sqlite),
+            Some(TypedValue::typed_string("Alicia").into())
+        );
+        let stats = conn.query_plan_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.invalidations), (1, 3, 2));
+    }
 }
@@ -25,7 +25,8 @@ pub use mentat_core::{DateTime, HasSchema, Keyword, Schema, TxReport, Utc, Uuid}
 pub use edn::query::FindSpec;
 
 pub use mentat_db::{
-    new_connection, AttributeSet, TxObserver, CORE_SCHEMA_VERSION, DB_SCHEMA_CORE,
+    new_connection, AttributeSet, DatomFilter, DatomObserver, DatomPattern, ObservedTx,
+    TxObserver, CORE_SCHEMA_VERSION, DB_SCHEMA_CORE,
 };
 
 #[cfg(feature = "sqlcipher")]
@@ -97,8 +98,9 @@ pub use mentat_transaction::entity_builder;
 pub use mentat_transaction::query;
 
 pub use mentat_transaction::query::{
-    q_once, IntoResult, PlainSymbol, QueryExecutionResult, QueryExplanation, QueryInputs,
-    QueryOutput, QueryPlanStep, QueryResults, RelResult, Variable,
+    parse_rules_string, q_once, DatabaseView, IntoResult, PlainSymbol, QueryExecutionResult,
+    QueryExplanation, QueryInputs, QueryOutput, QueryPlanStep, QueryResults, QuerySource,
+    RelResult, Rule, SrcVar, Variable,
 };
 
 pub mod conn;
@@ -110,16 +112,22 @@ pub mod vocabulary;
 mod sync;
 
 #[cfg(feature = "syncable")]
-pub use sync::Syncable;
+pub use sync::{open_log, Syncable};
 
 #[cfg(feature = "syncable")]
-pub use mentat_tolstoy::SyncReport;
+pub use mentat_tolstoy::{DirectoryLog, GlobalTransactionLog, RemoteClient, SqliteLog, SyncReport};
+
+#[cfg(feature = "syncable")]
+pub use mentat_tolstoy::conflict;
 
 pub use query_builder::QueryBuilder;
 
 pub use conn::Conn;
 
-pub use mentat_transaction::{CacheAction, CacheDirection, InProgress, Pullable, Queryable};
+pub use mentat_transaction::{
+    CacheAction, CacheDirection, InProgress, LiveQueryReceiver, LiveQueryUpdate, Pullable,
+    QueryPlanCacheStats, Queryable,
+};
 
 pub use store::Store;
 
@@ -15,7 +15,10 @@ pub use core_traits::{Binding, Entid, TypedValue, ValueType};
 
 use mentat_core::{DateTime, Keyword, Utc};
 
-use super::{HasSchema, QueryInputs, QueryOutput, Queryable, RelResult, Store, Variable};
+use super::{
+    parse_rules_string, HasSchema, PlainSymbol, QueryInputs, QueryOutput, QuerySource, Queryable,
+    RelResult, Rule, SrcVar, Store, Variable,
+};
 
 use public_traits::errors::{MentatError, Result};
 
@@ -23,6 +26,8 @@ pub struct QueryBuilder<'a> {
     query: String,
     values: BTreeMap<Variable, TypedValue>,
     types: BTreeMap<Variable, ValueType>,
+    rules: Vec<Rule>,
+    sources: BTreeMap<SrcVar, QuerySource>,
     store: &'a mut Store,
 }
 
@@ -35,6 +40,8 @@ impl<'a> QueryBuilder<'a> {
             query: query.into(),
             values: BTreeMap::new(),
             types: BTreeMap::new(),
+            rules: Vec::new(),
+            sources: BTreeMap::new(),
             store,
         }
     }
@@ -98,10 +105,35 @@ impl<'a> QueryBuilder<'a> {
         self
     }
 
+    /// Supply rule definitions, `[[(name ?a ?b) clause…] …]`, for a query that names `%` in
+    /// its `:in` clause.
+    pub fn bind_rules(&mut self, rules: &str) -> Result<&mut Self> {
+        self.rules.extend(parse_rules_string(rules)?);
+        Ok(self)
+    }
+
+    /// Bind a source variable, `$` or a name like `$history`, to a view of the store, an
+    /// attached store, or a relation.
+    pub fn bind_source<S: Into<QuerySource>>(
+        &mut self,
+        source: &str,
+        bound: S,
+    ) -> Result<&mut Self> {
+        let source = SrcVar::from_symbol(&PlainSymbol::plain(source))
+            .ok_or_else(|| MentatError::InvalidArgumentName(source.to_string()))?;
+        self.sources.insert(source, bound.into());
+        Ok(self)
+    }
+
     pub fn execute(&mut self) -> Result<QueryOutput> {
         let values = ::std::mem::take(&mut self.values);
         let types = ::std::mem::take(&mut self.types);
-        let query_inputs = QueryInputs::new(types, values)?;
+        let rules = ::std::mem::take(&mut self.rules);
+        let sources = ::std::mem::take(&mut self.sources);
+        let query_inputs = sources.into_iter().fold(
+            QueryInputs::new(types, values)?.with_rules(rules),
+            |inputs, (source, bound)| inputs.with_source(source, bound),
+        );
         let read = self.store.begin_read()?;
         read.q_once(&self.query, query_inputs).map_err(|e| e)
     }
@@ -129,7 +161,9 @@ impl<'a> QueryBuilder<'a> {
 
 #[cfg(test)]
 mod test {
-    use super::{QueryBuilder, Store, TypedValue};
+    use super::{MentatError, QueryBuilder, Store, TypedValue};
+
+    use crate::DatabaseView;
 
     #[test]
     fn test_scalar_query() {
@@ -538,4 +572,44 @@ mod test {
             25
         );
     }
+
+    #[test]
+    fn test_bind_source() {
+        let mut store = Store::open("").expect("store connection");
+        store
+            .transact(
+                r#"[
+            [:db/add "t" :db/ident :foo/long]
+            [:db/add "t" :db/valueType :db.type/long]
+            [:db/add "t" :db/cardinality :db.cardinality/one]
+        ]"#,
+            )
+            .expect("successful transaction");
+        let report = store
+            .transact(r#"[[:db/add "l" :foo/long 25]]"#)
+            .expect("successful transaction");
+        let l = *report.tempids.get("l").expect("found it");
+        store
+            .transact(&format!("[[:db/add {} :foo/long 26]]", l))
+            .expect("successful transaction");
+
+        let results = QueryBuilder::new(
+            &mut store,
+            r#"[:find [?v ...]
+                :in $history
+                :where [$history ?x :foo/long ?v]]"#,
+        )
+        .bind_source("$history", DatabaseView::History)
+        .expect("source variable")
+        .execute_coll()
+        .expect("CollResult");
+        assert_eq!(results.len(), 2);
+
+        // Source variables start with `$`.
+        let mut builder = QueryBuilder::new(&mut store, "[:find ?x :where [?x :foo/long _]]");
+        match builder.bind_source("history", DatabaseView::History) {
+            Err(MentatError::InvalidArgumentName(name)) => assert_eq!(name, "history"),
+            _ => panic!("expected an invalid source variable"),
+        }
+    }
 }
@@ -17,23 +17,25 @@ use std::sync::Arc;
 use core_traits::{Entid, StructuredMap, TypedValue};
 
 use mentat_core::{Keyword, TxReport, ValueRc};
-use mentat_db::TxObserver;
+use mentat_db::{DatomObserver, TxObserver};
 
 use mentat_transaction::{
-    CacheAction, CacheDirection, InProgress, InProgressRead, Pullable, Queryable,
+    CacheAction, CacheDirection, InProgress, InProgressRead, LiveQueryReceiver, Pullable, Queryable,
 };
 
 use crate::conn::Conn;
 
-use public_traits::errors::Result;
+use public_traits::errors::{MentatError, Result};
 
-use mentat_transaction::query::{PreparedResult, QueryExplanation, QueryInputs, QueryOutput};
+use mentat_transaction::query::{
+    PreparedResult, QueryExplanation, QueryInputs, QueryOutput, Variable,
+};
 
 #[cfg(feature = "syncable")]
-use mentat_tolstoy::{SyncFollowup, SyncReport, SyncResult};
+use mentat_tolstoy::{ConflictPolicy, GlobalTransactionLog, SyncFollowup, SyncReport, SyncResult};
 
 #[cfg(feature = "syncable")]
-use crate::sync::Syncable;
+use crate::sync::{open_log, Syncable};
 
 /// A convenience wrapper around a single SQLite connection and a Conn. This is suitable
 /// for applications that don't require complex connection management.
@@ -62,10 +64,32 @@ impl Store {
 
     #[cfg(feature = "syncable")]
     pub fn sync(&mut self, server_uri: &str, user_uuid: &str) -> Result<SyncResult> {
+        self.sync_with_policy(server_uri, user_uuid, &ConflictPolicy::default())
+    }
+
+    /// Like `sync`, settling conflicts between local and remote changes as `policy` says.
+    #[cfg(feature = "syncable")]
+    pub fn sync_with_policy(
+        &mut self,
+        server_uri: &str,
+        user_uuid: &str,
+        policy: &ConflictPolicy,
+    ) -> Result<SyncResult> {
+        let mut log = open_log(server_uri, user_uuid)?;
+        self.sync_with_log(&mut log, policy)
+    }
+
+    /// Like `sync_with_policy`, but with a log the caller has opened, such as a `RemoteClient`
+    /// that authenticates its requests.
+    #[cfg(feature = "syncable")]
+    pub fn sync_with_log<L>(&mut self, log: &mut L, policy: &ConflictPolicy) -> Result<SyncResult>
+    where
+        L: GlobalTransactionLog,
+    {
         let mut reports = vec![];
         loop {
             let mut ip = self.begin_transaction()?;
-            let report = ip.sync(server_uri, user_uuid)?;
+            let report = ip.sync_with_log(log, policy)?;
             ip.commit()?;
 
             match report {
@@ -158,13 +182,93 @@ impl Store {
         self.conn.register_observer(key, observer);
     }
 
+    pub fn register_datom_observer(&mut self, key: String, observer: Arc<DatomObserver>) {
+        self.conn.register_datom_observer(key, observer);
+    }
+
     pub fn unregister_observer(&mut self, key: &str) {
         self.conn.unregister_observer(key);
     }
 
+    /// Run `query` and register it as a live query named `key`. See `Conn::register_live_query`.
+    pub fn register_live_query(
+        &mut self,
+        key: String,
+        query: &str,
+        inputs: Vec<(Variable, TypedValue)>,
+        capacity: usize,
+    ) -> Result<(QueryOutput, LiveQueryReceiver)> {
+        self.conn
+            .register_live_query(&self.sqlite, key, query, inputs, capacity)
+    }
+
+    pub fn unregister_live_query(&mut self, key: &str) {
+        self.conn.unregister_live_query(key);
+    }
+
     pub fn last_tx_id(&self) -> Entid {
         self.conn.last_tx_id()
     }
+
+    /// Attach the Mentat store at `path` to this store's connection as `name`. Queries read it
+    /// through a source bound to `QuerySource::Attached(name)`. The attached store is resolved
+    /// with this store's schema, so every ident the two stores share must name the same entid in
+    /// both; if one doesn't, the store is detached again and an error is returned.
+    pub fn attach_store(&mut self, name: &str, path: &str) -> Result<()> {
+        let valid = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !name.eq_ignore_ascii_case("main")
+            && !name.eq_ignore_ascii_case("temp");
+        if !valid {
+            bail!(MentatError::InvalidArgumentName(name.to_string()));
+        }
+        self.sqlite
+            .execute("ATTACH DATABASE ? AS ?", rusqlite::params![path, name])?;
+        if let Err(e) = self.check_attached_idents(name) {
+            self.detach_store(name)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn check_attached_idents(&self, name: &str) -> Result<()> {
+        let schema = self.conn.current_schema();
+        let ours: BTreeMap<String, Entid> = schema
+            .ident_map
+            .iter()
+            .map(|(ident, entid)| (ident.to_string(), *entid))
+            .collect();
+
+        // `name` was checked to be a plain identifier by `attach_store`.
+        let mut stmt = self
+            .sqlite
+            .prepare(&format!("SELECT e, v FROM {}.idents", name))?;
+        let theirs: Vec<(Entid, String)> = stmt
+            .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        for (entid, ident) in theirs {
+            let same_entid = ours.get(&ident).is_none_or(|e| *e == entid);
+            let same_ident = schema
+                .entid_map
+                .get(&entid)
+                .is_none_or(|i| i.to_string() == ident);
+            if !same_entid || !same_ident {
+                bail!(MentatError::AttachedStoreSchemaMismatch(
+                    name.to_string(),
+                    ident
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Detach a store previously attached with `attach_store`.
+    pub fn detach_store(&mut self, name: &str) -> Result<()> {
+        self.sqlite
+            .execute("DETACH DATABASE ?", rusqlite::params![name])?;
+        Ok(())
+    }
 }
 
 impl Queryable for Store {
@@ -247,13 +351,16 @@ mod tests {
     use std::path::{Path, PathBuf};
     use std::sync::mpsc;
     use std::sync::Mutex;
-    use std::time::Duration;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     use mentat_db::cache::SQLiteAttributeCache;
 
-    use core_traits::{TypedValue, ValueType};
+    use core_traits::{Binding, TypedValue, ValueType};
+
+    use mentat_core::{CachedAttributes, HasSchema, TxDatom};
 
-    use mentat_core::{CachedAttributes, HasSchema};
+    use mentat_db::{DatomFilter, DatomPattern, ObservedTx};
 
     use mentat_transaction::entity_builder::BuildTerms;
 
@@ -261,6 +368,8 @@ mod tests {
 
     use mentat_query_algebrizer::QueryInputs;
 
+    use crate::AlgebrizerError;
+
     use crate::vocabulary::{AttributeBuilder, Definition, VersionedStore};
 
     use core_traits::attribute::Unique;
@@ -862,4 +971,340 @@ mod tests {
         assert_eq!(o.txids, tx_ids);
         assert_eq!(o.changes, changesets);
     }
+
+    fn entid(store: &Store, ident: &Keyword) -> Entid {
+        store
+            .conn()
+            .current_schema()
+            .get_entid(ident)
+            .expect("entid to exist")
+            .into()
+    }
+
+    #[test]
+    fn test_datom_observer_receives_filtered_datoms() {
+        let mut store = Store::open("").unwrap();
+        add_schema(&mut store);
+
+        let name_entid = entid(&store, &kw!(:todo/name));
+
+        let mut filter = DatomFilter::new();
+        filter.attribute(name_entid);
+        let (observer, rx) = DatomObserver::new(filter, 2);
+        let key = "Test Datom Observer".to_string();
+        store.register_datom_observer(key.clone(), Arc::new(observer));
+        assert!(store.is_registered_as_observer(&key));
+
+        let first = store
+            .transact(r#"[{:db/id "t" :todo/name "first" :todo/uuid #uuid "4cb3f828-752d-497a-90c9-b1fd516d5644"}]"#)
+            .expect("transacted");
+        // Not delivered: no datom for :todo/name.
+        store
+            .transact(r#"[{:label/name "Label 1" :label/color "blue"}]"#)
+            .expect("transacted");
+        let third = store
+            .transact(r#"[{:db/id "t" :todo/name "third"}]"#)
+            .expect("transacted");
+
+        let delay = Duration::from_secs(5);
+        let observed = rx.recv_timeout(delay).expect("first transaction observed");
+        assert_eq!(
+            observed,
+            ObservedTx {
+                tx_id: first.tx_id,
+                datoms: vec![TxDatom {
+                    e: first.tempids["t"],
+                    a: name_entid,
+                    v: TypedValue::typed_string("first"),
+                    added: true,
+                }],
+                missed: 0,
+            }
+        );
+        let observed = rx.recv_timeout(delay).expect("third transaction observed");
+        assert_eq!(observed.tx_id, third.tx_id);
+        assert_eq!(observed.datoms.len(), 1);
+        assert_eq!(observed.datoms[0].e, third.tempids["t"]);
+    }
+
+    #[test]
+    fn test_datom_observer_pattern() {
+        let mut store = Store::open("").unwrap();
+        add_schema(&mut store);
+
+        let report = store
+            .transact(r#"[{:db/id "l" :label/name "Label 1" :label/color "blue"}]"#)
+            .expect("transacted");
+        let label = report.tempids["l"];
+
+        let mut filter = DatomFilter::new();
+        filter.pattern(
+            DatomPattern::new(&store.conn().current_schema(), "[_ :label/color _ _ false]")
+                .expect("pattern"),
+        );
+        filter.pattern(
+            DatomPattern::new(&store.conn().current_schema(), "[?e :label/name ?e]")
+                .expect("pattern"),
+        );
+        let (observer, rx) = DatomObserver::new(filter, 8);
+        store.register_datom_observer("labels".to_string(), Arc::new(observer));
+
+        // Neither pattern matches an assertion of a color, but replacing a color retracts the
+        // old one.
+        let report = store
+            .transact(&format!(r#"[[:db/add {} :label/color "green"]]"#, label))
+            .expect("transacted");
+        let observed = rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("replacement observed");
+        assert_eq!(observed.tx_id, report.tx_id);
+        assert_eq!(
+            observed.datoms,
+            vec![TxDatom {
+                e: label,
+                a: entid(&store, &kw!(:label/color)),
+                v: TypedValue::typed_string("blue"),
+                added: false,
+            }]
+        );
+
+        let report = store
+            .transact(&format!(
+                r#"[[:db/retract {} :label/color "green"]]"#,
+                label
+            ))
+            .expect("transacted");
+
+        let observed = rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("retraction observed");
+        assert_eq!(observed.tx_id, report.tx_id);
+        assert_eq!(
+            observed.datoms,
+            vec![TxDatom {
+                e: label,
+                a: entid(&store, &kw!(:label/color)),
+                v: TypedValue::typed_string("green"),
+                added: false,
+            }]
+        );
+
+        // Unknown vocabulary is rejected.
+        assert!(DatomPattern::new(&store.conn().current_schema(), "[?e :label/size _]").is_err());
+        assert!(DatomPattern::new(&store.conn().current_schema(), "(foo ?x)").is_err());
+    }
+
+    #[test]
+    fn test_datom_observer_dropped_receiver() {
+        let mut store = Store::open("").unwrap();
+        add_schema(&mut store);
+
+        let (observer, rx) = DatomObserver::new(DatomFilter::new(), 1);
+        let observer = Arc::new(observer);
+        let key = "Dropped".to_string();
+        store.register_datom_observer(key.clone(), Arc::clone(&observer));
+        drop(rx);
+
+        store
+            .transact(r#"[{:label/name "Label 1"}]"#)
+            .expect("transacted");
+
+        // Delivery happens on another thread.
+        let start = Instant::now();
+        while !observer.is_disconnected() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(store.is_registered_as_observer(&key));
+
+        // The next commit forgets the observer.
+        store
+            .transact(r#"[{:label/name "Label 2"}]"#)
+            .expect("transacted");
+        assert!(!store.is_registered_as_observer(&key));
+    }
+
+    #[test]
+    fn test_datom_observer_backpressure() {
+        let mut store = Store::open("").unwrap();
+        add_schema(&mut store);
+
+        // Not drained until every transaction has committed.
+        let (observer, rx) = DatomObserver::new(DatomFilter::new(), 1);
+        let observer = Arc::new(observer);
+        store.register_datom_observer("slow".to_string(), Arc::clone(&observer));
+
+        let mut tx_ids = Vec::new();
+        for i in 0..4 {
+            let report = store
+                .transact(&format!(r#"[{{:label/name "Label {}"}}]"#, i))
+                .expect("transacted");
+            tx_ids.push(report.tx_id);
+        }
+
+        // Delivery waited for room, so every transaction arrives, in order.
+        let delay = Duration::from_secs(5);
+        for tx_id in tx_ids {
+            let observed = rx.recv_timeout(delay).expect("transaction observed");
+            assert_eq!(observed.tx_id, tx_id);
+            assert_eq!(observed.missed, 0);
+        }
+        assert_eq!(observer.dropped(), 0);
+    }
+
+    #[test]
+    fn test_datom_observer_reports_missed() {
+        let mut store = Store::open("").unwrap();
+        add_schema(&mut store);
+
+        let (observer, rx) = DatomObserver::dropping(DatomFilter::new(), 1);
+        let observer = Arc::new(observer);
+        store.register_datom_observer("slow".to_string(), Arc::clone(&observer));
+
+        let first = store
+            .transact(r#"[{:label/name "Label 1"}]"#)
+            .expect("transacted");
+        store
+            .transact(r#"[{:label/name "Label 2"}]"#)
+            .expect("transacted");
+
+        // Delivery happens on another thread.
+        let start = Instant::now();
+        while observer.dropped() < 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let delay = Duration::from_secs(5);
+        let observed = rx.recv_timeout(delay).expect("first transaction observed");
+        assert_eq!(observed.tx_id, first.tx_id);
+        assert_eq!(observed.missed, 0);
+
+        let third = store
+            .transact(r#"[{:label/name "Label 3"}]"#)
+            .expect("transacted");
+        let observed = rx.recv_timeout(delay).expect("third transaction observed");
+        assert_eq!(observed.tx_id, third.tx_id);
+        assert_eq!(observed.missed, 1);
+    }
+
+    #[test]
+    fn test_live_query() {
+        let mut store = Store::open("").unwrap();
+        add_schema(&mut store);
+        store
+            .transact(r#"[{:label/name "Label 1" :label/color "blue"}]"#)
+            .expect("transacted");
+
+        let key = "Labels".to_string();
+        let (output, rx) = store
+            .register_live_query(
+                key.clone(),
+                r#"[:find ?name :in ?color :where [?l :label/name ?name] [?l :label/color ?color]]"#,
+                vec![(
+                    Variable::from_valid_name("?color"),
+                    TypedValue::typed_string("blue"),
+                )],
+                4,
+            )
+            .expect("registered");
+        assert_eq!(
+            output.into_rel().expect("rel"),
+            vec![vec![TypedValue::typed_string("Label 1")]].into()
+        );
+        assert!(store.conn().is_registered_as_live_query(&key));
+
+        // Doesn't touch any attribute the query reads.
+        store
+            .transact(r#"[{:todo/name "Todo 1"}]"#)
+            .expect("transacted");
+        // Touches them, but doesn't change the results.
+        store
+            .transact(r#"[{:label/name "Label 2" :label/color "green"}]"#)
+            .expect("transacted");
+        let report = store
+            .transact(r#"[{:label/name "Label 3" :label/color "blue"}]"#)
+            .expect("transacted");
+
+        let delay = Duration::from_secs(5);
+        let update = rx.recv_timeout(delay).expect("update").expect("refreshed");
+        assert_eq!(update.tx_id, report.tx_id);
+        assert_eq!(update.added, vec![vec![Binding::from("Label 3")]]);
+        assert!(update.removed.is_empty());
+        assert_eq!(update.results.len(), 2);
+
+        let report = store
+            .transact(r#"[[:db/add (lookup-ref :label/name "Label 1") :label/color "red"]]"#)
+            .expect("transacted");
+        let update = rx.recv_timeout(delay).expect("update").expect("refreshed");
+        assert_eq!(update.tx_id, report.tx_id);
+        assert!(update.added.is_empty());
+        assert_eq!(update.removed, vec![vec![Binding::from("Label 1")]]);
+
+        store.unregister_live_query(&key);
+        assert!(!store.conn().is_registered_as_live_query(&key));
+        store
+            .transact(r#"[{:label/name "Label 4" :label/color "blue"}]"#)
+            .expect("transacted");
+        assert!(rx.recv_timeout(delay).is_err());
+    }
+
+    #[test]
+    fn test_live_query_missed_and_failed_updates() {
+        let mut store = Store::open("").unwrap();
+        add_schema(&mut store);
+
+        // Observations and live query updates are delivered in commit order, so once this has
+        // seen a transaction, the live query updates of earlier commits have been delivered.
+        let (observer, observed) = DatomObserver::new(DatomFilter::new(), 8);
+        store.register_datom_observer("all".to_string(), Arc::new(observer));
+
+        let key = "Labels".to_string();
+        let (_, rx) = store
+            .register_live_query(
+                key.clone(),
+                r#"[:find [?name ...] :where [(fulltext $ :label/name "label") [[_ ?name]]]]"#,
+                vec![],
+                1,
+            )
+            .expect("registered");
+
+        let delay = Duration::from_secs(5);
+        let transact = |store: &mut Store, transaction: &str| {
+            let report = store.transact(transaction).expect("transacted");
+            let tx = observed.recv_timeout(delay).expect("transaction observed");
+            assert_eq!(tx.tx_id, report.tx_id);
+            report
+        };
+
+        transact(&mut store, r#"[{:label/name "Label 1"}]"#);
+        let second = transact(&mut store, r#"[{:label/name "Label 2"}]"#);
+        transact(&mut store, r#"[{:todo/name "Todo 1"}]"#);
+
+        // The second update didn't fit, so it replaced the first.
+        let update = rx.recv_timeout(delay).expect("update").expect("refreshed");
+        assert_eq!(update.tx_id, second.tx_id);
+        assert_eq!(update.missed, 1);
+        assert_eq!(update.added, vec![vec![Binding::from("Label 2")]]);
+        assert_eq!(update.results.len(), 2);
+        assert!(rx.try_recv().is_err());
+
+        let third = transact(&mut store, r#"[{:label/name "Label 3"}]"#);
+        let update = rx.recv_timeout(delay).expect("update").expect("refreshed");
+        assert_eq!(update.tx_id, third.tx_id);
+        assert_eq!(update.missed, 0);
+        assert_eq!(update.added, vec![vec![Binding::from("Label 3")]]);
+        assert_eq!(update.results.len(), 3);
+
+        // The query names an attribute that no longer exists, so it can't be re-run.
+        transact(
+            &mut store,
+            r#"[[:db/add :label/name :db/ident :label/title]]"#,
+        );
+        match rx.recv_timeout(delay).expect("update") {
+            Err(MentatError::AlgebrizerError(AlgebrizerError::InvalidArgument(..))) => (),
+            result => panic!("wrong result: {:?}", result),
+        }
+        assert!(store.conn().is_registered_as_live_query(&key));
+    }
 }
@@ -14,22 +14,66 @@ use mentat_transaction::InProgress;
 
 use super::errors::Result;
 
-use mentat_tolstoy::{RemoteClient, SyncReport, Syncer};
+use mentat_tolstoy::{
+    ConflictPolicy, DirectoryLog, GlobalTransactionLog, RemoteClient, SqliteLog, SyncReport, Syncer,
+};
 
+/// Open the log that `server_uri` names on behalf of the user `user_uuid`:
+///
+/// - `file:///path/to/directory` is a `DirectoryLog` in that directory.
+/// - `sqlite:///path/to/file.db` is a `SqliteLog` in that SQLite file, which can be another
+///   Mentat store.
+/// - anything else is the base URI of a sync server, which a `RemoteClient` with default
+///   settings talks to.
+pub fn open_log(server_uri: &str, user_uuid: &str) -> Result<Box<dyn GlobalTransactionLog>> {
+    let user_uuid = Uuid::parse_str(user_uuid)?;
+    let log: Box<dyn GlobalTransactionLog> = if let Some(path) = server_uri.strip_prefix("file://")
+    {
+        Box::new(DirectoryLog::open(path, &user_uuid)?)
+    } else if let Some(path) = server_uri.strip_prefix("sqlite://") {
+        Box::new(SqliteLog::open(path, &user_uuid)?)
+    } else {
+        Box::new(RemoteClient::new(server_uri.to_string(), user_uuid))
+    };
+    Ok(log)
+}
+
+/// Where a store syncs to is named by a URI, as described by `open_log`. To sync with a server
+/// that needs credentials, a timeout or retries, configure a `RemoteClient` and use
+/// `sync_with_log`.
 pub trait Syncable {
-    fn sync(&mut self, server_uri: &str, user_uuid: &str) -> Result<SyncReport>;
+    fn sync(&mut self, server_uri: &str, user_uuid: &str) -> Result<SyncReport> {
+        self.sync_with_policy(server_uri, user_uuid, &ConflictPolicy::default())
+    }
+
+    /// Like `sync`, settling conflicts between local and remote changes as `policy` says.
+    fn sync_with_policy(
+        &mut self,
+        server_uri: &str,
+        user_uuid: &str,
+        policy: &ConflictPolicy,
+    ) -> Result<SyncReport> {
+        let mut log = open_log(server_uri, user_uuid)?;
+        self.sync_with_log(&mut log, policy)
+    }
+
+    /// Like `sync_with_policy`, but with a log the caller has opened.
+    fn sync_with_log<L>(&mut self, log: &mut L, policy: &ConflictPolicy) -> Result<SyncReport>
+    where
+        L: GlobalTransactionLog;
 }
 
 impl<'a, 'c> Syncable for InProgress<'a, 'c> {
-    fn sync(&mut self, server_uri: &str, user_uuid: &str) -> Result<SyncReport> {
+    fn sync_with_log<L>(&mut self, log: &mut L, policy: &ConflictPolicy) -> Result<SyncReport>
+    where
+        L: GlobalTransactionLog,
+    {
         // Syncer behaves as if it's part of InProgress.
         // This split into a separate crate is segment synchronization functionality
         // in a single crate which can be easily disabled by consumers,
         // and to separate concerns.
         // But for all intents and purposes, Syncer operates over a "mentat transaction",
         // which is exactly what InProgress represents.
-        let mut remote_client =
-            RemoteClient::new(server_uri.to_string(), Uuid::parse_str(&user_uuid)?);
-        Syncer::sync(self, &mut remote_client).map_err(|e| e)
+        Syncer::sync_with_policy(self, log, policy)
     }
 }
@@ -94,7 +94,7 @@ use std::collections::BTreeMap;
 
 use core_traits::KnownEntid;
 
-use core_traits::attribute::Unique;
+use core_traits::attribute::{FulltextTokenizer, Unique};
 
 use super::{
     Attribute, Binding, Entid, HasSchema, IntoResult, Keyword, TypedValue, ValueType,
@@ -305,6 +305,7 @@ lazy_static! {
     static ref DB_VALUE_TYPE: Keyword = Keyword::namespaced("db", "valueType");
     static ref DB_INDEX: Keyword = kw!(:db/index);
     static ref DB_FULLTEXT: Keyword = kw!(:db/fulltext);
+    static ref DB_FULLTEXT_TOKENIZER: Keyword = Keyword::namespaced("db", "fulltextTokenizer");
     static ref DB_CARDINALITY: Keyword = kw!(:db/cardinality);
     static ref DB_CARDINALITY_ONE: Keyword = kw!(:db.cardinality/one);
     static ref DB_CARDINALITY_MANY: Keyword = kw!(:db.cardinality/many);
@@ -423,6 +424,33 @@ impl Definition {
                 TypedValue::Boolean(attr.no_history),
             )?;
 
+            // Stores that predate tokenizers don't know about them, so we only mention the
+            // tokenizer when it's not the default, or when it's being reset to the default.
+            let existing_tokenizer = diff
+                .as_ref()
+                .and_then(|diff| diff.get(kw))
+                .map(|a| a.fulltext_tokenizer)
+                .unwrap_or_default();
+            if attr.fulltext_tokenizer != existing_tokenizer {
+                let a_fulltext_tokenizer = via.core_attribute(&DB_FULLTEXT_TOKENIZER)?;
+                let tokenizer = |t: FulltextTokenizer| {
+                    via.core_entid(&Keyword::namespaced("db.fulltextTokenizer", t.name()))
+                };
+                if attr.fulltext_tokenizer == FulltextTokenizer::default() {
+                    builder.retract(
+                        tempid.clone(),
+                        a_fulltext_tokenizer,
+                        tokenizer(existing_tokenizer)?,
+                    )?;
+                } else {
+                    builder.add(
+                        tempid.clone(),
+                        a_fulltext_tokenizer,
+                        tokenizer(attr.fulltext_tokenizer)?,
+                    )?;
+                }
+            }
+
             if let Some(u) = attr.unique {
                 let uu = match u {
                     Unique::Identity => v_unique_identity,
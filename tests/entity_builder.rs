@@ -199,3 +199,75 @@ fn test_entity_builder() {
         Some(TypedValue::Ref(*x))
     );
 }
+
+#[test]
+fn test_entity_builder_retract_entity_and_cas() {
+    let mut sqlite = mentat_db::db::new_connection("").unwrap();
+    let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+    let foo_one = kw!(:foo/one);
+    conn.transact(
+        &mut sqlite,
+        r#"[
+        [:db/add "o" :db/ident :foo/one]
+        [:db/add "o" :db/valueType :db.type/long]
+        [:db/add "o" :db/cardinality :db.cardinality/one]
+    ]"#,
+    )
+    .unwrap();
+    let report = conn
+        .transact(&mut sqlite, r#"[[:db/add "x" :foo/one 1]]"#)
+        .unwrap();
+    let x = KnownEntid(*report.tempids.get("x").expect("our tempid has an ID"));
+
+    let in_progress = conn
+        .begin_transaction(&mut sqlite)
+        .expect("begun successfully");
+    let mut builder = in_progress.builder().describe(x);
+    builder
+        .cas(
+            foo_one.clone(),
+            Some(TypedValue::Long(1)),
+            TypedValue::Long(2),
+        )
+        .expect("cas succeeded");
+    builder.commit().expect("commit succeeded");
+    assert_eq!(
+        conn.lookup_value_for_attribute(&sqlite, x.0, &foo_one)
+            .expect("lookup succeeded"),
+        Some(TypedValue::Long(2))
+    );
+
+    // A stale expectation fails.
+    let in_progress = conn
+        .begin_transaction(&mut sqlite)
+        .expect("begun successfully");
+    let mut builder = in_progress.builder().describe(x);
+    builder
+        .cas(foo_one.clone(), None, TypedValue::Long(3))
+        .expect("cas succeeded");
+    match builder.commit().expect_err("expected cas to fail") {
+        MentatError::DbError(e) => match e.kind() {
+            db_traits::errors::DbErrorKind::CasFailed {
+                expected, found, ..
+            } => {
+                assert_eq!(expected, None);
+                assert_eq!(found, Some(TypedValue::Long(2)));
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        },
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    let in_progress = conn
+        .begin_transaction(&mut sqlite)
+        .expect("begun successfully");
+    let mut builder = in_progress.builder().describe(x);
+    builder.retract_entity().expect("retract succeeded");
+    builder.commit().expect("commit succeeded");
+    assert_eq!(
+        conn.lookup_value_for_attribute(&sqlite, x.0, &foo_one)
+            .expect("lookup succeeded"),
+        None
+    );
+}
@@ -21,8 +21,8 @@ use core_traits::{Binding, StructuredMap};
 use mentat_core::ValueRc;
 
 use mentat::{
-    Entid, HasSchema, IntoResult, Keyword, Pullable, QueryInputs, Queryable, RelResult, Store,
-    TypedValue,
+    Entid, HasSchema, IntoResult, Keyword, MentatError, ProjectorError, PullError, Pullable,
+    QueryInputs, Queryable, RelResult, Store, TypedValue,
 };
 
 fn fixture_path(rest: &str) -> PathBuf {
@@ -364,6 +364,201 @@ fn test_simple_pull() {
     assert_eq!(results, expected);
 }
 
+fn pull_person(store: &Store, person: Entid, pattern: &str) -> Binding {
+    let query = format!(
+        r#"[:find (pull ?p [{}]) .
+            :in ?p
+            :where [?p :person/name _]]"#,
+        pattern
+    );
+    store
+        .q_once(
+            query.as_str(),
+            QueryInputs::with_value_sequence(vec![(var!(?p), TypedValue::Ref(person))]),
+        )
+        .into_scalar_result()
+        .expect("pulled")
+        .expect("result")
+}
+
+#[test]
+fn test_nested_pull() {
+    let mut store = Store::open("").expect("opened");
+    store
+        .transact(
+            r#"[
+        {:db/ident       :person/name
+         :db/valueType   :db.type/string
+         :db/unique      :db.unique/identity
+         :db/index       true
+         :db/cardinality :db.cardinality/one}
+        {:db/ident       :person/age
+         :db/valueType   :db.type/long
+         :db/cardinality :db.cardinality/one}
+        {:db/ident       :person/friend
+         :db/valueType   :db.type/ref
+         :db/cardinality :db.cardinality/many}
+        {:db/ident       :person/address
+         :db/valueType   :db.type/ref
+         :db/isComponent true
+         :db/cardinality :db.cardinality/one}
+        {:db/ident       :address/city
+         :db/valueType   :db.type/string
+         :db/cardinality :db.cardinality/one}
+    ]"#,
+        )
+        .expect("transacted schema");
+
+    let report = store
+        .transact(
+            r#"[
+        {:db/id "alice" :person/name "Alice" :person/age 30 :person/friend "bob"
+         :person/address {:address/city "Seattle"}}
+        {:db/id "bob" :person/name "Bob" :person/friend "carol"}
+        {:db/id "carol" :person/name "Carol" :person/friend "alice"}
+        {:db/id "dave" :person/name "Dave" :person/friend ["alice" "bob"]}
+    ]"#,
+        )
+        .expect("transacted data");
+    let alice = *report.tempids.get("alice").expect("alice");
+    let bob = *report.tempids.get("bob").expect("bob");
+    let carol = *report.tempids.get("carol").expect("carol");
+    let dave = *report.tempids.get("dave").expect("dave");
+
+    let named = |name: &str| -> Binding {
+        let m: StructuredMap = vec![(kw!(:person/name), TypedValue::from(name))].into();
+        m.into()
+    };
+    let map = |pairs: Vec<(Keyword, Binding)>| -> Binding {
+        let m: StructuredMap = pairs.into();
+        m.into()
+    };
+
+    // A nested pattern replaces each reference with a map.
+    let expected = map(vec![
+        (kw!(:person/name), "Alice".into()),
+        (kw!(:person/friend), vec![named("Bob")].into()),
+    ]);
+    assert_eq!(
+        pull_person(
+            &store,
+            alice,
+            "{:person/friend [:person/name]} :person/name"
+        ),
+        expected
+    );
+
+    // Reverse attributes find the entities that refer to this one.
+    let expected = map(vec![(
+        Keyword::namespaced("person", "_friend"),
+        vec![Binding::Scalar(TypedValue::Ref(bob))].into(),
+    )]);
+    assert_eq!(pull_person(&store, carol, ":person/_friend"), expected);
+
+    let expected = map(vec![(kw!(:person/friendedBy), vec![named("Bob")].into())]);
+    assert_eq!(
+        pull_person(
+            &store,
+            carol,
+            "{:person/_friend :as :person/friendedBy [:person/name]}"
+        ),
+        expected
+    );
+
+    // Recursion stops when it gets back to where it started…
+    let db_id = |e: Entid| -> Binding {
+        let m: StructuredMap = vec![(kw!(:db/id), TypedValue::Ref(e))].into();
+        m.into()
+    };
+    let carol_pulled = map(vec![
+        (kw!(:person/name), "Carol".into()),
+        (kw!(:person/friend), vec![db_id(alice)].into()),
+    ]);
+    let bob_pulled = map(vec![
+        (kw!(:person/name), "Bob".into()),
+        (kw!(:person/friend), vec![carol_pulled].into()),
+    ]);
+    let expected = map(vec![
+        (kw!(:person/name), "Alice".into()),
+        (kw!(:person/friend), vec![bob_pulled].into()),
+    ]);
+    assert_eq!(
+        pull_person(&store, alice, ":person/name {:person/friend ...}"),
+        expected
+    );
+
+    // … or when it's gone deep enough.
+    let expected = map(vec![
+        (kw!(:person/name), "Alice".into()),
+        (kw!(:person/friend), vec![named("Bob")].into()),
+    ]);
+    assert_eq!(
+        pull_person(&store, alice, ":person/name {:person/friend 1}"),
+        expected
+    );
+
+    // Limits and defaults.
+    match pull_person(&store, dave, "[:person/friend :limit 1]") {
+        Binding::Map(m) => match m.get(&ValueRc::new(kw!(:person/friend))) {
+            Some(Binding::Vec(friends)) => {
+                assert_eq!(friends.len(), 1);
+                assert!(
+                    friends[0] == TypedValue::Ref(alice).into()
+                        || friends[0] == TypedValue::Ref(bob).into()
+                );
+            }
+            friends => panic!("expected one friend, got {:?}", friends),
+        },
+        pulled => panic!("expected a map, got {:?}", pulled),
+    }
+
+    let expected = map(vec![
+        (kw!(:person/name), "Bob".into()),
+        (kw!(:person/age), TypedValue::Long(0).into()),
+    ]);
+    assert_eq!(
+        pull_person(&store, bob, ":person/name [:person/age :default 0]"),
+        expected
+    );
+    let expected = map(vec![(kw!(:person/age), TypedValue::Long(30).into())]);
+    assert_eq!(
+        pull_person(&store, alice, "[:person/age :default 0]"),
+        expected
+    );
+
+    // Components are pulled whole, even without a nested pattern.
+    let address = map(vec![(kw!(:address/city), "Seattle".into())]);
+    let expected = map(vec![
+        (kw!(:person/name), "Alice".into()),
+        (kw!(:person/address), address.clone()),
+    ]);
+    assert_eq!(
+        pull_person(&store, alice, ":person/name :person/address"),
+        expected
+    );
+
+    let schema = store.conn().current_schema();
+    let address_attr = schema.get_entid(&kw!(:person/address)).expect("address").0;
+    let pulled = store
+        .begin_read()
+        .expect("read")
+        .pull_attributes_for_entity(alice, vec![address_attr])
+        .expect("pulled");
+    let expected: StructuredMap = vec![(kw!(:person/address), address)].into();
+    assert_eq!(pulled, expected);
+
+    // Only refs can be reversed.
+    let query = r#"[:find (pull ?p [:person/_name]) . :where [?p :person/name "Alice"]]"#;
+    match store.q_once(query, None).expect_err("expected failure") {
+        MentatError::ProjectorError(ProjectorError::PullError(
+            PullError::NonRefReverseAttribute(name),
+        )) => {
+            assert_eq!(name, ":person/_name");
+        }
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
 // TEST:
 // - Constant query bodies in pull.
 // - Values that are present in the cache (=> constant pull, too).
@@ -25,8 +25,8 @@ use mentat_core::{DateTime, HasSchema, Utc, Uuid};
 use query_projector_traits::aggregates::SimpleAggregationOp;
 
 use mentat::{
-    new_connection, Binding, IntoResult, Keyword, PlainSymbol, QueryInputs, QueryResults,
-    Queryable, RelResult, Store, TxReport, TypedValue, Variable,
+    new_connection, Binding, DatabaseView, IntoResult, Keyword, PlainSymbol, QueryInputs,
+    QueryResults, QuerySource, Queryable, RelResult, SrcVar, Store, TxReport, TypedValue, Variable,
 };
 
 use mentat::query::q_uncached;
@@ -55,7 +55,7 @@ fn test_rel() {
     let end = time::Instant::now();
 
     // This will need to change each time we add a default ident.
-    assert_eq!(40, results.len());
+    assert_eq!(50, results.len());
 
     // Every row is a pair of a Ref and a Keyword.
     if let QueryResults::Rel(rel) = results {
@@ -186,7 +186,7 @@ fn test_coll() {
     .results;
     let end = time::Instant::now();
 
-    assert_eq!(40, results.len());
+    assert_eq!(50, results.len());
 
     if let QueryResults::Coll(ref coll) = results {
         assert!(coll.iter().all(|item| item.matches_type(ValueType::Ref)));
@@ -470,7 +470,7 @@ fn test_fulltext() {
                 ) => {
                     assert_eq!(x, v);
                     assert_eq!(text.as_str(), "hello darkness my old friend");
-                    assert_approx_eq!(score, 0.0f64.into());
+                    assert!(score.into_inner() > 0.0);
                 }
                 _ => panic!("Unexpected results."),
             }
@@ -555,6 +555,249 @@ fn test_fulltext() {
     }
 }
 
+#[test]
+fn test_fulltext_ranking() {
+    let mut c = new_connection("").expect("Couldn't open conn.");
+    let mut conn = Conn::connect(&mut c).expect("Couldn't open DB.");
+
+    conn.transact(
+        &mut c,
+        r#"[
+        {:db/ident :foo/title :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true}
+        {:db/ident :foo/body :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true}
+        {:db/ident :foo/tag :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+    ]"#,
+    )
+    .unwrap();
+
+    let ids = conn
+        .transact(
+            &mut c,
+            r#"[
+        {:db/id "a" :foo/title "owls" :foo/body "owls and more owls, all about owls"}
+        {:db/id "b" :foo/title "birds" :foo/body "a long and thorough survey of the garden birds of northern Europe, with one owl and many finches, sparrows and others"}
+        {:db/id "c" :foo/title "cats" :foo/body "nothing to see here"}
+        {:db/id "d" :foo/title "dogs" :foo/body "nothing to see here either"}
+        {:db/id "e" :foo/title "fish" :foo/tag "owls"}
+    ]"#,
+        )
+        .unwrap()
+        .tempids;
+    let a = *ids.get("a").unwrap();
+    let b = *ids.get("b").unwrap();
+
+    // The more relevant match comes first.
+    let r = conn
+        .q_once(
+            &c,
+            r#"[:find ?e ?score
+                :order (desc ?score)
+                :where [(fulltext $ :foo/body "owl OR owls") [[?e _ _ ?score]]]]"#,
+            None,
+        )
+        .expect("results")
+        .into_rel()
+        .expect("a relation");
+    let rows: Vec<Vec<Binding>> = r.into_iter().collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0][0], Binding::Scalar(TypedValue::Ref(a)));
+    assert_eq!(rows[1][0], Binding::Scalar(TypedValue::Ref(b)));
+    match (&rows[0][1], &rows[1][1]) {
+        (
+            Binding::Scalar(TypedValue::Double(first)),
+            Binding::Scalar(TypedValue::Double(second)),
+        ) => assert!(first > second && *second > 0.0.into()),
+        _ => panic!("Expected double scores."),
+    }
+
+    // Snippets and highlights mark the matching terms.
+    let r = conn
+        .q_once(
+            &c,
+            r#"[:find [?snippet ?highlight]
+                :where [(fulltext $ :foo/body "survey") [[_ _ _ _ ?snippet ?highlight]]]]"#,
+            None,
+        )
+        .expect("results")
+        .into_tuple()
+        .expect("a tuple");
+    assert_eq!(
+        r,
+        Some(vec![
+            "a long and thorough <b>survey</b> of the garden birds of northern Europe, with one owl..."
+                .into(),
+            "a long and thorough <b>survey</b> of the garden birds of northern Europe, with one owl and many finches, sparrows and others".into(),
+        ])
+    );
+
+    // Several attributes at once, binding the attribute that matched.  Non-fulltext attributes,
+    // like :foo/tag, are never searched.
+    let r = conn
+        .q_once(
+            &c,
+            r#"[:find ?e ?a
+                :order ?e ?a
+                :where [(fulltext $ ?a "owls") [[?e]]]]"#,
+            None,
+        )
+        .expect("results")
+        .into_rel()
+        .expect("a relation");
+    let title = conn
+        .current_schema()
+        .get_entid(&Keyword::namespaced("foo", "title"))
+        .unwrap()
+        .0;
+    let body = conn
+        .current_schema()
+        .get_entid(&Keyword::namespaced("foo", "body"))
+        .unwrap()
+        .0;
+    let rows: Vec<Vec<Binding>> = r.into_iter().collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec![TypedValue::Ref(a).into(), TypedValue::Ref(title).into()],
+            vec![TypedValue::Ref(a).into(), TypedValue::Ref(body).into()],
+        ]
+    );
+
+    let r = conn
+        .q_once(
+            &c,
+            r#"[:find [?e ...]
+                :order ?e
+                :where [(fulltext $ [:foo/title :foo/body] "birds OR dogs") [[?e]]]]"#,
+            None,
+        )
+        .expect("results")
+        .into_coll()
+        .expect("a collection");
+    assert_eq!(
+        r,
+        vec![
+            TypedValue::Ref(b).into(),
+            TypedValue::Ref(*ids.get("d").unwrap()).into(),
+        ]
+    );
+
+    let r = conn
+        .q_once(
+            &c,
+            r#"[:find (count ?e) .
+                :where [(fulltext $ _ "nothing") [[?e]]]]"#,
+            None,
+        )
+        .expect("results")
+        .into_scalar()
+        .expect("a scalar");
+    assert_eq!(r, Some(TypedValue::Long(2).into()));
+}
+
+#[test]
+fn test_fulltext_tokenizers() {
+    let mut c = new_connection("").expect("Couldn't open conn.");
+    let mut conn = Conn::connect(&mut c).expect("Couldn't open DB.");
+
+    conn.transact(
+        &mut c,
+        r#"[
+        {:db/ident :foo/plain :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true}
+        {:db/ident :foo/unaccented :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true
+         :db/fulltextTokenizer :db.fulltextTokenizer/unaccented}
+        {:db/ident :foo/stemmed :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true
+         :db/fulltextTokenizer :db.fulltextTokenizer/porter}
+    ]"#,
+    )
+    .unwrap();
+
+    let ids = conn
+        .transact(
+            &mut c,
+            r#"[
+        {:db/id "a" :foo/plain "Café crème" :foo/unaccented "Café crème" :foo/stemmed "the dogs were running"}
+    ]"#,
+        )
+        .unwrap()
+        .tempids;
+    let a = *ids.get("a").unwrap();
+
+    let search = |conn: &mut Conn, c: &rusqlite::Connection, attribute: &str, text: &str| {
+        let query = format!(
+            r#"[:find [?e ...] :where [(fulltext $ {} "{}") [[?e]]]]"#,
+            attribute, text
+        );
+        conn.q_once(c, query.as_str(), None)
+            .expect("results")
+            .into_coll()
+            .expect("a collection")
+    };
+
+    // The default tokenizer preserves diacritics.
+    assert_eq!(search(&mut conn, &c, ":foo/plain", "cafe"), vec![]);
+    assert_eq!(
+        search(&mut conn, &c, ":foo/plain", "café"),
+        vec![Binding::Scalar(TypedValue::Ref(a))]
+    );
+    assert_eq!(
+        search(&mut conn, &c, ":foo/unaccented", "cafe"),
+        vec![Binding::Scalar(TypedValue::Ref(a))]
+    );
+    assert_eq!(
+        search(&mut conn, &c, ":foo/stemmed", "dog run"),
+        vec![Binding::Scalar(TypedValue::Ref(a))]
+    );
+    assert_eq!(search(&mut conn, &c, ":foo/plain", "run"), vec![]);
+
+    // Attributes that don't share a tokenizer can't be searched together.
+    for attributes in &["[:foo/plain :foo/stemmed]", "_"] {
+        let query = format!(
+            r#"[:find [?e ...] :where [(fulltext $ {} "run") [[?e]]]]"#,
+            attributes
+        );
+        match conn
+            .q_once(&c, query.as_str(), None)
+            .expect_err("mixed tokenizers")
+        {
+            MentatError::AlgebrizerError(
+                query_algebrizer_traits::errors::AlgebrizerError::MixedFulltextTokenizers(_),
+            ) => {}
+            e => panic!("Unexpected error type {:?}", e),
+        }
+    }
+
+    // Altering the tokenizer takes effect for existing values.
+    conn.transact(
+        &mut c,
+        r#"[[:db/add :foo/plain :db/fulltextTokenizer :db.fulltextTokenizer/unaccented]]"#,
+    )
+    .unwrap();
+    assert_eq!(
+        search(&mut conn, &c, ":foo/plain", "cafe"),
+        vec![Binding::Scalar(TypedValue::Ref(a))]
+    );
+
+    // The trigram tokenizer matches substrings, but needs a recent SQLite.
+    let trigram = conn.transact(
+        &mut c,
+        r#"[{:db/ident :foo/code :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true
+             :db/fulltextTokenizer :db.fulltextTokenizer/trigram}]"#,
+    );
+    if rusqlite::version_number() >= 3_034_000 {
+        trigram.expect("trigram tokenizer");
+        let ids = conn
+            .transact(&mut c, r#"[{:db/id "b" :foo/code "XJ-4417-QR"}]"#)
+            .unwrap()
+            .tempids;
+        assert_eq!(
+            search(&mut conn, &c, ":foo/code", "4417"),
+            vec![Binding::Scalar(TypedValue::Ref(*ids.get("b").unwrap()))]
+        );
+    } else {
+        trigram.expect_err("trigram tokenizer requires SQLite 3.34.0");
+    }
+}
+
 #[test]
 fn test_instant_range_query() {
     let mut c = new_connection("").expect("Couldn't open conn.");
@@ -800,8 +1043,8 @@ fn test_aggregates_type_handling() {
         .expect("results")
         .unwrap();
 
-    // Yes, the current version is in the store as a Long!
-    let total = 30i64 + 20i64 + 10i64 + ::mentat_db::db::CURRENT_VERSION as i64;
+    // Yes, the core schema version is in the store as a Long!
+    let total = 30i64 + 20i64 + 10i64 + ::mentat_db::CORE_SCHEMA_VERSION as i64;
     assert_eq!(Binding::Scalar(TypedValue::Long(total)), r);
 
     let r = store
@@ -1587,75 +1830,435 @@ fn test_null_aggregates() {
 }
 
 #[test]
-fn test_aggregation_implicit_grouping() {
+fn test_static_aggregates() {
+    let store = Store::open("").expect("opened");
+
+    // Nothing can match an unknown attribute, so these are computed without running any SQL.
+    let q = |find: &str| format!("[:find {} :where [?x :foo/unknown ?y]]", find);
+
+    let scalar = store
+        .q_once(q("(count ?x) .").as_str(), None)
+        .into_scalar_result()
+        .expect("results");
+    assert_eq!(scalar, Some(Binding::Scalar(TypedValue::Long(0))));
+
+    let coll = store
+        .q_once(q("[(sum ?y) ...]").as_str(), None)
+        .into_coll_result()
+        .expect("results");
+    assert_eq!(coll, vec![Binding::Scalar(TypedValue::Long(0))]);
+
+    let tuple = store
+        .q_once(q("[(count ?x) (sum ?y)]").as_str(), None)
+        .into_tuple_result()
+        .expect("results");
+    assert_eq!(
+        tuple,
+        Some(vec![
+            Binding::Scalar(TypedValue::Long(0)),
+            Binding::Scalar(TypedValue::Long(0)),
+        ])
+    );
+
+    let rel = store
+        .q_once(q("(count ?x) (sum ?y)").as_str(), None)
+        .into_rel_result()
+        .expect("results");
+    assert_eq!(
+        rel,
+        vec![vec![TypedValue::Long(0), TypedValue::Long(0)]].into()
+    );
+
+    // A nil aggregate rejects the whole row, just as it does when we run the query.
+    let tuple = store
+        .q_once(q("[(count ?x) (max ?y)]").as_str(), None)
+        .into_tuple_result()
+        .expect("results");
+    assert_eq!(tuple, None);
+
+    let scalar = store
+        .q_once(q("(min ?y) .").as_str(), None)
+        .into_scalar_result()
+        .expect("results");
+    assert_eq!(scalar, None);
+
+    // Grouping by a variable means there are no groups at all.
+    let rel = store
+        .q_once(q("?x (count ?y)").as_str(), None)
+        .into_rel_result()
+        .expect("results");
+    assert_eq!(0, rel.row_count());
+
+    // When every aggregated variable is bound to a single value, there's exactly one row.
+    let inputs = || {
+        QueryInputs::with_value_sequence(vec![
+            (var!(?x), TypedValue::Long(5)),
+            (var!(?y), TypedValue::typed_string("foo")),
+        ])
+    };
+    let bound = |find: &str| format!("[:find {} :in ?x ?y :where [(ground 1) ?z]]", find);
+
+    let scalar = store
+        .q_once(bound("(avg ?x) .").as_str(), inputs())
+        .into_scalar_result()
+        .expect("results");
+    assert_eq!(
+        scalar,
+        Some(Binding::Scalar(TypedValue::Double(5.0.into())))
+    );
+
+    let coll = store
+        .q_once(bound("[(count ?y) ...]").as_str(), inputs())
+        .into_coll_result()
+        .expect("results");
+    assert_eq!(coll, vec![Binding::Scalar(TypedValue::Long(1))]);
+
+    let tuple = store
+        .q_once(bound("[(sum ?x) (max ?y)]").as_str(), inputs())
+        .into_tuple_result()
+        .expect("results");
+    assert_eq!(
+        tuple,
+        Some(vec![
+            Binding::Scalar(TypedValue::Long(5)),
+            Binding::Scalar(TypedValue::typed_string("foo")),
+        ])
+    );
+
+    let rel = store
+        .q_once(bound("?y (min ?x)").as_str(), inputs())
+        .into_rel_result()
+        .expect("results");
+    assert_eq!(
+        rel,
+        vec![vec![TypedValue::typed_string("foo"), TypedValue::Long(5)]].into()
+    );
+
+    // Strings can't be summed, whether or not we run the query.
+    match store
+        .q_once(bound("(sum ?y) .").as_str(), inputs())
+        .expect_err("expected query to fail")
+    {
+        MentatError::ProjectorError(
+            ::query_projector_traits::errors::ProjectorError::CannotApplyAggregateOperationToTypes(
+                ..,
+            ),
+        ) => {}
+        e => panic!("Unexpected error type {:?}", e),
+    }
+}
+
+#[test]
+fn test_complex_aggregates() {
     let mut store = Store::open("").expect("opened");
 
+    store.transact(r#"[
+        {:db/ident :foo/is-vegetarian :db/valueType :db.type/boolean :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age           :db/valueType :db.type/long    :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/name          :db/valueType :db.type/string  :db/cardinality :db.cardinality/one}
+    ]"#).unwrap();
+
     store
         .transact(
             r#"[
-        [:db/add "a" :db/ident :foo/score]
-        [:db/add "a" :db/valueType :db.type/long]
-        [:db/add "a" :db/cardinality :db.cardinality/one]
-        [:db/add "b" :db/ident :foo/name]
-        [:db/add "b" :db/valueType :db.type/string]
-        [:db/add "b" :db/cardinality :db.cardinality/one]
-        [:db/add "c" :db/ident :foo/is-vegetarian]
-        [:db/add "c" :db/valueType :db.type/boolean]
-        [:db/add "c" :db/cardinality :db.cardinality/one]
-        [:db/add "d" :db/ident :foo/play]
-        [:db/add "d" :db/valueType :db.type/ref]
-        [:db/add "d" :db/cardinality :db.cardinality/many]
-        [:db/add "d" :db/index true]
-        [:db/add "d" :db/unique :db.unique/value]
+        {:foo/name "Alice" :foo/is-vegetarian true :foo/age 14}
+        {:foo/name "Beli" :foo/is-vegetarian true :foo/age 22}
+        {:foo/name "Carlos" :foo/is-vegetarian false :foo/age 42}
+        {:foo/name "Diana" :foo/is-vegetarian false :foo/age 28}
+        {:foo/name "Erin" :foo/is-vegetarian true :foo/age 22}
     ]"#,
         )
         .unwrap();
 
-    let ids = store
-        .transact(
-            r#"[
-        [:db/add "a" :foo/name "Alice"]
-        [:db/add "b" :foo/name "Beli"]
-        [:db/add "c" :foo/name "Carlos"]
-        [:db/add "d" :foo/name "Diana"]
-        [:db/add "a" :foo/is-vegetarian true]
-        [:db/add "b" :foo/is-vegetarian true]
-        [:db/add "c" :foo/is-vegetarian false]
-        [:db/add "d" :foo/is-vegetarian false]
-        [:db/add "aa" :foo/score 14]
-        [:db/add "ab" :foo/score 99]
-        [:db/add "ac" :foo/score 14]
-        [:db/add "ba" :foo/score 22]
-        [:db/add "bb" :foo/score 11]
-        [:db/add "ca" :foo/score 42]
-        [:db/add "da" :foo/score 5]
-        [:db/add "db" :foo/score 28]
-        [:db/add "d"  :foo/play "da"]
-        [:db/add "d"  :foo/play "db"]
-        [:db/add "a"  :foo/play "aa"]
-        [:db/add "a"  :foo/play "ab"]
-        [:db/add "a"  :foo/play "ac"]
-        [:db/add "b"  :foo/play "ba"]
-        [:db/add "b"  :foo/play "bb"]
-        [:db/add "c"  :foo/play "ca"]
-    ]"#,
+    let longs = |values: Vec<i64>| {
+        Binding::Vec(
+            values
+                .into_iter()
+                .map(|v| Binding::Scalar(TypedValue::Long(v)))
+                .collect::<Vec<_>>()
+                .into(),
         )
-        .unwrap()
-        .tempids;
+    };
+    let double = |binding: Binding| binding.into_scalar().and_then(|v| v.into_double());
 
-    // How many different scores were there?
+    // `count-distinct` is computed by SQLite.
+    let r = store
+        .q_once(
+            r#"[:find [(count ?age) (count-distinct ?age)]
+                :with ?p
+                :where [?p :foo/age ?age]]"#,
+            None,
+        )
+        .into_tuple_result()
+        .expect("results");
     assert_eq!(
-        Binding::Scalar(TypedValue::Long(7)),
-        store
-            .q_once(
-                r#"[:find (count ?score) .
-                                :where
-                                [?game :foo/score ?score]]"#,
-                None
-            )
-            .into_scalar_result()
-            .expect("scalar results")
-            .unwrap()
+        r,
+        Some(vec![
+            Binding::Scalar(TypedValue::Long(5)),
+            Binding::Scalar(TypedValue::Long(4)),
+        ])
+    );
+
+    let r = store
+        .q_once(
+            r#"[:find ?veg (variance ?age)
+                :with ?p
+                :order ?veg
+                :where [?p :foo/is-vegetarian ?veg] [?p :foo/age ?age]]"#,
+            None,
+        )
+        .into_rel_result()
+        .expect("results");
+    let rows: Vec<Vec<Binding>> = r.into_iter().collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0][0], Binding::Scalar(TypedValue::Boolean(false)));
+    assert_approx_eq!(double(rows[0][1].clone()).expect("double"), 49.0);
+    assert_approx_eq!(double(rows[1][1].clone()).expect("double"), 128.0 / 9.0);
+
+    // The variance of large values that are close together doesn't vanish into rounding error.
+    store
+        .transact(
+            r#"[
+        {:db/ident :foo/ms :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+    ]"#,
+        )
+        .unwrap();
+    store
+        .transact(r#"[{:foo/ms 1000000001} {:foo/ms 1000000002} {:foo/ms 1000000003}]"#)
+        .unwrap();
+    let r = store
+        .q_once(
+            r#"[:find (variance ?ms) . :with ?p :where [?p :foo/ms ?ms]]"#,
+            None,
+        )
+        .into_scalar_result()
+        .expect("results");
+    assert_approx_eq!(double(r.expect("variance")).expect("double"), 2.0 / 3.0);
+
+    // The rest are computed as we project rows.
+    let r = store
+        .q_once(
+            r#"[:find (median ?age) . :with ?p :where [?p :foo/age ?age]]"#,
+            None,
+        )
+        .into_scalar_result()
+        .expect("results");
+    assert_eq!(r, Some(Binding::Scalar(TypedValue::Double(22.0.into()))));
+
+    let r = store
+        .q_once(
+            r#"[:find (stddev ?age) . :with ?p :where [?p :foo/age ?age]]"#,
+            None,
+        )
+        .into_scalar_result()
+        .expect("results");
+    assert_approx_eq!(double(r.expect("stddev")).expect("double"), 87.04f64.sqrt());
+
+    // Aggregates computed by SQLite and those we compute ourselves can be mixed.
+    let r = store
+        .q_once(
+            r#"[:find ?veg (count ?p) (median ?age) (distinct ?age)
+                :order ?veg
+                :where [?p :foo/is-vegetarian ?veg] [?p :foo/age ?age]]"#,
+            None,
+        )
+        .into_rel_result()
+        .expect("results");
+    assert_eq!(
+        r.into_iter().collect::<Vec<_>>(),
+        vec![
+            vec![
+                Binding::Scalar(TypedValue::Boolean(false)),
+                Binding::Scalar(TypedValue::Long(2)),
+                Binding::Scalar(TypedValue::Double(35.0.into())),
+                longs(vec![28, 42]),
+            ],
+            vec![
+                Binding::Scalar(TypedValue::Boolean(true)),
+                Binding::Scalar(TypedValue::Long(3)),
+                Binding::Scalar(TypedValue::Double(22.0.into())),
+                longs(vec![14, 22]),
+            ],
+        ]
+    );
+
+    let r = store
+        .q_once(
+            r#"[:find [(max 2 ?age) (min 2 ?age)] :with ?p :where [?p :foo/age ?age]]"#,
+            None,
+        )
+        .into_tuple_result()
+        .expect("results");
+    assert_eq!(r, Some(vec![longs(vec![42, 28]), longs(vec![14, 22])]));
+
+    let r = store
+        .q_once(
+            r#"[:find [(max 3 ?age) ...] :where [?p :foo/age ?age] [(< ?age 20)]]"#,
+            None,
+        )
+        .into_coll_result()
+        .expect("results");
+    assert_eq!(r, vec![longs(vec![14])]);
+
+    let r = store
+        .q_once(
+            r#"[:find (sample 2 ?name) . :where [_ :foo/name ?name]]"#,
+            None,
+        )
+        .into_scalar_result()
+        .expect("results");
+    match r {
+        Some(Binding::Vec(names)) => {
+            assert_eq!(names.len(), 2);
+            assert_ne!(names[0], names[1]);
+        }
+        r => panic!("Expected a sample, got {:?}", r),
+    }
+
+    // The limit applies to groups, not to the rows they're made from.
+    let r = store
+        .q_once(
+            r#"[:find ?age (distinct ?name)
+                :order ?age
+                :limit 2
+                :where [?p :foo/age ?age] [?p :foo/name ?name]]"#,
+            None,
+        )
+        .into_rel_result()
+        .expect("results");
+    assert_eq!(
+        r.into_iter().collect::<Vec<_>>(),
+        vec![
+            vec![
+                Binding::Scalar(TypedValue::Long(14)),
+                Binding::Vec(vec![Binding::Scalar(TypedValue::typed_string("Alice"))].into()),
+            ],
+            vec![
+                Binding::Scalar(TypedValue::Long(22)),
+                Binding::Vec(
+                    vec![
+                        Binding::Scalar(TypedValue::typed_string("Beli")),
+                        Binding::Scalar(TypedValue::typed_string("Erin")),
+                    ]
+                    .into()
+                ),
+            ],
+        ]
+    );
+
+    // Over no rows at all, collections are empty and the rest are nil.
+    let r = store
+        .q_once(
+            r#"[:find [(distinct ?age) (count-distinct ?age)]
+                :where [?p :foo/age ?age] [(> ?age 100)]]"#,
+            None,
+        )
+        .into_tuple_result()
+        .expect("results");
+    assert_eq!(
+        r,
+        Some(vec![longs(vec![]), Binding::Scalar(TypedValue::Long(0))])
+    );
+
+    let r = store
+        .q_once(
+            r#"[:find [(distinct ?age) (median ?age)]
+                :where [?p :foo/age ?age] [(> ?age 100)]]"#,
+            None,
+        )
+        .into_tuple_result()
+        .expect("results");
+    assert_eq!(r, None);
+
+    match store
+        .q_once(
+            r#"[:find (median ?name) . :where [_ :foo/name ?name]]"#,
+            None,
+        )
+        .expect_err("expected query to fail")
+    {
+        MentatError::ProjectorError(
+            ::query_projector_traits::errors::ProjectorError::CannotApplyAggregateOperationToTypes(
+                SimpleAggregationOp::Median,
+                types,
+            ),
+        ) => {
+            assert_eq!(types, ValueTypeSet::of_one(ValueType::String));
+        }
+        e => panic!("Unexpected error type {:?}", e),
+    }
+}
+
+#[test]
+fn test_aggregation_implicit_grouping() {
+    let mut store = Store::open("").expect("opened");
+
+    store
+        .transact(
+            r#"[
+        [:db/add "a" :db/ident :foo/score]
+        [:db/add "a" :db/valueType :db.type/long]
+        [:db/add "a" :db/cardinality :db.cardinality/one]
+        [:db/add "b" :db/ident :foo/name]
+        [:db/add "b" :db/valueType :db.type/string]
+        [:db/add "b" :db/cardinality :db.cardinality/one]
+        [:db/add "c" :db/ident :foo/is-vegetarian]
+        [:db/add "c" :db/valueType :db.type/boolean]
+        [:db/add "c" :db/cardinality :db.cardinality/one]
+        [:db/add "d" :db/ident :foo/play]
+        [:db/add "d" :db/valueType :db.type/ref]
+        [:db/add "d" :db/cardinality :db.cardinality/many]
+        [:db/add "d" :db/index true]
+        [:db/add "d" :db/unique :db.unique/value]
+    ]"#,
+        )
+        .unwrap();
+
+    let ids = store
+        .transact(
+            r#"[
+        [:db/add "a" :foo/name "Alice"]
+        [:db/add "b" :foo/name "Beli"]
+        [:db/add "c" :foo/name "Carlos"]
+        [:db/add "d" :foo/name "Diana"]
+        [:db/add "a" :foo/is-vegetarian true]
+        [:db/add "b" :foo/is-vegetarian true]
+        [:db/add "c" :foo/is-vegetarian false]
+        [:db/add "d" :foo/is-vegetarian false]
+        [:db/add "aa" :foo/score 14]
+        [:db/add "ab" :foo/score 99]
+        [:db/add "ac" :foo/score 14]
+        [:db/add "ba" :foo/score 22]
+        [:db/add "bb" :foo/score 11]
+        [:db/add "ca" :foo/score 42]
+        [:db/add "da" :foo/score 5]
+        [:db/add "db" :foo/score 28]
+        [:db/add "d"  :foo/play "da"]
+        [:db/add "d"  :foo/play "db"]
+        [:db/add "a"  :foo/play "aa"]
+        [:db/add "a"  :foo/play "ab"]
+        [:db/add "a"  :foo/play "ac"]
+        [:db/add "b"  :foo/play "ba"]
+        [:db/add "b"  :foo/play "bb"]
+        [:db/add "c"  :foo/play "ca"]
+    ]"#,
+        )
+        .unwrap()
+        .tempids;
+
+    // How many different scores were there?
+    assert_eq!(
+        Binding::Scalar(TypedValue::Long(7)),
+        store
+            .q_once(
+                r#"[:find (count ?score) .
+                                :where
+                                [?game :foo/score ?score]]"#,
+                None
+            )
+            .into_scalar_result()
+            .expect("scalar results")
+            .unwrap()
     );
 
     // How many different games resulted in scores?
@@ -1834,23 +2437,27 @@ fn test_tx_ids() {
         .tx_id;
 
     fn assert_tx_id_range(store: &Store, after: Entid, before: Entid, expected: Vec<TypedValue>) {
-        // TODO: after https://github.com/mozilla/mentat/issues/641, use q_prepare with inputs bound
-        // at execution time.
-        let r = store
-            .q_once(
+        let mut prepared = store
+            .q_prepare(
                 r#"[:find [?tx ...]
                                  :in ?after ?before
                                  :where
                                  [(tx-ids $ ?after ?before) [?tx ...]]
                                 ]"#,
-                QueryInputs::with_value_sequence(vec![
-                    (Variable::from_valid_name("?after"), TypedValue::Ref(after)),
-                    (
-                        Variable::from_valid_name("?before"),
-                        TypedValue::Ref(before),
-                    ),
+                QueryInputs::with_type_sequence(vec![
+                    (Variable::from_valid_name("?after"), ValueType::Ref),
+                    (Variable::from_valid_name("?before"), ValueType::Ref),
                 ]),
             )
+            .expect("prepared");
+        let r = prepared
+            .run(QueryInputs::with_value_sequence(vec![
+                (Variable::from_valid_name("?after"), TypedValue::Ref(after)),
+                (
+                    Variable::from_valid_name("?before"),
+                    TypedValue::Ref(before),
+                ),
+            ]))
             .expect("results")
             .into();
         match r {
@@ -1907,10 +2514,8 @@ fn run_tx_data_test(mut store: Store) {
         .expect("tx2 to apply");
 
     fn assert_tx_data(store: &Store, tx: &TxReport, value: TypedValue) {
-        // TODO: after https://github.com/mozilla/mentat/issues/641, use q_prepare with inputs bound
-        // at execution time.
-        let r = store
-            .q_once(
+        let mut prepared = store
+            .q_prepare(
                 r#"[:find ?e ?a-name ?v ?tx ?added
                                  :in ?tx-in
                                  :where
@@ -1918,11 +2523,17 @@ fn run_tx_data_test(mut store: Store) {
                                  [?a :db/ident ?a-name]
                                  :order ?e
                                 ]"#,
-                QueryInputs::with_value_sequence(vec![(
+                QueryInputs::with_type_sequence(vec![(
                     Variable::from_valid_name("?tx-in"),
-                    TypedValue::Ref(tx.tx_id),
+                    ValueType::Ref,
                 )]),
             )
+            .expect("prepared");
+        let r = prepared
+            .run(QueryInputs::with_value_sequence(vec![(
+                Variable::from_valid_name("?tx-in"),
+                TypedValue::Ref(tx.tx_id),
+            )]))
             .expect("results")
             .into();
 
@@ -1971,3 +2582,780 @@ fn test_encrypted() {
     // so the specific test we use doesn't matter that much.
     run_tx_data_test(Store::open_with_key("", "secret").expect("opened"));
 }
+
+#[test]
+fn test_rules() {
+    let mut store = Store::open("").expect("opened");
+    store
+        .transact(
+            r#"[
+        {:db/ident :person/name    :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :person/parent  :db/valueType :db.type/ref    :db/cardinality :db.cardinality/one}
+        {:db/ident :person/follows :db/valueType :db.type/ref    :db/cardinality :db.cardinality/many}
+    ]"#,
+        )
+        .expect("transacted schema");
+    store
+        .transact(
+            r#"[
+        {:db/id "a" :person/name "a"}
+        {:db/id "b" :person/name "b" :person/parent "a"}
+        {:db/id "c" :person/name "c" :person/parent "b"}
+        {:db/id "d" :person/name "d" :person/parent "c"}
+        {:db/id "e" :person/name "e" :person/follows "f"}
+        {:db/id "f" :person/name "f" :person/follows "e"}
+    ]"#,
+        )
+        .expect("transacted data");
+
+    let rules = mentat::parse_rules_string(
+        r#"[[(named ?p ?n) [?p :person/name ?n]]
+            [(related ?x ?y) [?x :person/parent ?y]]
+            [(related ?x ?y) [?y :person/parent ?x]]
+            [(ancestor ?c ?a) [?c :person/parent ?a]]
+            [(ancestor ?c ?a) [?c :person/parent ?p] (ancestor ?p ?a)]
+            [(reachable ?x ?y) [?x :person/follows ?y]]
+            [(reachable ?x ?y) (reachable ?x ?z) [?z :person/follows ?y]]]"#,
+    )
+    .expect("parsed rules");
+
+    let names = |query: &str, inputs: QueryInputs| -> Vec<String> {
+        let mut names: Vec<String> = store
+            .q_once(query, inputs)
+            .expect("query succeeded")
+            .into_coll()
+            .expect("coll")
+            .into_iter()
+            .map(|b| match b {
+                Binding::Scalar(TypedValue::String(s)) => (*s).clone(),
+                b => panic!("unexpected binding {:?}", b),
+            })
+            .collect();
+        names.sort();
+        names
+    };
+
+    // A rule with a single definition.
+    assert_eq!(
+        names(
+            r#"[:find [?n ...] :in % :where (named ?p ?n) [?p :person/parent _]]"#,
+            QueryInputs::default().with_rules(rules.clone())
+        ),
+        vec!["b", "c", "d"]
+    );
+
+    // A rule with several definitions, called with a constant argument.
+    assert_eq!(
+        names(
+            r#"[:find [?n ...] :in % :where [?b :person/name "b"] (related ?b ?x) (named ?x ?n)]"#,
+            QueryInputs::default().with_rules(rules.clone())
+        ),
+        vec!["a", "c"]
+    );
+
+    // A recursive rule.
+    assert_eq!(
+        names(
+            r#"[:find [?n ...] :in % ?start :where [?d :person/name ?start] (ancestor ?d ?a) [?a :person/name ?n]]"#,
+            QueryInputs::with_value_sequence(vec![(
+                Variable::from_valid_name("?start"),
+                TypedValue::typed_string("d")
+            )])
+            .with_rules(rules.clone())
+        ),
+        vec!["a", "b", "c"]
+    );
+
+    // A recursive rule, traversed in the other direction.
+    assert_eq!(
+        names(
+            r#"[:find [?n ...] :in % :where [?a :person/name "a"] (ancestor ?x ?a) [?x :person/name ?n]]"#,
+            QueryInputs::default().with_rules(rules.clone())
+        ),
+        vec!["b", "c", "d"]
+    );
+
+    // Recursion terminates in the presence of cycles.
+    assert_eq!(
+        names(
+            r#"[:find [?n ...] :in % :where [?e :person/name "e"] (reachable ?e ?x) [?x :person/name ?n]]"#,
+            QueryInputs::default().with_rules(rules.clone())
+        ),
+        vec!["e", "f"]
+    );
+
+    // Rules are only available to queries that ask for them.
+    match store
+        .q_once(
+            r#"[:find ?n :where (named _ ?n)]"#,
+            QueryInputs::default().with_rules(rules.clone()),
+        )
+        .expect_err("expected failure")
+    {
+        MentatError::AlgebrizerError(
+            query_algebrizer_traits::errors::AlgebrizerError::UnknownRule(name),
+        ) => assert_eq!(name, PlainSymbol::plain("named")),
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    match store
+        .q_once(
+            r#"[:find ?n :in % :where (named ?n)]"#,
+            QueryInputs::default().with_rules(rules),
+        )
+        .expect_err("expected failure")
+    {
+        MentatError::AlgebrizerError(
+            query_algebrizer_traits::errors::AlgebrizerError::InvalidNumberOfArguments(name, 1, 2),
+        ) => assert_eq!(name, PlainSymbol::plain("named")),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn test_database_views() {
+    let mut store = Store::open("").expect("opened");
+    store
+        .transact(
+            r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#,
+        )
+        .expect("transacted schema");
+
+    let report1 = store
+        .transact(r#"[{:db/id "x" :db/ident :foo/x :foo/name "one"}]"#)
+        .expect("tx1");
+    let tx1 = report1.tx_id;
+    let tx2 = store
+        .transact(r#"[[:db/add :foo/x :foo/name "two"] {:foo/name "why"}]"#)
+        .expect("tx2")
+        .tx_id;
+    let tx3 = store
+        .transact(r#"[[:db/retract :foo/x :foo/name "two"]]"#)
+        .expect("tx3")
+        .tx_id;
+
+    let names = |query: &str, inputs: QueryInputs| -> Vec<String> {
+        let mut names: Vec<String> = store
+            .q_once(query, inputs)
+            .expect("query succeeded")
+            .into_coll()
+            .expect("coll")
+            .into_iter()
+            .map(|b| match b {
+                Binding::Scalar(TypedValue::String(s)) => (*s).clone(),
+                b => panic!("unexpected binding {:?}", b),
+            })
+            .collect();
+        names.sort();
+        names
+    };
+
+    let query = r#"[:find [?n ...] :where [:foo/x :foo/name ?n]]"#;
+    assert!(names(query, QueryInputs::default()).is_empty());
+    assert_eq!(
+        names(
+            query,
+            QueryInputs::default().with_source(SrcVar::DefaultSrc, DatabaseView::AsOf(tx1))
+        ),
+        vec!["one"]
+    );
+    assert_eq!(
+        names(
+            query,
+            QueryInputs::default().with_source(SrcVar::DefaultSrc, DatabaseView::AsOf(tx2))
+        ),
+        vec!["two"]
+    );
+    assert!(names(
+        query,
+        QueryInputs::default().with_source(SrcVar::DefaultSrc, DatabaseView::AsOf(tx3))
+    )
+    .is_empty());
+
+    // A named source alongside the current database.
+    let query = r#"[:find [?n ...] :in $ $before :where [$before :foo/x :foo/name ?n]]"#;
+    assert_eq!(
+        names(
+            query,
+            QueryInputs::default().with_source(
+                SrcVar::NamedSrc("before".to_string()),
+                DatabaseView::AsOf(tx1)
+            )
+        ),
+        vec!["one"]
+    );
+
+    // Since only sees current datoms, and "two" has been retracted.
+    let query = r#"[:find [?n ...] :in $since :where [$since _ :foo/name ?n]]"#;
+    assert_eq!(
+        names(
+            query,
+            QueryInputs::default().with_source(
+                SrcVar::NamedSrc("since".to_string()),
+                DatabaseView::Since(tx1)
+            )
+        ),
+        vec!["why"]
+    );
+
+    // History includes retractions.
+    let results = store
+        .q_once(
+            r#"[:find ?n ?tx ?added
+                :in $h
+                :where [$h :foo/x :foo/name ?n ?tx ?added]
+                :order ?tx ?added]"#,
+            QueryInputs::default()
+                .with_source(SrcVar::NamedSrc("h".to_string()), DatabaseView::History),
+        )
+        .expect("query succeeded")
+        .into_rel()
+        .expect("rel")
+        .into_iter()
+        .map(|row| {
+            (
+                (*row[0].clone().into_string().expect("string")).clone(),
+                row[1].clone().into_entid().expect("tx"),
+                row[2].clone().into_boolean().expect("boolean"),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        vec![
+            ("one".to_string(), tx1, true),
+            ("one".to_string(), tx2, false),
+            ("two".to_string(), tx2, true),
+            ("two".to_string(), tx3, false),
+        ]
+    );
+
+    // Unbound named sources are rejected.
+    match store
+        .q_once(
+            r#"[:find ?n :in $h :where [$h _ :foo/name ?n]]"#,
+            QueryInputs::default(),
+        )
+        .expect_err("expected failure")
+    {
+        MentatError::AlgebrizerError(
+            query_algebrizer_traits::errors::AlgebrizerError::UnboundSource(source),
+        ) => assert_eq!(source, SrcVar::NamedSrc("h".to_string())),
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    assert_eq!(
+        store
+            .tx_for_instant(report1.tx_instant)
+            .expect("resolved instant"),
+        Some(tx1)
+    );
+}
+
+#[test]
+fn test_relation_sources() {
+    let mut store = Store::open("").expect("opened");
+    store
+        .transact(
+            r#"[
+        {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :person/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#,
+        )
+        .expect("transacted schema");
+    store
+        .transact(
+            r#"[
+        {:person/name "Alice" :person/age 30}
+        {:person/name "Bob"   :person/age 40}
+        {:person/name "Carol" :person/age 50}
+    ]"#,
+        )
+        .expect("transacted data");
+
+    let emails = SrcVar::NamedSrc("emails".to_string());
+    let with_emails = |inputs: QueryInputs| {
+        inputs
+            .with_relation(
+                emails.clone(),
+                vec![
+                    vec![
+                        TypedValue::typed_string("alice@example.com"),
+                        TypedValue::typed_string("Alice"),
+                    ],
+                    vec![
+                        TypedValue::typed_string("bob@example.com"),
+                        TypedValue::typed_string("Bob"),
+                    ],
+                    vec![
+                        TypedValue::typed_string("dave@example.com"),
+                        TypedValue::typed_string("Dave"),
+                    ],
+                ],
+            )
+            .expect("valid relation")
+    };
+
+    let rows = |query: &str, inputs: QueryInputs| -> Vec<Vec<TypedValue>> {
+        let mut rows: Vec<Vec<TypedValue>> = store
+            .q_once(query, with_emails(inputs))
+            .expect("query succeeded")
+            .into_rel()
+            .expect("rel")
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|b| b.into_scalar().expect("scalar"))
+                    .collect()
+            })
+            .collect();
+        rows.sort();
+        rows
+    };
+
+    // Join the relation against the store.
+    assert_eq!(
+        rows(
+            r#"[:find ?email ?age
+                :in $ $emails
+                :where [$emails ?email ?name]
+                       [?p :person/name ?name]
+                       [?p :person/age ?age]]"#,
+            QueryInputs::default()
+        ),
+        vec![
+            vec![
+                TypedValue::typed_string("alice@example.com"),
+                TypedValue::Long(30)
+            ],
+            vec![
+                TypedValue::typed_string("bob@example.com"),
+                TypedValue::Long(40)
+            ],
+        ]
+    );
+
+    // A bound input picks out a single tuple.
+    assert_eq!(
+        rows(
+            r#"[:find ?age ?email
+                :in $ $emails ?name
+                :where [$emails ?email ?name]
+                       [?p :person/name ?name]
+                       [?p :person/age ?age]]"#,
+            QueryInputs::with_value_sequence(vec![(
+                Variable::from_valid_name("?name"),
+                TypedValue::typed_string("Bob"),
+            )])
+        ),
+        vec![vec![
+            TypedValue::Long(40),
+            TypedValue::typed_string("bob@example.com")
+        ]]
+    );
+
+    // Relations can appear in `not` and `or`.
+    assert_eq!(
+        rows(
+            r#"[:find ?name ?age
+                :in $ $emails
+                :where [?p :person/name ?name]
+                       [?p :person/age ?age]
+                       (not [$emails _ ?name])]"#,
+            QueryInputs::default()
+        ),
+        vec![vec![
+            TypedValue::typed_string("Carol"),
+            TypedValue::Long(50)
+        ]]
+    );
+    assert_eq!(
+        rows(
+            r#"[:find ?name ?age
+                :in $ $emails
+                :where [?p :person/name ?name]
+                       [?p :person/age ?age]
+                       (or-join [?name]
+                           [$emails _ ?name]
+                           (and [?q :person/name ?name]
+                                [?q :person/age 30]))]"#,
+            QueryInputs::default()
+        ),
+        vec![
+            vec![TypedValue::typed_string("Alice"), TypedValue::Long(30)],
+            vec![TypedValue::typed_string("Bob"), TypedValue::Long(40)],
+        ]
+    );
+}
+
+#[test]
+fn test_attached_store_source() {
+    let path = ::std::env::temp_dir().join(format!("mentat-attached-{}.db", ::std::process::id()));
+    let path = path.to_str().expect("path").to_string();
+    let _ = ::std::fs::remove_file(&path);
+
+    let schema = r#"[{:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#;
+    {
+        let mut other = Store::open(&path).expect("opened other");
+        other.transact(schema).expect("transacted schema");
+        other
+            .transact(r#"[{:person/name "Alice"} {:person/name "Bob"}]"#)
+            .expect("transacted other data");
+    }
+
+    let mut store = Store::open("").expect("opened");
+    store.transact(schema).expect("transacted schema");
+    store
+        .transact(r#"[{:person/name "Bob"} {:person/name "Carol"}]"#)
+        .expect("transacted data");
+    store.attach_store("other", &path).expect("attached");
+
+    let names = |store: &Store, query: &str| -> Vec<String> {
+        let inputs = QueryInputs::default().with_source(
+            SrcVar::NamedSrc("other".to_string()),
+            QuerySource::Attached("other".to_string()),
+        );
+        let mut names: Vec<String> = store
+            .q_once(query, inputs)
+            .expect("query succeeded")
+            .into_coll()
+            .expect("coll")
+            .into_iter()
+            .map(|b| match b {
+                Binding::Scalar(TypedValue::String(s)) => (*s).clone(),
+                b => panic!("unexpected binding {:?}", b),
+            })
+            .collect();
+        names.sort();
+        names
+    };
+
+    assert_eq!(
+        names(
+            &store,
+            r#"[:find [?n ...] :in $ $other :where [$other _ :person/name ?n]]"#
+        ),
+        vec!["Alice", "Bob"]
+    );
+
+    // Names present in both stores.
+    assert_eq!(
+        names(
+            &store,
+            r#"[:find [?n ...]
+                :in $ $other
+                :where [$other _ :person/name ?n]
+                       [_ :person/name ?n]]"#
+        ),
+        vec!["Bob"]
+    );
+
+    match store.attach_store("main", &path) {
+        Err(MentatError::InvalidArgumentName(name)) => assert_eq!(name, "main"),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    store.detach_store("other").expect("detached");
+    let _ = ::std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_attached_store_schema_mismatch() {
+    let path = ::std::env::temp_dir().join(format!(
+        "mentat-attached-mismatch-{}.db",
+        ::std::process::id()
+    ));
+    let path = path.to_str().expect("path").to_string();
+    let _ = ::std::fs::remove_file(&path);
+
+    let name = r#"{:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}"#;
+    let age = r#"{:db/ident :person/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}"#;
+
+    // The other store installs the same attributes in the opposite order, so their entids differ.
+    {
+        let mut other = Store::open(&path).expect("opened other");
+        other
+            .transact(&format!("[{}]", age))
+            .expect("transacted age");
+        other
+            .transact(&format!("[{}]", name))
+            .expect("transacted name");
+        other
+            .transact(r#"[{:person/name "Alice" :person/age 30}]"#)
+            .expect("transacted other data");
+    }
+
+    let mut store = Store::open("").expect("opened");
+    store
+        .transact(&format!("[{}]", name))
+        .expect("transacted name");
+    store
+        .transact(&format!("[{}]", age))
+        .expect("transacted age");
+
+    match store.attach_store("other", &path) {
+        Err(MentatError::AttachedStoreSchemaMismatch(store_name, _)) => {
+            assert_eq!(store_name, "other")
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // The mismatched store isn't left attached.
+    let inputs = QueryInputs::default().with_source(
+        SrcVar::NamedSrc("other".to_string()),
+        QuerySource::Attached("other".to_string()),
+    );
+    assert!(store
+        .q_once(
+            r#"[:find [?n ...] :in $ $other :where [$other _ :person/name ?n]]"#,
+            inputs
+        )
+        .is_err());
+
+    let _ = ::std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_where_functions() {
+    let mut store = Store::open("").expect("opened");
+    store
+        .transact(
+            r#"[
+        {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :person/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#,
+        )
+        .expect("transacted schema");
+    store
+        .transact(
+            r#"[
+        {:person/name "Alice" :person/age 30}
+        {:person/name "Bob"   :person/age 40}
+        {:person/name "alfred" :person/age 0}
+    ]"#,
+        )
+        .expect("transacted data");
+
+    let rows = |query: &str| -> Vec<Vec<TypedValue>> {
+        let mut rows: Vec<Vec<TypedValue>> = store
+            .q_once(query, None)
+            .expect("query succeeded")
+            .into_rel()
+            .expect("rel")
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|b| b.into_scalar().expect("scalar"))
+                    .collect()
+            })
+            .collect();
+        rows.sort();
+        rows
+    };
+
+    assert_eq!(
+        rows(
+            r#"[:find ?n ?next ?half
+                :where [?p :person/name ?n]
+                       [?p :person/age ?age]
+                       [(+ ?age 1) ?next]
+                       [(/ ?age 2) ?half]]"#
+        ),
+        vec![
+            vec![
+                TypedValue::typed_string("Alice"),
+                TypedValue::Long(31),
+                TypedValue::Double(15.0.into())
+            ],
+            vec![
+                TypedValue::typed_string("Bob"),
+                TypedValue::Long(41),
+                TypedValue::Double(20.0.into())
+            ],
+            vec![
+                TypedValue::typed_string("alfred"),
+                TypedValue::Long(1),
+                TypedValue::Double(0.0.into())
+            ],
+        ]
+    );
+
+    // Dividing by zero binds nothing.
+    assert_eq!(
+        rows(
+            r#"[:find ?n ?x
+                :where [?p :person/name ?n]
+                       [?p :person/age ?age]
+                       [(/ 60 ?age) ?x]]"#
+        ),
+        vec![
+            vec![
+                TypedValue::typed_string("Alice"),
+                TypedValue::Double(2.0.into())
+            ],
+            vec![
+                TypedValue::typed_string("Bob"),
+                TypedValue::Double(1.5.into())
+            ],
+        ]
+    );
+
+    assert_eq!(
+        rows(
+            r#"[:find ?s ?p
+                :where [?e :person/name ?n]
+                       [?e :person/age ?age]
+                       [(upper-case ?n) ?u]
+                       [(str ?u " " ?age) ?s]
+                       [(subs ?n 1 3) ?p]]"#
+        ),
+        vec![
+            vec![
+                TypedValue::typed_string("ALFRED 0"),
+                TypedValue::typed_string("lf")
+            ],
+            vec![
+                TypedValue::typed_string("ALICE 30"),
+                TypedValue::typed_string("li")
+            ],
+            vec![
+                TypedValue::typed_string("BOB 40"),
+                TypedValue::typed_string("ob")
+            ],
+        ]
+    );
+
+    let names = |predicate: &str| -> Vec<Vec<TypedValue>> {
+        rows(&format!(
+            "[:find ?n :where [_ :person/name ?n] {}]",
+            predicate
+        ))
+    };
+    let named = |names: &[&str]| -> Vec<Vec<TypedValue>> {
+        names
+            .iter()
+            .map(|n| vec![TypedValue::typed_string(n)])
+            .collect()
+    };
+    assert_eq!(names(r#"[(starts-with? ?n "Al")]"#), named(&["Alice"]));
+    assert_eq!(names(r#"[(ends-with? ?n "ce")]"#), named(&["Alice"]));
+    assert_eq!(
+        names(r#"[(ends-with? ?n "")]"#),
+        named(&["Alice", "Bob", "alfred"])
+    );
+    assert_eq!(
+        names(r#"[(includes? ?n "l")]"#),
+        named(&["Alice", "alfred"])
+    );
+    assert_eq!(
+        names(r#"[(re-find "^[Aa]l" ?n)]"#),
+        named(&["Alice", "alfred"])
+    );
+    assert_eq!(
+        names(r#"[(lower-case ?n) ?l] [(starts-with? ?l "al")]"#),
+        named(&["Alice", "alfred"])
+    );
+}
+
+#[test]
+fn test_output_formats() {
+    let mut store = Store::open("").expect("opened");
+    store
+        .transact(
+            r#"[
+        {:db/ident :person/name :db/valueType :db.type/string  :db/cardinality :db.cardinality/one}
+        {:db/ident :person/role :db/valueType :db.type/keyword :db/cardinality :db.cardinality/one}
+        {:db/ident :person/born :db/valueType :db.type/instant :db/cardinality :db.cardinality/one}
+        {:db/ident :person/id   :db/valueType :db.type/uuid    :db/cardinality :db.cardinality/one}
+        {:db/ident :person/rank :db/valueType :db.type/double  :db/cardinality :db.cardinality/one}
+    ]"#,
+        )
+        .expect("transacted schema");
+    store
+        .transact(
+            r#"[
+        {:person/name "Alice, \"Al\""
+         :person/role :role/admin
+         :person/born #inst "2018-01-01T12:34:56.789Z"
+         :person/id #uuid "550e8400-e29b-41d4-a716-446655440000"
+         :person/rank 1.5}
+    ]"#,
+        )
+        .expect("transacted data");
+
+    let output = store
+        .q_once(
+            r#"[:find ?name ?role ?born ?id ?rank
+                :where [?p :person/name ?name]
+                       [?p :person/role ?role]
+                       [?p :person/born ?born]
+                       [?p :person/id ?id]
+                       [?p :person/rank ?rank]]"#,
+            None,
+        )
+        .expect("query succeeded");
+
+    assert_eq!(
+        output.to_json().to_string(),
+        r#"[["Alice, \"Al\"",":role/admin","2018-01-01T12:34:56.789Z","550e8400-e29b-41d4-a716-446655440000",1.5]]"#
+    );
+    assert_eq!(
+        output.to_csv(),
+        "?name,?role,?born,?id,?rank\n\
+         \"Alice, \"\"Al\"\"\",:role/admin,2018-01-01T12:34:56.789Z,550e8400-e29b-41d4-a716-446655440000,1.5\n"
+    );
+    assert_eq!(
+        output.to_edn(),
+        edn::parse::value(
+            r#"[["Alice, \"Al\"" :role/admin #inst "2018-01-01T12:34:56.789Z"
+                 #uuid "550e8400-e29b-41d4-a716-446655440000" 1.5]]"#
+        )
+        .expect("parsed")
+        .without_spans()
+    );
+
+    // Empty scalars are null and nil, and CSV has only the header.
+    let output = store
+        .q_once(
+            r#"[:find ?name . :where [_ :person/name ?name] [(> 1 2)]]"#,
+            None,
+        )
+        .expect("query succeeded");
+    assert_eq!(output.to_json().to_string(), "null");
+    assert_eq!(output.to_edn(), edn::Value::Nil);
+    assert_eq!(output.to_csv(), "?name\n");
+
+    // Pulled entities are objects keyed by attribute, and JSON in CSV cells.
+    let output = store
+        .q_once(
+            r#"[:find [(pull ?p [:person/name :person/rank]) ...]
+                :where [?p :person/role :role/admin]]"#,
+            None,
+        )
+        .expect("query succeeded");
+    assert_eq!(
+        output.to_json().to_string(),
+        r#"[{":person/name":"Alice, \"Al\"",":person/rank":1.5}]"#
+    );
+    assert_eq!(
+        output.to_csv(),
+        "(pull ?p [ :person/name :person/rank ])\n\
+         \"{\"\":person/name\"\":\"\"Alice, \\\"\"Al\\\"\"\"\",\"\":person/rank\"\":1.5}\"\n"
+    );
+
+    // Non-finite doubles are null in JSON, and empty cells in CSV.
+    store
+        .transact(r#"[{:person/name "Bob" :person/rank #f +Infinity}]"#)
+        .expect("transacted data");
+    let output = store
+        .q_once(
+            r#"[:find ?name ?rank
+                :where [?p :person/name "Bob"]
+                       [?p :person/name ?name]
+                       [?p :person/rank ?rank]]"#,
+            None,
+        )
+        .expect("query succeeded");
+    assert_eq!(output.to_json().to_string(), r#"[["Bob",null]]"#);
+    assert_eq!(output.to_csv(), "?name,?rank\nBob,\n");
+}
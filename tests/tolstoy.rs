@@ -17,20 +17,34 @@ mod tolstoy_tests {
     use std::collections::HashMap;
 
     use std::collections::hash_map::Entry;
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     use uuid::Uuid;
 
-    use mentat::{conn::Conn, new_connection};
+    use mentat::{
+        conn::Conn,
+        new_connection,
+        query::{QueryInputs, Variable},
+        HasSchema, Keyword, Queryable, Store,
+    };
 
     use mentat_db::{assert_matches, TX0};
 
+    use mentat_sync_server::SyncServer;
+
     use mentat_tolstoy::{
-        debug::parts_to_datoms, GlobalTransactionLog, SyncFollowup, SyncReport, Syncer, Tx, TxPart,
+        debug::parts_to_datoms, Conflict, ConflictPolicy, GlobalTransactionLog, LastWriterWins,
+        RemoteClient, RemoteWins, Resolution, SyncFollowup, SyncMetadata, SyncReport, Syncer, Tx,
+        TxPart,
     };
 
     use mentat_tolstoy::debug::txs_after;
 
-    use core_traits::{Entid, TypedValue, ValueType};
+    use core_traits::{attribute, Attribute, Entid, TypedValue, ValueType};
     use mentat_tolstoy::tx_processor::{Processor, TxReceiver};
     use public_traits::errors::{MentatError, Result};
     use tolstoy_traits::errors::TolstoyError;
@@ -105,6 +119,8 @@ mod tolstoy_tests {
         // Keep transactions in order:
         pub tx_rowid: HashMap<Uuid, usize>,
         pub rowid_tx: Vec<Uuid>,
+        // Timelines that lost a merge, with their parents:
+        pub timelines: HashMap<Uuid, (Uuid, Vec<Vec<TxPart>>)>,
     }
 
     impl TestRemoteClient {
@@ -115,6 +131,7 @@ mod tolstoy_tests {
                 transactions: HashMap::default(),
                 tx_rowid: HashMap::default(),
                 rowid_tx: vec![],
+                timelines: HashMap::default(),
             }
         }
     }
@@ -172,6 +189,25 @@ mod tolstoy_tests {
             self.tx_rowid.insert(*tx, self.rowid_tx.len() - 1);
             Ok(())
         }
+
+        fn put_timeline(
+            &mut self,
+            timeline: &Uuid,
+            parent_tx: &Uuid,
+            txs: &[Vec<Uuid>],
+        ) -> Result<()> {
+            let txs = txs
+                .iter()
+                .map(|chunk_txs| {
+                    chunk_txs
+                        .iter()
+                        .map(|chunk_tx| self.chunks.get(chunk_tx).unwrap().clone())
+                        .collect()
+                })
+                .collect();
+            self.timelines.insert(*timeline, (*parent_tx, txs));
+            Ok(())
+        }
     }
 
     macro_rules! assert_sync {
@@ -227,6 +263,15 @@ mod tolstoy_tests {
         };
     }
 
+    fn person_attribute(conn: &Conn, name: &str) -> Attribute {
+        let schema = conn.current_schema();
+        schema
+            .attribute_for_ident(&Keyword::namespaced("person", name))
+            .expect("attribute")
+            .0
+            .clone()
+    }
+
     #[test]
     fn test_reader() {
         let mut c = new_connection("").expect("Couldn't open conn.");
@@ -393,6 +438,16 @@ mod tolstoy_tests {
             [:db.schema/version :db/ident :db.schema/version ?tx true]
             [:db.schema/attribute :db/ident :db.schema/attribute ?tx true]
             [:db.schema/core :db/ident :db.schema/core ?tx true]
+            [:db.partition/start :db/ident :db.partition/start ?tx true]
+            [:db.partition/end :db/ident :db.partition/end ?tx true]
+            [:db.partition/allowExcision :db/ident :db.partition/allowExcision ?tx true]
+            [:db/fulltextTokenizer :db/ident :db/fulltextTokenizer ?tx true]
+            [:db.fulltextTokenizer/unicode61 :db/ident :db.fulltextTokenizer/unicode61 ?tx true]
+            [:db.fulltextTokenizer/unaccented :db/ident :db.fulltextTokenizer/unaccented ?tx true]
+            [:db.fulltextTokenizer/porter :db/ident :db.fulltextTokenizer/porter ?tx true]
+            [:db.fulltextTokenizer/trigram :db/ident :db.fulltextTokenizer/trigram ?tx true]
+            [:db.excise/excisedAttrs :db/ident :db.excise/excisedAttrs ?tx true]
+            [:db.excise/excisedCount :db/ident :db.excise/excisedCount ?tx true]
             [?tx :db/txInstant ?ms ?tx true]
             [:db/ident :db/valueType 24 ?tx true]
             [:db/txInstant :db/valueType 31 ?tx true]
@@ -406,10 +461,20 @@ mod tolstoy_tests {
             [:db/index :db/valueType 30 ?tx true]
             [:db/fulltext :db/valueType 30 ?tx true]
             [:db/noHistory :db/valueType 30 ?tx true]
+            [:db/excise :db/valueType 23 ?tx true]
+            [:db.excise/attrs :db/valueType 23 ?tx true]
+            [:db.excise/beforeT :db/valueType 23 ?tx true]
+            [:db.excise/before :db/valueType 31 ?tx true]
             [:db.alter/attribute :db/valueType 23 ?tx true]
             [:db/doc :db/valueType 27 ?tx true]
             [:db.schema/version :db/valueType 25 ?tx true]
             [:db.schema/attribute :db/valueType 23 ?tx true]
+            [:db.partition/start :db/valueType 25 ?tx true]
+            [:db.partition/end :db/valueType 25 ?tx true]
+            [:db.partition/allowExcision :db/valueType 30 ?tx true]
+            [:db/fulltextTokenizer :db/valueType 23 ?tx true]
+            [:db.excise/excisedAttrs :db/valueType 23 ?tx true]
+            [:db.excise/excisedCount :db/valueType 25 ?tx true]
             [:db/ident :db/cardinality 33 ?tx true]
             [:db/txInstant :db/cardinality 33 ?tx true]
             [:db.install/partition :db/cardinality 34 ?tx true]
@@ -422,10 +487,20 @@ mod tolstoy_tests {
             [:db/index :db/cardinality 33 ?tx true]
             [:db/fulltext :db/cardinality 33 ?tx true]
             [:db/noHistory :db/cardinality 33 ?tx true]
+            [:db/excise :db/cardinality 33 ?tx true]
+            [:db.excise/attrs :db/cardinality 34 ?tx true]
+            [:db.excise/beforeT :db/cardinality 33 ?tx true]
+            [:db.excise/before :db/cardinality 33 ?tx true]
             [:db.alter/attribute :db/cardinality 34 ?tx true]
             [:db/doc :db/cardinality 33 ?tx true]
             [:db.schema/version :db/cardinality 33 ?tx true]
             [:db.schema/attribute :db/cardinality 34 ?tx true]
+            [:db.partition/start :db/cardinality 33 ?tx true]
+            [:db.partition/end :db/cardinality 33 ?tx true]
+            [:db.partition/allowExcision :db/cardinality 33 ?tx true]
+            [:db/fulltextTokenizer :db/cardinality 33 ?tx true]
+            [:db.excise/excisedAttrs :db/cardinality 34 ?tx true]
+            [:db.excise/excisedCount :db/cardinality 33 ?tx true]
             [:db/ident :db/unique 36 ?tx true]
             [:db.schema/attribute :db/unique 35 ?tx true]
             [:db/ident :db/index true ?tx true]
@@ -890,19 +965,16 @@ mod tolstoy_tests {
             remote_client
         );
 
-        // We currently have a primitive conflict resolution strategy,
-        // ending up with a new "Vanya" entity.
+        // Renaming an entity that was retracted on the other side names it again.
         assert_transactions!(
             sqlite_2,
             conn_2,
             // These hard-coded entids are brittle but deterministic.
-            // They signify that we end up with a new entity Vanya, separate from the one
-            // that was renamed.
             r#"[[65537 :person/name "Ivan" ?tx true]
             [?tx :db/txInstant ?ms ?tx true]]"#,
             r#"[[65537 :person/name "Ivan" ?tx false]
             [?tx :db/txInstant ?ms ?tx true]]"#,
-            r#"[[65538 :person/name "Vanya" ?tx true]
+            r#"[[65537 :person/name "Vanya" ?tx true]
             [?tx :db/txInstant ?ms ?tx true]]"#
         );
     }
@@ -1110,8 +1182,7 @@ mod tolstoy_tests {
         );
 
         // These hard-coded entids are brittle but deterministic.
-        // They signify that we end up with a new entity Vanechka, separate from the one
-        // that was renamed.
+        // Both renamed the same entity. By default, local wins the conflict.
         assert_transactions!(
             sqlite_1,
             conn_1,
@@ -1120,9 +1191,11 @@ mod tolstoy_tests {
             r#"[[65537 :person/name "Ivan" ?tx false]
             [65537 :person/name "Vanya" ?tx true]
             [?tx :db/txInstant ?ms ?tx true]]"#,
-            // A new entity is created for the second rename.
-            r#"[[65538 :person/name "Vanechka" ?tx true]
-            [?tx :db/txInstant ?ms ?tx true]]"#
+            r#"[[65537 :person/name "Vanechka" ?tx true]
+            [65537 :person/name "Vanya" ?tx false]
+            [?tx :db/txInstant ?ms ?tx true]]"#,
+            // The merge transaction.
+            r#"[[?tx :db/txInstant ?ms ?tx true]]"#
         );
     }
 
@@ -1163,7 +1236,7 @@ mod tolstoy_tests {
             remote_client
         );
         assert_sync!(
-            error => MentatError::TolstoyError(TolstoyError::NotYetImplemented(_)),
+            error => MentatError::TolstoyError(TolstoyError::SchemaAlterationConflict(_, _)),
             conn_2, sqlite_2, remote_client);
     }
 
@@ -1358,12 +1431,8 @@ mod tolstoy_tests {
             remote_client
         );
 
-        // We currently have a primitive conflict resolution strategy,
-        // ending up with a new "Vanya" entity.
-
         // These hard-coded entids are brittle but deterministic.
-        // They signify that we end up with a new entity Vanya, separate from the one
-        // that was renamed.
+        // Renaming an entity that was retracted on the other side names it again.
         assert_transactions!(
             sqlite_2,
             conn_2,
@@ -1373,8 +1442,8 @@ mod tolstoy_tests {
             [?tx :db/txInstant ?ms ?tx true]]"#,
             r#"[[65537 :person/name "Ivan" ?tx false]
             [?tx :db/txInstant ?ms ?tx true]]"#,
-            r#"[[65538 :person/name "Ivan" ?tx false]
-            [65538 :person/name "Vanya" ?tx true]
+            // The second Ivan, 65538, is left alone.
+            r#"[[65537 :person/name "Vanya" ?tx true]
             [?tx :db/txInstant ?ms ?tx true]]"#
         );
     }
@@ -1878,8 +1947,1041 @@ mod tolstoy_tests {
 
         // Merge bootstrap+schema transactions from 1 into 2.
         assert_sync!(
-            error => MentatError::TolstoyError(TolstoyError::NotYetImplemented(_)),
+            error => MentatError::TolstoyError(TolstoyError::SchemaAlterationConflict(_, _)),
+            conn_2, sqlite_2, remote_client
+        );
+    }
+
+    // Both 1 and 2 start out with this schema, and with remote in sync with both.
+    fn connect_with_synced_schema(
+        remote_client: &mut TestRemoteClient,
+    ) -> (rusqlite::Connection, Conn, rusqlite::Connection, Conn) {
+        let mut sqlite_1 = new_connection("").unwrap();
+        let mut sqlite_2 = new_connection("").unwrap();
+
+        let mut conn_1 = Conn::connect(&mut sqlite_1).unwrap();
+        let mut conn_2 = Conn::connect(&mut sqlite_2).unwrap();
+
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                "[
+            {:db/ident :person/name
+              :db/valueType :db.type/string
+              :db/cardinality :db.cardinality/one}
+            {:db/ident :person/email
+              :db/valueType :db.type/string
+              :db/cardinality :db.cardinality/one
+              :db/index true
+              :db/unique :db.unique/value}]",
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            *remote_client
+        );
+        assert_sync!(
+            SyncReport::Merge(SyncFollowup::None),
+            conn_2,
+            sqlite_2,
+            *remote_client
+        );
+
+        (sqlite_1, conn_1, sqlite_2, conn_2)
+    }
+
+    #[test]
+    fn test_schema_alteration_fast_forward() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                "[[:db/add :person/name :db/cardinality :db.cardinality/many]
+            [:db/add :person/name :db/index true]
+            [:db/retract :person/email :db/unique :db.unique/value]]",
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        assert_sync!(
+            SyncReport::LocalFastForward,
+            conn_2,
+            sqlite_2,
+            remote_client
+        );
+
+        let name = person_attribute(&conn_2, "name");
+        assert!(name.multival);
+        assert!(name.index);
+        assert_eq!(person_attribute(&conn_2, "email").unique, None);
+        assert_eq!(name, person_attribute(&conn_1, "name"));
+    }
+
+    #[test]
+    fn test_merge_schema_alterations() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+
+        // 1 and 2 alter different properties of the same attribute.
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                "[[:db/add :person/name :db/cardinality :db.cardinality/many]]",
+            )
+            .expect("transacted");
+
+        conn_2
+            .transact(&mut sqlite_2, "[[:db/add :person/name :db/index true]]")
+            .expect("transacted");
+
+        // 2 also alters another attribute, in a separate transaction.
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                "[[:db/retract :person/email :db/unique :db.unique/value]]",
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        assert_sync!(
+            SyncReport::Merge(SyncFollowup::FullSync),
+            conn_2,
+            sqlite_2,
+            remote_client
+        );
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_2,
+            sqlite_2,
+            remote_client
+        );
+        assert_sync!(
+            SyncReport::LocalFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+
+        // Both sides end up with both alterations.
+        for conn in &[&conn_1, &conn_2] {
+            let name = person_attribute(conn, "name");
+            assert!(name.multival);
+            assert!(name.index);
+            assert_eq!(person_attribute(conn, "email").unique, None);
+        }
+    }
+
+    #[test]
+    fn test_merge_same_schema_alteration() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+
+        // 1 and 2 make the same alteration.
+        for (sqlite, conn) in [(&mut sqlite_1, &mut conn_1), (&mut sqlite_2, &mut conn_2)] {
+            conn.transact(
+                sqlite,
+                "[[:db/add :person/email :db/unique :db.unique/identity]]",
+            )
+            .expect("transacted");
+        }
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+
+        // 2's alteration is a no-op on top of 1's, so there's nothing to upload.
+        assert_sync!(
+            SyncReport::Merge(SyncFollowup::None),
+            conn_2,
+            sqlite_2,
+            remote_client
+        );
+
+        assert_eq!(
+            person_attribute(&conn_2, "email").unique,
+            Some(attribute::Unique::Identity)
+        );
+        assert_sync!(SyncReport::NoChanges, conn_1, sqlite_1, remote_client);
+    }
+
+    #[test]
+    fn test_merge_conflicting_schema_alterations() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                "[[:db/add :person/email :db/unique :db.unique/identity]]",
+            )
+            .expect("transacted");
+
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                "[[:db/retract :person/email :db/unique :db.unique/value]]",
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        let mut ip = conn_2
+            .begin_transaction(&mut sqlite_2)
+            .expect("begun successfully");
+        match Syncer::sync(&mut ip, &mut remote_client).expect_err("expected a conflict") {
+            MentatError::TolstoyError(TolstoyError::SchemaAlterationConflict(
+                attribute,
+                property,
+            )) => {
+                assert_eq!(attribute, ":person/email");
+                assert_eq!(property, ":db/unique");
+            }
+            e => panic!("Failed with wrong error: {:?}", e),
+        }
+        ip.rollback().expect("rolled back");
+
+        // The failed merge left 2 as it was.
+        assert_eq!(person_attribute(&conn_2, "email").unique, None);
+    }
+
+    #[test]
+    fn test_merge_schema_alteration_undone_locally() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                "[[:db/add :person/name :db/cardinality :db.cardinality/many]]",
+            )
+            .expect("transacted");
+
+        // 2 changes its mind, which would overwrite 1's alteration if replayed on top of it.
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                "[[:db/add :person/name :db/cardinality :db.cardinality/many]]",
+            )
+            .expect("transacted");
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                "[[:db/add :person/name :db/cardinality :db.cardinality/one]]",
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        assert_sync!(
+            error => MentatError::TolstoyError(TolstoyError::SchemaAlterationConflict(_, _)),
             conn_2, sqlite_2, remote_client
         );
     }
+
+    fn sync_with_policy(
+        sqlite: &mut rusqlite::Connection,
+        conn: &mut Conn,
+        remote_client: &mut TestRemoteClient,
+        policy: &ConflictPolicy,
+    ) -> SyncReport {
+        let mut ip = conn.begin_transaction(sqlite).expect("begun successfully");
+        let report = Syncer::sync_with_policy(&mut ip, remote_client, policy).expect("sync report");
+        ip.commit().expect("committed");
+        report
+    }
+
+    /// Have 1 create a person named `name`, and sync it to 2.
+    fn sync_new_person(
+        sqlite_1: &mut rusqlite::Connection,
+        conn_1: &mut Conn,
+        sqlite_2: &mut rusqlite::Connection,
+        conn_2: &mut Conn,
+        remote_client: &mut TestRemoteClient,
+        name: &str,
+    ) -> Entid {
+        let report = conn_1
+            .transact(
+                sqlite_1,
+                format!(r#"[{{:db/id "p" :person/name "{}"}}]"#, name),
+            )
+            .expect("transacted");
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            *conn_1,
+            *sqlite_1,
+            *remote_client
+        );
+        assert_sync!(
+            SyncReport::LocalFastForward,
+            *conn_2,
+            *sqlite_2,
+            *remote_client
+        );
+        report.tempids["p"]
+    }
+
+    fn person_value(
+        sqlite: &rusqlite::Connection,
+        conn: &Conn,
+        e: Entid,
+        attribute: &str,
+    ) -> Option<String> {
+        let query = format!("[:find ?v . :in ?e :where [?e :person/{} ?v]]", attribute);
+        let inputs = QueryInputs::with_value_sequence(vec![(
+            Variable::from_valid_name("?e"),
+            TypedValue::Ref(e),
+        )]);
+        conn.q_once(sqlite, &query, inputs)
+            .expect("queried")
+            .into_scalar()
+            .expect("scalar")
+            .and_then(|v| v.into_string())
+            .map(|v| v.to_string())
+    }
+
+    #[test]
+    fn test_merge_conflict_remote_wins() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+        let ivan = sync_new_person(
+            &mut sqlite_1,
+            &mut conn_1,
+            &mut sqlite_2,
+            &mut conn_2,
+            &mut remote_client,
+            "Ivan",
+        );
+        let root = remote_client.head;
+
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                format!(r#"[[:db/add {} :person/name "Vanya"]]"#, ivan),
+            )
+            .expect("transacted");
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                format!(r#"[[:db/add {} :person/name "Vanechka"]]"#, ivan),
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        let merged_head = remote_client.head;
+
+        // 2's rename loses, so there's nothing left to upload but the merge transaction.
+        assert_eq!(
+            SyncReport::Merge(SyncFollowup::FullSync),
+            sync_with_policy(
+                &mut sqlite_2,
+                &mut conn_2,
+                &mut remote_client,
+                &ConflictPolicy::new(RemoteWins)
+            )
+        );
+        assert_eq!(
+            Some("Vanya".to_string()),
+            person_value(&sqlite_2, &conn_2, ivan, "name")
+        );
+
+        // The merge is recorded, and 2's rename is kept on the remote.
+        let merges = SyncMetadata::merges(&sqlite_2.transaction().expect("tx")).expect("merges");
+        assert_eq!(1, merges.len());
+        assert_eq!(merged_head, merges[0].remote_head);
+        let (parent, txs) = &remote_client.timelines[&merges[0].timeline];
+        assert_eq!(root, *parent);
+        assert_eq!(1, txs.len());
+        assert!(txs[0]
+            .iter()
+            .any(|part| part.e == ivan && part.v == TypedValue::typed_string("Vanechka")));
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_2,
+            sqlite_2,
+            remote_client
+        );
+        assert_sync!(
+            SyncReport::LocalFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        assert_eq!(
+            Some("Vanya".to_string()),
+            person_value(&sqlite_1, &conn_1, ivan, "name")
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict_last_writer_wins() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+        let ivan = sync_new_person(
+            &mut sqlite_1,
+            &mut conn_1,
+            &mut sqlite_2,
+            &mut conn_2,
+            &mut remote_client,
+            "Ivan",
+        );
+        let petr = sync_new_person(
+            &mut sqlite_1,
+            &mut conn_1,
+            &mut sqlite_2,
+            &mut conn_2,
+            &mut remote_client,
+            "Petr",
+        );
+
+        // 1 renames Ivan last, and 2 renames Petr last.
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                format!(r#"[[:db/add {} :person/name "Vanechka"]]"#, ivan),
+            )
+            .expect("transacted");
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                format!(
+                    r#"[[:db/add {} :person/name "Vanya"] [:db/add {} :person/name "Petya"]]"#,
+                    ivan, petr
+                ),
+            )
+            .expect("transacted");
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                format!(r#"[[:db/add {} :person/name "Petrusha"]]"#, petr),
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        assert_eq!(
+            SyncReport::Merge(SyncFollowup::FullSync),
+            sync_with_policy(
+                &mut sqlite_2,
+                &mut conn_2,
+                &mut remote_client,
+                &ConflictPolicy::new(LastWriterWins)
+            )
+        );
+        assert_eq!(
+            Some("Vanya".to_string()),
+            person_value(&sqlite_2, &conn_2, ivan, "name")
+        );
+        assert_eq!(
+            Some("Petrusha".to_string()),
+            person_value(&sqlite_2, &conn_2, petr, "name")
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict_per_attribute() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+        let ivan = sync_new_person(
+            &mut sqlite_1,
+            &mut conn_1,
+            &mut sqlite_2,
+            &mut conn_2,
+            &mut remote_client,
+            "Ivan",
+        );
+
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                format!(
+                    r#"[[:db/add {} :person/name "Vanya"]
+                        [:db/add {} :person/email "vanya@example.com"]]"#,
+                    ivan, ivan
+                ),
+            )
+            .expect("transacted");
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                format!(
+                    r#"[[:db/add {} :person/name "Vanechka"]
+                        [:db/add {} :person/email "vanechka@example.com"]]"#,
+                    ivan, ivan
+                ),
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+
+        // Names are combined, and otherwise remote wins.
+        let policy = ConflictPolicy::new(RemoteWins).with_attribute(
+            Keyword::namespaced("person", "name"),
+            |conflict: &Conflict| match (&conflict.remote, &conflict.local) {
+                (TypedValue::String(remote), TypedValue::String(local)) => {
+                    Resolution::Value(format!("{} or {}", remote, local).into())
+                }
+                _ => Resolution::Remote,
+            },
+        );
+        assert_eq!(
+            SyncReport::Merge(SyncFollowup::FullSync),
+            sync_with_policy(&mut sqlite_2, &mut conn_2, &mut remote_client, &policy)
+        );
+        assert_eq!(
+            Some("Vanya or Vanechka".to_string()),
+            person_value(&sqlite_2, &conn_2, ivan, "name")
+        );
+        assert_eq!(
+            Some("vanya@example.com".to_string()),
+            person_value(&sqlite_2, &conn_2, ivan, "email")
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict_attribute_installed_since_root() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+        let ivan = sync_new_person(
+            &mut sqlite_1,
+            &mut conn_1,
+            &mut sqlite_2,
+            &mut conn_2,
+            &mut remote_client,
+            "Ivan",
+        );
+
+        // Both sides install :person/nick, but 2 installs another attribute first, so the two
+        // entids differ.
+        let nick = "{:db/ident :person/nick
+                     :db/valueType :db.type/string
+                     :db/cardinality :db.cardinality/one}";
+        conn_1
+            .transact(&mut sqlite_1, format!("[{}]", nick))
+            .expect("transacted");
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                format!(
+                    "[{{:db/ident :person/mood
+                        :db/valueType :db.type/string
+                        :db/cardinality :db.cardinality/one}}
+                      {}]",
+                    nick
+                ),
+            )
+            .expect("transacted");
+        assert_ne!(
+            conn_1
+                .current_schema()
+                .get_entid(&Keyword::namespaced("person", "nick")),
+            conn_2
+                .current_schema()
+                .get_entid(&Keyword::namespaced("person", "nick"))
+        );
+
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                format!(r#"[[:db/add {} :person/nick "Vanya"]]"#, ivan),
+            )
+            .expect("transacted");
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                format!(r#"[[:db/add {} :person/nick "Vanechka"]]"#, ivan),
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        assert_eq!(
+            SyncReport::Merge(SyncFollowup::FullSync),
+            sync_with_policy(
+                &mut sqlite_2,
+                &mut conn_2,
+                &mut remote_client,
+                &ConflictPolicy::new(RemoteWins)
+            )
+        );
+
+        // The conflict was noticed and settled, and 2's nickname is kept on the remote.
+        assert_eq!(
+            Some("Vanya".to_string()),
+            person_value(&sqlite_2, &conn_2, ivan, "nick")
+        );
+        assert_eq!(None, person_value(&sqlite_2, &conn_2, ivan, "mood"));
+        let merges = SyncMetadata::merges(&sqlite_2.transaction().expect("tx")).expect("merges");
+        assert_eq!(1, merges.len());
+        assert!(remote_client.timelines.contains_key(&merges[0].timeline));
+    }
+
+    #[test]
+    fn test_failed_merge_uploads_no_timeline() {
+        let mut remote_client = TestRemoteClient::new();
+        let (mut sqlite_1, mut conn_1, mut sqlite_2, mut conn_2) =
+            connect_with_synced_schema(&mut remote_client);
+        let ivan = sync_new_person(
+            &mut sqlite_1,
+            &mut conn_1,
+            &mut sqlite_2,
+            &mut conn_2,
+            &mut remote_client,
+            "Ivan",
+        );
+
+        // Both sides give Ivan a different email, which is a conflict to settle...
+        conn_1
+            .transact(
+                &mut sqlite_1,
+                format!(
+                    r#"[[:db/add :person/name :db/cardinality :db.cardinality/many]
+                        [:db/add {} :person/email "vanya@example.com"]]"#,
+                    ivan
+                ),
+            )
+            .expect("transacted");
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                format!(
+                    r#"[[:db/add :person/name :db/cardinality :db.cardinality/many]
+                        [:db/add {} :person/email "vanechka@example.com"]]"#,
+                    ivan
+                ),
+            )
+            .expect("transacted");
+
+        // ... but 2 also undoes a schema alteration that 1 kept, so the merge fails.
+        conn_2
+            .transact(
+                &mut sqlite_2,
+                "[[:db/add :person/name :db/cardinality :db.cardinality/one]]",
+            )
+            .expect("transacted");
+
+        assert_sync!(
+            SyncReport::RemoteFastForward,
+            conn_1,
+            sqlite_1,
+            remote_client
+        );
+        assert_sync!(
+            error => MentatError::TolstoyError(TolstoyError::SchemaAlterationConflict(_, _)),
+            conn_2, sqlite_2, remote_client
+        );
+        assert!(remote_client.timelines.is_empty());
+    }
+
+    /// Sync a person from one store to another through the log at `uri`.
+    fn sync_between_stores(uri: &str) {
+        let user = Uuid::new_v4().to_string();
+
+        let mut store_1 = Store::open("").expect("opened");
+        store_1
+            .transact(
+                r#"[
+                {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            ]"#,
+            )
+            .expect("transacted");
+        store_1
+            .transact(r#"[{:person/name "Ivan"}]"#)
+            .expect("transacted");
+        store_1.sync(uri, &user).expect("synced");
+
+        let mut store_2 = Store::open("").expect("opened");
+        store_2.sync(uri, &user).expect("synced");
+        assert_eq!(
+            Some(TypedValue::from("Ivan").into()),
+            store_2
+                .q_once("[:find ?name . :where [_ :person/name ?name]]", None)
+                .expect("queried")
+                .into_scalar()
+                .expect("scalar")
+        );
+    }
+
+    #[test]
+    fn test_sync_through_directory() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        sync_between_stores(&format!("file://{}", dir.path().display()));
+    }
+
+    #[test]
+    fn test_sync_through_sqlite_store() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("log.mentatdb");
+
+        // The log lives alongside another Mentat store, which stays usable.
+        let mut store = Store::open(path.to_str().expect("path")).expect("opened");
+        sync_between_stores(&format!("sqlite://{}", path.display()));
+        store
+            .transact(r#"[{:db/ident :test/ident}]"#)
+            .expect("transacted");
+    }
+
+    /// Start a sync server on localhost that keeps its logs in `dir`, and return its URI.
+    fn start_sync_server(dir: &Path) -> String {
+        start(SyncServer::bind("127.0.0.1:0", dir.join("sync.db")).expect("bound"))
+    }
+
+    fn start(server: SyncServer) -> String {
+        let uri = format!("http://{}", server.local_addr().expect("address"));
+        thread::spawn(move || server.run().expect("served"));
+        uri
+    }
+
+    fn assert_status(expected: u16, result: Result<Uuid>) {
+        match result.expect_err("request failed") {
+            MentatError::TolstoyError(TolstoyError::UnexpectedStatus(_, status)) => {
+                assert_eq!(expected, status)
+            }
+            e => panic!("wrong error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_sync_through_server() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        sync_between_stores(&start_sync_server(dir.path()));
+    }
+
+    #[test]
+    fn test_sync_server_head_compare_and_set() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut remote_client = RemoteClient::new(start_sync_server(dir.path()), Uuid::new_v4());
+        assert_eq!(Uuid::nil(), remote_client.head().expect("head"));
+
+        let part = TxPart {
+            partitions: None,
+            e: 65536,
+            a: 1,
+            v: TypedValue::Long(1),
+            tx: 268435456,
+            added: true,
+        };
+        let (first, second, chunk) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        remote_client.put_chunk(&chunk, &part).expect("put chunk");
+        remote_client
+            .put_transaction(&first, &Uuid::nil(), &[chunk])
+            .expect("put transaction");
+        remote_client.set_head(&first).expect("moved head");
+        assert_eq!(first, remote_client.head().expect("head"));
+
+        // Another client uploaded on top of the empty log it last saw, so its head is refused.
+        remote_client
+            .put_transaction(&second, &Uuid::nil(), &[chunk])
+            .expect("put transaction");
+        match remote_client.set_head(&second).expect_err("head moved") {
+            MentatError::TolstoyError(TolstoyError::RemoteHeadMoved(new, current)) => {
+                assert_eq!(second, new);
+                assert_eq!(first, current);
+            }
+            e => panic!("wrong error: {:?}", e),
+        }
+        assert_eq!(first, remote_client.head().expect("head"));
+        assert_eq!(
+            vec![Tx {
+                tx: first,
+                parts: vec![part.clone()]
+            }],
+            remote_client
+                .transactions_after(&Uuid::nil())
+                .expect("transactions")
+        );
+
+        // Published transactions and chunks can't be rewritten.
+        assert_status(
+            409,
+            remote_client
+                .put_transaction(&first, &second, &[chunk])
+                .map(|_| Uuid::nil()),
+        );
+        let other = TxPart {
+            v: TypedValue::Long(2),
+            ..part.clone()
+        };
+        assert_status(
+            409,
+            remote_client.put_chunk(&chunk, &other).map(|_| Uuid::nil()),
+        );
+        remote_client
+            .put_chunk(&chunk, &part)
+            .expect("put identical chunk");
+    }
+
+    #[test]
+    fn test_sync_server_concurrent_head_moves() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let uri = start_sync_server(dir.path());
+        let user = Uuid::new_v4();
+
+        // Every client uploads on top of the empty log, and then they all race to move the head.
+        let clients = 8;
+        let barrier = Arc::new(Barrier::new(clients));
+        let handles: Vec<_> = (0..clients)
+            .map(|_| {
+                let mut remote_client = RemoteClient::new(uri.clone(), user);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    let tx = Uuid::new_v4();
+                    remote_client
+                        .put_transaction(&tx, &Uuid::nil(), &[])
+                        .expect("put transaction");
+                    barrier.wait();
+                    (tx, remote_client.set_head(&tx))
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("joined"))
+            .collect();
+
+        let winners: Vec<Uuid> = results
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(tx, _)| *tx)
+            .collect();
+        assert_eq!(1, winners.len());
+        for (_, result) in results.iter().filter(|(_, result)| result.is_err()) {
+            match result {
+                Err(MentatError::TolstoyError(TolstoyError::RemoteHeadMoved(_, current))) => {
+                    assert_eq!(winners[0], *current)
+                }
+                e => panic!("wrong result: {:?}", e),
+            }
+        }
+        assert_eq!(
+            winners[0],
+            RemoteClient::new(uri, user).head().expect("head")
+        );
+    }
+
+    #[test]
+    fn test_merge_through_server() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let uri = start_sync_server(dir.path());
+        let user = Uuid::new_v4().to_string();
+
+        let mut store_1 = Store::open("").expect("opened");
+        store_1
+            .transact(
+                r#"[
+                {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            ]"#,
+            )
+            .expect("transacted");
+        store_1
+            .transact(r#"[{:person/name "Ivan"}]"#)
+            .expect("transacted");
+        store_1.sync(&uri, &user).expect("synced");
+
+        let mut store_2 = Store::open("").expect("opened");
+        store_2.sync(&uri, &user).expect("synced");
+
+        store_1
+            .transact(r#"[[:db/add "petr" :person/name "Petr"]]"#)
+            .expect("transacted");
+        store_1.sync(&uri, &user).expect("synced");
+        store_2
+            .transact(r#"[[:db/add "oleg" :person/name "Oleg"]]"#)
+            .expect("transacted");
+        store_2.sync(&uri, &user).expect("synced");
+        store_1.sync(&uri, &user).expect("synced");
+
+        let names = "[:find [?name ...] :order ?name :where [_ :person/name ?name]]";
+        for store in &[store_1, store_2] {
+            let synced: Vec<String> = store
+                .q_once(names, None)
+                .expect("queried")
+                .into_coll()
+                .expect("coll")
+                .into_iter()
+                .filter_map(|name| name.into_string())
+                .map(|name| name.to_string())
+                .collect();
+            assert_eq!(vec!["Ivan", "Oleg", "Petr"], synced);
+        }
+    }
+
+    #[test]
+    fn test_remote_client_bearer_token() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let server = SyncServer::bind("127.0.0.1:0", dir.path().join("sync.db"))
+            .expect("bound")
+            .with_bearer_token("secret");
+        let uri = start(server);
+        let user = Uuid::new_v4();
+
+        assert_status(401, RemoteClient::new(uri.clone(), user).head());
+        let remote_client = RemoteClient::new(uri.clone(), user)
+            .with_bearer_token("guess")
+            .expect("valid token");
+        assert_status(401, remote_client.head());
+        let remote_client = RemoteClient::new(uri, user)
+            .with_bearer_token("secret")
+            .expect("valid token");
+        assert_eq!(Uuid::nil(), remote_client.head().expect("head"));
+    }
+
+    #[test]
+    fn test_sync_through_configured_client() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let server = SyncServer::bind("127.0.0.1:0", dir.path().join("sync.db"))
+            .expect("bound")
+            .with_bearer_token("secret");
+        let uri = start(server);
+        let user = Uuid::new_v4();
+        let policy = ConflictPolicy::default();
+
+        // A store can't sync by URI alone with a server that wants credentials.
+        let mut store_1 = Store::open("").expect("opened");
+        store_1
+            .transact(
+                r#"[
+                {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            ]"#,
+            )
+            .expect("transacted");
+        store_1
+            .transact(r#"[{:person/name "Ivan"}]"#)
+            .expect("transacted");
+        match store_1.sync(&uri, &user.to_string()) {
+            Err(MentatError::TolstoyError(TolstoyError::UnexpectedStatus(_, 401))) => (),
+            Err(e) => panic!("wrong error: {:?}", e),
+            Ok(_) => panic!("synced without credentials"),
+        }
+
+        let client = || {
+            RemoteClient::new(uri.clone(), user)
+                .with_bearer_token("secret")
+                .expect("valid token")
+        };
+        store_1
+            .sync_with_log(&mut client(), &policy)
+            .expect("synced");
+
+        let mut store_2 = Store::open("").expect("opened");
+        store_2
+            .sync_with_log(&mut client(), &policy)
+            .expect("synced");
+        assert_eq!(
+            Some(TypedValue::from("Ivan").into()),
+            store_2
+                .q_once("[:find ?name . :where [_ :person/name ?name]]", None)
+                .expect("queried")
+                .into_scalar()
+                .expect("scalar")
+        );
+    }
+
+    #[test]
+    fn test_remote_client_unexpected_status() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut remote_client = RemoteClient::new(start_sync_server(dir.path()), Uuid::new_v4());
+
+        // The transaction's chunk was never uploaded. That won't change, so it isn't retried.
+        let tx = Uuid::new_v4();
+        remote_client
+            .put_transaction(&tx, &Uuid::nil(), &[Uuid::new_v4()])
+            .expect("put transaction");
+        remote_client.set_head(&tx).expect("moved head");
+        let started = Instant::now();
+        assert_status(
+            404,
+            remote_client
+                .transactions_after(&Uuid::nil())
+                .map(|_| Uuid::nil()),
+        );
+        assert!(started.elapsed() < Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_remote_client_retries() {
+        // Nothing listens on this port once the listener is dropped.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .expect("bound")
+            .local_addr()
+            .expect("address");
+        let remote_client = RemoteClient::new(format!("http://{}", addr), Uuid::new_v4())
+            .with_retries(2, Duration::from_millis(50));
+        let started = Instant::now();
+        match remote_client.head().expect_err("refused") {
+            MentatError::TolstoyError(TolstoyError::NetworkError(_)) => (),
+            e => panic!("wrong error: {:?}", e),
+        }
+        // Waited 50ms before the first retry, and 100ms before the second.
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn test_remote_client_timeout() {
+        // Connections are queued, but never answered.
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bound");
+        let remote_client = RemoteClient::new(
+            format!("http://{}", listener.local_addr().expect("address")),
+            Uuid::new_v4(),
+        )
+        .with_timeout(Duration::from_millis(100))
+        .with_retries(0, Duration::from_millis(0));
+        match remote_client.head().expect_err("timed out") {
+            MentatError::TolstoyError(TolstoyError::RequestTimedOut(_)) => (),
+            e => panic!("wrong error: {:?}", e),
+        }
+    }
 }
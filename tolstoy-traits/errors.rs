@@ -41,6 +41,30 @@ pub enum TolstoyError {
     #[fail(display = "not yet implemented: {}", _0)]
     NotYetImplemented(String),
 
+    #[fail(
+        display = "local and remote made incompatible changes to {} of attribute {}",
+        _1, _0
+    )]
+    SchemaAlterationConflict(String, String),
+
+    #[fail(display = "remote head is {}, which {} doesn't descend from", _1, _0)]
+    RemoteHeadMoved(uuid::Uuid, uuid::Uuid),
+
+    #[fail(display = "{} {} was already uploaded with different contents", _0, _1)]
+    UploadConflict(String, uuid::Uuid),
+
+    #[fail(display = "{} is locked by another writer", _0)]
+    LogLocked(String),
+
+    #[fail(display = "{} answered {}", _0, _1)]
+    UnexpectedStatus(String, u16),
+
+    #[fail(display = "{} timed out", _0)]
+    RequestTimedOut(String),
+
+    #[fail(display = "invalid value for header {}", _0)]
+    InvalidHeader(String),
+
     #[fail(display = "{}", _0)]
     DbError(#[cause] DbError),
 
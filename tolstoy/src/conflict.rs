@@ -0,0 +1,186 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Settling conflicts between local and remote changes during a merge.
+//!
+//! A conflict occurs when both sides asserted different values of the same cardinality-one
+//! attribute on an entity that existed before they diverged. Each conflict is settled by the
+//! `ConflictResolver` that a `ConflictPolicy` names for its attribute.
+
+use std::collections::BTreeMap;
+
+use core_traits::{Entid, TypedValue};
+
+use mentat_core::{DateTime, Keyword, Utc};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict {
+    pub e: Entid,
+    pub a: Entid,
+    /// The value that local last asserted, and when.
+    pub local: TypedValue,
+    pub local_instant: DateTime<Utc>,
+    /// The value that remote last asserted, and when.
+    pub remote: TypedValue,
+    pub remote_instant: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    Local,
+    Remote,
+    /// Neither side's value, but this one.
+    Value(TypedValue),
+}
+
+pub trait ConflictResolver {
+    fn resolve(&self, conflict: &Conflict) -> Resolution;
+}
+
+/// Keep whichever value was asserted last, going by the transactions' `:db/txInstant`. Ties go
+/// to local.
+pub struct LastWriterWins;
+
+impl ConflictResolver for LastWriterWins {
+    fn resolve(&self, conflict: &Conflict) -> Resolution {
+        if conflict.remote_instant > conflict.local_instant {
+            Resolution::Remote
+        } else {
+            Resolution::Local
+        }
+    }
+}
+
+pub struct RemoteWins;
+
+impl ConflictResolver for RemoteWins {
+    fn resolve(&self, _conflict: &Conflict) -> Resolution {
+        Resolution::Remote
+    }
+}
+
+/// The default. Local transactions are replayed on top of remote ones, so this is how merges
+/// settled conflicts before they could be settled any other way.
+pub struct LocalWins;
+
+impl ConflictResolver for LocalWins {
+    fn resolve(&self, _conflict: &Conflict) -> Resolution {
+        Resolution::Local
+    }
+}
+
+impl<F> ConflictResolver for F
+where
+    F: Fn(&Conflict) -> Resolution,
+{
+    fn resolve(&self, conflict: &Conflict) -> Resolution {
+        self(conflict)
+    }
+}
+
+/// Which `ConflictResolver` settles conflicts over each attribute.
+pub struct ConflictPolicy {
+    default: Box<dyn ConflictResolver>,
+    attributes: BTreeMap<Keyword, Box<dyn ConflictResolver>>,
+}
+
+impl Default for ConflictPolicy {
+    fn default() -> ConflictPolicy {
+        ConflictPolicy::new(LocalWins)
+    }
+}
+
+impl ConflictPolicy {
+    /// A policy that settles every conflict with `default`.
+    pub fn new<R>(default: R) -> ConflictPolicy
+    where
+        R: ConflictResolver + 'static,
+    {
+        ConflictPolicy {
+            default: Box::new(default),
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    /// Settle conflicts over `attribute` with `resolver` instead of the default.
+    pub fn with_attribute<R>(mut self, attribute: Keyword, resolver: R) -> ConflictPolicy
+    where
+        R: ConflictResolver + 'static,
+    {
+        self.attributes.insert(attribute, Box::new(resolver));
+        self
+    }
+
+    pub fn resolver(&self, attribute: Option<&Keyword>) -> &dyn ConflictResolver {
+        attribute
+            .and_then(|a| self.attributes.get(a))
+            .unwrap_or(&self.default)
+            .as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mentat_core::FromMicros;
+
+    fn conflict(local_micros: i64, remote_micros: i64) -> Conflict {
+        Conflict {
+            e: 65536,
+            a: 65537,
+            local: TypedValue::Long(1),
+            local_instant: DateTime::<Utc>::from_micros(local_micros),
+            remote: TypedValue::Long(2),
+            remote_instant: DateTime::<Utc>::from_micros(remote_micros),
+        }
+    }
+
+    #[test]
+    fn test_last_writer_wins() {
+        assert_eq!(Resolution::Local, LastWriterWins.resolve(&conflict(2, 1)));
+        assert_eq!(Resolution::Remote, LastWriterWins.resolve(&conflict(1, 2)));
+        assert_eq!(Resolution::Local, LastWriterWins.resolve(&conflict(1, 1)));
+    }
+
+    #[test]
+    fn test_policy_per_attribute() {
+        let policy = ConflictPolicy::new(RemoteWins).with_attribute(
+            Keyword::namespaced("person", "age"),
+            |c: &Conflict| match (&c.local, &c.remote) {
+                (TypedValue::Long(l), TypedValue::Long(r)) => {
+                    Resolution::Value(TypedValue::Long(l + r))
+                }
+                _ => Resolution::Local,
+            },
+        );
+
+        let age = Keyword::namespaced("person", "age");
+        let name = Keyword::namespaced("person", "name");
+        assert_eq!(
+            Resolution::Value(TypedValue::Long(3)),
+            policy.resolver(Some(&age)).resolve(&conflict(1, 2))
+        );
+        assert_eq!(
+            Resolution::Remote,
+            policy.resolver(Some(&name)).resolve(&conflict(1, 2))
+        );
+        assert_eq!(
+            Resolution::Remote,
+            policy.resolver(None).resolve(&conflict(1, 2))
+        );
+        assert_eq!(
+            Resolution::Local,
+            ConflictPolicy::default()
+                .resolver(None)
+                .resolve(&conflict(1, 2))
+        );
+    }
+}
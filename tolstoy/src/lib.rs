@@ -21,13 +21,20 @@ extern crate serde_derive;
 extern crate core_traits;
 
 pub mod bootstrap;
+pub mod conflict;
+pub use crate::conflict::{
+    Conflict, ConflictPolicy, ConflictResolver, LastWriterWins, LocalWins, RemoteWins, Resolution,
+};
+pub mod local_log;
+pub use crate::local_log::{DirectoryLog, SqliteLog};
 pub mod metadata;
-pub use crate::metadata::{PartitionsTable, SyncMetadata};
+pub use crate::metadata::{MergeRecord, PartitionsTable, SyncMetadata};
 mod datoms;
 pub mod debug;
 pub mod remote_client;
 pub use crate::remote_client::RemoteClient;
 pub mod schema;
+mod schema_merge;
 pub mod syncer;
 pub use crate::syncer::{SyncFollowup, SyncReport, SyncResult, Syncer};
 pub mod logger;
@@ -0,0 +1,651 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Transaction logs kept on this machine, for syncing without a server: through a shared
+//! directory, a USB stick, or in tests.
+//!
+//! `DirectoryLog` keeps each user's log in a directory of JSON files, in the same shapes that
+//! `RemoteClient` sends over HTTP. `SqliteLog` keeps it in tables of an SQLite file, which can be
+//! another Mentat store.
+//!
+//! Both follow a transaction's parents back from the head to find the transactions after another
+//! one, and both only move the head forward: the new head must descend from the current one.
+//! Transactions, chunks and timelines can't change once uploaded: uploading one again is only
+//! allowed with the same contents.
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use public_traits::errors::Result;
+
+use tolstoy_traits::errors::TolstoyError;
+
+use crate::types::{GlobalTransactionLog, Tx, TxPart};
+
+#[derive(Serialize, Deserialize)]
+struct SerializedHead {
+    head: Uuid,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedTransaction {
+    parent: Uuid,
+    chunks: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedTimeline {
+    parent: Uuid,
+    transactions: Vec<Vec<Uuid>>,
+}
+
+/// The transactions after `from` on the way to `head`, oldest first. Fails if `from` isn't
+/// `head` or one of its ancestors.
+fn chain_after<F>(head: Uuid, from: &Uuid, mut parent_of: F) -> Result<Vec<Uuid>>
+where
+    F: FnMut(&Uuid) -> Result<Uuid>,
+{
+    let mut chain = vec![];
+    let mut tx = head;
+    while tx != *from {
+        if tx.is_nil() {
+            bail!(TolstoyError::BadRemoteState(format!(
+                "{} is not an ancestor of head {}",
+                from, head
+            )));
+        }
+        let parent = parent_of(&tx)?;
+        chain.push(tx);
+        tx = parent;
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+/// A transaction log in a directory, with a subdirectory for each user:
+///
+/// ```text
+/// <user>/head
+/// <user>/transactions/<uuid>
+/// <user>/chunks/<uuid>
+/// <user>/timelines/<uuid>
+/// ```
+///
+/// Every file is written to a temporary name, synced to disk, and then renamed into place, so
+/// readers never see a partial file and the head never names a file that isn't on disk yet.
+/// Moving the head happens while holding `<user>/head.lock`, which is created exclusively, so two
+/// writers sharing the directory can't both move the head from the same starting point. A writer
+/// that dies while moving the head leaves the lock behind, and it must be removed by hand.
+pub struct DirectoryLog {
+    root: PathBuf,
+}
+
+/// How long `DirectoryLog::set_head` waits for another writer to release the head lock.
+const HEAD_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Holds a `DirectoryLog`'s head lock until dropped.
+struct HeadLock {
+    path: PathBuf,
+}
+
+impl HeadLock {
+    fn acquire(path: PathBuf) -> Result<HeadLock> {
+        let start = Instant::now();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(HeadLock { path }),
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if start.elapsed() > HEAD_LOCK_TIMEOUT {
+                        bail!(TolstoyError::LogLocked(path.display().to_string()));
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for HeadLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl DirectoryLog {
+    /// Open `user`'s log in `path`, creating any directories that don't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P, user: &Uuid) -> Result<DirectoryLog> {
+        let root = path.as_ref().join(user.to_string());
+        for dir in &["transactions", "chunks", "timelines"] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        Ok(DirectoryLog { root })
+    }
+
+    fn write(&self, path: PathBuf, contents: String) -> Result<()> {
+        let temporary = self.root.join(format!(".{}", Uuid::new_v4()));
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, &path)?;
+        // Make the rename itself durable.
+        if let Some(parent) = path.parent() {
+            sync_directory(parent)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self, kind: &str, uuid: &Uuid) -> Result<String> {
+        match fs::read_to_string(self.root.join(kind).join(uuid.to_string())) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => bail!(TolstoyError::BadRemoteState(
+                format!("missing {} {}", kind, uuid)
+            )),
+            result => Ok(result?),
+        }
+    }
+
+    fn transaction(&self, tx: &Uuid) -> Result<SerializedTransaction> {
+        Ok(serde_json::from_str(&self.read("transactions", tx)?)?)
+    }
+
+    /// Write `contents` as `kind/uuid`, unless it's already there.
+    fn write_once(&self, kind: &str, uuid: &Uuid, contents: String) -> Result<()> {
+        let path = self.root.join(kind).join(uuid.to_string());
+        match fs::read_to_string(&path) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => self.write(path, contents),
+            Ok(ref existing) if *existing == contents => Ok(()),
+            Ok(_) => bail!(TolstoyError::UploadConflict(kind.to_string(), *uuid)),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> Result<()> {
+    fs::File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+// Directories can't be opened as files elsewhere, and renames are durable once they return.
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+impl GlobalTransactionLog for DirectoryLog {
+    fn head(&self) -> Result<Uuid> {
+        match fs::read_to_string(self.root.join("head")) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Uuid::nil()),
+            result => {
+                let head: SerializedHead = serde_json::from_str(&result?)?;
+                Ok(head.head)
+            }
+        }
+    }
+
+    fn transactions_after(&self, tx: &Uuid) -> Result<Vec<Tx>> {
+        let mut txs = vec![];
+        for uuid in chain_after(self.head()?, tx, |uuid| Ok(self.transaction(uuid)?.parent))? {
+            let mut parts = vec![];
+            for chunk in self.transaction(&uuid)?.chunks {
+                parts.push(serde_json::from_str(&self.read("chunks", &chunk)?)?);
+            }
+            txs.push(Tx { tx: uuid, parts });
+        }
+        Ok(txs)
+    }
+
+    fn set_head(&mut self, tx: &Uuid) -> Result<()> {
+        let _lock = HeadLock::acquire(self.root.join("head.lock"))?;
+        let head = self.head()?;
+        if chain_after(*tx, &head, |uuid| Ok(self.transaction(uuid)?.parent)).is_err() {
+            bail!(TolstoyError::RemoteHeadMoved(*tx, head));
+        }
+        let json = serde_json::to_string(&SerializedHead { head: *tx })?;
+        self.write(self.root.join("head"), json)
+    }
+
+    fn put_transaction(&mut self, tx: &Uuid, parent_tx: &Uuid, chunk_txs: &[Uuid]) -> Result<()> {
+        let json = serde_json::to_string(&SerializedTransaction {
+            parent: *parent_tx,
+            chunks: chunk_txs.to_vec(),
+        })?;
+        self.write_once("transactions", tx, json)
+    }
+
+    fn put_chunk(&mut self, tx: &Uuid, payload: &TxPart) -> Result<()> {
+        let json = serde_json::to_string(payload)?;
+        self.write_once("chunks", tx, json)
+    }
+
+    fn put_timeline(&mut self, timeline: &Uuid, parent_tx: &Uuid, txs: &[Vec<Uuid>]) -> Result<()> {
+        let json = serde_json::to_string(&SerializedTimeline {
+            parent: *parent_tx,
+            transactions: txs.to_vec(),
+        })?;
+        self.write_once("timelines", timeline, json)
+    }
+}
+
+lazy_static! {
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    static ref SQLITE_LOG_STATEMENTS: Vec<&'static str> = { vec![
+        "CREATE TABLE IF NOT EXISTS tolstoy_log_heads (user BLOB NOT NULL PRIMARY KEY, head BLOB NOT NULL)",
+        "CREATE TABLE IF NOT EXISTS tolstoy_log_transactions (user BLOB NOT NULL, tx BLOB NOT NULL, parent BLOB NOT NULL, chunks TEXT NOT NULL, PRIMARY KEY (user, tx))",
+        "CREATE TABLE IF NOT EXISTS tolstoy_log_chunks (user BLOB NOT NULL, chunk BLOB NOT NULL, payload TEXT NOT NULL, PRIMARY KEY (user, chunk))",
+        "CREATE TABLE IF NOT EXISTS tolstoy_log_timelines (user BLOB NOT NULL, timeline BLOB NOT NULL, parent BLOB NOT NULL, transactions TEXT NOT NULL, PRIMARY KEY (user, timeline))",
+        ]
+    };
+}
+
+/// How long `SqliteLog` waits for another connection to release a lock on the file.
+const SQLITE_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A transaction log in an SQLite file, kept in `tolstoy_log_*` tables so that the file can also
+/// be a Mentat store. Checking and moving the head happen in one SQLite transaction.
+pub struct SqliteLog {
+    conn: rusqlite::Connection,
+    user: Vec<u8>,
+}
+
+impl SqliteLog {
+    /// Open `user`'s log in the SQLite file at `path`, creating the file and tables as needed.
+    pub fn open<P: AsRef<Path>>(path: P, user: &Uuid) -> Result<SqliteLog> {
+        SqliteLog::new(rusqlite::Connection::open(path)?, user)
+    }
+
+    pub fn new(conn: rusqlite::Connection, user: &Uuid) -> Result<SqliteLog> {
+        conn.busy_timeout(SQLITE_BUSY_TIMEOUT)?;
+        for statement in SQLITE_LOG_STATEMENTS.iter() {
+            conn.execute(statement, rusqlite::params![])?;
+        }
+        Ok(SqliteLog {
+            conn,
+            user: user.as_bytes().to_vec(),
+        })
+    }
+
+    /// Switch to `user`'s log in the same file.
+    pub fn set_user(&mut self, user: &Uuid) {
+        self.user = user.as_bytes().to_vec();
+    }
+
+    /// Run `insert`, which ignores rows that already exist. If the row already existed, `same`,
+    /// which takes the same parameters, must find that it has the same contents.
+    fn insert_once(
+        &self,
+        kind: &str,
+        uuid: &Uuid,
+        insert: &str,
+        same: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<()> {
+        if self.conn.execute(insert, params)? == 0 {
+            let same: bool = self.conn.query_row(same, params, |row| row.get(0))?;
+            if !same {
+                bail!(TolstoyError::UploadConflict(kind.to_string(), *uuid));
+            }
+        }
+        Ok(())
+    }
+
+    fn head_in(conn: &rusqlite::Connection, user: &[u8]) -> Result<Uuid> {
+        let mut stmt = conn.prepare_cached("SELECT head FROM tolstoy_log_heads WHERE user = ?")?;
+        let mut rows = stmt.query(rusqlite::params![user])?;
+        match rows.next()? {
+            Some(row) => {
+                let bytes: Vec<u8> = row.get(0)?;
+                Ok(Uuid::from_slice(bytes.as_slice())?)
+            }
+            None => Ok(Uuid::nil()),
+        }
+    }
+
+    fn transaction_in(
+        conn: &rusqlite::Connection,
+        user: &[u8],
+        tx: &Uuid,
+    ) -> Result<SerializedTransaction> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT parent, chunks FROM tolstoy_log_transactions WHERE user = ? AND tx = ?",
+        )?;
+        let mut rows = stmt.query(rusqlite::params![user, &tx.as_bytes().to_vec()])?;
+        match rows.next()? {
+            Some(row) => {
+                let parent: Vec<u8> = row.get(0)?;
+                let chunks: String = row.get(1)?;
+                Ok(SerializedTransaction {
+                    parent: Uuid::from_slice(parent.as_slice())?,
+                    chunks: serde_json::from_str(&chunks)?,
+                })
+            }
+            None => bail!(TolstoyError::BadRemoteState(format!(
+                "missing transaction {}",
+                tx
+            ))),
+        }
+    }
+
+    /// The parent and chunks of `tx`, which needn't be in the log yet.
+    pub fn transaction(&self, tx: &Uuid) -> Result<(Uuid, Vec<Uuid>)> {
+        let transaction = SqliteLog::transaction_in(&self.conn, &self.user, tx)?;
+        Ok((transaction.parent, transaction.chunks))
+    }
+
+    pub fn chunk(&self, chunk: &Uuid) -> Result<TxPart> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT payload FROM tolstoy_log_chunks WHERE user = ? AND chunk = ?",
+        )?;
+        let mut rows = stmt.query(rusqlite::params![&self.user, &chunk.as_bytes().to_vec()])?;
+        match rows.next()? {
+            Some(row) => {
+                let payload: String = row.get(0)?;
+                Ok(serde_json::from_str(&payload)?)
+            }
+            None => bail!(TolstoyError::BadRemoteState(format!(
+                "missing chunk {}",
+                chunk
+            ))),
+        }
+    }
+}
+
+impl GlobalTransactionLog for SqliteLog {
+    fn head(&self) -> Result<Uuid> {
+        SqliteLog::head_in(&self.conn, &self.user)
+    }
+
+    fn transactions_after(&self, tx: &Uuid) -> Result<Vec<Tx>> {
+        let chain = chain_after(self.head()?, tx, |uuid| {
+            Ok(SqliteLog::transaction_in(&self.conn, &self.user, uuid)?.parent)
+        })?;
+        let mut txs = vec![];
+        for uuid in chain {
+            let mut parts = vec![];
+            for chunk in SqliteLog::transaction_in(&self.conn, &self.user, &uuid)?.chunks {
+                parts.push(self.chunk(&chunk)?);
+            }
+            txs.push(Tx { tx: uuid, parts });
+        }
+        Ok(txs)
+    }
+
+    fn set_head(&mut self, tx: &Uuid) -> Result<()> {
+        let user = &self.user;
+        let db_tx = self
+            .conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        let head = SqliteLog::head_in(&db_tx, user)?;
+        if chain_after(*tx, &head, |uuid| {
+            Ok(SqliteLog::transaction_in(&db_tx, user, uuid)?.parent)
+        })
+        .is_err()
+        {
+            bail!(TolstoyError::RemoteHeadMoved(*tx, head));
+        }
+        db_tx.execute(
+            "INSERT OR REPLACE INTO tolstoy_log_heads (user, head) VALUES (?, ?)",
+            rusqlite::params![user, &tx.as_bytes().to_vec()],
+        )?;
+        db_tx.commit()?;
+        Ok(())
+    }
+
+    fn put_transaction(&mut self, tx: &Uuid, parent_tx: &Uuid, chunk_txs: &[Uuid]) -> Result<()> {
+        self.insert_once(
+            "transactions",
+            tx,
+            "INSERT OR IGNORE INTO tolstoy_log_transactions (user, tx, parent, chunks) VALUES (?, ?, ?, ?)",
+            "SELECT EXISTS (SELECT 1 FROM tolstoy_log_transactions WHERE user = ? AND tx = ? AND parent = ? AND chunks = ?)",
+            rusqlite::params![
+                &self.user,
+                &tx.as_bytes().to_vec(),
+                &parent_tx.as_bytes().to_vec(),
+                &serde_json::to_string(chunk_txs)?
+            ],
+        )
+    }
+
+    fn put_chunk(&mut self, tx: &Uuid, payload: &TxPart) -> Result<()> {
+        self.insert_once(
+            "chunks",
+            tx,
+            "INSERT OR IGNORE INTO tolstoy_log_chunks (user, chunk, payload) VALUES (?, ?, ?)",
+            "SELECT EXISTS (SELECT 1 FROM tolstoy_log_chunks WHERE user = ? AND chunk = ? AND payload = ?)",
+            rusqlite::params![
+                &self.user,
+                &tx.as_bytes().to_vec(),
+                &serde_json::to_string(payload)?
+            ],
+        )
+    }
+
+    fn put_timeline(&mut self, timeline: &Uuid, parent_tx: &Uuid, txs: &[Vec<Uuid>]) -> Result<()> {
+        self.insert_once(
+            "timelines",
+            timeline,
+            "INSERT OR IGNORE INTO tolstoy_log_timelines (user, timeline, parent, transactions) VALUES (?, ?, ?, ?)",
+            "SELECT EXISTS (SELECT 1 FROM tolstoy_log_timelines WHERE user = ? AND timeline = ? AND parent = ? AND transactions = ?)",
+            rusqlite::params![
+                &self.user,
+                &timeline.as_bytes().to_vec(),
+                &parent_tx.as_bytes().to_vec(),
+                &serde_json::to_string(txs)?
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core_traits::TypedValue;
+
+    use public_traits::errors::MentatError;
+
+    fn part(e: i64) -> TxPart {
+        TxPart {
+            partitions: None,
+            e,
+            a: 10,
+            v: TypedValue::Long(e),
+            tx: 268435456,
+            added: true,
+        }
+    }
+
+    /// Put a transaction of a single chunk, and return its uuid.
+    fn put<L: GlobalTransactionLog>(log: &mut L, parent: &Uuid, e: i64) -> Uuid {
+        let tx = Uuid::new_v4();
+        let chunk = Uuid::new_v4();
+        log.put_chunk(&chunk, &part(e)).expect("put chunk");
+        log.put_transaction(&tx, parent, &[chunk])
+            .expect("put transaction");
+        tx
+    }
+
+    fn exercise<L: GlobalTransactionLog>(log: &mut L) {
+        assert_eq!(Uuid::nil(), log.head().expect("head"));
+        assert_eq!(
+            Vec::<Tx>::new(),
+            log.transactions_after(&Uuid::nil()).expect("txs")
+        );
+
+        let first = put(log, &Uuid::nil(), 65536);
+        let second = put(log, &first, 65537);
+
+        // Transactions aren't in the log until the head moves.
+        assert_eq!(0, log.transactions_after(&Uuid::nil()).expect("txs").len());
+
+        log.set_head(&second).expect("moved head");
+        assert_eq!(second, log.head().expect("head"));
+        assert_eq!(
+            vec![
+                Tx {
+                    tx: first,
+                    parts: vec![part(65536)]
+                },
+                Tx {
+                    tx: second,
+                    parts: vec![part(65537)]
+                },
+            ],
+            log.transactions_after(&Uuid::nil()).expect("txs")
+        );
+        assert_eq!(
+            vec![second],
+            log.transactions_after(&first)
+                .expect("txs")
+                .into_iter()
+                .map(|tx| tx.tx)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0, log.transactions_after(&second).expect("txs").len());
+
+        // Transactions on another branch are neither in the log nor allowed to become its head.
+        let branch = put(log, &first, 65538);
+        match log
+            .transactions_after(&branch)
+            .expect_err("not an ancestor")
+        {
+            MentatError::TolstoyError(TolstoyError::BadRemoteState(_)) => (),
+            e => panic!("wrong error: {:?}", e),
+        }
+        match log.set_head(&branch).expect_err("not a fast-forward") {
+            MentatError::TolstoyError(TolstoyError::RemoteHeadMoved(new, current)) => {
+                assert_eq!(branch, new);
+                assert_eq!(second, current);
+            }
+            e => panic!("wrong error: {:?}", e),
+        }
+        assert_eq!(second, log.head().expect("head"));
+
+        // Uploads can be repeated, but not changed.
+        let chunk = Uuid::new_v4();
+        log.put_chunk(&chunk, &part(65539)).expect("put chunk");
+        log.put_chunk(&chunk, &part(65539))
+            .expect("put chunk again");
+        match log
+            .put_chunk(&chunk, &part(65540))
+            .expect_err("changed chunk")
+        {
+            MentatError::TolstoyError(TolstoyError::UploadConflict(kind, uuid)) => {
+                assert_eq!("chunks", kind);
+                assert_eq!(chunk, uuid);
+            }
+            e => panic!("wrong error: {:?}", e),
+        }
+        log.put_transaction(&second, &first, &[])
+            .expect_err("changed transaction");
+        assert_eq!(
+            vec![part(65537)],
+            log.transactions_after(&first).expect("txs")[0].parts
+        );
+
+        let timeline = Uuid::new_v4();
+        log.put_timeline(&timeline, &first, &[vec![Uuid::new_v4()]])
+            .expect("put timeline");
+        log.put_timeline(&timeline, &second, &[])
+            .expect_err("changed timeline");
+    }
+
+    #[test]
+    fn test_directory_log() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let user = Uuid::new_v4();
+        exercise(&mut DirectoryLog::open(dir.path(), &user).expect("opened"));
+
+        // Users don't share logs, and reopening finds the same log.
+        let other = DirectoryLog::open(dir.path(), &Uuid::new_v4()).expect("opened");
+        assert_eq!(Uuid::nil(), other.head().expect("head"));
+        let reopened = DirectoryLog::open(dir.path(), &user).expect("opened");
+        assert_eq!(
+            2,
+            reopened
+                .transactions_after(&Uuid::nil())
+                .expect("txs")
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_directory_log_concurrent_writers() {
+        use std::sync::{Arc, Barrier};
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let user = Uuid::new_v4();
+
+        // Two devices branch from the same head, and race to publish their branches.
+        let barrier = Arc::new(Barrier::new(2));
+        let handles: Vec<_> = (0..2)
+            .map(|i| {
+                let mut log = DirectoryLog::open(dir.path(), &user).expect("opened");
+                let tx = put(&mut log, &Uuid::nil(), 65536 + i);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    (tx, log.set_head(&tx))
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|h| h.join().expect("joined"))
+            .collect();
+
+        let winners: Vec<Uuid> = results
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(tx, _)| *tx)
+            .collect();
+        assert_eq!(1, winners.len());
+        for (_, result) in results.iter().filter(|(_, result)| result.is_err()) {
+            match result {
+                Err(MentatError::TolstoyError(TolstoyError::RemoteHeadMoved(_, current))) => {
+                    assert_eq!(winners[0], *current)
+                }
+                e => panic!("wrong result: {:?}", e),
+            }
+        }
+
+        let log = DirectoryLog::open(dir.path(), &user).expect("opened");
+        assert_eq!(winners[0], log.head().expect("head"));
+        assert!(!dir.path().join(user.to_string()).join("head.lock").exists());
+    }
+
+    #[test]
+    fn test_sqlite_log() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("log.db");
+        let user = Uuid::new_v4();
+        exercise(&mut SqliteLog::open(&path, &user).expect("opened"));
+
+        let other = SqliteLog::open(&path, &Uuid::new_v4()).expect("opened");
+        assert_eq!(Uuid::nil(), other.head().expect("head"));
+        let reopened = SqliteLog::open(&path, &user).expect("opened");
+        assert_eq!(
+            2,
+            reopened
+                .transactions_after(&Uuid::nil())
+                .expect("txs")
+                .len()
+        );
+    }
+}
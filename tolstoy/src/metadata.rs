@@ -37,6 +37,17 @@ pub struct SyncMetadata {
     pub head: Entid,
 }
 
+/// A merge that settled conflicts between local and remote changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergeRecord {
+    /// The local transaction that concluded the merge.
+    pub tx: Entid,
+    /// The remote head that local transactions were rebased on top of.
+    pub remote_head: Uuid,
+    /// The uploaded timeline of local transactions as they were before the merge.
+    pub timeline: Uuid,
+}
+
 pub enum PartitionsTable {
     Core,
     Tolstoy,
@@ -105,6 +116,53 @@ impl SyncMetadata {
         }
     }
 
+    /// Record `partitions` as the partitions at the end of the last sync.
+    pub fn set_partitions(tx: &rusqlite::Transaction<'_>, partitions: &PartitionMap) -> Result<()> {
+        let mut stmt = tx.prepare(
+            "INSERT OR REPLACE INTO tolstoy_parts (part, start, end, idx, allow_excision) VALUES (?, ?, ?, ?, ?)",
+        )?;
+        for (name, partition) in partitions.iter() {
+            stmt.execute(rusqlite::params![
+                name,
+                partition.start,
+                partition.end,
+                partition.next_entid(),
+                partition.allow_excision
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub fn record_merge(tx: &rusqlite::Transaction<'_>, merge: &MergeRecord) -> Result<()> {
+        tx.execute(
+            "INSERT INTO tolstoy_merges (tx, remote_head, timeline) VALUES (?, ?, ?)",
+            rusqlite::params![
+                &merge.tx,
+                &merge.remote_head.as_bytes().to_vec(),
+                &merge.timeline.as_bytes().to_vec()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Merges that settled conflicts, oldest first.
+    pub fn merges(tx: &rusqlite::Transaction<'_>) -> Result<Vec<MergeRecord>> {
+        let mut stmt =
+            tx.prepare("SELECT tx, remote_head, timeline FROM tolstoy_merges ORDER BY tx")?;
+        let merges: Result<Vec<MergeRecord>> = stmt
+            .query_and_then(rusqlite::params![], |row| -> Result<MergeRecord> {
+                let remote_head: Vec<u8> = row.get(1)?;
+                let timeline: Vec<u8> = row.get(2)?;
+                Ok(MergeRecord {
+                    tx: row.get(0)?,
+                    remote_head: Uuid::from_slice(remote_head.as_slice())?,
+                    timeline: Uuid::from_slice(timeline.as_slice())?,
+                })
+            })?
+            .collect();
+        merges
+    }
+
     pub fn root_and_head_tx(tx: &rusqlite::Transaction<'_>) -> Result<(Entid, Entid)> {
         let mut stmt: ::rusqlite::Statement<'_> = tx.prepare(
             "SELECT tx FROM timelined_transactions WHERE timeline = 0 GROUP BY tx ORDER BY tx",
@@ -10,18 +10,39 @@
 
 #![allow(dead_code)]
 
-use hyper::{body, header, Body, Client, Method, Request, StatusCode};
+use std::thread;
+use std::time::Duration;
+
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{body, header, Body, Client, Method, Request, StatusCode, Uri};
 use hyper_tls::HttpsConnector;
 // TODO: https://github.com/mozilla/mentat/issues/570
 // use serde_cbor;
-use futures::executor::block_on;
+use serde::de::DeserializeOwned;
+use tokio::runtime::Runtime;
 use uuid::Uuid;
 
 use crate::logger::d;
-use public_traits::errors::Result;
+use public_traits::errors::{MentatError, Result};
+use tolstoy_traits::errors::TolstoyError;
 
 use crate::types::{GlobalTransactionLog, Tx, TxPart};
 
+lazy_static! {
+    /// hyper drives its connections with Tokio. Every `RemoteClient` runs its requests here, and
+    /// shares one pool of connections.
+    static ref RUNTIME: Runtime = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .core_threads(2)
+        .thread_name("tolstoy-remote-client")
+        .enable_all()
+        .build()
+        .expect("Tokio runtime");
+    static ref CLIENT: Client<HttpsConnector<HttpConnector>> =
+        Client::builder().build(HttpsConnector::new());
+}
+
 #[derive(Serialize, Deserialize)]
 struct SerializedHead {
     head: Uuid,
@@ -33,6 +54,12 @@ struct SerializedTransaction<'a> {
     chunks: &'a [Uuid],
 }
 
+#[derive(Serialize)]
+struct SerializedTimeline<'a> {
+    parent: &'a Uuid,
+    transactions: &'a [Vec<Uuid>],
+}
+
 #[derive(Deserialize)]
 struct DeserializableTransaction {
     parent: Uuid,
@@ -48,9 +75,45 @@ struct SerializedTransactions {
     transactions: Vec<Uuid>,
 }
 
+/// Percent-encode everything but RFC 3986's unreserved characters.
+fn escape_path_segment(segment: &str) -> String {
+    let mut escaped = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                escaped.push(byte as char)
+            }
+            _ => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+/// Whether trying the same request again might succeed: the connection failed or timed out, or the
+/// server is overloaded or failed itself.
+fn is_transient(error: &MentatError) -> bool {
+    match error {
+        MentatError::TolstoyError(TolstoyError::NetworkError(e)) => !e.is_user() && !e.is_parse(),
+        MentatError::TolstoyError(TolstoyError::RequestTimedOut(_)) => true,
+        MentatError::TolstoyError(TolstoyError::UnexpectedStatus(_, status)) => {
+            *status == StatusCode::TOO_MANY_REQUESTS.as_u16() || *status >= 500
+        }
+        _ => false,
+    }
+}
+
+/// A `GlobalTransactionLog` on a server, which `RemoteClient` talks to over HTTP.
+///
+/// Requests that fail in ways that might be temporary are retried, waiting twice as long before
+/// each retry as before the last. By default a request is tried up to four times, waiting a
+/// quarter of a second before the first retry, and each attempt times out after thirty seconds.
 pub struct RemoteClient {
     base_uri: String,
     user_uuid: Uuid,
+    headers: HeaderMap,
+    timeout: Duration,
+    retries: usize,
+    backoff: Duration,
 }
 
 impl RemoteClient {
@@ -58,162 +121,163 @@ impl RemoteClient {
         RemoteClient {
             base_uri,
             user_uuid,
+            headers: HeaderMap::new(),
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(250),
         }
     }
 
-    fn bound_base_uri(&self) -> String {
-        // TODO escaping
-        format!("{}/{}", self.base_uri, self.user_uuid)
+    /// Send `header` with every request.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
     }
 
-    // TODO what we want is a method that returns a deserialized json structure.
-    // It'll need a type T so that consumers can specify what downloaded json will map to. I ran
-    // into borrow issues doing that - probably need to restructure this and use PhantomData markers
-    // or somesuch. But for now, we get code duplication.
-    fn get_uuid(&self, uri: String) -> Result<Uuid> {
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, Body>(https);
-
-        d(&"client".to_string());
-
-        let uri = uri.parse()?;
-
-        d(&format!("GET {:?}", uri));
-
-        let work = async {
-            let res = client.get(uri).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            dbg!("response.status: {}", res.status());
+    /// Authenticate every request with `token`.
+    pub fn with_bearer_token(self, token: &str) -> Result<Self> {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| TolstoyError::InvalidHeader(header::AUTHORIZATION.to_string()))?;
+        Ok(self.with_header(header::AUTHORIZATION, value))
+    }
 
-            let body_bytes = body::to_bytes(res.into_body()).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            let body =
-                String::from_utf8(body_bytes.to_vec()).expect("response was not valid utf-8");
-            let json: SerializedHead = serde_json::from_str(&body)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            Ok(json.head)
-        };
-        block_on(work)
+    /// Give up on an attempt at a request after `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
     }
 
-    fn put<T>(&self, uri: String, payload: T, expected: StatusCode) -> Result<()>
-    where
-        hyper::Body: std::convert::From<T>,
-    {
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, Body>(https);
+    /// Retry a request up to `retries` times, waiting `backoff` before the first retry.
+    pub fn with_retries(mut self, retries: usize, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
 
-        d(&format!("PUT {:?}", uri));
+    fn bound_base_uri(&self) -> String {
+        format!(
+            "{}/{}",
+            self.base_uri.trim_end_matches('/'),
+            escape_path_segment(&self.user_uuid.to_string())
+        )
+    }
 
-        let req = Request::builder()
-            .method(Method::PUT)
-            .uri(uri)
-            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
-            .body(payload.into())
-            .unwrap();
+    /// Send a request, retrying it if it fails in a way that might be temporary, and return the
+    /// body of a response with the `expected` status.
+    fn request(
+        &self,
+        method: Method,
+        uri: String,
+        payload: Option<String>,
+        expected: StatusCode,
+    ) -> Result<body::Bytes> {
+        let uri: Uri = uri.parse().map_err(TolstoyError::from)?;
+        let mut backoff = self.backoff;
+        let mut retries = 0;
+        loop {
+            d(&format!("{} {:?}", method, uri));
+            match self.attempt(&method, &uri, payload.clone(), expected) {
+                Err(ref e) if retries < self.retries && is_transient(e) => {
+                    d(&format!(
+                        "{} {:?} failed, retrying in {:?}: {}",
+                        method, uri, backoff, e
+                    ));
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
 
-        let work = async {
-            let res = client.request(req).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            let status_code = res.status();
+    fn attempt(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        payload: Option<String>,
+        expected: StatusCode,
+    ) -> Result<body::Bytes> {
+        let is_json = payload.is_some();
+        let mut request = Request::new(payload.map_or_else(Body::empty, Body::from));
+        *request.method_mut() = method.clone();
+        *request.uri_mut() = uri.clone();
+        *request.headers_mut() = self.headers.clone();
+        if is_json {
+            request.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+        }
 
-            if status_code != expected {
-                d(&format!("bad put response: {:?}", status_code));
-            }
-            Ok(())
+        let timeout = self.timeout;
+        let work = async move {
+            let response = CLIENT.request(request).await?;
+            let status = response.status();
+            let body = body::to_bytes(response.into_body()).await?;
+            Ok::<_, hyper::Error>((status, body))
         };
-        block_on(work)
+        let response = futures::executor::block_on(
+            RUNTIME.spawn(async move { tokio::time::timeout(timeout, work).await }),
+        )
+        .map_err(|e| TolstoyError::UnexpectedState(e.to_string()))?;
+
+        let described = || format!("{} {}", method, uri);
+        match response {
+            Err(_) => bail!(TolstoyError::RequestTimedOut(described())),
+            Ok(Err(e)) => bail!(TolstoyError::NetworkError(e)),
+            Ok(Ok((status, body))) if status == expected => Ok(body),
+            Ok(Ok((status, _))) => {
+                bail!(TolstoyError::UnexpectedStatus(described(), status.as_u16()))
+            }
+        }
     }
 
-    fn get_transactions(&self, parent_uuid: &Uuid) -> Result<Vec<Uuid>> {
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, Body>(https);
+    fn get<T: DeserializeOwned>(&self, uri: String) -> Result<T> {
+        let body = self.request(Method::GET, uri, None, StatusCode::OK)?;
+        Ok(serde_json::from_slice(&body).map_err(TolstoyError::from)?)
+    }
 
-        d(&"client".to_string());
+    fn put(&self, uri: String, payload: String, expected: StatusCode) -> Result<()> {
+        self.request(Method::PUT, uri, Some(payload), expected)?;
+        Ok(())
+    }
 
+    fn get_transactions(&self, parent_uuid: &Uuid) -> Result<Vec<Uuid>> {
         let uri = format!(
             "{}/transactions?from={}",
             self.bound_base_uri(),
-            parent_uuid
+            escape_path_segment(&parent_uuid.to_string())
         );
-        let uri = uri.parse()?;
-
-        d(&format!("GET {:?}", uri));
-
-        let work = async {
-            let res = client.get(uri).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            dbg!("response.status: {}", res.status());
-
-            let body_bytes = body::to_bytes(res.into_body()).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            let body =
-                String::from_utf8(body_bytes.to_vec()).expect("response was not valid utf-8");
-            let json: SerializedTransactions = serde_json::from_str(&body)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            d(&format!("got transactions: {:?}", &json.transactions));
-            Ok(json.transactions)
-        };
-        block_on(work)
+        let json: SerializedTransactions = self.get(uri)?;
+        d(&format!("got transactions: {:?}", &json.transactions));
+        Ok(json.transactions)
     }
 
     fn get_chunks(&self, transaction_uuid: &Uuid) -> Result<Vec<Uuid>> {
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, Body>(https);
-
-        d(&"client".to_string());
-
         let uri = format!(
             "{}/transactions/{}",
             self.bound_base_uri(),
             transaction_uuid
         );
-        let uri = uri.parse()?;
-
-        d(&format!("GET {:?}", uri));
-
-        let work = async {
-            let res = client.get(uri).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            dbg!("response.status: {}", res.status());
-
-            let body_bytes = body::to_bytes(res.into_body()).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            let body =
-                String::from_utf8(body_bytes.to_vec()).expect("response was not valid utf-8");
-            let json: DeserializableTransaction = serde_json::from_str(&body)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-            d(&format!("got transaction chunks: {:?}", &json.chunks));
-            Ok(json.chunks)
-        };
-        block_on(work)
+        let json: DeserializableTransaction = self.get(uri)?;
+        d(&format!("got transaction chunks: {:?}", &json.chunks));
+        Ok(json.chunks)
     }
 
     fn get_chunk(&self, chunk_uuid: &Uuid) -> Result<TxPart> {
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, Body>(https);
-
-        d(&"client".to_string());
-
         let uri = format!("{}/chunks/{}", self.bound_base_uri(), chunk_uuid);
-        let uri = uri.parse()?;
-
-        d(&format!("GET {:?}", uri));
-
-        let work = async {
-            let res = client.get(uri).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            dbg!("response.status: {}", res.status());
-
-            let body_bytes = body::to_bytes(res.into_body()).await.unwrap(); // TODO use '?' fix From hyper::Error to MentatError;
-            let body =
-                String::from_utf8(body_bytes.to_vec()).expect("response was not valid utf-8");
-            let json: TxPart = serde_json::from_str(&body)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            d(&format!("got transaction chunk: {:?}", &json));
-            Ok(json)
-        };
-        block_on(work)
+        let json: TxPart = self.get(uri)?;
+        d(&format!("got transaction chunk: {:?}", &json));
+        Ok(json)
     }
 }
 
 impl GlobalTransactionLog for RemoteClient {
     fn head(&self) -> Result<Uuid> {
         let uri = format!("{}/head", self.bound_base_uri());
-        self.get_uuid(uri)
+        let json: SerializedHead = self.get(uri)?;
+        Ok(json.head)
     }
 
     fn set_head(&mut self, uuid: &Uuid) -> Result<()> {
@@ -223,7 +287,15 @@ impl GlobalTransactionLog for RemoteClient {
         let uri = format!("{}/head", self.bound_base_uri());
         let json = serde_json::to_string(&head)?;
         d(&format!("serialized head: {:?}", json));
-        self.put(uri, json, StatusCode::NO_CONTENT)
+        match self.put(uri, json, StatusCode::NO_CONTENT) {
+            // The server only moves the head forward, and it's somewhere we haven't seen.
+            Err(MentatError::TolstoyError(TolstoyError::UnexpectedStatus(_, status)))
+                if status == StatusCode::CONFLICT.as_u16() =>
+            {
+                bail!(TolstoyError::RemoteHeadMoved(*uuid, self.head()?))
+            }
+            result => result,
+        }
     }
 
     /// Slurp transactions and datoms after `tx`, returning them as owned data.
@@ -285,6 +357,19 @@ impl GlobalTransactionLog for RemoteClient {
         // TODO don't want to clone every datom!
         self.put(uri, payload, StatusCode::CREATED)
     }
+
+    fn put_timeline(&mut self, timeline: &Uuid, parent_tx: &Uuid, txs: &[Vec<Uuid>]) -> Result<()> {
+        // {"parent": uuid, "transactions": [[chunk1, chunk2...], ...]}
+        let timeline_json = SerializedTimeline {
+            parent: parent_tx,
+            transactions: txs,
+        };
+
+        let uri = format!("{}/timelines/{}", self.bound_base_uri(), timeline);
+        let json = serde_json::to_string(&timeline_json)?;
+        d(&format!("serialized timeline: {:?}", json));
+        self.put(uri, json, StatusCode::CREATED)
+    }
 }
 
 #[cfg(test)]
@@ -301,5 +386,55 @@ mod tests {
             "https://example.com/api/0.1/316ea470-ce35-4adf-9c61-e0de6e289c59",
             remote_client.bound_base_uri()
         );
+
+        let server_uri = String::from("https://example.com/api/0.1/");
+        let remote_client = RemoteClient::new(server_uri, user_uuid);
+        assert_eq!(
+            "https://example.com/api/0.1/316ea470-ce35-4adf-9c61-e0de6e289c59",
+            remote_client.bound_base_uri()
+        );
+    }
+
+    #[test]
+    fn test_escape_path_segment() {
+        assert_eq!(
+            "316ea470-ce35-4adf-9c61-e0de6e289c59",
+            escape_path_segment("316ea470-ce35-4adf-9c61-e0de6e289c59")
+        );
+        assert_eq!("a%2Fb%20c%3F%25~", escape_path_segment("a/b c?%~"));
+        assert_eq!("%C3%A9", escape_path_segment("é"));
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        let status = |status: u16| {
+            MentatError::from(TolstoyError::UnexpectedStatus("GET /".to_string(), status))
+        };
+        assert!(is_transient(&status(503)));
+        assert!(is_transient(&status(429)));
+        assert!(!is_transient(&status(404)));
+        assert!(!is_transient(&status(409)));
+        assert!(is_transient(&MentatError::from(
+            TolstoyError::RequestTimedOut("GET /".to_string())
+        )));
+        assert!(!is_transient(&MentatError::from(
+            TolstoyError::BadRemoteState("".to_string())
+        )));
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let remote_client = RemoteClient::new("https://example.com".to_string(), Uuid::nil())
+            .with_bearer_token("secret")
+            .expect("valid token");
+        assert_eq!(
+            Some(&HeaderValue::from_static("Bearer secret")),
+            remote_client.headers.get(header::AUTHORIZATION)
+        );
+        assert!(
+            RemoteClient::new("https://example.com".to_string(), Uuid::nil())
+                .with_bearer_token("line\nbreak")
+                .is_err()
+        );
     }
 }
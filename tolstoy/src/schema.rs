@@ -21,12 +21,15 @@ lazy_static! {
     /// SQL statements to be executed, in order, to create the Tolstoy SQL schema (version 1).
     /// "tolstoy_parts" records what the partitions were at the end of last sync, and is used
     /// as a "root partition" during renumbering (a three-way merge of partitions).
+    /// "tolstoy_merges" records the merge transactions that settled conflicts, along with the
+    /// remote head that was merged and the uploaded timeline that local transactions were on.
     #[cfg_attr(rustfmt, rustfmt_skip)]
     static ref SCHEMA_STATEMENTS: Vec<&'static str> = { vec![
         "CREATE TABLE IF NOT EXISTS tolstoy_tu (tx INTEGER PRIMARY KEY, uuid BLOB NOT NULL UNIQUE) WITHOUT ROWID",
         "CREATE TABLE IF NOT EXISTS tolstoy_metadata (key BLOB NOT NULL UNIQUE, value BLOB NOT NULL)",
         "CREATE TABLE IF NOT EXISTS tolstoy_parts (part TEXT NOT NULL PRIMARY KEY, start INTEGER NOT NULL, end INTEGER NOT NULL, idx INTEGER NOT NULL, allow_excision SMALLINT NOT NULL)",
         "CREATE INDEX IF NOT EXISTS idx_tolstoy_tu_ut ON tolstoy_tu (uuid, tx)",
+        "CREATE TABLE IF NOT EXISTS tolstoy_merges (tx INTEGER PRIMARY KEY, remote_head BLOB NOT NULL, timeline BLOB NOT NULL)",
         ]
     };
 }
@@ -0,0 +1,142 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Three-way merging of attribute definitions.
+//!
+//! While merging, local transactions are rebased on top of remote ones. Before that happens we
+//! know three versions of the schema: the shared root ("base"), the local schema, and the remote
+//! schema. Each property of each local attribute -- its cardinality, uniqueness, and so on -- is
+//! merged separately: a property that only one side changed takes that side's value, and a
+//! property that both sides changed must have been changed to the same value. Attributes that
+//! both sides installed independently must be defined in the same way.
+//!
+//! The merged definitions are deterministic: they don't depend on which side does the merging.
+
+use std::collections::BTreeMap;
+
+use core_traits::{Attribute, Entid};
+
+use mentat_core::{HasSchema, Schema};
+
+use public_traits::errors::Result;
+
+use tolstoy_traits::errors::TolstoyError;
+
+/// The entid in `current`, a schema built on top of `base`, of the local attribute `e`. Attributes
+/// in the shared root have the same entid everywhere; attributes installed since might not, and
+/// are found by their ident. `None` if `current` doesn't know the attribute.
+pub fn attribute_entid(base: &Schema, local: &Schema, current: &Schema, e: Entid) -> Option<Entid> {
+    if base.attribute_map.contains_key(&e) {
+        return Some(e);
+    }
+    local
+        .get_ident(e)
+        .and_then(|ident| current.get_entid(ident))
+        .map(|known| known.0)
+}
+
+/// The definitions that local attributes should have once local transactions are rebased on top
+/// of remote ones, keyed by local entid. Fails if both sides changed a property in different ways.
+pub fn merge_attributes(
+    base: &Schema,
+    local: &Schema,
+    remote: &Schema,
+) -> Result<BTreeMap<Entid, Attribute>> {
+    let mut merged = BTreeMap::new();
+    for (&e, local_attribute) in local.attribute_map.iter() {
+        let name = attribute_name(local, e);
+        let base_attribute = base.attribute_map.get(&e);
+        let remote_attribute = attribute_entid(base, local, remote, e)
+            .and_then(|remote_e| remote.attribute_map.get(&remote_e));
+        merged.insert(
+            e,
+            merge_attribute(&name, base_attribute, local_attribute, remote_attribute)?,
+        );
+    }
+    Ok(merged)
+}
+
+/// Check that rebasing produced the definitions that `merge_attributes` expected. A local
+/// transaction can change a property and a later one change it back, which leaves nothing to merge
+/// but still overwrites a remote change when both are replayed.
+pub fn verify_merged_attributes(
+    base: &Schema,
+    local: &Schema,
+    current: &Schema,
+    merged: &BTreeMap<Entid, Attribute>,
+) -> Result<()> {
+    for (&e, expected) in merged.iter() {
+        let actual = attribute_entid(base, local, current, e)
+            .and_then(|current_e| current.attribute_map.get(&current_e));
+        // With no common ancestor, any difference is a conflict.
+        merge_attribute(&attribute_name(local, e), None, expected, actual)?;
+    }
+    Ok(())
+}
+
+fn attribute_name(schema: &Schema, e: Entid) -> String {
+    schema
+        .get_ident(e)
+        .map_or_else(|| e.to_string(), |ident| ident.to_string())
+}
+
+fn merge_attribute(
+    name: &str,
+    base: Option<&Attribute>,
+    local: &Attribute,
+    remote: Option<&Attribute>,
+) -> Result<Attribute> {
+    let remote = match remote {
+        Some(remote) if remote != local => remote,
+        _ => return Ok(local.clone()),
+    };
+
+    macro_rules! merge {
+        ($field:ident, $property:expr) => {
+            merge_property(
+                name,
+                $property,
+                base.map(|a| &a.$field),
+                &local.$field,
+                &remote.$field,
+            )?
+        };
+    }
+
+    Ok(Attribute {
+        value_type: merge!(value_type, ":db/valueType"),
+        multival: merge!(multival, ":db/cardinality"),
+        unique: merge!(unique, ":db/unique"),
+        index: merge!(index, ":db/index"),
+        fulltext: merge!(fulltext, ":db/fulltext"),
+        fulltext_tokenizer: merge!(fulltext_tokenizer, ":db/fulltextTokenizer"),
+        component: merge!(component, ":db/isComponent"),
+        no_history: merge!(no_history, ":db/noHistory"),
+    })
+}
+
+fn merge_property<T: Clone + PartialEq>(
+    name: &str,
+    property: &str,
+    base: Option<&T>,
+    local: &T,
+    remote: &T,
+) -> Result<T> {
+    if local == remote || base == Some(remote) {
+        Ok(local.clone())
+    } else if base == Some(local) {
+        Ok(remote.clone())
+    } else {
+        bail!(TolstoyError::SchemaAlterationConflict(
+            name.to_string(),
+            property.to_string()
+        ))
+    }
+}
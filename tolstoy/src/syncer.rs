@@ -10,7 +10,7 @@
 
 use std::fmt;
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use uuid::Uuid;
 
@@ -18,6 +18,7 @@ use core_traits::{Entid, KnownEntid, TypedValue};
 
 use edn::entities::{EntityPlace, LookupRef, TxFunction};
 use edn::PlainSymbol;
+use mentat_core::{DateTime, HasSchema, Utc};
 use mentat_db::{entids, timelines, PartitionMap, CORE_SCHEMA_VERSION};
 use mentat_transaction::{InProgress, Queryable, TermBuilder};
 
@@ -26,11 +27,13 @@ use mentat_transaction::entity_builder::BuildTerms;
 use mentat_transaction::query::{QueryInputs, Variable};
 
 use crate::bootstrap::BootstrapHelper;
+use crate::conflict::{Conflict, ConflictPolicy, Resolution};
 
 use public_traits::errors::Result;
 
-use crate::metadata::{PartitionsTable, SyncMetadata};
+use crate::metadata::{MergeRecord, PartitionsTable, SyncMetadata};
 use crate::schema::ensure_current_version;
+use crate::schema_merge;
 use crate::tx_mapper::TxMapper;
 use crate::tx_processor::{Processor, TxReceiver};
 use crate::tx_uploader::TxUploader;
@@ -324,6 +327,63 @@ impl Syncer {
         }
     }
 
+    /// The `:db/txInstant` of a transaction, given its parts.
+    fn tx_instant(parts: &[TxPart]) -> Result<DateTime<Utc>> {
+        for part in parts {
+            if let (entids::DB_TX_INSTANT, TypedValue::Instant(instant)) = (part.a, &part.v) {
+                return Ok(*instant);
+            }
+        }
+        bail!(TolstoyError::BadRemoteState(
+            "Missing txInstant in a transaction".to_string()
+        ))
+    }
+
+    /// Remember the last value asserted for each entity and attribute, and when, so that local
+    /// and remote changes can be compared. Changes to metadata, like the schema, are merged
+    /// separately (see `schema_merge`).
+    fn note_writes(
+        writes: &mut BTreeMap<(Entid, Entid), (TypedValue, DateTime<Utc>)>,
+        parts: &[TxPart],
+    ) -> Result<()> {
+        let instant = Syncer::tx_instant(parts)?;
+        for part in parts {
+            if part.a == entids::DB_TX_INSTANT || entids::might_update_metadata(part.a) {
+                continue;
+            }
+            let key = (part.e, part.a);
+            if part.added {
+                writes.insert(key, (part.v.clone(), instant));
+            } else if writes.get(&key).is_some_and(|(v, _)| *v == part.v) {
+                writes.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload local transactions as they were before a merge rewrote them.
+    fn upload_timeline<R>(
+        remote_client: &mut R,
+        timeline: &Uuid,
+        parent_tx: &Uuid,
+        txs: &[LocalTx],
+    ) -> Result<()>
+    where
+        R: GlobalTransactionLog,
+    {
+        let mut chunked_txs = Vec::with_capacity(txs.len());
+        for tx in txs {
+            let mut chunks = Vec::with_capacity(tx.parts.len());
+            for part in &tx.parts {
+                let chunk = Uuid::new_v4();
+                remote_client.put_chunk(&chunk, part)?;
+                chunks.push(chunk);
+            }
+            chunked_txs.push(chunks);
+        }
+        remote_client.put_timeline(timeline, parent_tx, &chunked_txs)
+    }
+
     fn fast_forward_local<'a, 'c>(
         in_progress: &mut InProgress<'a, 'c>,
         txs: Vec<Tx>,
@@ -369,16 +429,28 @@ impl Syncer {
         Ok(SyncReport::LocalFastForward)
     }
 
-    fn merge(
+    fn merge<R>(
         ip: &mut InProgress<'_, '_>,
+        remote_client: &mut R,
+        policy: &ConflictPolicy,
         incoming_txs: Vec<Tx>,
         mut local_txs_to_merge: Vec<LocalTx>,
-    ) -> Result<SyncReport> {
+    ) -> Result<SyncReport>
+    where
+        R: GlobalTransactionLog,
+    {
         d(&"Rewinding local transactions.".to_string());
 
         // 1) Rewind local to shared root.
         local_txs_to_merge.sort(); // TODO sort at the interface level?
 
+        let shared_root = SyncMetadata::remote_head(&ip.transaction)?;
+        let local_schema = ip.schema.clone();
+        let mut local_writes = BTreeMap::new();
+        for local_tx in &local_txs_to_merge {
+            Syncer::note_writes(&mut local_writes, &local_tx.parts)?;
+        }
+
         let (new_schema, new_partition_map) = timelines::move_from_main_timeline(
             &ip.transaction,
             &ip.schema,
@@ -392,15 +464,21 @@ impl Syncer {
             ip.schema = schema
         };
         ip.partition_map = new_partition_map;
+        let base_schema = ip.schema.clone();
+        let base_partition_map = ip.partition_map.clone();
+        let existed_at_base = |e: Entid| base_partition_map.values().any(|p| p.contains_entid(e));
 
         // 2) Transact incoming.
         // 2.1) Prepare remote tx tuples (TermBuilder, PartitionMap, Uuid), which represent
         // a remote transaction, its global identifier and partitions after it's applied.
         d(&"Transacting incoming...".to_string());
         let mut builders = vec![];
+        let mut remote_writes = BTreeMap::new();
         for remote_tx in incoming_txs {
             let mut builder = TermBuilder::new();
 
+            Syncer::note_writes(&mut remote_writes, &remote_tx.parts)?;
+
             let partition_map = match remote_tx.parts[0].partitions.clone() {
                 Some(parts) => parts,
                 None => {
@@ -427,6 +505,75 @@ impl Syncer {
             remote_report = Some((ip.transact_builder(builder)?.tx_id, remote_tx));
         }
 
+        // Local and remote might have both installed or altered the same attributes.
+        // Work out what the attributes should look like once we're done, failing if
+        // the two sides changed them in incompatible ways.
+        let merged_attributes =
+            schema_merge::merge_attributes(&base_schema, &local_schema, &ip.schema)?;
+
+        // Both sides might have asserted different values of a cardinality-one attribute on the
+        // same entity. Settle these conflicts as the policy says: local assertions are dropped
+        // if remote's value should stand, and rewritten if another value should replace both.
+        // Remote writes are keyed by attribute entids on this side of the merge, which might
+        // differ from local ones for attributes installed since the shared root. Conflicts are
+        // described in terms of this side, but settled against the local datoms they replace.
+        let mut conflicts = vec![];
+        for (&(e, local_a), (local, local_instant)) in local_writes.iter() {
+            let a = match schema_merge::attribute_entid(
+                &base_schema,
+                &local_schema,
+                &ip.schema,
+                local_a,
+            ) {
+                Some(a) => a,
+                None => continue,
+            };
+            let (remote, remote_instant) = match remote_writes.get(&(e, a)) {
+                Some(remote_write) => remote_write,
+                None => continue,
+            };
+            let cardinality_one = ip
+                .schema
+                .attribute_map
+                .get(&a)
+                .is_some_and(|attribute| !attribute.multival);
+            if local == remote || !cardinality_one || !existed_at_base(e) {
+                continue;
+            }
+            conflicts.push((
+                local_a,
+                Conflict {
+                    e,
+                    a,
+                    local: local.clone(),
+                    local_instant: *local_instant,
+                    remote: remote.clone(),
+                    remote_instant: *remote_instant,
+                },
+            ));
+        }
+
+        let mut resolutions = HashMap::new();
+        for (local_a, conflict) in &conflicts {
+            let resolver = policy.resolver(ip.schema.get_ident(conflict.a));
+            let resolved = match resolver.resolve(conflict) {
+                Resolution::Local => continue,
+                Resolution::Remote => None,
+                Resolution::Value(ref v) if *v == conflict.remote => None,
+                Resolution::Value(v) => Some(v),
+            };
+            d(&format!("resolved {:?} as {:?}", conflict, resolved));
+            resolutions.insert((conflict.e, *local_a), resolved);
+        }
+
+        // Keep local transactions as they were, including any values that lost, for auditing.
+        // They're uploaded once the merge has succeeded.
+        let timeline = if conflicts.is_empty() {
+            None
+        } else {
+            Some((Uuid::new_v4(), local_txs_to_merge.clone()))
+        };
+
         d(&"Transacting local on top of incoming...".to_string());
         // 3) Rebase local transactions on top of remote.
         let mut clean_rebase = true;
@@ -441,55 +588,27 @@ impl Syncer {
             // In the latter case, rewrite it as a tempid, and let the transactor allocate it.
             let mut entids_that_will_allocate = HashSet::new();
 
-            // We currently support "strict schema merging": we'll smush attribute definitions,
-            // but only if they're the same.
-            // e.g. prohibited would be defining different cardinality for the same attribute.
-            // Defining new attributes is allowed if:
-            // - attribute is defined either on local or remote,
-            // - attribute is defined on both local and remote in the same way.
-            // Modifying an attribute is currently not supported (requires higher order schema migrations).
-            // Note that "same" local and remote attributes might have different entids in the
-            // two sets of transactions.
-
-            // Set of entities that may alter "installed" attribute.
-            // Since this is a rebase of local on top of remote, an "installed"
-            // attribute might be one that was present in the root, or one that was
-            // defined by remote.
-            let mut might_alter_installed_attributes = HashSet::new();
-
-            // Set of entities that describe a new attribute, not present in the root
-            // or on the remote.
-            let mut will_not_alter_installed_attribute = HashSet::new();
-
-            // Note that at this point, remote and local have flipped - we're transacting
-            // local on top of incoming (which are already in the schema).
-
-            // Go through local datoms, and classify any schema-altering entids into
-            // one of the two sets above.
+            // Entities that existed at the shared root are the same entities on both sides,
+            // and are referred to verbatim.
+            // Attribute definitions are merged one property at a time (see `schema_merge`).
+            // Attributes are referred to by their entid on this side of the merge, which might
+            // differ from the local one if the attribute was installed since the shared root,
+            // both when they're changed and when they're used.
+            // Attributes that aren't known yet are installed through a tempid: since :db/ident
+            // is unique, the transactor will upsert them if an earlier transaction installed them.
+            let mut known_entids = HashMap::new();
             for part in &local_tx.parts {
-                // If we have an ident definition locally, check if remote
-                // already defined this ident. If it did, we'll need to ensure
-                // both local and remote are defining it in the same way.
-                if part.a == entids::DB_IDENT {
-                    match part.v {
-                        TypedValue::Keyword(ref local_kw) => {
-                            // Remote did not define this ident. Make a note of it,
-                            // so that we'll know to ignore its attribute datoms.
-                            if !ip.schema.ident_map.contains_key(local_kw) {
-                                will_not_alter_installed_attribute.insert(part.e);
-
-                            // Otherwise, we'll need to ensure we have the same attribute definition
-                            // for it.
-                            } else {
-                                might_alter_installed_attributes.insert(part.e);
-                            }
-                        }
-                        _ => panic!("programming error: wrong value type for a local ident"),
+                if local_schema.attribute_map.contains_key(&part.e) {
+                    if let Some(e) = schema_merge::attribute_entid(
+                        &base_schema,
+                        &local_schema,
+                        &ip.schema,
+                        part.e,
+                    ) {
+                        known_entids.insert(part.e, e);
                     }
-                } else if entids::is_a_schema_attribute(part.a)
-                    && !will_not_alter_installed_attribute.contains(&part.e)
-                {
-                    might_alter_installed_attributes.insert(part.e);
+                } else if existed_at_base(part.e) {
+                    known_entids.insert(part.e, part.e);
                 }
             }
 
@@ -501,6 +620,9 @@ impl Syncer {
                     // We have preserved the original txInstant value on the alternate timeline.
                     entids::DB_TX_INSTANT => continue,
 
+                    // Known entities are changed in place.
+                    _ if known_entids.contains_key(&part.e) => continue,
+
                     // 'e's will be replaced with tempids, letting transactor handle everything.
                     // Non-unique entities are "duplicated". Unique entities are upserted.
                     _ => {
@@ -535,11 +657,26 @@ impl Syncer {
                 }
 
                 let e: EntityPlace<TypedValue>;
-                let a = KnownEntid(part.a);
-                let v = part.v;
+                let a = KnownEntid(
+                    schema_merge::attribute_entid(&base_schema, &local_schema, &ip.schema, part.a)
+                        .unwrap_or(part.a),
+                );
+                let mut v = part.v;
+
+                // Settled conflicts (see above).
+                if part.added {
+                    match resolutions.get(&(part.e, part.a)) {
+                        Some(None) => continue,
+                        Some(Some(resolved)) => v = resolved.clone(),
+                        None => (),
+                    }
+                }
 
+                // Refer to known entities by their entid on this side of the merge.
+                if let Some(&known_e) = known_entids.get(&part.e) {
+                    e = KnownEntid(known_e).into();
                 // Rewrite entids if they will allocate (see entity merging notes above).
-                if entids_that_will_allocate.contains(&part.e) {
+                } else if entids_that_will_allocate.contains(&part.e) {
                     e = builder.named_tempid(format!("{}", part.e)).into();
                 // Otherwise, refer to existing entities.
                 } else {
@@ -565,6 +702,24 @@ impl Syncer {
                             continue;
                         }
 
+                        // Retracting from a known entity, e.g. a property of an attribute like
+                        // its :db/unique, is a change like any other. Remote might have already
+                        // changed the datom, though, in which case there's nothing to retract.
+                        if let Some(&known_e) = known_entids.get(&part.e) {
+                            let asserted = ip.q_once(
+                                "[:find ?e . :in ?e ?a ?v :where [?e ?a ?v]]",
+                                QueryInputs::with_value_sequence(vec![
+                                    (Variable::from_valid_name("?e"), TypedValue::Ref(known_e)),
+                                    (Variable::from_valid_name("?a"), a.into()),
+                                    (Variable::from_valid_name("?v"), v.clone()),
+                                ]),
+                            )?;
+                            if !asserted.is_empty() {
+                                builder.retract(e, a, v)?;
+                            }
+                            continue;
+                        }
+
                         // TODO handle tempids in ValuePlace, as well.
 
                         // Retractions with non-upserting tempids are not currently supported.
@@ -576,7 +731,7 @@ impl Syncer {
                         // - we skip retractions of non-unique attributes,
                         // - we "pre-run" a lookup-ref to ensure it will resolve,
                         //   and skip the retraction otherwise.
-                        match ip.schema.attribute_map.get(&part.a) {
+                        match ip.schema.attribute_map.get(&a.0) {
                             Some(attributes) => {
                                 // A lookup-ref using a non-unique attribute will fail.
                                 // Skip this retraction, since we can't make sense of it.
@@ -631,26 +786,6 @@ impl Syncer {
 
             let report = ip.transact_builder(builder)?;
 
-            // Let's check that we didn't modify any schema attributes.
-            // Our current attribute map in the schema isn't rich enough to allow
-            // for this check: it's missing a notion of "attribute absence" - we can't
-            // distinguish between a missing attribute and a default value.
-            // Instead, we simply query the database, checking if transaction produced
-            // any schema-altering datoms.
-            for e in might_alter_installed_attributes.iter() {
-                if let Some(resolved_e) = report.tempids.get(&format!("{}", e)) {
-                    if SyncMetadata::has_entity_assertions_in_tx(
-                        &ip.transaction,
-                        *resolved_e,
-                        report.tx_id,
-                    )? {
-                        bail!(TolstoyError::NotYetImplemented(
-                            "Can't sync with schema alterations yet.".to_string()
-                        ));
-                    }
-                }
-            }
-
             if !SyncMetadata::is_tx_empty(&ip.transaction, report.tx_id)? {
                 d(&format!("tx {} is not a no-op", report.tx_id));
                 clean_rebase = false;
@@ -664,9 +799,31 @@ impl Syncer {
             }
         }
 
-        // TODO
+        // Replaying local transactions shouldn't have undone any remote alterations.
+        schema_merge::verify_merged_attributes(
+            &base_schema,
+            &local_schema,
+            &ip.schema,
+            &merged_attributes,
+        )?;
+
         // At this point, we've rebased local transactions on top of remote.
-        // This would be a good point to create a "merge commit" and upload our loosing timeline.
+        // If that settled any conflicts, conclude with a merge transaction, which the follow-up
+        // sync will upload, and remember which timeline the local transactions are on.
+        if let Some((timeline, _)) = timeline {
+            let report = ip.transact_builder(TermBuilder::new())?;
+            SyncMetadata::record_merge(
+                &ip.transaction,
+                &MergeRecord {
+                    tx: report.tx_id,
+                    remote_head: remote_report
+                        .as_ref()
+                        .map_or(shared_root, |&(_, uuid)| uuid),
+                    timeline,
+                },
+            )?;
+            clean_rebase = false;
+        }
 
         // Since we don't upload during a merge (instead, we request a follow-up sync),
         // set the locally known remote HEAD to what we received from the 'remote'.
@@ -674,6 +831,11 @@ impl Syncer {
             SyncMetadata::set_remote_head_and_map(&mut ip.transaction, (entid, &uuid).into())?;
         }
 
+        // Only now that nothing can fail the merge, upload the timeline it recorded.
+        if let Some((timeline, txs)) = timeline {
+            Syncer::upload_timeline(remote_client, &timeline, &shared_root, &txs)?;
+        }
+
         // If necessary, request a full sync as a follow-up to fast-forward remote.
         if clean_rebase {
             Ok(SyncReport::Merge(SyncFollowup::None))
@@ -684,7 +846,8 @@ impl Syncer {
 
     fn first_sync_against_non_empty<R>(
         ip: &mut InProgress<'_, '_>,
-        remote_client: &R,
+        remote_client: &mut R,
+        policy: &ConflictPolicy,
         local_metadata: &SyncMetadata,
     ) -> Result<SyncReport>
     where
@@ -764,6 +927,8 @@ impl Syncer {
                     &ip.transaction, Some(local_metadata.root), LocalTxSet::new())?;
                 Syncer::merge(
                     ip,
+                    remote_client,
+                    policy,
                     incoming_txs[1..].to_vec(),
                     local_txs,
                 )
@@ -772,6 +937,35 @@ impl Syncer {
     }
 
     pub fn sync<R>(ip: &mut InProgress<'_, '_>, remote_client: &mut R) -> Result<SyncReport>
+    where
+        R: GlobalTransactionLog,
+    {
+        Syncer::sync_with_policy(ip, remote_client, &ConflictPolicy::default())
+    }
+
+    /// Like `sync`, settling conflicts between local and remote changes as `policy` says.
+    pub fn sync_with_policy<R>(
+        ip: &mut InProgress<'_, '_>,
+        remote_client: &mut R,
+        policy: &ConflictPolicy,
+    ) -> Result<SyncReport>
+    where
+        R: GlobalTransactionLog,
+    {
+        let report = Syncer::sync_changes(ip, remote_client, policy)?;
+
+        // Remember what's been allocated so far. Transactions uploaded by the next sync
+        // might refer to these entids without allocating them, e.g. when altering an attribute.
+        SyncMetadata::set_partitions(&ip.transaction, &ip.partition_map)?;
+
+        Ok(report)
+    }
+
+    fn sync_changes<R>(
+        ip: &mut InProgress<'_, '_>,
+        remote_client: &mut R,
+        policy: &ConflictPolicy,
+    ) -> Result<SyncReport>
     where
         R: GlobalTransactionLog,
     {
@@ -794,7 +988,12 @@ impl Syncer {
 
         // Currently, first sync against a non-empty remote is special.
         if locally_known_remote_head == Uuid::nil() && remote_head != Uuid::nil() {
-            return Syncer::first_sync_against_non_empty(ip, remote_client, &local_metadata);
+            return Syncer::first_sync_against_non_empty(
+                ip,
+                remote_client,
+                policy,
+                &local_metadata,
+            );
         }
 
         match Syncer::what_do(remote_state, local_state) {
@@ -852,11 +1051,14 @@ impl Syncer {
                     Some(combine_local_from_tx),
                     LocalTxSet::new(),
                 )?;
+                // Remote txs to merge...
+                let remote_txs = remote_client.transactions_after(&locally_known_remote_head)?;
                 // Merge!
                 Syncer::merge(
                     ip,
-                    // Remote txs to merge...
-                    remote_client.transactions_after(&locally_known_remote_head)?,
+                    remote_client,
+                    policy,
+                    remote_txs,
                     // ... with the local txs.
                     local_txs,
                 )
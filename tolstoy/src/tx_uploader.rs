@@ -14,7 +14,7 @@ use uuid::Uuid;
 
 use core_traits::Entid;
 
-use mentat_db::{PartitionMap, V1_PARTS};
+use mentat_db::PartitionMap;
 
 use public_traits::errors::Result;
 
@@ -59,11 +59,10 @@ fn allocate_partition_map_for_entids<T>(entids: T, local_partitions: &PartitionM
 where
     T: Iterator<Item = Entid>,
 {
+    // Bootstrap partitions as well as any partitions installed locally.
     let mut parts = HashMap::new();
-    for name in V1_PARTS.iter().map(|&(ref part, ..)| part.to_string()) {
-        // This shouldn't fail: locally-sourced partitions must be present within with V1_PARTS.
-        let p = local_partitions.get(&name).unwrap();
-        parts.insert(name, (p, p.clone()));
+    for (name, p) in local_partitions.iter() {
+        parts.insert(name.clone(), (p, p.clone()));
     }
 
     // For a given partition, set its index to one greater than the largest encountered entid within its partition space.
@@ -104,10 +103,11 @@ impl<'c> TxReceiver<UploaderReport> for TxUploader<'c> {
 
         // TODO this should live within a transaction, once server support is in place.
         // For now, we're uploading the PartitionMap in transaction's first chunk.
-        datoms[0].partitions = Some(allocate_partition_map_for_entids(
-            datoms.iter().map(|d| d.e),
-            &self.local_partitions,
-        ));
+        // Each transaction's partitions include whatever earlier transactions allocated,
+        // even if it doesn't mention those entids itself (e.g. it only alters an attribute).
+        self.local_partitions =
+            allocate_partition_map_for_entids(datoms.iter().map(|d| d.e), &self.local_partitions);
+        datoms[0].partitions = Some(self.local_partitions.clone());
 
         // Upload all chunks.
         for datom in &datoms {
@@ -185,7 +185,7 @@ pub mod tests {
         let new_map = allocate_partition_map_for_entids(entids.into_iter(), &bootstrap_map);
         assert_eq!(65537, new_map.get(PARTITION_USER).unwrap().next_entid());
         // Other partitions are untouched.
-        assert_eq!(41, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(51, new_map.get(PARTITION_DB).unwrap().next_entid());
         assert_eq!(268435456, new_map.get(PARTITION_TX).unwrap().next_entid());
 
         // Only tx partition.
@@ -194,12 +194,12 @@ pub mod tests {
         assert_eq!(268435667, new_map.get(PARTITION_TX).unwrap().next_entid());
         // Other partitions are untouched.
         assert_eq!(65536, new_map.get(PARTITION_USER).unwrap().next_entid());
-        assert_eq!(41, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(51, new_map.get(PARTITION_DB).unwrap().next_entid());
 
         // Only DB partition.
-        let entids = vec![41];
+        let entids = vec![51];
         let new_map = allocate_partition_map_for_entids(entids.into_iter(), &bootstrap_map);
-        assert_eq!(42, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(52, new_map.get(PARTITION_DB).unwrap().next_entid());
         // Other partitions are untouched.
         assert_eq!(65536, new_map.get(PARTITION_USER).unwrap().next_entid());
         assert_eq!(268435456, new_map.get(PARTITION_TX).unwrap().next_entid());
@@ -210,13 +210,28 @@ pub mod tests {
         assert_eq!(65538, new_map.get(PARTITION_USER).unwrap().next_entid());
         assert_eq!(268435457, new_map.get(PARTITION_TX).unwrap().next_entid());
         // DB partition is untouched.
-        assert_eq!(41, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(51, new_map.get(PARTITION_DB).unwrap().next_entid());
 
         // DB, user and tx partitions.
-        let entids = vec![41, 65666, 268435457];
+        let entids = vec![51, 65666, 268435457];
         let new_map = allocate_partition_map_for_entids(entids.into_iter(), &bootstrap_map);
         assert_eq!(65667, new_map.get(PARTITION_USER).unwrap().next_entid());
         assert_eq!(268435458, new_map.get(PARTITION_TX).unwrap().next_entid());
-        assert_eq!(42, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(52, new_map.get(PARTITION_DB).unwrap().next_entid());
+
+        // Locally installed partitions.
+        let mut custom_map = bootstrap_map.clone();
+        custom_map.get_mut(PARTITION_USER).unwrap().end = 16777215;
+        custom_map.insert(
+            ":test.part/logs".to_string(),
+            Partition::new(16777216, 16842751, 16777216, false),
+        );
+        let entids = vec![65536, 16777220];
+        let new_map = allocate_partition_map_for_entids(entids.into_iter(), &custom_map);
+        assert_eq!(65537, new_map.get(PARTITION_USER).unwrap().next_entid());
+        assert_eq!(
+            16777221,
+            new_map.get(":test.part/logs").unwrap().next_entid()
+        );
     }
 }
@@ -86,4 +86,36 @@ pub trait GlobalTransactionLog {
     fn set_head(&mut self, tx: &Uuid) -> Result<()>;
     fn put_transaction(&mut self, tx: &Uuid, parent_tx: &Uuid, chunk_txs: &[Uuid]) -> Result<()>;
     fn put_chunk(&mut self, tx: &Uuid, payload: &TxPart) -> Result<()>;
+
+    /// Keep transactions that a merge rewrote, for auditing. Each transaction is a list of chunks,
+    /// the first made on top of `parent_tx`. They're stored as `timeline` and don't become part
+    /// of the log.
+    fn put_timeline(&mut self, timeline: &Uuid, parent_tx: &Uuid, txs: &[Vec<Uuid>]) -> Result<()>;
+}
+
+/// A log chosen at runtime, like one named by a URI, syncs just like the log it wraps.
+impl<L: GlobalTransactionLog + ?Sized> GlobalTransactionLog for Box<L> {
+    fn head(&self) -> Result<Uuid> {
+        (**self).head()
+    }
+
+    fn transactions_after(&self, tx: &Uuid) -> Result<Vec<Tx>> {
+        (**self).transactions_after(tx)
+    }
+
+    fn set_head(&mut self, tx: &Uuid) -> Result<()> {
+        (**self).set_head(tx)
+    }
+
+    fn put_transaction(&mut self, tx: &Uuid, parent_tx: &Uuid, chunk_txs: &[Uuid]) -> Result<()> {
+        (**self).put_transaction(tx, parent_tx, chunk_txs)
+    }
+
+    fn put_chunk(&mut self, tx: &Uuid, payload: &TxPart) -> Result<()> {
+        (**self).put_chunk(tx, payload)
+    }
+
+    fn put_timeline(&mut self, timeline: &Uuid, parent_tx: &Uuid, txs: &[Vec<Uuid>]) -> Result<()> {
+        (**self).put_timeline(timeline, parent_tx, txs)
+    }
 }
@@ -21,10 +21,14 @@ use failure::Error;
 use combine::error::StringStreamError;
 use mentat::CacheDirection;
 
+use std::str::FromStr;
+
 pub static COMMAND_CACHE: &str = &"cache";
 pub static COMMAND_CLOSE: &str = &"close";
 pub static COMMAND_EXIT_LONG: &str = &"exit";
 pub static COMMAND_EXIT_SHORT: &str = &"e";
+pub static COMMAND_EXPORT: &str = &"export";
+pub static COMMAND_FORMAT: &str = &"format";
 pub static COMMAND_HELP: &str = &"help";
 pub static COMMAND_IMPORT_LONG: &str = &"import";
 pub static COMMAND_IMPORT_SHORT: &str = &"i";
@@ -41,11 +45,53 @@ pub static COMMAND_TIMER_LONG: &str = &"timer";
 pub static COMMAND_TRANSACT_LONG: &str = &"transact";
 pub static COMMAND_TRANSACT_SHORT: &str = &"t";
 
+pub static SUBCOMMAND_EXPLAIN: &str = &"explain";
+
+/// The commands that can be run from the command line, like `mentat_cli query DB QUERY`, rather
+/// than from the REPL.
+pub static SUBCOMMANDS: &[&str] = &[
+    COMMAND_QUERY_LONG,
+    COMMAND_TRANSACT_LONG,
+    COMMAND_IMPORT_LONG,
+    COMMAND_EXPORT,
+    COMMAND_SCHEMA,
+    COMMAND_SYNC,
+    SUBCOMMAND_EXPLAIN,
+];
+
+/// How query results are printed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+    Edn,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat, Error> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "edn" => Ok(OutputFormat::Edn),
+            _ => bail!(CliError::CommandParse(format!(
+                "Unrecognized format {:?}; expected table, json, csv, or edn",
+                s
+            ))),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Command {
     Cache(String, CacheDirection),
     Close,
     Exit,
+    Export,
+    Format(OutputFormat),
     Help(Vec<String>),
     Import(String),
     Open(String),
@@ -73,6 +119,8 @@ impl Command {
             &Command::Cache(_, _)
             | &Command::Close
             | &Command::Exit
+            | &Command::Export
+            | &Command::Format(_)
             | &Command::Help(_)
             | &Command::Import(_)
             | &Command::Open(_)
@@ -93,6 +141,8 @@ impl Command {
             &Command::Cache(_, _)
             | &Command::Close
             | &Command::Exit
+            | &Command::Export
+            | &Command::Format(_)
             | &Command::Help(_)
             | &Command::Open(_)
             | &Command::OpenEncrypted(_, _)
@@ -110,6 +160,14 @@ impl Command {
             }
             Command::Close => format!(".{}", COMMAND_CLOSE),
             Command::Exit => format!(".{}", COMMAND_EXIT_LONG),
+            Command::Export => format!(".{}", COMMAND_EXPORT),
+            Command::Format(format) => {
+                format!(
+                    ".{} {}",
+                    COMMAND_FORMAT,
+                    format!("{:?}", format).to_lowercase()
+                )
+            }
             Command::Help(ref args) => format!(".{} {:?}", COMMAND_HELP, args),
             Command::Import(ref args) => format!(".{} {}", COMMAND_IMPORT_LONG, args),
             Command::Open(ref args) => format!(".{} {}", COMMAND_OPEN, args),
@@ -129,6 +187,46 @@ impl Command {
     }
 }
 
+/// Build the command for a subcommand given on the command line, from the arguments that follow
+/// the database path.
+pub fn subcommand(name: &str, args: Vec<String>) -> Result<Command, Error> {
+    let with_args = |count: usize| -> Result<Vec<String>, Error> {
+        if args.len() < count {
+            bail!(CliError::CommandParse(
+                "Missing required argument".to_string()
+            ));
+        }
+        if args.len() > count {
+            bail!(CliError::CommandParse(format!(
+                "Unrecognized argument {:?}",
+                args[count]
+            )));
+        }
+        Ok(args.clone())
+    };
+
+    if name == COMMAND_QUERY_LONG {
+        Ok(Command::Query(with_args(1)?.remove(0)))
+    } else if name == COMMAND_TRANSACT_LONG {
+        Ok(Command::Transact(with_args(1)?.remove(0)))
+    } else if name == COMMAND_IMPORT_LONG {
+        Ok(Command::Import(with_args(1)?.remove(0)))
+    } else if name == SUBCOMMAND_EXPLAIN {
+        Ok(Command::QueryExplain(with_args(1)?.remove(0)))
+    } else if name == COMMAND_EXPORT {
+        with_args(0).map(|_| Command::Export)
+    } else if name == COMMAND_SCHEMA {
+        with_args(0).map(|_| Command::Schema)
+    } else if name == COMMAND_SYNC {
+        with_args(2).map(Command::Sync)
+    } else {
+        bail!(CliError::CommandParse(format!(
+            "Unrecognized subcommand {:?}",
+            name
+        )))
+    }
+}
+
 pub fn command(s: &str) -> Result<Command, Error> {
     let path = || many1::<String, _, _>(satisfy(|c: char| !c.is_whitespace()));
     let argument = || many1::<String, _, _>(satisfy(|c: char| !c.is_whitespace()));
@@ -216,6 +314,12 @@ pub fn command(s: &str) -> Result<Command, Error> {
         .with(edn_arg_parser())
         .map(|x| Ok(Command::QueryExplain(x)));
 
+    let export_parser = opener(COMMAND_EXPORT, 0).map(|args_res| args_res.map(|_| Command::Export));
+
+    let format_parser = opener(COMMAND_FORMAT, 1).map(|args_res| {
+        args_res.and_then(|args: Vec<String>| Ok(Command::Format(args[0].parse()?)))
+    });
+
     let help_parser = string(COMMAND_HELP)
         .with(spaces())
         .with(arguments())
@@ -290,7 +394,9 @@ pub fn command(s: &str) -> Result<Command, Error> {
         attempt(open_parser),
         attempt(close_parser),
         attempt(explain_query_parser),
+        attempt(export_parser),
         attempt(exit_parser),
+        attempt(format_parser),
         attempt(query_prepared_parser),
         attempt(query_parser),
         attempt(schema_parser),
@@ -590,6 +696,73 @@ mod tests {
         assert_eq!(err.to_string(), format!("Invalid command {:?}", input));
     }
 
+    #[test]
+    fn test_format_parser() {
+        let input = ".format json";
+        let cmd = command(&input).expect("Expected format command");
+        assert_eq!(cmd, Command::Format(OutputFormat::Json));
+        assert_eq!(cmd.output(), ".format json");
+
+        let input = ".format csv ";
+        let cmd = command(&input).expect("Expected format command");
+        assert_eq!(cmd, Command::Format(OutputFormat::Csv));
+
+        let input = ".format xml";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(
+            err.to_string(),
+            "Unrecognized format \"xml\"; expected table, json, csv, or edn"
+        );
+
+        let input = ".format";
+        command(&input).expect_err("Expected an error");
+    }
+
+    #[test]
+    fn test_export_parser() {
+        let input = ".export";
+        let cmd = command(&input).expect("Expected export command");
+        assert_eq!(cmd, Command::Export);
+
+        let input = ".export foo";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Unrecognized argument \"foo\"");
+
+        // `.e` is still a shortcut for `.exit`.
+        let input = ".e";
+        let cmd = command(&input).expect("Expected exit command");
+        assert_eq!(cmd, Command::Exit);
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let query = "[:find ?x :where [?x _ _]]".to_string();
+        let cmd = subcommand("query", vec![query.clone()]).expect("Expected query command");
+        assert_eq!(cmd, Command::Query(query.clone()));
+
+        let cmd = subcommand("explain", vec![query.clone()]).expect("Expected explain command");
+        assert_eq!(cmd, Command::QueryExplain(query));
+
+        let cmd = subcommand("import", vec!["tx.edn".to_string()]).expect("Expected import");
+        assert_eq!(cmd, Command::Import("tx.edn".to_string()));
+
+        let cmd = subcommand("export", vec![]).expect("Expected export command");
+        assert_eq!(cmd, Command::Export);
+
+        let args = vec!["https://example.com".to_string(), "user".to_string()];
+        let cmd = subcommand("sync", args.clone()).expect("Expected sync command");
+        assert_eq!(cmd, Command::Sync(args));
+
+        let err = subcommand("schema", vec!["extra".to_string()]).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Unrecognized argument \"extra\"");
+
+        let err = subcommand("transact", vec![]).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Missing required argument");
+
+        let err = subcommand("timer", vec![]).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Unrecognized subcommand \"timer\"");
+    }
+
     #[test]
     fn test_import_parser() {
         let input = ".import /foo/bar/";
@@ -10,7 +10,7 @@
 
 #![crate_name = "mentat_cli"]
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[macro_use]
 extern crate failure_derive;
@@ -98,6 +98,12 @@ pub fn run() -> i32 {
         "Execute an import on startup. Imports are executed before queries.",
         "PATH",
     );
+    opts.optopt(
+        "f",
+        "format",
+        "How to print query results: table, json, csv, or edn. Defaults to table.",
+        "FORMAT",
+    );
     opts.optflag("v", "version", "Print version and exit");
     opts.optflag(
         "",
@@ -129,9 +135,23 @@ pub fn run() -> i32 {
         false => None,
     };
 
+    let format = match matches.opt_str("format").map(|f| f.parse()).transpose() {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("{}: {}", args[0], e);
+            return 1;
+        }
+    };
+
+    if let Some(name) = matches.free.first() {
+        if command_parser::SUBCOMMANDS.contains(&name.as_str()) {
+            return run_subcommand(&matches.free, key, format);
+        }
+    }
+
     let mut last_arg: Option<&str> = None;
 
-    let cmds: Vec<command_parser::Command> = args
+    let mut cmds: Vec<command_parser::Command> = args
         .iter()
         .filter_map(|arg| match last_arg {
             Some("-d") => {
@@ -164,6 +184,10 @@ pub fn run() -> i32 {
         })
         .collect();
 
+    if let Some(format) = format {
+        cmds.insert(0, command_parser::Command::Format(format));
+    }
+
     let mut repl = match repl::Repl::new(!matches.opt_present("no-tty")) {
         Ok(repl) => repl,
         Err(e) => {
@@ -177,6 +201,66 @@ pub fn run() -> i32 {
     0
 }
 
+/// Run a single command against a database without starting the REPL, like
+/// `mentat_cli query db.sqlite '[:find ...]'`. A query or transaction that isn't given as an
+/// argument is read from standard input. Returns 0 on success; errors are printed to standard
+/// error, and 1 is returned.
+fn run_subcommand(
+    free: &[String],
+    key: Option<String>,
+    format: Option<command_parser::OutputFormat>,
+) -> i32 {
+    match execute_subcommand(free, key, format) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn execute_subcommand(
+    free: &[String],
+    key: Option<String>,
+    format: Option<command_parser::OutputFormat>,
+) -> Result<(), failure::Error> {
+    use std::io::Read;
+
+    let name = free[0].as_str();
+    let path = free
+        .get(1)
+        .ok_or_else(|| CliError::CommandParse(format!("Missing database path for {}", name)))?;
+
+    let mut args = free[2..].to_vec();
+    let reads_stdin = name == command_parser::COMMAND_QUERY_LONG
+        || name == command_parser::COMMAND_TRANSACT_LONG
+        || name == command_parser::SUBCOMMAND_EXPLAIN;
+    if args.is_empty() && reads_stdin {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        args.push(input);
+    }
+    let command = command_parser::subcommand(name, args)?;
+
+    // Opening a database creates it, which only subcommands that write should do.
+    let writes = name == command_parser::COMMAND_TRANSACT_LONG
+        || name == command_parser::COMMAND_IMPORT_LONG;
+    if !writes && !Path::new(path).exists() {
+        return Err(CliError::CommandParse(format!("No database at {}", path)).into());
+    }
+
+    let mut repl = repl::Repl::new(false).map_err(failure::err_msg)?;
+    match key {
+        Some(k) => repl.open_with_key(path.as_str(), k)?,
+        None => repl.open(path.as_str())?,
+    }
+    if let Some(format) = format {
+        repl.execute(command_parser::Command::Format(format))?;
+    }
+    repl.execute(command)?;
+    Ok(())
+}
+
 /// Returns a version string.
 pub fn version() -> &'static str {
     env!("CARGO_PKG_VERSION")
@@ -185,7 +269,19 @@ pub fn version() -> &'static str {
 fn print_usage(arg0: &str, opts: &Options) {
     print!(
         "{}",
-        opts.usage(&format!("Usage: {} [OPTIONS] [FILE]", arg0))
+        opts.usage(&format!(
+            "Usage: {arg0} [OPTIONS] [FILE]\n       {arg0} [OPTIONS] COMMAND DATABASE [ARGS]\n\n\
+             Commands, which run without starting the REPL:\n    \
+             query DATABASE [QUERY]\n    \
+             transact DATABASE [TRANSACTION]\n    \
+             import DATABASE PATH\n    \
+             export DATABASE\n    \
+             schema DATABASE\n    \
+             sync DATABASE URL USER\n    \
+             explain DATABASE [QUERY]\n\n\
+             A query or transaction that isn't given is read from standard input.",
+            arg0 = arg0
+        ))
     );
 }
 
@@ -195,6 +291,37 @@ fn print_version() {
 
 #[cfg(test)]
 mod tests {
+    extern crate tempfile;
+
+    use super::execute_subcommand;
+
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn test_subcommand_missing_database() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("missing.db");
+        let path = path.to_str().expect("path").to_string();
+
+        for (name, arg) in &[
+            ("query", Some("[:find ?e :where [?e _]]")),
+            ("schema", None),
+            ("export", None),
+        ] {
+            let mut free = vec![name.to_string(), path.clone()];
+            free.extend(arg.map(|arg| arg.to_string()));
+            assert_eq!(
+                format!("No database at {}", path),
+                execute_subcommand(&free, None, None)
+                    .expect_err("missing database")
+                    .to_string()
+            );
+            assert!(!dir.path().join("missing.db").exists());
+        }
+
+        let free = vec!["transact".to_string(), path.clone(), "[]".to_string()];
+        execute_subcommand(&free, None, None).expect("transacted");
+        assert!(dir.path().join("missing.db").exists());
+    }
 }
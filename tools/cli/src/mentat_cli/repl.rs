@@ -8,9 +8,10 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::collections::BTreeSet;
 use std::io::Write;
 
-use failure::Error;
+use failure::{err_msg, Error};
 
 use linefeed::Interface;
 
@@ -20,20 +21,23 @@ use termion::{color, style};
 
 use time::{Duration, Instant};
 
-use core_traits::StructuredMap;
+use core_traits::{Entid, StructuredMap};
 
 use mentat::{
-    Binding, CacheDirection, Keyword, QueryExplanation, QueryOutput, QueryResults, Queryable,
-    Store, TxReport, TypedValue,
+    Binding, CacheDirection, HasSchema, Keyword, QueryExplanation, QueryOutput, QueryResults,
+    Queryable, Store, TxReport, TypedValue,
 };
 
-use command_parser::Command;
+use mentat_db::entids::{DB_IDENT, DB_TX_INSTANT};
+use mentat_db::{TypedSQLValue, TX0};
+
+use command_parser::{Command, OutputFormat};
 
 use command_parser::{
-    COMMAND_CACHE, COMMAND_EXIT_LONG, COMMAND_EXIT_SHORT, COMMAND_HELP, COMMAND_IMPORT_LONG,
-    COMMAND_OPEN, COMMAND_QUERY_EXPLAIN_LONG, COMMAND_QUERY_EXPLAIN_SHORT, COMMAND_QUERY_LONG,
-    COMMAND_QUERY_PREPARED_LONG, COMMAND_QUERY_SHORT, COMMAND_SCHEMA, COMMAND_TIMER_LONG,
-    COMMAND_TRANSACT_LONG, COMMAND_TRANSACT_SHORT,
+    COMMAND_CACHE, COMMAND_EXIT_LONG, COMMAND_EXIT_SHORT, COMMAND_FORMAT, COMMAND_HELP,
+    COMMAND_IMPORT_LONG, COMMAND_OPEN, COMMAND_QUERY_EXPLAIN_LONG, COMMAND_QUERY_EXPLAIN_SHORT,
+    COMMAND_QUERY_LONG, COMMAND_QUERY_PREPARED_LONG, COMMAND_QUERY_SHORT, COMMAND_SCHEMA,
+    COMMAND_TIMER_LONG, COMMAND_TRANSACT_LONG, COMMAND_TRANSACT_SHORT,
 };
 
 // These are still defined when this feature is disabled (so that we can
@@ -79,6 +83,8 @@ lazy_static! {
 
             (COMMAND_TIMER_LONG, "Enable or disable timing of query and transact operations."),
 
+            (COMMAND_FORMAT, "Print query results as a table, JSON, CSV, or EDN. Usage: `.format json`"),
+
             (COMMAND_CACHE, "Cache an attribute. Usage: `.cache :foo/bar reverse`"),
 
             #[cfg(feature = "syncable")]
@@ -160,6 +166,7 @@ pub struct Repl {
     path: String,
     store: Store,
     timer_on: bool,
+    output_format: OutputFormat,
 }
 
 impl Repl {
@@ -190,6 +197,7 @@ impl Repl {
             path: "".to_string(),
             store,
             timer_on: false,
+            output_format: OutputFormat::Table,
         })
     }
 
@@ -226,19 +234,29 @@ impl Repl {
         self.input_reader.save_history();
     }
 
-    fn cache(&mut self, attr: String, direction: CacheDirection) {
-        if let Some(kw) = parse_namespaced_keyword(attr.as_str()) {
-            match self.store.cache(&kw, direction) {
-                Result::Ok(_) => (),
-                Result::Err(e) => eprintln!("Couldn't cache attribute: {}", e),
-            };
-        } else {
-            eprintln!("Invalid attribute {}", attr);
+    fn cache(&mut self, attr: String, direction: CacheDirection) -> Result<(), Error> {
+        match parse_namespaced_keyword(attr.as_str()) {
+            Some(kw) => self
+                .store
+                .cache(&kw, direction)
+                .map_err(|e| err_msg(format!("Couldn't cache attribute: {}", e))),
+            None => Err(err_msg(format!("Invalid attribute {}", attr))),
         }
     }
 
-    /// Runs a single command input.
+    /// Runs a single command input, printing any error. Returns false if the REPL should exit.
     fn handle_command(&mut self, cmd: Command) -> bool {
+        match self.execute(cmd) {
+            Ok(keep_going) => keep_going,
+            Err(e) => {
+                eprintln!("{}", e);
+                true
+            }
+        }
+    }
+
+    /// Runs a single command, returning false if the REPL should exit.
+    pub fn execute(&mut self, cmd: Command) -> Result<bool, Error> {
         let should_print_times = self.timer_on && cmd.is_timed();
 
         let mut start = Instant::now();
@@ -246,101 +264,82 @@ impl Repl {
 
         match cmd {
             Command::Cache(attr, direction) => {
-                self.cache(attr, direction);
+                self.cache(attr, direction)?;
             }
             Command::Close => {
-                self.close();
+                self.close()?;
             }
             Command::Exit => {
                 eprintln!("Exiting…");
-                return false;
+                return Ok(false);
+            }
+            Command::Export => {
+                self.export()?;
+            }
+            Command::Format(format) => {
+                self.output_format = format;
             }
             Command::Help(args) => {
-                self.help_command(args);
+                self.help_command(args)?;
             }
             Command::Import(path) => {
-                self.execute_import(path);
+                self.execute_import(path)?;
             }
             Command::Open(db) => {
-                match self.open(db) {
-                    Ok(_) => println!("Database {:?} opened", self.db_name()),
-                    Err(e) => eprintln!("{}", e.to_string()),
-                };
+                self.open(db)?;
+                println!("Database {:?} opened", self.db_name());
             }
             Command::OpenEncrypted(db, encryption_key) => {
-                match self.open_with_key(db, &encryption_key) {
-                    Ok(_) => println!(
-                        "Database {:?} opened with key {:?}",
-                        self.db_name(),
-                        encryption_key
-                    ),
-                    Err(e) => eprintln!("{}", e.to_string()),
-                }
+                self.open_with_key(db, &encryption_key)?;
+                println!(
+                    "Database {:?} opened with key {:?}",
+                    self.db_name(),
+                    encryption_key
+                );
             }
             Command::Query(query) => {
-                self.store
-                    .q_once(query.as_str(), None)
-                    .map_err(|e| e.into())
-                    .and_then(|o| {
-                        end = Some(Instant::now());
-                        self.print_results(o)
-                    })
-                    .map_err(|err| {
-                        eprintln!("{:?}.", err);
-                    })
-                    .ok();
+                let output = self.store.q_once(query.as_str(), None)?;
+                end = Some(Instant::now());
+                self.print_results(output)?;
             }
             Command::QueryExplain(query) => {
-                self.explain_query(query);
+                self.explain_query(query)?;
             }
             Command::QueryPrepared(query) => {
-                self.store
-                    .q_prepare(query.as_str(), None)
-                    .and_then(|mut p| {
-                        let prepare_end = Instant::now();
-                        if should_print_times {
-                            eprint_out("Prepare time");
-                            eprint!(": ");
-                            format_time(prepare_end - start);
-                        }
-                        // TODO: This is a hack.
-                        start = Instant::now();
-                        let r = p.run(None);
-                        end = Some(Instant::now());
-                        r
-                    })
-                    .map(|o| self.print_results(o))
-                    .map_err(|err| {
-                        eprintln!("{:?}.", err);
-                    })
-                    .ok();
+                let mut prepared = self.store.q_prepare(query.as_str(), None)?;
+                let prepare_end = Instant::now();
+                if should_print_times {
+                    eprint_out("Prepare time");
+                    eprint!(": ");
+                    format_time(prepare_end - start);
+                }
+                // TODO: This is a hack.
+                start = Instant::now();
+                let output = prepared.run(None)?;
+                end = Some(Instant::now());
+                self.print_results(output)?;
             }
             Command::Schema => {
                 let edn = self.store.conn().current_schema().to_edn_value();
-                match edn.to_pretty(120) {
-                    Ok(s) => println!("{}", s),
-                    Err(e) => eprintln!("{}", e),
-                };
+                println!("{}", edn.to_pretty(120)?);
             }
 
             #[cfg(feature = "syncable")]
             Command::Sync(args) => {
-                match self.store.sync(&args[0], &args[1]) {
-                    Ok(report) => println!("Sync report: {}", report),
-                    Err(e) => eprintln!("{:?}", e),
-                };
+                let report = self.store.sync(&args[0], &args[1])?;
+                println!("Sync report: {}", report);
             }
 
             #[cfg(not(feature = "syncable"))]
             Command::Sync(_) => {
-                eprintln!(".sync requires the syncable Mentat feature");
+                bail!(err_msg(".sync requires the syncable Mentat feature"));
             }
 
             Command::Timer(on) => {
                 self.toggle_timer(on);
             }
             Command::Transact(transaction) => {
-                self.execute_transact(transaction);
+                self.execute_transact(transaction)?;
             }
         }
 
@@ -351,20 +350,37 @@ impl Repl {
             format_time(end - start);
         }
 
-        true
+        Ok(true)
     }
 
-    fn execute_import<T>(&mut self, path: T)
+    fn execute_import<T>(&mut self, path: T) -> Result<(), Error>
     where
         T: Into<String>,
     {
         use std::io::Read;
         let path = path.into();
         let mut content: String = "".to_string();
-        match ::std::fs::File::open(path.clone()).and_then(|mut f| f.read_to_string(&mut content)) {
-            Ok(_) => self.execute_transact(content),
-            Err(e) => eprintln!("Error reading file {}: {}", path, e),
+        if let Err(e) =
+            ::std::fs::File::open(path.clone()).and_then(|mut f| f.read_to_string(&mut content))
+        {
+            return Err(err_msg(format!("Error reading file {}: {}", path, e)));
+        }
+
+        // The file holds one or more transactions, like those written by `export`. They're
+        // transacted in order, and all or none of them are committed.
+        let forms = match edn::parse::value(&format!("[{}]", content))?.inner {
+            edn::SpannedValue::Vector(forms) => forms,
+            _ => unreachable!(),
+        };
+        let mut tx = self.store.begin_transaction()?;
+        for form in forms {
+            // Spans are offsets into the wrapped content.
+            let span = form.span;
+            let report = tx.transact(&content[span.0 as usize - 1..span.1 as usize - 1])?;
+            println!("{:?}", report);
         }
+        tx.commit()?;
+        Ok(())
     }
 
     fn open_common(
@@ -392,14 +408,18 @@ impl Repl {
         Ok(())
     }
 
-    fn open<T>(&mut self, path: T) -> ::mentat::errors::Result<()>
+    pub fn open<T>(&mut self, path: T) -> ::mentat::errors::Result<()>
     where
         T: Into<String>,
     {
         self.open_common(path.into(), None)
     }
 
-    fn open_with_key<T, U>(&mut self, path: T, encryption_key: U) -> ::mentat::errors::Result<()>
+    pub fn open_with_key<T, U>(
+        &mut self,
+        path: T,
+        encryption_key: U,
+    ) -> ::mentat::errors::Result<()>
     where
         T: Into<String>,
         U: AsRef<str>,
@@ -408,19 +428,18 @@ impl Repl {
     }
 
     // Close the current store by opening a new in-memory store in its place.
-    fn close(&mut self) {
+    fn close(&mut self) -> Result<(), Error> {
         let old_db_name = self.db_name();
-        match self.open("") {
-            Ok(_) => println!("Database {:?} closed.", old_db_name),
-            Err(e) => eprintln!("{}", e),
-        };
+        self.open("")?;
+        println!("Database {:?} closed.", old_db_name);
+        Ok(())
     }
 
     fn toggle_timer(&mut self, on: bool) {
         self.timer_on = on;
     }
 
-    fn help_command(&self, args: Vec<String>) {
+    fn help_command(&self, args: Vec<String>) -> Result<(), Error> {
         let stdout = ::std::io::stdout();
         let mut output = TabWriter::new(stdout.lock());
         if args.is_empty() {
@@ -437,16 +456,34 @@ impl Repl {
                     write!(output, ".{}\t", cmd).unwrap();
                     writeln!(output, "{}", msg).unwrap();
                 } else {
-                    eprintln!("Unrecognised command {}", arg);
-                    return;
+                    bail!(err_msg(format!("Unrecognised command {}", arg)));
                 }
             }
         }
         writeln!(output).unwrap();
         output.flush().unwrap();
+        Ok(())
     }
 
     fn print_results(&self, query_output: QueryOutput) -> Result<(), Error> {
+        match self.output_format {
+            OutputFormat::Table => self.print_table(query_output),
+            OutputFormat::Json => {
+                println!("{}", query_output.to_json());
+                Ok(())
+            }
+            OutputFormat::Csv => {
+                print!("{}", query_output.to_csv());
+                Ok(())
+            }
+            OutputFormat::Edn => {
+                println!("{}", query_output.to_edn().to_pretty(120)?);
+                Ok(())
+            }
+        }
+    }
+
+    fn print_table(&self, query_output: QueryOutput) -> Result<(), Error> {
         let stdout = ::std::io::stdout();
         let mut output = TabWriter::new(stdout.lock());
 
@@ -499,14 +536,13 @@ impl Repl {
         Ok(())
     }
 
-    pub fn explain_query(&self, query: String) {
-        match self.store.q_explain(query.as_str(), None) {
-            Result::Err(err) => println!("{:?}.", err),
-            Result::Ok(QueryExplanation::KnownConstant) => println!("Query is known constant!"),
-            Result::Ok(QueryExplanation::KnownEmpty(empty_because)) => {
+    pub fn explain_query(&self, query: String) -> Result<(), Error> {
+        match self.store.q_explain(query.as_str(), None)? {
+            QueryExplanation::KnownConstant => println!("Query is known constant!"),
+            QueryExplanation::KnownEmpty(empty_because) => {
                 println!("Query is known empty: {:?}", empty_because)
             }
-            Result::Ok(QueryExplanation::ExecutionPlan { query, steps }) => {
+            QueryExplanation::ExecutionPlan { query, steps } => {
                 println!("SQL: {}", query.sql);
                 if !query.args.is_empty() {
                     println!("  Bindings:");
@@ -546,13 +582,94 @@ impl Repl {
                 }
             }
         };
+        Ok(())
     }
 
-    pub fn execute_transact(&mut self, transaction: String) {
-        match self.transact(transaction) {
-            Result::Ok(report) => println!("{:?}", report),
-            Result::Err(err) => eprintln!("Error: {:?}.", err),
+    /// Print every datom asserted since the store was created, except for transaction timestamps,
+    /// as transactions of `[:db/add e a v]` that `import` replays into another store.
+    ///
+    /// Entities are written as their idents, or as tempids like `"e65536"` if they don't have
+    /// one. An ident can't be used in the transaction that defines it, so a first transaction
+    /// defines the exported idents and attributes, and a second holds everything else.
+    fn export(&self) -> Result<(), Error> {
+        println!("{}", self.export_edn()?);
+        Ok(())
+    }
+
+    fn export_edn(&self) -> Result<String, Error> {
+        let rows = self
+            .store
+            .q_once(
+                "[:find ?e ?a ?v ?tx :where [?e ?a ?v ?tx] :order ?e ?a]",
+                None,
+            )?
+            .into_rel()?;
+
+        let mut datoms = Vec::with_capacity(rows.row_count());
+        for row in rows.rows() {
+            let (e, a, tx) = match (row[0].as_scalar(), row[1].as_scalar(), row[3].as_scalar()) {
+                (
+                    Some(&TypedValue::Ref(e)),
+                    Some(&TypedValue::Ref(a)),
+                    Some(&TypedValue::Ref(tx)),
+                ) => (e, a, tx),
+                _ => unreachable!(),
+            };
+            if tx <= TX0 || a == DB_TX_INSTANT {
+                continue;
+            }
+            match row[2].as_scalar() {
+                Some(v) => datoms.push((e, a, v.clone())),
+                None => unreachable!(),
+            }
         }
+
+        // Idents that are exported don't exist yet in the store they're imported into.
+        let defined: BTreeSet<Entid> = datoms
+            .iter()
+            .filter(|&&(_, a, _)| a == DB_IDENT)
+            .map(|&(e, _, _)| e)
+            .collect();
+
+        let schema = self.store.conn().current_schema();
+        let entity = |e: Entid, in_schema: bool| match schema.get_ident(e) {
+            Some(ident) if !in_schema || !defined.contains(&e) => {
+                edn::Value::Keyword(ident.clone())
+            }
+            _ => edn::Value::Text(format!("e{}", e)),
+        };
+        let add = edn::Value::Keyword(Keyword::namespaced("db", "add"));
+
+        let mut schema_tx = vec![];
+        let mut data_tx = vec![];
+        for (e, a, v) in datoms {
+            let in_schema = defined.contains(&e) && !defined.contains(&a);
+            let v = match v {
+                TypedValue::Ref(v) => entity(v, in_schema),
+                v => v.to_edn_value_pair().0,
+            };
+            let datom =
+                edn::Value::Vector(vec![add.clone(), entity(e, in_schema), entity(a, false), v]);
+            if in_schema {
+                schema_tx.push(datom);
+            } else {
+                data_tx.push(datom);
+            }
+        }
+
+        let mut out = String::new();
+        if !schema_tx.is_empty() {
+            out.push_str(&edn::Value::Vector(schema_tx).to_pretty(120)?);
+            out.push('\n');
+        }
+        out.push_str(&edn::Value::Vector(data_tx).to_pretty(120)?);
+        Ok(out)
+    }
+
+    pub fn execute_transact(&mut self, transaction: String) -> Result<(), Error> {
+        let report = self.transact(transaction)?;
+        println!("{:?}", report);
+        Ok(())
     }
 
     fn transact(&mut self, transaction: String) -> ::mentat::errors::Result<TxReport> {
@@ -616,3 +733,68 @@ impl Repl {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use std::io::Write;
+
+    use super::Repl;
+
+    use mentat::Queryable;
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let mut repl = Repl::new(false).expect("repl");
+        repl.execute_transact(
+            r#"[{:db/ident :person/name
+                 :db/valueType :db.type/string
+                 :db/cardinality :db.cardinality/one
+                 :db/fulltext true
+                 :db/index true}
+                {:db/ident :person/friend
+                 :db/valueType :db.type/ref
+                 :db/cardinality :db.cardinality/many}
+                {:db/ident :person/role
+                 :db/valueType :db.type/ref
+                 :db/cardinality :db.cardinality/one}
+                {:db/ident :role/admin}]"#
+                .to_string(),
+        )
+        .expect("transacted");
+        repl.execute_transact(
+            r#"[{:db/id "ivan" :person/name "Ivan" :person/friend "petr" :person/role :role/admin}
+                {:db/id "petr" :person/name "Petr \"P\"" :person/friend "ivan"}]"#
+                .to_string(),
+        )
+        .expect("transacted");
+        let exported = repl.export_edn().expect("exported");
+
+        let mut file = tempfile::NamedTempFile::new().expect("tempfile");
+        file.write_all(exported.as_bytes()).expect("written");
+        let mut imported = Repl::new(false).expect("repl");
+        imported
+            .execute_import(file.path().to_str().expect("path"))
+            .expect("imported");
+
+        let friends = r#"[:find ?name ?friend-name
+                          :order ?name
+                          :where [?e :person/name ?name]
+                                 [?e :person/friend ?f]
+                                 [?f :person/name ?friend-name]]"#;
+        let roles = r#"[:find ?name ?role
+                        :where [?e :person/name ?name]
+                               [?e :person/role ?r]
+                               [?r :db/ident ?role]]"#;
+        for (query, count) in &[(friends, 2), (roles, 1)] {
+            let expected = repl.store.q_once(query, None).expect("queried").results;
+            assert_eq!(*count, expected.len());
+            assert_eq!(
+                expected,
+                imported.store.q_once(query, None).expect("queried").results
+            );
+        }
+        assert_eq!(exported, imported.export_edn().expect("exported"));
+    }
+}
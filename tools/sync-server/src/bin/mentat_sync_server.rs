@@ -0,0 +1,74 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use getopts::Options;
+
+use mentat_sync_server::SyncServer;
+
+fn main() {
+    env_logger::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    let mut opts = Options::new();
+    opts.optopt(
+        "d",
+        "",
+        "The SQLite file that holds the transaction logs. Defaults to mentat_sync.db.",
+        "DATABASE",
+    );
+    opts.optopt(
+        "l",
+        "listen",
+        "The address to listen on. Defaults to 127.0.0.1:3030.",
+        "ADDRESS",
+    );
+    opts.optopt(
+        "t",
+        "token",
+        "Only answer requests that carry this bearer token.",
+        "TOKEN",
+    );
+    opts.optflag("h", "help", "Print this help message and exit");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}: {}", args[0], e);
+            std::process::exit(1);
+        }
+    };
+
+    if matches.opt_present("help") {
+        print!("{}", opts.usage(&format!("Usage: {} [OPTIONS]", args[0])));
+        return;
+    }
+
+    let path = matches
+        .opt_str("d")
+        .unwrap_or_else(|| "mentat_sync.db".to_string());
+    let addr = matches
+        .opt_str("listen")
+        .unwrap_or_else(|| "127.0.0.1:3030".to_string());
+
+    let token = matches.opt_str("token");
+
+    let result = SyncServer::bind(&addr, &path).and_then(|server| {
+        let server = match token {
+            Some(ref token) => server.with_bearer_token(token),
+            None => server,
+        };
+        println!("Serving {} on http://{}", path, server.local_addr()?);
+        server.run()
+    });
+    if let Err(e) = result {
+        eprintln!("{}: {}", args[0], e);
+        std::process::exit(1);
+    }
+}
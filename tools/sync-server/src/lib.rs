@@ -0,0 +1,291 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A reference server for the protocol that `RemoteClient` speaks. It keeps every user's
+//! transaction log in one SQLite file, using `SqliteLog`.
+//!
+//! Every path starts with the user's UUID:
+//!
+//! - `GET /{user}/head` is `{"head": uuid}`, which is nil while the log is empty.
+//! - `PUT /{user}/head` with `{"head": uuid}` moves the head.
+//! - `GET /{user}/transactions?from={uuid}` lists the transactions after `from`, oldest first.
+//! - `GET /{user}/transactions/{uuid}` is a transaction's parent and chunks.
+//! - `PUT /{user}/transactions/{uuid}` with `{"parent": uuid, "chunks": [uuid, ...]}`.
+//! - `GET /{user}/chunks/{uuid}` and `PUT /{user}/chunks/{uuid}`, whose body is a `TxPart`.
+//! - `PUT /{user}/timelines/{uuid}` with `{"parent": uuid, "transactions": [[uuid, ...], ...]}`.
+//!
+//! Moving the head is an optimistic compare-and-set. Clients upload transactions on top of the
+//! head they last saw, and then move the head to the last of them; if another client moved the
+//! head in the meantime, the new head doesn't descend from it, and the server answers
+//! `409 Conflict`. The client should sync again.
+//!
+//! Requests are served one at a time, on a single connection to the SQLite file.
+//!
+//! Transactions, chunks and timelines can't change once uploaded: uploading one again with
+//! different contents is `409 Conflict`.
+//!
+//! Uploads answer `201 Created` and moving the head `204 No Content`. Unknown transactions and
+//! chunks are `404 Not Found`, and malformed requests `400 Bad Request`. A server can require a
+//! bearer token, and answers requests without it `401 Unauthorized`.
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate serde_derive;
+
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{body, header, Body, Method, Request, Response, Server, StatusCode};
+
+use uuid::Uuid;
+
+use mentat_tolstoy::{GlobalTransactionLog, SqliteLog, TxPart};
+
+use public_traits::errors::{MentatError, Result};
+
+use tolstoy_traits::errors::TolstoyError;
+
+#[derive(Serialize, Deserialize)]
+struct SerializedHead {
+    head: Uuid,
+}
+
+#[derive(Deserialize)]
+struct SerializedTransaction {
+    parent: Uuid,
+    chunks: Vec<Uuid>,
+}
+
+#[derive(Serialize)]
+struct SerializableTransaction {
+    id: Uuid,
+    parent: Uuid,
+    chunks: Vec<Uuid>,
+    /// How many transactions precede this one.
+    seq: i64,
+}
+
+#[derive(Serialize)]
+struct SerializedTransactions {
+    limit: i64,
+    from: Uuid,
+    transactions: Vec<Uuid>,
+}
+
+#[derive(Deserialize)]
+struct SerializedTimeline {
+    parent: Uuid,
+    transactions: Vec<Vec<Uuid>>,
+}
+
+pub struct SyncServer {
+    listener: TcpListener,
+    log: Arc<Mutex<SqliteLog>>,
+    token: Option<String>,
+}
+
+impl SyncServer {
+    /// Listen on `addr`, keeping logs in the SQLite file at `path`.
+    pub fn bind<A, P>(addr: A, path: P) -> Result<SyncServer>
+    where
+        A: ToSocketAddrs,
+        P: AsRef<Path>,
+    {
+        Ok(SyncServer {
+            listener: TcpListener::bind(addr)?,
+            log: Arc::new(Mutex::new(SqliteLog::open(path, &Uuid::nil())?)),
+            token: None,
+        })
+    }
+
+    /// Only answer requests that carry `Authorization: Bearer {token}`.
+    pub fn with_bearer_token(mut self, token: &str) -> SyncServer {
+        self.token = Some(format!("Bearer {}", token));
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve requests. This only returns if the server fails.
+    pub fn run(self) -> Result<()> {
+        let SyncServer {
+            listener,
+            log,
+            token,
+        } = self;
+        let mut runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(async move {
+            let make_service = make_service_fn(move |_| {
+                let log = log.clone();
+                let token = token.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        handle(log.clone(), token.clone(), request)
+                    }))
+                }
+            });
+            Server::from_tcp(listener)?.serve(make_service).await?;
+            Ok(())
+        })
+    }
+}
+
+async fn handle(
+    log: Arc<Mutex<SqliteLog>>,
+    token: Option<String>,
+    request: Request<Body>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    if let Some(token) = token {
+        let authorization = request.headers().get(header::AUTHORIZATION);
+        if authorization.map(|value| value.as_bytes()) != Some(token.as_bytes()) {
+            debug!("{} {}: unauthorized", method, uri);
+            return Ok(reply(StatusCode::UNAUTHORIZED, ""));
+        }
+    }
+    let response = match body::to_bytes(request.into_body()).await {
+        Ok(payload) => {
+            // SQLite blocks, so keep it off the runtime's threads.
+            let (method, uri) = (method.clone(), uri.clone());
+            tokio::task::spawn_blocking(move || {
+                respond(&log, &method, uri.path(), uri.query(), &payload)
+            })
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::new(std::io::ErrorKind::Other, e).into()))
+        }
+        Err(e) => Err(e.into()),
+    };
+    let response = response.unwrap_or_else(|e| {
+        let status = error_status(&e);
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            error!("{} {}: {}", method, uri, e);
+        }
+        reply(status, e.to_string())
+    });
+    debug!("{} {}: {}", method, uri, response.status());
+    Ok(response)
+}
+
+fn error_status(error: &MentatError) -> StatusCode {
+    match error {
+        MentatError::TolstoyError(TolstoyError::BadRemoteState(_)) => StatusCode::NOT_FOUND,
+        MentatError::TolstoyError(TolstoyError::RemoteHeadMoved(..))
+        | MentatError::TolstoyError(TolstoyError::UploadConflict(..)) => StatusCode::CONFLICT,
+        MentatError::SerializationError(_) | MentatError::UuidError(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn reply<T: Into<Body>>(status: StatusCode, body: T) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
+
+fn reply_json<T: serde::Serialize>(value: &T) -> Result<Response<Body>> {
+    Ok(reply(StatusCode::OK, serde_json::to_string(value)?))
+}
+
+fn respond(
+    log: &Mutex<SqliteLog>,
+    method: &Method,
+    uri_path: &str,
+    query: Option<&str>,
+    payload: &[u8],
+) -> Result<Response<Body>> {
+    let segments: Vec<&str> = uri_path.trim_matches('/').split('/').collect();
+    let (user, resource) = match segments.split_first() {
+        Some((user, resource)) => (Uuid::parse_str(user)?, resource),
+        None => return Ok(reply(StatusCode::NOT_FOUND, "")),
+    };
+    // A panic while serving another request can't leave an SQLite transaction open.
+    let mut log = log.lock().unwrap_or_else(PoisonError::into_inner);
+    log.set_user(&user);
+
+    match (method, resource) {
+        (&Method::GET, ["head"]) => reply_json(&SerializedHead { head: log.head()? }),
+        (&Method::PUT, ["head"]) => {
+            let head: SerializedHead = serde_json::from_slice(payload)?;
+            log.set_head(&head.head)?;
+            Ok(reply(StatusCode::NO_CONTENT, ""))
+        }
+        (&Method::GET, ["transactions"]) => {
+            let from = query
+                .into_iter()
+                .flat_map(|query| query.split('&'))
+                .find_map(|pair| pair.strip_prefix("from="))
+                .map_or(Ok(Uuid::nil()), Uuid::parse_str)?;
+            let transactions: Vec<Uuid> = log
+                .transactions_after(&from)?
+                .into_iter()
+                .map(|tx| tx.tx)
+                .collect();
+            reply_json(&SerializedTransactions {
+                limit: transactions.len() as i64,
+                from,
+                transactions,
+            })
+        }
+        (&Method::GET, ["transactions", tx]) => {
+            let id = Uuid::parse_str(tx)?;
+            let (parent, chunks) = log.transaction(&id)?;
+            let mut seq = 0;
+            let mut ancestor = parent;
+            while !ancestor.is_nil() {
+                seq += 1;
+                ancestor = log.transaction(&ancestor)?.0;
+            }
+            reply_json(&SerializableTransaction {
+                id,
+                parent,
+                chunks,
+                seq,
+            })
+        }
+        (&Method::PUT, ["transactions", tx]) => {
+            let transaction: SerializedTransaction = serde_json::from_slice(payload)?;
+            log.put_transaction(
+                &Uuid::parse_str(tx)?,
+                &transaction.parent,
+                &transaction.chunks,
+            )?;
+            Ok(reply(StatusCode::CREATED, ""))
+        }
+        (&Method::GET, ["chunks", chunk]) => reply_json(&log.chunk(&Uuid::parse_str(chunk)?)?),
+        (&Method::PUT, ["chunks", chunk]) => {
+            let part: TxPart = serde_json::from_slice(payload)?;
+            log.put_chunk(&Uuid::parse_str(chunk)?, &part)?;
+            Ok(reply(StatusCode::CREATED, ""))
+        }
+        (&Method::PUT, ["timelines", timeline]) => {
+            let timeline_json: SerializedTimeline = serde_json::from_slice(payload)?;
+            log.put_timeline(
+                &Uuid::parse_str(timeline)?,
+                &timeline_json.parent,
+                &timeline_json.transactions,
+            )?;
+            Ok(reply(StatusCode::CREATED, ""))
+        }
+        (_, ["head"])
+        | (_, ["transactions"])
+        | (_, ["transactions", _])
+        | (_, ["chunks", _])
+        | (_, ["timelines", _]) => Ok(reply(StatusCode::METHOD_NOT_ALLOWED, "")),
+        _ => Ok(reply(StatusCode::NOT_FOUND, "")),
+    }
+}
@@ -99,6 +99,14 @@ where
         E: Into<EntityPlace<TypedValue>>,
         A: Into<AttributePlace>,
         V: Into<ValuePlace<TypedValue>>;
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where
+        E: Into<EntityPlace<TypedValue>>;
+    fn cas<E, A, V>(&mut self, e: E, a: A, old: Option<V>, new: V) -> Result<()>
+    where
+        E: Into<EntityPlace<TypedValue>>,
+        A: Into<AttributePlace>,
+        V: Into<ValuePlace<TypedValue>>;
 }
 
 impl BuildTerms for TermBuilder {
@@ -153,6 +161,29 @@ impl BuildTerms for TermBuilder {
         });
         Ok(())
     }
+
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where
+        E: Into<EntityPlace<TypedValue>>,
+    {
+        self.terms.push(Entity::RetractEntity { e: e.into() });
+        Ok(())
+    }
+
+    fn cas<E, A, V>(&mut self, e: E, a: A, old: Option<V>, new: V) -> Result<()>
+    where
+        E: Into<EntityPlace<TypedValue>>,
+        A: Into<AttributePlace>,
+        V: Into<ValuePlace<TypedValue>>,
+    {
+        self.terms.push(Entity::Cas {
+            e: e.into(),
+            a: a.into(),
+            old: old.map(|v| v.into()),
+            new: new.into(),
+        });
+        Ok(())
+    }
 }
 
 impl Default for TermBuilder {
@@ -223,6 +254,18 @@ where
     {
         self.builder.retract(self.entity.clone(), a, v)
     }
+
+    pub fn retract_entity(&mut self) -> Result<()> {
+        self.builder.retract_entity(self.entity.clone())
+    }
+
+    pub fn cas<A, V>(&mut self, a: A, old: Option<V>, new: V) -> Result<()>
+    where
+        A: Into<AttributePlace>,
+        V: Into<ValuePlace<TypedValue>>,
+    {
+        self.builder.cas(self.entity.clone(), a, old, new)
+    }
 }
 
 pub struct InProgressBuilder<'a, 'c> {
@@ -303,6 +346,22 @@ impl<'a, 'c> BuildTerms for InProgressBuilder<'a, 'c> {
     {
         self.builder.retract(e, a, v)
     }
+
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where
+        E: Into<EntityPlace<TypedValue>>,
+    {
+        self.builder.retract_entity(e)
+    }
+
+    fn cas<E, A, V>(&mut self, e: E, a: A, old: Option<V>, new: V) -> Result<()>
+    where
+        E: Into<EntityPlace<TypedValue>>,
+        A: Into<AttributePlace>,
+        V: Into<ValuePlace<TypedValue>>,
+    {
+        self.builder.cas(e, a, old, new)
+    }
 }
 
 impl<'a, 'c> EntityBuilder<InProgressBuilder<'a, 'c>> {
@@ -21,7 +21,9 @@ extern crate mentat_db;
 extern crate mentat_query_algebrizer;
 extern crate mentat_query_projector;
 extern crate mentat_query_pull;
+extern crate mentat_query_sql;
 extern crate mentat_sql;
+extern crate query_algebrizer_traits;
 
 use std::sync::{Arc, Mutex};
 
@@ -29,14 +31,14 @@ use std::io::Read;
 
 use std::borrow::Borrow;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use std::fs::File;
 
 use std::path::Path;
 
 use edn::entities::{OpType, TempId};
-use edn::{InternSet, Keyword};
+use edn::{DateTime, InternSet, Keyword, Utc};
 
 use core_traits::{Attribute, Entid, KnownEntid, StructuredMap, TypedValue, ValueType};
 
@@ -48,7 +50,7 @@ use mentat_query_pull::{pull_attributes_for_entities, pull_attributes_for_entity
 
 use mentat_db::{
     transact, transact_terms, InProgressObserverTransactWatcher, PartitionMap, TransactWatcher,
-    TransactableValue, TxObservationService,
+    TransactableValue, TxDataWatcher, TxObservationService,
 };
 
 use mentat_db::internal_types::TermWithTempIds;
@@ -56,16 +58,25 @@ use mentat_db::internal_types::TermWithTempIds;
 use mentat_db::cache::{InProgressCacheTransactWatcher, InProgressSQLiteAttributeCache};
 
 pub mod entity_builder;
+pub mod live_query;
 pub mod metadata;
 pub mod query;
+pub mod query_plan_cache;
 
 pub use crate::entity_builder::{InProgressBuilder, TermBuilder};
 
+pub use crate::live_query::{LiveQueries, LiveQueryReceiver, LiveQueryUpdate};
+
 pub use crate::metadata::Metadata;
 
+pub use crate::query_plan_cache::{
+    QueryPlanCache, QueryPlanCacheStats, DEFAULT_QUERY_PLAN_CACHE_CAPACITY,
+};
+
 use crate::query::{
     lookup_value_for_attribute, lookup_values_for_attribute, q_explain, q_once, q_prepare,
-    q_uncached, Known, PreparedResult, QueryExplanation, QueryInputs, QueryOutput,
+    q_uncached, IntoResult, Known, PreparedResult, QueryExplanation, QueryInputs, QueryOutput,
+    Variable,
 };
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -93,6 +104,7 @@ pub struct InProgress<'a, 'c> {
     pub schema: Schema,
     pub cache: InProgressSQLiteAttributeCache,
     pub use_caching: bool,
+    pub report_tx_data: bool,
     pub tx_observer: &'a Mutex<TxObservationService>,
     pub tx_observer_watcher: InProgressObserverTransactWatcher,
 }
@@ -127,6 +139,25 @@ pub trait Queryable {
     ) -> Result<Option<TypedValue>>
     where
         E: Into<Entid>;
+
+    /// Return the most recent transaction committed at or before `instant`, if any. Use this to
+    /// construct a `DatabaseView::AsOf` or `DatabaseView::Since` for a point in time.
+    fn tx_for_instant(&self, instant: DateTime<Utc>) -> Result<Option<Entid>> {
+        let inputs = QueryInputs::with_value_sequence(vec![(
+            Variable::from_valid_name("?instant"),
+            TypedValue::Instant(instant),
+        )]);
+        self.q_once(
+            r#"[:find ?tx .
+                :in ?instant
+                :where [?tx :db/txInstant ?t]
+                       [(<= ?t ?instant)]
+                :order (desc ?tx)]"#,
+            inputs,
+        )
+        .into_scalar_result()
+        .map(|tx| tx.and_then(|tx| tx.into_entid()))
+    }
 }
 
 pub trait Pullable {
@@ -153,6 +184,12 @@ impl<'a, 'c> InProgress<'a, 'c> {
         self.use_caching = yesno;
     }
 
+    /// Choose whether transaction reports include the transaction's datoms and the schema before
+    /// and after it.  Off by default, so that bulk imports don't pay for collecting them.
+    pub fn report_tx_data(&mut self, yesno: bool) {
+        self.report_tx_data = yesno;
+    }
+
     /// If you only have a reference to an `InProgress`, you can't use the easy builder.
     /// This exists so you can make your own.
     pub fn transact_builder(&mut self, builder: TermBuilder) -> Result<TxReport> {
@@ -165,11 +202,14 @@ impl<'a, 'c> InProgress<'a, 'c> {
     where
         I: IntoIterator<Item = TermWithTempIds>,
     {
-        let w = InProgressTransactWatcher::new(
-            &mut self.tx_observer_watcher,
-            self.cache.transact_watcher(),
+        let w = TxDataWatcher::new(
+            InProgressTransactWatcher::new(
+                &mut self.tx_observer_watcher,
+                self.cache.transact_watcher(),
+            ),
+            self.report_tx_data,
         );
-        let (report, next_partition_map, next_schema, _watcher) = transact_terms(
+        let (mut report, next_partition_map, next_schema, w) = transact_terms(
             &self.transaction,
             self.partition_map.clone(),
             &self.schema,
@@ -178,6 +218,7 @@ impl<'a, 'c> InProgress<'a, 'c> {
             terms,
             tempid_set,
         )?;
+        w.complete(&mut report, next_schema.as_ref());
         self.partition_map = next_partition_map;
         if let Some(schema) = next_schema {
             self.schema = schema;
@@ -197,11 +238,14 @@ impl<'a, 'c> InProgress<'a, 'c> {
         //    `Metadata` on return. If we used `Cell` or other mechanisms, we'd be using
         //    `Default::default` in those situations to extract the partition map, and so there
         //    would still be some cost.
-        let w = InProgressTransactWatcher::new(
-            &mut self.tx_observer_watcher,
-            self.cache.transact_watcher(),
+        let w = TxDataWatcher::new(
+            InProgressTransactWatcher::new(
+                &mut self.tx_observer_watcher,
+                self.cache.transact_watcher(),
+            ),
+            self.report_tx_data,
         );
-        let (report, next_partition_map, next_schema, _watcher) = transact(
+        let (mut report, next_partition_map, next_schema, w) = transact(
             &self.transaction,
             self.partition_map.clone(),
             &self.schema,
@@ -209,6 +253,7 @@ impl<'a, 'c> InProgress<'a, 'c> {
             w,
             entities,
         )?;
+        w.complete(&mut report, next_schema.as_ref());
         self.partition_map = next_partition_map;
         if let Some(schema) = next_schema {
             self.schema = schema;
@@ -250,6 +295,30 @@ impl<'a, 'c> InProgress<'a, 'c> {
             bail!(MentatError::UnexpectedLostTransactRace);
         }
 
+        // Re-run any live queries this transaction might have changed before committing, so that
+        // they see exactly what's committed.
+        let live_query_refresh = match self.tx_observer_watcher.txes.keys().last() {
+            Some(&tx_id) if !metadata.live_queries.is_empty() => {
+                let attributes: BTreeSet<Entid> = self
+                    .tx_observer_watcher
+                    .txes
+                    .values()
+                    .flatten()
+                    .cloned()
+                    .collect();
+                let schema_changed = self.schema != *(metadata.schema);
+                let known = Known::new(&self.schema, Some(&self.cache));
+                Some(metadata.live_queries.refresh(
+                    &self.transaction,
+                    known,
+                    tx_id,
+                    &attributes,
+                    schema_changed,
+                ))
+            }
+            _ => None,
+        };
+
         // Commit the SQLite transaction while we hold the mutex.
         self.transaction.commit()?;
 
@@ -268,10 +337,17 @@ impl<'a, 'c> InProgress<'a, 'c> {
         }
 
         let txes = self.tx_observer_watcher.txes;
+        let tx_datoms = self.tx_observer_watcher.tx_datoms;
         self.tx_observer
             .lock()
             .unwrap()
-            .in_progress_did_commit(txes);
+            .in_progress_did_commit(txes, tx_datoms);
+
+        if let Some(refresh) = live_query_refresh {
+            if let Some(cmd) = metadata.live_queries.conclude(refresh) {
+                self.tx_observer.lock().unwrap().dispatch(cmd);
+            }
+        }
 
         Ok(())
     }
@@ -0,0 +1,397 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Live queries are re-run whenever a committed transaction might have changed their results.
+//!
+//! A transaction might have changed a query's results if it asserted or retracted a datom of an
+//! attribute the algebrized query reads, or if it changed the schema. Affected queries are re-run
+//! inside the committing transaction, just before it commits, so that they see exactly what was
+//! committed; the new results are then delivered on the `TxObservationService` thread.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use std::time::{Duration, Instant};
+
+use core_traits::{Binding, Entid, TypedValue};
+
+use mentat_db::tx_observer::Command;
+
+use public_traits::errors::Result;
+
+use crate::query::{
+    q_once_with_attributes, Known, QueryInputs, QueryOutput, QueryResults, Variable,
+};
+
+/// New results for a live query, delivered after a transaction changed them.
+///
+/// `added` and `removed` are the rows that appeared in and disappeared from the results, in the
+/// order they appear in the new and old results. Scalar and tuple results are a single row, and
+/// collection results are rows of one binding. Results whose order changed, but whose rows didn't,
+/// have no added or removed rows.
+///
+/// `added` and `removed` are relative to the previous update, so if `missed` isn't zero they don't
+/// account for the changes in the updates that this one replaced; `results` is always complete.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LiveQueryUpdate {
+    /// The last transaction that was committed with the change.
+    pub tx_id: Entid,
+    pub results: QueryResults,
+    pub added: Vec<Vec<Binding>>,
+    pub removed: Vec<Vec<Binding>>,
+    /// How many updates this one replaced, because the channel was full, since the previous
+    /// `LiveQueryUpdate` was received.
+    pub missed: usize,
+}
+
+struct ChannelState {
+    queue: VecDeque<Result<LiveQueryUpdate>>,
+    // Updates replaced by errors since the last update was queued.
+    missed: usize,
+    sender_alive: bool,
+    receiver_alive: bool,
+}
+
+/// A bounded queue of a live query's updates. Sending never waits: when the queue is full, the
+/// newest update or error takes the place of the last one queued, so the receiver always ends
+/// up with the latest results.
+struct Channel {
+    state: Mutex<ChannelState>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+impl Channel {
+    fn lock(&self) -> MutexGuard<'_, ChannelState> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// The sending end of a live query's channel, shared with the commands that deliver its updates.
+struct LiveQuerySender {
+    channel: Arc<Channel>,
+}
+
+impl LiveQuerySender {
+    fn is_disconnected(&self) -> bool {
+        !self.channel.lock().receiver_alive
+    }
+
+    fn send(&self, mut update: Result<LiveQueryUpdate>) {
+        let mut state = self.channel.lock();
+        if !state.receiver_alive {
+            return;
+        }
+        let mut missed = state.missed;
+        if state.queue.len() >= self.channel.capacity {
+            if let Some(Ok(replaced)) = state.queue.pop_back() {
+                missed += replaced.missed + 1;
+            }
+        }
+        match update {
+            Ok(ref mut update) => {
+                update.missed = missed;
+                state.missed = 0;
+            }
+            Err(_) => state.missed = missed,
+        }
+        state.queue.push_back(update);
+        self.channel.ready.notify_one();
+    }
+}
+
+impl Drop for LiveQuerySender {
+    fn drop(&mut self) {
+        self.channel.lock().sender_alive = false;
+        self.channel.ready.notify_all();
+    }
+}
+
+/// The receiving end of a live query's channel. Like `std::sync::mpsc::Receiver`, receiving fails
+/// once the query has been unregistered and every update sent before then has been received.
+/// Dropping the receiver unregisters the query.
+pub struct LiveQueryReceiver {
+    channel: Arc<Channel>,
+}
+
+impl LiveQueryReceiver {
+    pub fn try_recv(&self) -> ::std::result::Result<Result<LiveQueryUpdate>, TryRecvError> {
+        let mut state = self.channel.lock();
+        match state.queue.pop_front() {
+            Some(update) => Ok(update),
+            None if state.sender_alive => Err(TryRecvError::Empty),
+            None => Err(TryRecvError::Disconnected),
+        }
+    }
+
+    pub fn recv(&self) -> ::std::result::Result<Result<LiveQueryUpdate>, RecvError> {
+        let mut state = self.channel.lock();
+        loop {
+            match state.queue.pop_front() {
+                Some(update) => return Ok(update),
+                None if !state.sender_alive => return Err(RecvError),
+                None => state = self.channel.ready.wait(state).unwrap(),
+            }
+        }
+    }
+
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> ::std::result::Result<Result<LiveQueryUpdate>, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.channel.lock();
+        loop {
+            match state.queue.pop_front() {
+                Some(update) => return Ok(update),
+                None if !state.sender_alive => return Err(RecvTimeoutError::Disconnected),
+                None => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    state = self
+                        .channel
+                        .ready
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for LiveQueryReceiver {
+    fn drop(&mut self) {
+        let mut state = self.channel.lock();
+        state.receiver_alive = false;
+        state.queue.clear();
+    }
+}
+
+struct LiveQuery {
+    query: String,
+    // Variables can't be shared between threads, so keep their names.
+    inputs: Vec<(String, TypedValue)>,
+    // `None` if any attribute can change the results.
+    attributes: Option<BTreeSet<Entid>>,
+    results: QueryResults,
+    sender: Arc<LiveQuerySender>,
+}
+
+impl LiveQuery {
+    fn inputs(&self) -> QueryInputs {
+        QueryInputs::with_value_sequence(
+            self.inputs
+                .iter()
+                .map(|(name, value)| (Variable::from_valid_name(name), value.clone()))
+                .collect(),
+        )
+    }
+
+    fn is_affected_by(&self, attributes: &BTreeSet<Entid>) -> bool {
+        self.attributes
+            .as_ref()
+            .is_none_or(|mine| !mine.is_disjoint(attributes))
+    }
+}
+
+/// A live query's new results, and the attributes they depend on.
+type Refreshed = Result<(QueryResults, Option<BTreeSet<Entid>>)>;
+
+/// The results of re-running some live queries inside a transaction that hasn't yet committed.
+pub struct LiveQueryRefresh {
+    tx_id: Entid,
+    refreshed: Vec<(String, Refreshed)>,
+}
+
+/// The live queries registered with a connection.
+#[derive(Default)]
+pub struct LiveQueries {
+    queries: BTreeMap<String, LiveQuery>,
+}
+
+impl LiveQueries {
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    pub fn is_registered(&self, key: &str) -> bool {
+        self.queries.contains_key(key)
+    }
+
+    /// Run `query` and register it as `key`, replacing any live query registered as `key`.
+    /// Returns the query's current results and the receiving end of a channel that holds at most
+    /// `capacity` (but at least one) undelivered updates. Delivery never waits for the receiver:
+    /// an update that arrives while the channel is full takes the place of the last one queued,
+    /// and counts it in its `missed`, so the last update received always has the latest results.
+    /// If the query fails to re-run, the error is sent instead of an update, and the query stays
+    /// registered. Dropping the receiver unregisters the query.
+    ///
+    /// Each commit that might change the results re-runs the query before committing, while it
+    /// holds the connection's metadata lock, so a live query adds the time it takes to run to the
+    /// latency of those commits.
+    ///
+    /// Live queries can only bind values to their `:in` variables, not rules or sources.
+    pub fn register(
+        &mut self,
+        sqlite: &rusqlite::Connection,
+        known: Known,
+        key: String,
+        query: &str,
+        inputs: Vec<(Variable, TypedValue)>,
+        capacity: usize,
+    ) -> Result<(QueryOutput, LiveQueryReceiver)> {
+        let (output, attributes) = q_once_with_attributes(
+            sqlite,
+            known,
+            query,
+            QueryInputs::with_value_sequence(inputs.clone()),
+        )?;
+
+        let channel = Arc::new(Channel {
+            state: Mutex::new(ChannelState {
+                queue: VecDeque::new(),
+                missed: 0,
+                sender_alive: true,
+                receiver_alive: true,
+            }),
+            ready: Condvar::new(),
+            capacity: capacity.max(1),
+        });
+        let live_query = LiveQuery {
+            query: query.to_string(),
+            inputs: inputs
+                .into_iter()
+                .map(|(var, value)| (var.as_str().to_string(), value))
+                .collect(),
+            attributes,
+            results: output.results.clone(),
+            sender: Arc::new(LiveQuerySender {
+                channel: Arc::clone(&channel),
+            }),
+        };
+        self.queries.insert(key, live_query);
+        Ok((output, LiveQueryReceiver { channel }))
+    }
+
+    pub fn unregister(&mut self, key: &str) {
+        self.queries.remove(key);
+    }
+
+    /// Re-run the live queries that a transaction, which touched `attributes` and whose last
+    /// transaction ID is `tx_id`, might have changed. Every query is re-run if the schema changed.
+    /// Nothing changes until the refresh is passed to `conclude`, which should only happen if
+    /// the transaction commits.
+    pub fn refresh(
+        &mut self,
+        sqlite: &rusqlite::Connection,
+        known: Known,
+        tx_id: Entid,
+        attributes: &BTreeSet<Entid>,
+        schema_changed: bool,
+    ) -> LiveQueryRefresh {
+        // Forget queries whose receivers have gone away.
+        self.queries.retain(|_, q| !q.sender.is_disconnected());
+
+        let refreshed = self
+            .queries
+            .iter()
+            .filter(|&(_, q)| schema_changed || q.is_affected_by(attributes))
+            .map(|(key, q)| {
+                let result = q_once_with_attributes(sqlite, known, &q.query, q.inputs())
+                    .map(|(output, attributes)| (output.results, attributes));
+                (key.clone(), result)
+            })
+            .collect();
+        LiveQueryRefresh { tx_id, refreshed }
+    }
+
+    /// Record the results of a refresh whose transaction committed, and return a command that
+    /// delivers any changes. A live query that failed to re-run -- perhaps because the schema
+    /// changed under it -- is sent the error, and keeps its previous results.
+    pub fn conclude(&mut self, refresh: LiveQueryRefresh) -> Option<Box<dyn Command + Send>> {
+        let mut deliveries = Vec::new();
+        for (key, result) in refresh.refreshed {
+            let q = match self.queries.get_mut(&key) {
+                Some(q) => q,
+                None => continue,
+            };
+            match result {
+                Err(e) => deliveries.push((q.sender.clone(), Err(e))),
+                Ok((results, attributes)) => {
+                    q.attributes = attributes;
+                    if q.results == results {
+                        continue;
+                    }
+                    let (added, removed) = diff_rows(&q.results, &results);
+                    q.results = results.clone();
+                    let update = LiveQueryUpdate {
+                        tx_id: refresh.tx_id,
+                        results,
+                        added,
+                        removed,
+                        missed: 0,
+                    };
+                    deliveries.push((q.sender.clone(), Ok(update)));
+                }
+            }
+        }
+
+        if deliveries.is_empty() {
+            None
+        } else {
+            Some(Box::new(LiveQueryCommand { deliveries }))
+        }
+    }
+}
+
+struct LiveQueryCommand {
+    deliveries: Vec<(Arc<LiveQuerySender>, Result<LiveQueryUpdate>)>,
+}
+
+impl Command for LiveQueryCommand {
+    fn execute(&mut self) {
+        for (sender, update) in self.deliveries.drain(..) {
+            sender.send(update);
+        }
+    }
+}
+
+fn rows(results: &QueryResults) -> Vec<Vec<Binding>> {
+    match *results {
+        QueryResults::Scalar(ref binding) => binding.iter().map(|b| vec![b.clone()]).collect(),
+        QueryResults::Tuple(ref tuple) => tuple.iter().cloned().collect(),
+        QueryResults::Coll(ref coll) => coll.iter().map(|b| vec![b.clone()]).collect(),
+        QueryResults::Rel(ref rel) => rel.rows().map(|row| row.to_vec()).collect(),
+    }
+}
+
+/// Bindings can't be ordered or hashed, so this is quadratic in the number of rows.
+fn diff_rows(
+    before: &QueryResults,
+    after: &QueryResults,
+) -> (Vec<Vec<Binding>>, Vec<Vec<Binding>>) {
+    let mut removed = rows(before);
+    let mut added = Vec::new();
+    for row in rows(after) {
+        match removed.iter().position(|r| *r == row) {
+            Some(index) => {
+                removed.remove(index);
+            }
+            None => added.push(row),
+        }
+    }
+    (added, removed)
+}
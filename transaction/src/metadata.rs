@@ -12,11 +12,14 @@ use mentat_db::PartitionMap;
 
 use mentat_db::cache::SQLiteAttributeCache;
 
+use crate::live_query::LiveQueries;
+
 pub struct Metadata {
     pub generation: u64,
     pub partition_map: PartitionMap,
     pub schema: Arc<Schema>,
     pub attribute_cache: SQLiteAttributeCache,
+    pub live_queries: LiveQueries,
 }
 
 impl Metadata {
@@ -32,6 +35,7 @@ impl Metadata {
             partition_map,
             schema,
             attribute_cache: cache,
+            live_queries: Default::default(),
         }
     }
 }
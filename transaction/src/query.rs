@@ -9,24 +9,24 @@
 // specific language governing permissions and limitations under the License.
 
 use rusqlite;
-use rusqlite::types::ToSql;
+use rusqlite::types::{ToSql, ToSqlOutput};
 
-use std::rc::Rc;
+use std::collections::{BTreeMap, BTreeSet};
 
-use core_traits::{Binding, Entid, KnownEntid, TypedValue};
+use core_traits::{Binding, Entid, KnownEntid, TypedValue, ValueType};
 
-use mentat_core::{HasSchema, Schema};
+use mentat_core::{HasSchema, Schema, ValueRc};
 
 use mentat_query_algebrizer::{
     algebrize_with_inputs, parse_find_string, AlgebraicQuery, EmptyBecause, FindQuery,
 };
 
-pub use mentat_query_algebrizer::QueryInputs;
+pub use mentat_query_algebrizer::{parse_rules_string, DatabaseView, QueryInputs, QuerySource};
 
-pub use edn::query::{Keyword, PlainSymbol, Variable};
+pub use edn::query::{Keyword, PlainSymbol, Rule, SrcVar, Variable};
 
 use edn::query::{
-    Element, FindSpec, Pattern, PatternNonValuePlace, PatternValuePlace, WhereClause,
+    Element, FindSpec, Limit, Pattern, PatternNonValuePlace, PatternValuePlace, WhereClause,
 };
 
 use mentat_query_projector::{ConstantProjector, Projector};
@@ -35,6 +35,12 @@ use mentat_query_projector::translate::{query_to_select, ProjectedSelect};
 
 use mentat_sql::SQLQuery;
 
+use mentat_query_sql::input_parameter_name;
+
+use mentat_db::TypedSQLValue;
+
+use query_algebrizer_traits::errors::AlgebrizerError;
+
 pub use mentat_query_algebrizer::Known;
 
 pub use mentat_query_projector::{
@@ -48,10 +54,11 @@ use public_traits::errors::{MentatError, Result};
 pub type QueryExecutionResult = Result<QueryOutput>;
 pub type PreparedResult<'sqlite> = Result<PreparedQuery<'sqlite>>;
 
+/// A query that's been algebrized and translated into SQL once, and can be run many times.
+///
+/// Values bound when the query is prepared are part of the query. `:in` variables whose types, but
+/// not values, were bound when the query was prepared are bound each time it's run.
 pub enum PreparedQuery<'sqlite> {
-    Empty {
-        find_spec: Rc<FindSpec>,
-    },
     Constant {
         select: ConstantProjector,
     },
@@ -59,18 +66,21 @@ pub enum PreparedQuery<'sqlite> {
         statement: rusqlite::Statement<'sqlite>,
         schema: Schema,
         connection: &'sqlite rusqlite::Connection,
-        args: Vec<(String, Rc<rusqlite::types::Value>)>,
+        args: Vec<(String, ValueRc<rusqlite::types::Value>)>,
+        /// The variables to bind when the query is run, their types, and their SQL parameters.
+        inputs: Vec<(Variable, ValueType, String)>,
         projector: Box<dyn Projector>,
     },
 }
 
 impl<'sqlite> PreparedQuery<'sqlite> {
-    pub fn run<T>(&mut self, _inputs: T) -> QueryExecutionResult
+    /// Run the query, binding its remaining `:in` variables to the values in `inputs`. Each must
+    /// have a value of the type it was prepared with; other inputs are ignored.
+    pub fn run<T>(&mut self, inputs: T) -> QueryExecutionResult
     where
         T: Into<Option<QueryInputs>>,
     {
         match self {
-            PreparedQuery::Empty { ref find_spec } => Ok(QueryOutput::empty(find_spec)),
             PreparedQuery::Constant { ref select } => {
                 select.project_without_rows().map_err(|e| e.into())
             }
@@ -79,9 +89,11 @@ impl<'sqlite> PreparedQuery<'sqlite> {
                 ref schema,
                 ref connection,
                 ref args,
+                inputs: ref input_types,
                 ref projector,
             } => {
-                let rows = run_statement(statement, args)?;
+                let inputs = inputs.into().unwrap_or_default();
+                let rows = run_statement_with_inputs(statement, args, input_types, &inputs)?;
                 projector
                     .project(schema, connection, rows)
                     .map_err(|e| e.into())
@@ -90,6 +102,81 @@ impl<'sqlite> PreparedQuery<'sqlite> {
     }
 }
 
+/// Run `statement`, binding `args` and the values in `inputs` of the variables in `input_types`.
+fn run_statement_with_inputs<'stmt>(
+    statement: &'stmt mut rusqlite::Statement,
+    args: &[(String, ValueRc<rusqlite::types::Value>)],
+    input_types: &[(Variable, ValueType, String)],
+    inputs: &QueryInputs,
+) -> Result<rusqlite::Rows<'stmt>> {
+    if input_types.is_empty() {
+        return run_statement(statement, args);
+    }
+    let values = input_values(input_types, inputs)?;
+    let mut refs: Vec<(&str, &dyn ToSql)> = args
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_ref() as &dyn ToSql))
+        .collect();
+    refs.extend(values.iter().map(|(k, v)| (*k, v as &dyn ToSql)));
+    Ok(statement.query_named(&refs)?)
+}
+
+/// The `:in` variables of `algebrized` that have types but no values, and so must be bound when
+/// it runs, with their types and SQL parameters. Fails if any of them has no type.
+fn run_time_inputs(algebrized: &AlgebraicQuery) -> Result<Vec<(Variable, ValueType, String)>> {
+    let mut untyped = BTreeSet::new();
+    let mut input_types = Vec::new();
+    for var in algebrized.unbound_variables() {
+        match algebrized.cc.known_type(&var) {
+            Some(value_type) => {
+                let parameter = format!("${}", input_parameter_name(&var));
+                input_types.push((var, value_type, parameter));
+            }
+            None => {
+                untyped.insert(var);
+            }
+        }
+    }
+    if !untyped.is_empty() {
+        bail!(MentatError::UnboundVariables(
+            untyped.into_iter().map(|v| v.to_string()).collect()
+        ));
+    }
+    Ok(input_types)
+}
+
+/// The SQL values of the inputs a prepared query needs, keyed by parameter name.
+fn input_values<'a>(
+    input_types: &'a [(Variable, ValueType, String)],
+    inputs: &'a QueryInputs,
+) -> Result<Vec<(&'a str, ToSqlOutput<'a>)>> {
+    let missing: BTreeSet<PlainSymbol> = input_types
+        .iter()
+        .filter(|(var, _, _)| !inputs.values().contains_key(var))
+        .map(|(var, _, _)| var.name())
+        .collect();
+    if !missing.is_empty() {
+        bail!(MentatError::UnboundVariables(
+            missing.into_iter().map(|v| v.to_string()).collect()
+        ));
+    }
+
+    input_types
+        .iter()
+        .map(|(var, value_type, parameter)| {
+            let value = &inputs.values()[var];
+            if value.value_type() != *value_type {
+                bail!(AlgebrizerError::InputTypeDisagreement(
+                    var.name(),
+                    *value_type,
+                    value.value_type()
+                ));
+            }
+            Ok((parameter.as_str(), value.to_sql_value_pair().0))
+        })
+        .collect()
+}
+
 pub trait IntoResult {
     fn into_scalar_result(self) -> Result<Option<Binding>>;
     fn into_coll_result(self) -> Result<Vec<Binding>>;
@@ -284,7 +371,7 @@ where
 
 fn run_statement<'sqlite, 'stmt, 'bound>(
     statement: &'stmt mut rusqlite::Statement<'sqlite>,
-    bindings: &'bound [(String, Rc<rusqlite::types::Value>)],
+    bindings: &'bound [(String, ValueRc<rusqlite::types::Value>)],
 ) -> Result<rusqlite::Rows<'stmt>> {
     let rows = if bindings.is_empty() {
         statement.query(rusqlite::params![])?
@@ -301,7 +388,7 @@ fn run_statement<'sqlite, 'stmt, 'bound>(
 fn run_sql_query<'sqlite, 'sql, 'bound, T, F>(
     sqlite: &'sqlite rusqlite::Connection,
     sql: &'sql str,
-    bindings: &'bound [(String, Rc<rusqlite::types::Value>)],
+    bindings: &'bound [(String, ValueRc<rusqlite::types::Value>)],
     mut mapper: F,
 ) -> Result<Vec<T>>
 where
@@ -328,36 +415,114 @@ where
     algebrize_query(known, parsed, inputs)
 }
 
+/// A query that has been parsed, algebrized, and translated into SQL, ready to be run any number
+/// of times. Input values and cached attribute values are baked into the plan, so it's only valid
+/// for the inputs, schema, and attribute cache it was planned with. Inputs whose types, but not
+/// values, were known when it was planned are bound each time it's run.
+pub enum QueryPlan {
+    Constant(ConstantProjector),
+    Query {
+        query: SQLQuery,
+        /// The variables to bind when the query is run, their types, and their SQL parameters.
+        inputs: Vec<(Variable, ValueType, String)>,
+        projector: Box<dyn Projector>,
+    },
+}
+
+impl QueryPlan {
+    fn from_algebrized(schema: &Schema, algebrized: AlgebraicQuery) -> Result<QueryPlan> {
+        let inputs = run_time_inputs(&algebrized)?;
+        match query_to_select(schema, algebrized)? {
+            ProjectedSelect::Constant(constant) => Ok(QueryPlan::Constant(constant)),
+            ProjectedSelect::Query { query, projector } => Ok(QueryPlan::Query {
+                query: query.to_sql_query()?,
+                inputs,
+                projector,
+            }),
+        }
+    }
+
+    /// Run the plan, binding the `:in` variables it was planned without values for to the values
+    /// in `inputs`.
+    pub fn run(
+        &self,
+        sqlite: &rusqlite::Connection,
+        schema: &Schema,
+        inputs: &QueryInputs,
+    ) -> QueryExecutionResult {
+        match *self {
+            QueryPlan::Constant(ref constant) => {
+                constant.project_without_rows().map_err(|e| e.into())
+            }
+            QueryPlan::Query {
+                ref query,
+                inputs: ref input_types,
+                ref projector,
+            } => {
+                let mut statement = sqlite.prepare(query.sql.as_str())?;
+                // An input that the query doesn't use doesn't need a value.
+                let mut used = Vec::with_capacity(input_types.len());
+                for input in input_types {
+                    if statement.parameter_index(&input.2)?.is_some() {
+                        used.push(input.clone());
+                    }
+                }
+                let rows = run_statement_with_inputs(&mut statement, &query.args, &used, inputs)?;
+
+                projector
+                    .project(schema, sqlite, rows)
+                    .map_err(|e| e.into())
+            }
+        }
+    }
+}
+
 fn run_algebrized_query<'sqlite>(
     known: Known,
     sqlite: &'sqlite rusqlite::Connection,
     algebrized: AlgebraicQuery,
 ) -> QueryExecutionResult {
-    assert!(
-        algebrized.unbound_variables().is_empty(),
-        "Unbound variables should be checked by now"
-    );
-    if algebrized.is_known_empty() {
-        // We don't need to do any SQL work at all.
-        return Ok(QueryOutput::empty(&algebrized.find_spec));
-    }
-
-    let select = query_to_select(known.schema, algebrized)?;
-    match select {
-        ProjectedSelect::Constant(constant) => {
-            constant.project_without_rows().map_err(|e| e.into())
-        }
-        ProjectedSelect::Query { query, projector } => {
-            let SQLQuery { sql, args } = query.to_sql_query()?;
+    QueryPlan::from_algebrized(known.schema, algebrized)?.run(
+        sqlite,
+        known.schema,
+        &QueryInputs::default(),
+    )
+}
 
-            let mut statement = sqlite.prepare(sql.as_str())?;
-            let rows = run_statement(&mut statement, &args)?;
+/// Parse, algebrize, and translate a query without running it. See `QueryPlan`.
+pub fn q_plan<T>(known: Known, query: &str, inputs: T) -> Result<QueryPlan>
+where
+    T: Into<Option<QueryInputs>>,
+{
+    let algebrized = algebrize_query_str(known, query, inputs)?;
+    QueryPlan::from_algebrized(known.schema, algebrized)
+}
 
-            projector
-                .project(known.schema, sqlite, rows)
-                .map_err(|e| e.into())
-        }
+/// Like `q_plan`, but knowing only the types of the `:in` variables, so that the plan can be run
+/// with any values of those types. Returns `None` if the query can't be planned without its input
+/// values -- because they're needed to resolve idents, as a limit, or by a function that takes a
+/// constant -- or if the plan would be known to be empty, which might depend on the values.
+pub fn q_plan_for_types(
+    known: Known,
+    query: &str,
+    types: &BTreeMap<Variable, ValueType>,
+) -> Option<QueryPlan> {
+    let parsed = parse_find_string(query).ok()?;
+    if let Limit::Variable(_) = parsed.limit {
+        // The limit is checked when it's bound.
+        return None;
     }
+    let inputs = QueryInputs::with_type_sequence(
+        types
+            .iter()
+            .map(|(var, value_type)| (var.clone(), *value_type))
+            .collect(),
+    );
+    let algebrized = algebrize_with_inputs(known, parsed, 0, inputs).ok()?;
+    if algebrized.is_known_empty() {
+        return None;
+    }
+    QueryPlan::from_algebrized(known.schema, algebrized).ok()
 }
 
 /// Take an EDN query string, a reference to an open SQLite connection, a Mentat schema, and an
@@ -380,6 +545,23 @@ where
     run_algebrized_query(known, sqlite, algebrized)
 }
 
+/// Just like `q_once`, but also return the attributes whose datoms can change the query's results,
+/// as described by `AlgebraicQuery::referenced_attributes`.
+pub fn q_once_with_attributes<T>(
+    sqlite: &rusqlite::Connection,
+    known: Known,
+    query: &str,
+    inputs: T,
+) -> Result<(QueryOutput, Option<BTreeSet<Entid>>)>
+where
+    T: Into<Option<QueryInputs>>,
+{
+    let algebrized = algebrize_query_str(known, query, inputs)?;
+    let attributes = algebrized.referenced_attributes(known.schema);
+    let output = run_algebrized_query(known, sqlite, algebrized)?;
+    Ok((output, attributes))
+}
+
 /// Just like `q_once`, but doesn't use any cached values.
 pub fn q_uncached<T>(
     sqlite: &rusqlite::Connection,
@@ -405,23 +587,11 @@ pub fn q_prepare<'sqlite, 'schema, 'cache, 'query, T>(
 where
     T: Into<Option<QueryInputs>>,
 {
-    let algebrized = algebrize_query_str(known, query, inputs)?;
-
-    let unbound = algebrized.unbound_variables();
-    if !unbound.is_empty() {
-        // TODO: Allow binding variables at execution time, not just
-        // preparation time.
-        bail!(MentatError::UnboundVariables(
-            unbound.into_iter().map(|v| v.to_string()).collect()
-        ));
-    }
+    let parsed = parse_find_string(query)?;
+    let algebrized = algebrize_with_inputs(known, parsed, 0, inputs.into().unwrap_or_default())?;
 
-    if algebrized.is_known_empty() {
-        // We don't need to do any SQL work at all.
-        return Ok(PreparedQuery::Empty {
-            find_spec: algebrized.find_spec,
-        });
-    }
+    // Variables without values are bound when the query is run, so we need to know their types.
+    let input_types = run_time_inputs(&algebrized)?;
 
     let select = query_to_select(known.schema, algebrized)?;
     match select {
@@ -430,11 +600,20 @@ where
             let SQLQuery { sql, args } = query.to_sql_query()?;
             let statement = sqlite.prepare(sql.as_str())?;
 
+            // An input that the query doesn't use doesn't need a value.
+            let mut inputs = Vec::with_capacity(input_types.len());
+            for input in input_types {
+                if statement.parameter_index(&input.2)?.is_some() {
+                    inputs.push(input);
+                }
+            }
+
             Ok(PreparedQuery::Bound {
                 statement,
                 schema: known.schema.clone(),
                 connection: sqlite,
                 args,
+                inputs,
                 projector,
             })
         }
@@ -0,0 +1,284 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A least-recently-used cache of query plans, so that running the same query again skips
+//! parsing, algebrizing, and generating SQL.
+//!
+//! Plans are keyed by the query text and the types of its inputs, and are planned knowing only
+//! those types, so that one plan serves every value: the values are bound as SQL parameters each
+//! time the plan runs. Some queries can't be planned that way, because the algebrizer needs the
+//! input values themselves -- to resolve an ident, say, or as a limit. Their plans bake the values
+//! in, and are keyed by the values too.
+//!
+//! The algebrizer also answers what it can from the attribute cache, so a plan depends on the
+//! schema and, if any attributes are cached, on the cached values. The cache is emptied whenever
+//! either of those might have changed.
+
+use std::collections::{BTreeMap, HashMap};
+
+use std::sync::Arc;
+
+use core_traits::{TypedValue, ValueType};
+
+use mentat_core::{CachedAttributes, Schema};
+
+use mentat_db::cache::SQLiteAttributeCache;
+
+use crate::query::{
+    q_once, q_plan, q_plan_for_types, Known, QueryExecutionResult, QueryInputs, QueryPlan, Variable,
+};
+
+/// How many plans a `Conn` keeps unless told otherwise.
+pub const DEFAULT_QUERY_PLAN_CACHE_CAPACITY: usize = 128;
+
+/// How well a `QueryPlanCache` is doing.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QueryPlanCacheStats {
+    /// Queries that were run from a cached plan.
+    pub hits: u64,
+    /// Queries that were planned and then cached.
+    pub misses: u64,
+    /// Times the cache was emptied because the plans in it were out of date.
+    pub invalidations: u64,
+    /// Plans in the cache right now.
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+#[derive(Clone, Eq, Hash, PartialEq)]
+struct QueryPlanKey {
+    query: String,
+    types: Vec<(Variable, ValueType)>,
+    /// Only present for queries whose plans need the input values.
+    values: Option<Vec<(Variable, TypedValue)>>,
+}
+
+impl QueryPlanKey {
+    /// Rules and sources aren't part of the key, so queries that use them aren't cached.
+    fn new(query: &str, inputs: &QueryInputs) -> Option<QueryPlanKey> {
+        if inputs.has_rules_or_sources() {
+            return None;
+        }
+        Some(QueryPlanKey {
+            query: query.to_string(),
+            types: inputs
+                .types()
+                .iter()
+                .map(|(var, t)| (var.clone(), *t))
+                .collect(),
+            values: None,
+        })
+    }
+
+    fn with_values(&self, inputs: &QueryInputs) -> QueryPlanKey {
+        QueryPlanKey {
+            values: Some(
+                inputs
+                    .values()
+                    .iter()
+                    .map(|(var, v)| (var.clone(), v.clone()))
+                    .collect(),
+            ),
+            ..self.clone()
+        }
+    }
+}
+
+struct CachedPlan {
+    /// `None` under a key without values, if the query's plans need the input values.
+    plan: Option<QueryPlan>,
+    last_used: u64,
+}
+
+pub struct QueryPlanCache {
+    capacity: usize,
+
+    /// The schema the cached plans were planned with.
+    schema: Option<Arc<Schema>>,
+
+    /// The generation of the metadata the cached plans were planned with, if they depend on the
+    /// attribute cache.
+    generation: Option<u64>,
+
+    plans: HashMap<QueryPlanKey, CachedPlan>,
+
+    /// Keys by when they were last used, oldest first.
+    recency: BTreeMap<u64, QueryPlanKey>,
+    clock: u64,
+
+    hits: u64,
+    misses: u64,
+    invalidations: u64,
+}
+
+impl Default for QueryPlanCache {
+    fn default() -> QueryPlanCache {
+        QueryPlanCache::new(DEFAULT_QUERY_PLAN_CACHE_CAPACITY)
+    }
+}
+
+impl QueryPlanCache {
+    /// A cache that holds at most `capacity` plans. A capacity of zero disables caching.
+    pub fn new(capacity: usize) -> QueryPlanCache {
+        QueryPlanCache {
+            capacity,
+            schema: None,
+            generation: None,
+            plans: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            invalidations: 0,
+        }
+    }
+
+    pub fn stats(&self) -> QueryPlanCacheStats {
+        QueryPlanCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            invalidations: self.invalidations,
+            entries: self.plans.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// Change how many plans the cache holds, dropping the least recently used plans if there are
+    /// now too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict_to(capacity);
+    }
+
+    /// Drop every cached plan. Call this when something the plans depend on has changed in a way
+    /// that `q_once` can't notice, like the set of cached attributes.
+    pub fn invalidate(&mut self) {
+        if !self.plans.is_empty() {
+            self.invalidations += 1;
+        }
+        self.plans.clear();
+        self.recency.clear();
+    }
+
+    /// Run `query` just like `query::q_once`, using and caching a plan when possible.
+    /// `generation` is the generation of the metadata that `schema` and `cache` belong to.
+    pub fn q_once<T>(
+        &mut self,
+        sqlite: &rusqlite::Connection,
+        schema: &Arc<Schema>,
+        cache: &SQLiteAttributeCache,
+        generation: u64,
+        query: &str,
+        inputs: T,
+    ) -> QueryExecutionResult
+    where
+        T: Into<Option<QueryInputs>>,
+    {
+        let known = Known::new(schema, Some(cache));
+        let inputs = inputs.into().unwrap_or_default();
+        let key = match QueryPlanKey::new(query, &inputs) {
+            Some(key) if self.capacity > 0 => key,
+            _ => return q_once(sqlite, known, query, inputs),
+        };
+
+        // Plans that consulted the attribute cache are only good until the next commit.
+        let generation = if cache.has_cached_attributes() {
+            Some(generation)
+        } else {
+            None
+        };
+        self.validate(schema, generation);
+
+        match self.touch(&key) {
+            Some(true) => {
+                self.hits += 1;
+                return self.run(&key, sqlite, schema, &inputs);
+            }
+            Some(false) => (),
+            None => {
+                if let Some(plan) = q_plan_for_types(known, query, inputs.types()) {
+                    self.misses += 1;
+                    let output = plan.run(sqlite, schema, &inputs);
+                    self.insert(key, Some(plan));
+                    return output;
+                }
+                // Remember not to try that again.
+                self.insert(key.clone(), None);
+            }
+        }
+
+        let key = key.with_values(&inputs);
+        if self.touch(&key).is_some() {
+            self.hits += 1;
+            return self.run(&key, sqlite, schema, &inputs);
+        }
+
+        self.misses += 1;
+        let plan = q_plan(known, query, inputs)?;
+        let output = plan.run(sqlite, schema, &QueryInputs::default());
+        self.insert(key, Some(plan));
+        output
+    }
+
+    /// Mark the plan for `key` as just used. Returns whether it's a plan that can be run, or
+    /// `None` if there isn't one.
+    fn touch(&mut self, key: &QueryPlanKey) -> Option<bool> {
+        let cached = self.plans.get_mut(key)?;
+        self.clock += 1;
+        let key = self.recency.remove(&cached.last_used).unwrap();
+        cached.last_used = self.clock;
+        self.recency.insert(self.clock, key);
+        Some(cached.plan.is_some())
+    }
+
+    fn run(
+        &self,
+        key: &QueryPlanKey,
+        sqlite: &rusqlite::Connection,
+        schema: &Schema,
+        inputs: &QueryInputs,
+    ) -> QueryExecutionResult {
+        let plan = self.plans[key].plan.as_ref().expect("a runnable plan");
+        plan.run(sqlite, schema, inputs)
+    }
+
+    fn insert(&mut self, key: QueryPlanKey, plan: Option<QueryPlan>) {
+        self.evict_to(self.capacity - 1);
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.plans.insert(
+            key,
+            CachedPlan {
+                plan,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn validate(&mut self, schema: &Arc<Schema>, generation: Option<u64>) {
+        let same_schema = self
+            .schema
+            .as_ref()
+            .is_some_and(|planned| Arc::ptr_eq(planned, schema));
+        if !same_schema || self.generation != generation {
+            self.invalidate();
+            self.schema = Some(schema.clone());
+            self.generation = generation;
+        }
+    }
+
+    fn evict_to(&mut self, capacity: usize) {
+        while self.plans.len() > capacity {
+            let oldest = *self.recency.keys().next().unwrap();
+            let key = self.recency.remove(&oldest).unwrap();
+            self.plans.remove(&key);
+        }
+    }
+}